@@ -1326,6 +1326,7 @@ impl<B: Backend> PipelineState<B> {
                         format: f::Format::Rg32Float,
                         offset: 0,
                     },
+                    normalized: None,
                 });
                 pipeline_desc.attributes.push(pso::AttributeDesc {
                     location: 1,
@@ -1334,6 +1335,7 @@ impl<B: Backend> PipelineState<B> {
                         format: f::Format::Rg32Float,
                         offset: 8,
                     },
+                    normalized: None,
                 });
 
                 device.create_graphics_pipeline(&pipeline_desc)
@@ -751,6 +751,7 @@ fn swapchain_stuff(
                     format: f::Format::Rg32Float,
                     offset: 0,
                 },
+                normalized: None,
             });
             pipeline_desc.attributes.push(pso::AttributeDesc {
                 location: 1,
@@ -759,6 +760,7 @@ fn swapchain_stuff(
                     format: f::Format::Rg32Float,
                     offset: 8,
                 },
+                normalized: None,
             });
 
             device.create_graphics_pipeline(&pipeline_desc)
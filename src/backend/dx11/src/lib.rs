@@ -314,8 +314,15 @@ impl hal::Instance for Instance {
 
             let limits = hal::Limits {
                 max_texture_size: d3d11::D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION as _,
+                max_image_array_layers: d3d11::D3D11_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION as _,
+                max_image_3d_size: d3d11::D3D11_REQ_TEXTURE3D_U_V_OR_W_DIMENSION as _,
+                max_uniform_buffer_bindings: 14, // D3D11_COMMONSHADER_CONSTANT_BUFFER_API_SLOT_COUNT
+                max_storage_buffer_bindings: 8, // D3D11_PS_CS_UAV_REGISTER_COUNT
                 max_patch_size: 0, // TODO
                 max_viewports: d3d11::D3D11_VIEWPORT_AND_SCISSORRECT_OBJECT_COUNT_PER_PIPELINE as _,
+                max_viewport_dimensions: [d3d11::D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION as _; 2],
+                sub_pixel_precision_bits: d3d11::D3D11_SUBPIXEL_FRACTIONAL_BIT_COUNT,
+                viewport_sub_pixel_bits: d3d11::D3D11_SUBPIXEL_FRACTIONAL_BIT_COUNT,
                 max_compute_group_count: [
                     d3d11::D3D11_CS_THREAD_GROUP_MAX_X,
                     d3d11::D3D11_CS_THREAD_GROUP_MAX_Y,
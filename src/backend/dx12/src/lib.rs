@@ -1013,8 +1013,15 @@ impl hal::Instance for Instance {
                     if depth_bounds_test_supported { Features::DEPTH_BOUNDS } else { Features::empty() },
                 limits: Limits { // TODO
                     max_texture_size: 0,
+                    max_image_array_layers: d3d12::D3D12_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION as _,
+                    max_image_3d_size: d3d12::D3D12_REQ_TEXTURE3D_U_V_OR_W_DIMENSION as _,
+                    max_uniform_buffer_bindings: 14, // D3D12_COMMONSHADER_CONSTANT_BUFFER_API_SLOT_COUNT
+                    max_storage_buffer_bindings: 64, // D3D12_UAV_SLOT_COUNT
                     max_patch_size: 0,
                     max_viewports: 0,
+                    max_viewport_dimensions: [d3d12::D3D12_REQ_TEXTURE2D_U_OR_V_DIMENSION as _; 2],
+                    sub_pixel_precision_bits: d3d12::D3D12_SUBPIXEL_FRACTIONAL_BIT_COUNT,
+                    viewport_sub_pixel_bits: d3d12::D3D12_SUBPIXEL_FRACTIONAL_BIT_COUNT,
                     max_compute_group_count: [
                         d3d12::D3D12_CS_THREAD_GROUP_MAX_X,
                         d3d12::D3D12_CS_THREAD_GROUP_MAX_Y,
@@ -2,17 +2,18 @@
 
 use gl;
 
-use hal::{self, buffer, command, image, memory, pass, pso, query, ColorSlot};
+use hal::{self, buffer, command, format, image, memory, pass, pso, query, ColorSlot};
 use hal::format::ChannelType;
 use hal::range::RangeArg;
 
-use {native as n, Backend};
+use {conv, info, native as n, Backend};
 use pool::{self, BufferMemory};
 
 use std::borrow::Borrow;
 use std::{mem, slice};
 use std::ops::Range;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // Command buffer implementation details:
 //
@@ -52,6 +53,19 @@ impl BufferSlice {
     }
 }
 
+/// Layout of compressed blocks in a buffer, in bytes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompressedLayout {
+    /// Height of a block in texels.
+    pub block_height: u32,
+    /// Size of the blocks covering the width of the region.
+    pub row_size: u32,
+    /// Distance between the starts of two rows of blocks.
+    pub row_pitch: u32,
+    /// Distance between the starts of two layers or depth slices.
+    pub slice_pitch: u32,
+}
+
 ///
 #[derive(Clone, Debug)]
 pub enum Command {
@@ -78,7 +92,22 @@ pub enum Command {
         depth_range_ptr: BufferSlice,
     },
     SetScissors(u32, BufferSlice),
+    SetScissorTest(bool),
     SetBlendColor(pso::ColorValue),
+    SetDepthBias(Option<pso::DepthBias>),
+    SetDepthClamp(bool),
+    /// Set a uniform of the bound program from push constant data.
+    SetUniform(n::UniformDesc, BufferSlice),
+    SetDepth(pso::DepthTest),
+    /// Stencil test with all dynamic states resolved.
+    SetStencil(pso::StencilTest),
+    /// Enable the depth bounds test with the given bounds or disable it.
+    SetDepthBounds(Option<Range<f32>>),
+    /// Enable per-sample shading with the given minimum rate or disable it.
+    SetSampleShading(Option<f32>),
+    /// Force the sRGB conversion of color writes on or off,
+    /// or restore the default state.
+    SetFramebufferSrgb(Option<bool>),
 
     /// Clear floating-point color drawbuffer of bound framebuffer.
     ClearBufferColorF(DrawBuffer, [f32; 4]),
@@ -92,28 +121,97 @@ pub enum Command {
     /// Set list of color attachments for drawing.
     /// The buffer slice contains a list of `GLenum`.
     DrawBuffers(BufferSlice),
+    /// Discard the contents of framebuffer attachments.
+    /// The buffer slice contains a list of `GLenum`.
+    InvalidateFramebuffer(FrameBufferTarget, BufferSlice),
+
+    /// Blit the region of color attachment 0 of the read framebuffer into
+    /// color attachment 1 of the draw framebuffer, using the given filter.
+    /// The flag indicates if an sRGB conversion is required.
+    BlitFramebuffer([gl::types::GLint; 4], [gl::types::GLint; 4], gl::types::GLenum, bool),
 
     BindFrameBuffer(FrameBufferTarget, n::FrameBuffer),
+    /// Bind the copy framebuffer of the current context for reading and drawing.
+    BindCopyFrameBuffer,
     BindTargetView(FrameBufferTarget, AttachmentPoint, n::ImageView),
-    SetDrawColorBuffers(usize),
     SetPatchSize(gl::types::GLint),
-    BindProgram(gl::types::GLuint),
+    /// Toggle line and polygon smoothing.
+    SetSmooth(bool, bool),
+    /// Bind a buffer range as transform feedback target: index, buffer, offset, size.
+    BindTransformFeedbackBuffer(gl::types::GLuint, n::RawBuffer, buffer::Offset, buffer::Offset),
+    /// Start capturing primitives of the given mode into the transform feedback buffers.
+    BeginTransformFeedback(gl::types::GLenum),
+    PauseTransformFeedback,
+    ResumeTransformFeedback,
+    EndTransformFeedback,
+    BeginQuery(gl::types::GLenum, gl::types::GLuint),
+    EndQuery(gl::types::GLenum),
+    WriteTimestamp(gl::types::GLuint),
+    /// Mark queries as unavailable until they begin again.
+    /// The buffer slice contains a list of query names.
+    ResetQueries(BufferSlice),
+    /// Write the results of queries into a buffer at the given offset and stride.
+    /// The buffer slice contains a list of query names.
+    CopyQueryResults(BufferSlice, n::RawBuffer, buffer::Offset, buffer::Offset, n::QueryResultFlags),
+    BindProgram(n::BoundProgram),
     BindBlendSlot(ColorSlot, pso::ColorBlendDesc),
+    /// Bind a vertex attribute to a vertex buffer with the given stride.
     BindAttribute(n::AttributeDesc, gl::types::GLuint, gl::types::GLsizei, n::VertexAttribFunction),
     //UnbindAttribute(n::AttributeDesc),
-    CopyBufferToBuffer(n::RawBuffer, n::RawBuffer, command::BufferCopy),
+    /// Copy regions (`command::BufferCopy`) between buffers, bound once for all regions.
+    CopyBufferToBuffer(n::RawBuffer, n::RawBuffer, BufferSlice),
+    /// Write the data of the buffer slice into a buffer at the given offset.
+    UpdateBuffer(n::RawBuffer, buffer::Offset, BufferSlice),
     CopyBufferToTexture(n::RawBuffer, n::Texture, command::BufferImageCopy),
+    /// Upload rows of compressed blocks with the internal format.
+    CopyBufferToCompressedTexture(
+        n::RawBuffer, n::Texture, n::TextureTarget, gl::types::GLenum, CompressedLayout, command::BufferImageCopy,
+    ),
     CopyBufferToSurface(n::RawBuffer, n::Surface, command::BufferImageCopy),
     CopyTextureToBuffer(n::Texture, n::RawBuffer, command::BufferImageCopy),
     CopySurfaceToBuffer(n::Surface, n::RawBuffer, command::BufferImageCopy),
-    CopyImageToTexture(n::ImageKind, n::Texture, command::ImageCopy),
-    CopyImageToSurface(n::ImageKind, n::Surface, command::ImageCopy),
+    /// Copy a region between images, converting the format if required.
+    CopyImage(n::Image, n::Image, command::ImageCopy),
+    /// Clear a mip level and array layer of an image to the raw color value.
+    ClearImage(n::Image, [u32; 4], image::Level, image::Layer),
+    /// Blit a region between images with a shader,
+    /// used for conversions not supported by `glBlitFramebuffer`.
+    BlitImageShader(n::Image, n::Image, command::ImageBlit),
+    /// Resolve the depth and stencil aspects of a multisampled image region.
+    ResolveDepthStencil(n::Image, n::Image, command::ImageResolve, n::DepthResolveMode),
 
     BindBufferRange(gl::types::GLenum, gl::types::GLuint, n::RawBuffer, gl::types::GLintptr, gl::types::GLsizeiptr),
-    BindTexture(gl::types::GLenum, n::Texture),
+    BindTexture(gl::types::GLenum, n::Texture, n::TextureTarget),
     BindSampler(gl::types::GLuint, n::Texture),
+    /// Apply the sampler state to the texture bound to a unit, without sampler objects.
+    BindSamplerInfo(gl::types::GLuint, image::SamplerInfo),
+    /// Bind a texture level to an image unit: unit, texture, level, layered, layer, access, format.
+    BindImageTexture(
+        gl::types::GLuint,
+        n::Texture,
+        image::Level,
+        bool,
+        image::Layer,
+        gl::types::GLenum,
+        gl::types::GLenum,
+    ),
+    /// Bind the texture of an attachment of a framebuffer to a
+    /// texture unit for reading it as input attachment.
+    BindInputAttachment(gl::types::GLuint, n::FrameBuffer, pass::AttachmentId),
+    /// Resolve a multisampled color attachment of a framebuffer into an
+    /// attachment of its resolve framebuffer, within the given rect.
+    ResolveAttachment(n::FrameBuffer, gl::types::GLenum, gl::types::GLenum, [gl::types::GLint; 4]),
+    /// Program the sample locations of the bound draw framebuffer.
+    /// The buffer slice contains a list of `f32` coordinate pairs,
+    /// an empty list restores the standard locations.
+    SetSampleLocations(BufferSlice),
+    /// Order memory accesses with `glMemoryBarrier` using the given barrier bits.
+    MemoryBarrier(gl::types::GLbitfield),
 }
 
+/// Maximum number of bytes written by a single `update_buffer`.
+const MAX_UPDATE_BUFFER_SIZE: usize = 65536;
+
 pub type FrameBufferTarget = gl::types::GLenum;
 pub type AttachmentPoint = gl::types::GLenum;
 pub type DrawBuffer = gl::types::GLint;
@@ -130,6 +228,9 @@ pub struct RenderPassCache {
     render_pass: n::RenderPass,
     framebuffer: n::FrameBuffer,
     attachment_clears: Vec<AttachmentClear>,
+    // Texture units and attachments read by each subpass.
+    input_attachments: Vec<Vec<(gl::types::GLuint, pass::AttachmentId)>>,
+    render_area: pso::Rect,
 }
 
 // Cache current states of the command buffer
@@ -141,17 +242,24 @@ struct Cache {
     index_type: Option<hal::IndexType>,
     // Stencil reference values (front, back).
     stencil_ref: Option<(pso::StencilValue, pso::StencilValue)>,
+    // Dynamic stencil read masks (front, back).
+    stencil_read_mask: Option<(pso::StencilValue, pso::StencilValue)>,
+    // Dynamic stencil write masks (front, back).
+    stencil_write_mask: Option<(pso::StencilValue, pso::StencilValue)>,
+    // Stencil test, set by the current pipeline.
+    stencil_test: Option<pso::StencilTest>,
+    // Stencil test last applied, with the dynamic states resolved.
+    stencil: Option<pso::StencilTest>,
     // Blend color.
     blend_color: Option<pso::ColorValue>,
-    ///
+    // Bound framebuffer and its target.
     framebuffer: Option<(FrameBufferTarget, n::FrameBuffer)>,
-    ///
     // Indicates that invalid commands have been recorded.
     error_state: bool,
     // Vertices per patch for tessellation primitives (patches).
     patch_size: Option<gl::types::GLint>,
-    // Active program name.
-    program: Option<gl::types::GLuint>,
+    // Active program or program pipeline.
+    program: Option<n::BoundProgram>,
     // Blend per attachment.
     blend_targets: Option<Vec<Option<pso::ColorBlendDesc>>>,
     // Maps bound vertex buffer offset (index) to handle.
@@ -160,6 +268,34 @@ struct Cache {
     vertex_buffer_descs: Vec<Option<pso::VertexBufferDesc>>,
     // Active attributes.
     attributes: Vec<n::AttributeDesc>,
+    // Uniforms of the active program, set by push constants.
+    uniforms: Vec<n::UniformDesc>,
+    // Shadow copy of the push constant range, for uniforms only partially covered by an update.
+    push_constants: Vec<u32>,
+    // Line and polygon smoothing, set by the current pipeline.
+    smooth: Option<(bool, bool)>,
+    // Depth bias, set by the current pipeline or dynamically.
+    depth_bias: Option<Option<pso::DepthBias>>,
+    // Depth clamping, set by the current pipeline.
+    depth_clamp: Option<bool>,
+    // Depth test, set by the current pipeline.
+    depth: Option<pso::DepthTest>,
+    // Depth bounds test, set by the current pipeline or dynamically.
+    depth_bounds: Option<Option<Range<f32>>>,
+    // Depth bounds test of the current pipeline, enabling it only for pipelines using it.
+    pipeline_depth_bounds: Option<pso::State<Range<f32>>>,
+    // Depth bounds set via `set_depth_bounds`, applied to pipelines with dynamic bounds.
+    dynamic_depth_bounds: Option<Range<f32>>,
+    // Minimum sample shading rate, set by the current pipeline.
+    sample_shading: Option<Option<f32>>,
+    // Forced framebuffer sRGB conversion, set by the current pipeline.
+    framebuffer_srgb: Option<Option<bool>>,
+    // Scissor rect of the first viewport.
+    scissor: Option<pso::Rect>,
+    // Framebuffer attachments whose contents were discarded by a previous render pass.
+    discarded_attachments: Vec<(n::FrameBuffer, pass::AttachmentId)>,
+    // Active transform feedback, `true` if paused.
+    transform_feedback: Option<bool>,
 }
 
 impl Cache {
@@ -168,6 +304,10 @@ impl Cache {
             primitive: None,
             index_type: None,
             stencil_ref: None,
+            stencil_read_mask: None,
+            stencil_write_mask: None,
+            stencil_test: None,
+            stencil: None,
             blend_color: None,
             framebuffer: None,
             error_state: false,
@@ -177,21 +317,41 @@ impl Cache {
             vertex_buffers: Vec::new(),
             vertex_buffer_descs: Vec::new(),
             attributes: Vec::new(),
+            uniforms: Vec::new(),
+            push_constants: Vec::new(),
+            smooth: None,
+            depth_bias: None,
+            depth_clamp: None,
+            depth: None,
+            depth_bounds: None,
+            pipeline_depth_bounds: None,
+            dynamic_depth_bounds: None,
+            sample_shading: None,
+            framebuffer_srgb: None,
+            scissor: None,
+            discarded_attachments: Vec::new(),
+            transform_feedback: None,
         }
     }
 }
 
-// This is a subset of the device limits stripped down to the ones needed
-// for command buffer validation.
+// This is a subset of the device limits and capabilities stripped down to
+// the ones needed for command buffer validation.
 #[derive(Debug, Clone, Copy)]
 pub struct Limits {
     max_viewports: usize,
+    max_viewport_dimensions: [u32; 2],
+    transform_feedback_pause: bool,
+    input_attachment_unit_base: u32,
 }
 
-impl From<hal::Limits> for Limits {
-    fn from(l: hal::Limits) -> Self {
+impl Limits {
+    pub(crate) fn new(l: &hal::Limits, private_caps: &info::PrivateCaps) -> Self {
         Limits {
             max_viewports: l.max_viewports,
+            max_viewport_dimensions: l.max_viewport_dimensions,
+            transform_feedback_pause: private_caps.transform_feedback_pause,
+            input_attachment_unit_base: private_caps.input_attachment_unit_base,
         }
     }
 }
@@ -208,8 +368,12 @@ pub struct RawCommandBuffer {
     // Only relevant if individual resets are allowed.
     pub(crate) id: u64,
     individual_reset: bool,
+    // Recorded with `ONE_TIME_SUBMIT`, the memory of the commands is
+    // released after the submission for individually reset buffers.
+    pub(crate) one_time_submit: bool,
+    // Set on the first submission, shared by clones of the buffer.
+    pub(crate) submitted: Arc<AtomicBool>,
 
-    fbo: n::FrameBuffer,
     /// The framebuffer to use for rendering to the main targets (0 by default).
     ///
     /// Use this to set the framebuffer that will be used for the screen display targets created
@@ -231,7 +395,6 @@ pub struct RawCommandBuffer {
 
 impl RawCommandBuffer {
     pub(crate) fn new(
-        fbo: n::FrameBuffer,
         limits: Limits,
         memory: Arc<Mutex<BufferMemory>>,
     ) -> Self {
@@ -257,7 +420,8 @@ impl RawCommandBuffer {
             buf: BufferSlice::new(),
             id,
             individual_reset,
-            fbo,
+            one_time_submit: false,
+            submitted: Arc::new(AtomicBool::new(false)),
             display_fb: 0 as n::FrameBuffer,
             cache: Cache::new(),
             pass_cache: None,
@@ -274,6 +438,7 @@ impl RawCommandBuffer {
         self.cache = Cache::new();
         self.pass_cache = None;
         self.cur_subpass = !0;
+        self.submitted.store(false, Ordering::Release);
     }
 
     fn push_cmd(&mut self, cmd: Command) {
@@ -354,6 +519,84 @@ impl RawCommandBuffer {
         }
     }
 
+    fn update_depth_bias(&mut self, depth_bias: Option<pso::DepthBias>) {
+        if self.cache.depth_bias != Some(depth_bias) {
+            self.cache.depth_bias = Some(depth_bias);
+            self.push_cmd(Command::SetDepthBias(depth_bias));
+        }
+    }
+
+    // Set the uniforms of the bound program overlapping the push constant range,
+    // see `n::UniformDesc` for the layout.
+    fn push_constants(&mut self, offset: u32, constants: &[u32]) {
+        if self.cache.program.is_none() {
+            error!("Push constants require a bound pipeline");
+            self.cache.error_state = true;
+            return;
+        }
+        let end = offset + constants.len() as u32 * 4;
+        {
+            let shadow = &mut self.cache.push_constants;
+            let (start, end) = (offset as usize / 4, end as usize / 4);
+            if shadow.len() < end {
+                shadow.resize(end, 0);
+            }
+            shadow[start .. end].copy_from_slice(constants);
+        }
+
+        // Uniforms partially covered by the range are set with the previously pushed
+        // values of the rest of the uniform.
+        let uniforms = self.cache.uniforms
+            .iter()
+            .filter(|u| u.offset < end && offset < u.offset + u.size)
+            .map(|u| (*u, pack_uniform(u, &self.cache.push_constants)))
+            .collect::<Vec<_>>();
+        for (uniform, data) in uniforms {
+            let data = self.add(&data);
+            self.push_cmd(Command::SetUniform(uniform, data));
+        }
+    }
+
+    // Apply the stencil test of the current pipeline, with the dynamic
+    // states replaced by the values set on the command buffer.
+    fn update_stencil(&mut self) {
+        let stencil = match self.cache.stencil_test {
+            Some(pso::StencilTest::On { front, back }) => {
+                let (ref_front, ref_back) = self.cache.stencil_ref.unwrap_or((0, 0));
+                let (read_front, read_back) = self.cache.stencil_read_mask.unwrap_or((!0, !0));
+                let (write_front, write_back) = self.cache.stencil_write_mask.unwrap_or((!0, !0));
+                pso::StencilTest::On {
+                    front: resolve_stencil_face(front, ref_front, read_front, write_front),
+                    back: resolve_stencil_face(back, ref_back, read_back, write_back),
+                }
+            }
+            Some(pso::StencilTest::Off) | None => pso::StencilTest::Off,
+        };
+        if self.cache.stencil != Some(stencil) {
+            self.cache.stencil = Some(stencil);
+            self.push_cmd(Command::SetStencil(stencil));
+        }
+    }
+
+    fn update_depth_bounds(&mut self, depth_bounds: Option<Range<f32>>) {
+        if self.cache.depth_bounds.as_ref() != Some(&depth_bounds) {
+            self.cache.depth_bounds = Some(depth_bounds.clone());
+            self.push_cmd(Command::SetDepthBounds(depth_bounds));
+        }
+    }
+
+    // Name of a query, pools of unsupported query types have none.
+    fn query_name(&mut self, pool: &n::QueryPool, id: query::QueryId) -> Option<gl::types::GLuint> {
+        match pool.queries.get(id as usize) {
+            Some(&name) => Some(name),
+            None => {
+                error!("Query {} is out of the range of the pool", id);
+                self.cache.error_state = true;
+                None
+            }
+        }
+    }
+
     pub(crate) fn bind_attributes(&mut self) {
         let Cache {
             ref attributes,
@@ -373,7 +616,6 @@ impl RawCommandBuffer {
 
             match vertex_buffer_descs.get(binding) {
                 Some(&Some(desc)) => {
-                    assert_eq!(desc.rate, 0); // TODO: Input rate
                     push_cmd_internal(
                         &self.id,
                         &mut self.memory,
@@ -386,9 +628,366 @@ impl RawCommandBuffer {
         }
     }
 
+    // Blit a region between two color images, layer by layer, via the internal FBO.
+    //
+    // Blits and resolves between sRGB and linear images convert the values
+    // (decoding the source or encoding into the destination), while blits
+    // between images of the same encoding copy the values unchanged.
+    fn blit(
+        &mut self,
+        src: &n::Image,
+        src_sub: &image::SubresourceLayers,
+        src_rect: [gl::types::GLint; 4],
+        dst: &n::Image,
+        dst_sub: &image::SubresourceLayers,
+        dst_rect: [gl::types::GLint; 4],
+        filter: gl::types::GLenum,
+    ) {
+        if src_sub.aspects != format::Aspects::COLOR || dst_sub.aspects != format::Aspects::COLOR {
+            error!("Blitting depth or stencil aspects is not supported");
+            self.cache.error_state = true;
+            return;
+        }
+
+        let srgb = (src.channel == ChannelType::Srgb) != (dst.channel == ChannelType::Srgb);
+        let layered = src_sub.layers != (0..1) || dst_sub.layers != (0..1);
+
+        // Framebuffers aren't shared between contexts, the queue binds the one of the current context.
+        self.push_cmd(Command::BindCopyFrameBuffer);
+        for (src_layer, dst_layer) in src_sub.layers.clone().zip(dst_sub.layers.clone()) {
+            let (src_layer, dst_layer) = if layered {
+                (Some(src_layer), Some(dst_layer))
+            } else {
+                (None, None)
+            };
+            let src_view = image_view(src, src_sub.level, src_layer);
+            let dst_view = image_view(dst, dst_sub.level, dst_layer);
+            self.push_cmd(Command::BindTargetView(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, src_view));
+            self.push_cmd(Command::BindTargetView(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT1, dst_view));
+            self.push_cmd(Command::BlitFramebuffer(src_rect, dst_rect, filter, srgb));
+        }
+    }
+
+    fn resolve_depth_stencil(
+        &mut self,
+        src: &n::Image,
+        dst: &n::Image,
+        r: &command::ImageResolve,
+        mode: n::DepthResolveMode,
+    ) {
+        let aspects = format::Aspects::DEPTH | format::Aspects::STENCIL;
+        if r.src_subresource.aspects != r.dst_subresource.aspects || !aspects.contains(r.src_subresource.aspects) {
+            error!("Can't resolve {:?} into {:?} aspects", r.src_subresource.aspects, r.dst_subresource.aspects);
+            self.cache.error_state = true;
+            return;
+        }
+        self.push_cmd(Command::ResolveDepthStencil(*src, *dst, r.clone(), mode));
+    }
+
+    /// Resolve the depth and stencil aspects of multisampled images, selecting
+    /// the resolved depth values with `depth_mode`.
+    ///
+    /// Stencil values are always taken from the first sample. Check
+    /// `PhysicalDevice::supports_depth_resolve_mode` for the supported modes,
+    /// the minimum and maximum modes additionally require the source to be
+    /// created with `SAMPLED` usage.
+    pub fn resolve_depth_stencil_image<T>(
+        &mut self,
+        src: &n::Image,
+        dst: &n::Image,
+        depth_mode: n::DepthResolveMode,
+        regions: T,
+    ) where
+        T: IntoIterator,
+        T::Item: Borrow<command::ImageResolve>,
+    {
+        for region in regions {
+            self.resolve_depth_stencil(src, dst, region.borrow(), depth_mode);
+        }
+    }
+
+    /// Copy the results of a range of queries into a buffer.
+    ///
+    /// The result of the `i`-th query is written at `offset + i * stride`.
+    /// Without `QueryResultFlags::WAIT`, results of queries, which are not yet
+    /// available, are skipped, leaving the buffer contents untouched.
+    /// Queries reset without beginning again are never available, their results
+    /// are only written with `QueryResultFlags::PARTIAL`, as zero.
+    ///
+    /// With `QueryResultFlags::WITH_AVAILABILITY` an additional value of the
+    /// same size is written directly after each result, non-zero if the result
+    /// is available. The stride must fit both values.
+    ///
+    /// Requires `GL_ARB_query_buffer_object` (core in GL 4.4).
+    pub fn copy_query_pool_results(
+        &mut self,
+        pool: &n::QueryPool,
+        queries: Range<query::QueryId>,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        stride: buffer::Offset,
+        flags: n::QueryResultFlags,
+    ) {
+        let result_size = if flags.contains(n::QueryResultFlags::BITS_64) { 8 } else { 4 };
+        let values = if flags.contains(n::QueryResultFlags::WITH_AVAILABILITY) { 2 } else { 1 };
+        if queries.end - queries.start > 1 && stride < result_size * values {
+            error!("Query result stride {} is smaller than the {} bytes written per query", stride, result_size * values);
+            self.cache.error_state = true;
+            return;
+        }
+
+        let names = match pool.queries.get(queries.start as usize .. queries.end as usize) {
+            Some(names) => names,
+            None => {
+                error!("Queries {:?} are out of the range of the pool", queries);
+                self.cache.error_state = true;
+                return;
+            }
+        };
+        let names = self.add(names);
+        self.push_cmd(Command::CopyQueryResults(names, buffer.raw, offset, stride, flags));
+    }
+
+    /// Replace a color attachment of a framebuffer, e.g. to ping-pong between
+    /// two images with a single framebuffer instead of one per image.
+    ///
+    /// Only the attachment is rebound, the view must have the same size and format
+    /// as the replaced one so the framebuffer stays complete without validating it again.
+    /// Must be recorded outside of render passes, affects all later uses of the framebuffer.
+    pub fn set_color_attachment(&mut self, framebuffer: &n::FrameBuffer, index: usize, view: &n::ImageView) {
+        if *framebuffer == n::DEFAULT_FRAMEBUFFER {
+            error!("Attachments of the default framebuffer can't be replaced");
+            self.cache.error_state = true;
+            return;
+        }
+        let point = gl::COLOR_ATTACHMENT0 + index as gl::types::GLenum;
+        self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, *framebuffer));
+        self.push_cmd(Command::BindTargetView(gl::DRAW_FRAMEBUFFER, point, view.clone()));
+    }
+
+    /// Capture the vertex outputs of the following draws into `buffers`, bound to
+    /// the transform feedback buffer indices in order. The captured outputs of SPIR-V
+    /// shaders are selected by their `XfbBuffer` and `Offset` decorations, GLSL shaders
+    /// need `xfb_offset` qualifiers, requiring GL 4.4 or `GL_ARB_enhanced_layouts`.
+    ///
+    /// The captured primitive type follows the primitive topology of the bound
+    /// graphics pipeline, which has to stay compatible until the capture ends.
+    pub fn begin_transform_feedback(&mut self, buffers: &[(&n::Buffer, Range<buffer::Offset>)]) {
+        if self.cache.transform_feedback.is_some() {
+            error!("Transform feedback is already active");
+            self.cache.error_state = true;
+            return;
+        }
+        let primitive = match self.cache.primitive.and_then(conv::transform_feedback_primitive) {
+            Some(primitive) => primitive,
+            None => {
+                error!("Transform feedback requires a bound pipeline drawing points, lines or triangles");
+                self.cache.error_state = true;
+                return;
+            }
+        };
+        for (index, &(buffer, ref range)) in buffers.iter().enumerate() {
+            if range.start >= range.end || range.end > buffer.size {
+                error!("Transform feedback range {:?} exceeds the buffer size {}", range, buffer.size);
+                self.cache.error_state = true;
+                return;
+            }
+            self.push_cmd(Command::BindTransformFeedbackBuffer(
+                index as _, buffer.raw, range.start, range.end - range.start,
+            ));
+        }
+        self.push_cmd(Command::BeginTransformFeedback(primitive));
+        self.cache.transform_feedback = Some(false);
+    }
+
+    /// Pause the active transform feedback, draws recorded until it's resumed aren't captured.
+    /// The write offsets into the captured buffers are kept.
+    pub fn pause_transform_feedback(&mut self) {
+        if !self.limits.transform_feedback_pause {
+            error!("Pausing transform feedback requires GL_ARB_transform_feedback2");
+            self.cache.error_state = true;
+            return;
+        }
+        if self.cache.transform_feedback != Some(false) {
+            error!("Only active, unpaused transform feedback can be paused");
+            self.cache.error_state = true;
+            return;
+        }
+        self.push_cmd(Command::PauseTransformFeedback);
+        self.cache.transform_feedback = Some(true);
+    }
+
+    /// Resume the paused transform feedback, appending to the previously captured data.
+    pub fn resume_transform_feedback(&mut self) {
+        if self.cache.transform_feedback != Some(true) {
+            error!("Only paused transform feedback can be resumed");
+            self.cache.error_state = true;
+            return;
+        }
+        self.push_cmd(Command::ResumeTransformFeedback);
+        self.cache.transform_feedback = Some(false);
+    }
+
+    /// End the active or paused transform feedback.
+    pub fn end_transform_feedback(&mut self) {
+        if self.cache.transform_feedback.take().is_none() {
+            error!("Transform feedback is not active");
+            self.cache.error_state = true;
+            return;
+        }
+        self.push_cmd(Command::EndTransformFeedback);
+    }
+
+    /// Program custom locations of the samples of the current framebuffer,
+    /// given in `0..1` within the pixel, one per sample.
+    ///
+    /// The locations apply to the framebuffer bound when the command is executed
+    /// and stay active until changed. An empty slice restores the standard locations.
+    ///
+    /// Requires `GL_ARB_sample_locations` or `GL_NV_sample_locations`,
+    /// see `PhysicalDevice::supports_sample_locations`.
+    pub fn set_sample_locations(&mut self, locations: &[[f32; 2]]) {
+        let locations = self.add(locations);
+        self.push_cmd(Command::SetSampleLocations(locations));
+    }
+
+    /// Set viewports with floating point bounds `[x, y, w, h]`, keeping sub-pixel
+    /// offsets instead of rounding them like `set_viewports`.
+    ///
+    /// Fractional bounds are only preserved with GL 4.1 or `GL_ARB_viewport_array`,
+    /// otherwise they are truncated to integers.
+    pub fn set_fractional_viewports(&mut self, first_viewport: u32, viewports: &[([f32; 4], Range<f32>)]) {
+        self.push_viewports(first_viewport, viewports.iter().cloned());
+    }
+
+    fn push_viewports<I>(&mut self, first_viewport: u32, viewports: I)
+    where
+        I: Iterator<Item = ([f32; 4], Range<f32>)>,
+    {
+        // OpenGL has two functions for setting the viewports.
+        // Configuring the rectangle area and setting the depth bounds are separated.
+        //
+        // We try to store everything into a contiguous block of memory,
+        // which allows us to avoid memory allocations when executing the commands.
+        let mut viewport_ptr = BufferSlice { offset: 0, size: 0 };
+        let mut depth_range_ptr = BufferSlice { offset: 0, size: 0 };
+
+        let mut len = 0;
+        for (rect, depth) in viewports {
+            let max = self.limits.max_viewport_dimensions;
+            if rect[2] > max[0] as f32 || rect[3] > max[1] as f32 {
+                error!("Viewport size {}x{} exceeds the maximum of {}x{}", rect[2], rect[3], max[0], max[1]);
+                self.cache.error_state = true;
+                return;
+            }
+            viewport_ptr.append(self.add::<f32>(&rect));
+            let depth_range = &conv::map_depth_range(&depth);
+            depth_range_ptr.append(self.add::<f64>(depth_range));
+            len += 1;
+        }
+
+        match len {
+            0 => {
+                error!("Number of viewports can not be zero.");
+                self.cache.error_state = true;
+            }
+            n if n + first_viewport as usize <= self.limits.max_viewports => {
+                self.push_cmd(Command::SetViewports { first_viewport, viewport_ptr, depth_range_ptr });
+            }
+            _ => {
+                error!("Number of viewports and first viewport index exceed the number of maximum viewports");
+                self.cache.error_state = true;
+            }
+        }
+    }
+
+    fn bind_descriptor_sets<I, J>(
+        &mut self,
+        layout: &n::PipelineLayout,
+        first_set: usize,
+        sets: I,
+        offsets: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<n::DescriptorSet>,
+        J: IntoIterator,
+        J::Item: Borrow<command::DescriptorSetOffset>,
+    {
+        assert!(offsets.into_iter().next().is_none()); // TODO: offsets unsupported
+
+        let mut set = first_set as _;
+        let drd = &*layout.desc_remap_data.read().unwrap();
+
+        for desc_set in sets {
+            let desc_set = desc_set.borrow();
+            for new_binding in &*desc_set.bindings.lock().unwrap() {
+                match new_binding {
+                    n::DescSetBindings::Buffer {ty: btype, binding, buffer, offset, size} => {
+                        let target = match btype {
+                            n::BindingTypes::UniformBuffers => gl::UNIFORM_BUFFER,
+                            n::BindingTypes::StorageBuffers => gl::SHADER_STORAGE_BUFFER,
+                            n::BindingTypes::Images |
+                            n::BindingTypes::StorageImages |
+                            n::BindingTypes::Samplers => panic!("Wrong desc set binding"),
+                        };
+                        for binding in drd.get_binding(*btype, set, *binding).unwrap() {
+                            self.push_cmd(Command::BindBufferRange(
+                                target,
+                                *binding,
+                                *buffer,
+                                *offset,
+                                *size,
+                            ))
+                        }
+                    }
+                    n::DescSetBindings::Texture(binding, texture, target) => {
+                        for binding in drd.get_binding(n::BindingTypes::Images, set, *binding).unwrap() {
+                            self.push_cmd(Command::BindTexture(
+                                *binding,
+                                *texture,
+                                *target,
+                            ))
+                        }
+                    }
+                    n::DescSetBindings::Image { binding, texture, level, layered, layer, format } => {
+                        let access = drd.get_image_access(set, *binding);
+                        for binding in drd.get_binding(n::BindingTypes::StorageImages, set, *binding).unwrap() {
+                            self.push_cmd(Command::BindImageTexture(
+                                *binding,
+                                *texture,
+                                *level,
+                                *layered,
+                                *layer,
+                                access,
+                                *format,
+                            ))
+                        }
+                    }
+                    n::DescSetBindings::Sampler(binding, sampler) => {
+                        for binding in drd.get_sampler_binding(set, *binding).unwrap() {
+                            self.push_cmd(Command::BindSampler(
+                                *binding,
+                                *sampler,
+                            ))
+                        }
+                    }
+                    n::DescSetBindings::SamplerInfo(binding, info) => {
+                        for binding in drd.get_sampler_binding(set, *binding).unwrap() {
+                            self.push_cmd(Command::BindSamplerInfo(
+                                *binding,
+                                info.clone(),
+                            ))
+                        }
+                    }
+                }
+            }
+            set += 1;
+        }
+    }
+
     fn begin_subpass(&mut self) {
         // Split processing and command recording due to borrowchk.
-        let (draw_buffers, clear_cmds) = {
+        let (draw_buffers, clear_cmds, input_cmds) = {
             let state = self.pass_cache.as_ref().unwrap();
             let subpass = &state.render_pass.subpasses[self.cur_subpass];
 
@@ -464,7 +1063,20 @@ impl RawCommandBuffer {
                 })
                 .collect::<Vec<_>>();
 
-            (draw_buffers, clear_cmds)
+            // Input attachments are emulated by sampling the attachment textures.
+            let input_cmds = if state.framebuffer == n::DEFAULT_FRAMEBUFFER {
+                if !state.input_attachments[self.cur_subpass].is_empty() {
+                    warn!("Input attachments of the default framebuffer can't be read");
+                }
+                Vec::new()
+            } else {
+                state.input_attachments[self.cur_subpass]
+                    .iter()
+                    .map(|&(unit, id)| Command::BindInputAttachment(unit, state.framebuffer, id))
+                    .collect::<Vec<_>>()
+            };
+
+            (draw_buffers, clear_cmds, input_cmds)
         };
 
         // Record commands
@@ -474,21 +1086,60 @@ impl RawCommandBuffer {
         for cmd in clear_cmds {
             self.push_cmd(cmd);
         }
+
+        for cmd in input_cmds {
+            self.push_cmd(cmd);
+        }
+    }
+
+    fn end_subpass(&mut self) {
+        // Resolve multisampled color attachments into their resolve attachments.
+        let resolve_cmds = {
+            let state = self.pass_cache.as_ref().unwrap();
+            let area = state.render_area;
+            let rect = [
+                area.x as gl::types::GLint,
+                area.y as gl::types::GLint,
+                area.x as gl::types::GLint + area.w as gl::types::GLint,
+                area.y as gl::types::GLint + area.h as gl::types::GLint,
+            ];
+
+            state.render_pass
+                .resolve_blits(self.cur_subpass)
+                .into_iter()
+                .map(|(src, dst)| {
+                    let attachments = &state.render_pass.attachments;
+                    Command::ResolveAttachment(
+                        state.framebuffer,
+                        conv::attachment_point(src, attachments[src].format),
+                        conv::attachment_point(dst, attachments[dst].format),
+                        rect,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for cmd in resolve_cmds {
+            self.push_cmd(cmd);
+        }
     }
 }
 
 impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     fn begin(
         &mut self,
-        _flags: hal::command::CommandBufferFlags,
+        flags: hal::command::CommandBufferFlags,
         _inheritance_info: hal::command::CommandBufferInheritanceInfo<Backend>
-    ) { // TODO: Implement flags!
+    ) { // TODO: Implement remaining flags!
         if self.individual_reset {
             // Implicit buffer reset when individual reset is set.
             self.reset(false);
         } else {
             self.soft_reset();
         }
+        // Recording is the same for all buffers, the state cache only lives for a single
+        // recording. One-time buffers are rejected on resubmission and release their memory.
+        self.one_time_submit = flags.contains(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
     }
 
     fn finish(&mut self) {
@@ -527,12 +1178,42 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         &mut self,
         _stages: Range<hal::pso::PipelineStage>,
         _dependencies: memory::Dependencies,
-        _barriers: T,
+        barriers: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<memory::Barrier<'a, Backend>>,
     {
-        // TODO
+        // Only resources written by transform feedback or shader stores need
+        // explicit ordering, everything else is kept coherent by the driver.
+        let mut feedback_bits = 0;
+        let mut image_bits = 0;
+        for barrier in barriers {
+            match *barrier.borrow() {
+                memory::Barrier::AllBuffers(ref states) |
+                memory::Barrier::Buffer { ref states, .. } => {
+                    if states.start.contains(buffer::Access::TRANSFORM_FEEDBACK_WRITE) {
+                        feedback_bits |= conv::buffer_access_to_barrier_bits(states.end);
+                    }
+                }
+                memory::Barrier::AllImages(ref states) => {
+                    if states.start.contains(image::Access::SHADER_WRITE) {
+                        image_bits |= conv::image_access_to_barrier_bits(states.end);
+                    }
+                }
+                memory::Barrier::Image { ref states, .. } => {
+                    if (states.start.0).contains(image::Access::SHADER_WRITE) {
+                        image_bits |= conv::image_access_to_barrier_bits(states.end.0);
+                    }
+                }
+            }
+        }
+
+        if feedback_bits != 0 {
+            feedback_bits |= gl::TRANSFORM_FEEDBACK_BARRIER_BIT;
+        }
+        if feedback_bits | image_bits != 0 {
+            self.push_cmd(Command::MemoryBarrier(feedback_bits | image_bits));
+        }
     }
 
     fn fill_buffer<R>(&mut self, _buffer: &n::Buffer, _range: R, _data: u32)
@@ -542,15 +1223,36 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         unimplemented!()
     }
 
-    fn update_buffer(&mut self, _buffer: &n::Buffer, _offset: buffer::Offset, _data: &[u8]) {
-        unimplemented!()
+    fn update_buffer(&mut self, buffer: &n::Buffer, offset: buffer::Offset, data: &[u8]) {
+        // Same restrictions as `vkCmdUpdateBuffer`.
+        if offset % 4 != 0 || data.len() % 4 != 0 {
+            error!("Buffer update offset {} and size {} must be multiples of 4", offset, data.len());
+            self.cache.error_state = true;
+            return;
+        }
+        if data.len() > MAX_UPDATE_BUFFER_SIZE {
+            error!("Buffer update size {} exceeds the maximum of {}", data.len(), MAX_UPDATE_BUFFER_SIZE);
+            self.cache.error_state = true;
+            return;
+        }
+        if offset + data.len() as buffer::Offset > buffer.size {
+            error!("Buffer update {:?} exceeds the buffer size {}", offset .. offset + data.len() as u64, buffer.size);
+            self.cache.error_state = true;
+            return;
+        }
+        if data.is_empty() {
+            return;
+        }
+
+        let data = self.add(data);
+        self.push_cmd(Command::UpdateBuffer(buffer.raw, offset, data));
     }
 
     fn begin_render_pass<T>(
         &mut self,
         render_pass: &n::RenderPass,
         framebuffer: &n::FrameBuffer,
-        _render_area: pso::Rect,
+        render_area: pso::Rect,
         clear_values: T,
         _first_subpass: command::SubpassContents,
     ) where
@@ -567,13 +1269,20 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         //      select correct ClearBuffer variant.
         //    * Check for attachment loading clearing strategy
 
-        // TODO: store ops:
-        //   < GL 4.5: Ignore
-        //  >= GL 4.5: Invalidate framebuffer attachment when store op is `DONT_CARE`.
+        // Store ops are handled in `end_render_pass`.
 
         // 2./3.
         self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, *framebuffer));
 
+        // Loaded attachments keep the contents stored by previous passes,
+        // unless these didn't store them.
+        for (i, attachment) in render_pass.attachments.iter().enumerate() {
+            if loads_attachment(attachment) && self.cache.discarded_attachments.contains(&(*framebuffer, i)) {
+                warn!("Attachment {} of framebuffer {} is loaded, but a previous render pass didn't store it",
+                    i, framebuffer);
+            }
+        }
+
         let attachment_clears = render_pass.attachments
             .iter()
             .zip(clear_values.into_iter())
@@ -594,23 +1303,91 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
                 }
             }).collect();
 
+        let input_attachments = (0 .. render_pass.subpasses.len())
+            .map(|subpass| render_pass.input_attachment_bindings(subpass, self.limits.input_attachment_unit_base))
+            .collect();
+
         self.pass_cache = Some(RenderPassCache {
             render_pass: render_pass.clone(),
             framebuffer: *framebuffer,
             attachment_clears,
+            input_attachments,
+            render_area,
         });
 
+        // Restrict rendering, including the clears on subpass entry, to the render area.
+        // Viewport and scissor can still be overridden by the user afterwards.
+        // The rect uses the same convention as `set_viewports`, flipping the
+        // Y axis is done in the vertex shaders.
+        self.set_viewports(0, &[pso::Viewport { rect: render_area, depth: 0.0 .. 1.0 }]);
+        self.set_scissors(0, &[render_area]);
+        self.push_cmd(Command::SetScissorTest(true));
+
         // Enter first subpass
         self.cur_subpass = 0;
         self.begin_subpass();
     }
 
     fn next_subpass(&mut self, _contents: command::SubpassContents) {
-        unimplemented!()
+        self.end_subpass();
+
+        // Invalidate attachments not used or preserved by the remaining subpasses.
+        let invalidate = {
+            let state = self.pass_cache.as_ref().unwrap();
+            let render_pass = &state.render_pass;
+            invalidated_attachments(
+                render_pass
+                    .discarded_attachments(self.cur_subpass)
+                    .into_iter()
+                    .map(|id| (id, &render_pass.attachments[id])),
+                state.framebuffer == n::DEFAULT_FRAMEBUFFER,
+            )
+        };
+        if !invalidate.is_empty() {
+            let attachments = self.add(&invalidate);
+            self.push_cmd(Command::InvalidateFramebuffer(gl::DRAW_FRAMEBUFFER, attachments));
+        }
+
+        self.cur_subpass += 1;
+        self.begin_subpass();
     }
 
     fn end_render_pass(&mut self) {
-        // TODO
+        // Invalidate framebuffer attachments when store op is `DONT_CARE`.
+        let invalidate = match self.pass_cache {
+            Some(ref state) => invalidated_attachments(
+                state.render_pass.attachments.iter().enumerate(),
+                state.framebuffer == n::DEFAULT_FRAMEBUFFER,
+            ),
+            None => {
+                error!("No render pass active.");
+                self.cache.error_state = true;
+                return;
+            }
+        };
+
+        {
+            let state = self.pass_cache.as_ref().unwrap();
+            let discarded = &mut self.cache.discarded_attachments;
+            for (i, attachment) in state.render_pass.attachments.iter().enumerate() {
+                let id = (state.framebuffer, i);
+                discarded.retain(|&other| other != id);
+                if attachment.format.is_some() && !stores_attachment(attachment) {
+                    discarded.push(id);
+                }
+            }
+        }
+
+        // Resolve before the multisampled attachments may be invalidated.
+        self.end_subpass();
+
+        if !invalidate.is_empty() {
+            let attachments = self.add(&invalidate);
+            self.push_cmd(Command::InvalidateFramebuffer(gl::DRAW_FRAMEBUFFER, attachments));
+        }
+
+        // Don't clip transfer operations outside of render passes.
+        self.push_cmd(Command::SetScissorTest(false));
     }
 
     fn clear_image<T>(
@@ -619,73 +1396,169 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         _: image::Layout,
         color: command::ClearColorRaw,
         _depth_stencil: command::ClearDepthStencilRaw,
-        _subresource_ranges: T,
+        subresource_ranges: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<image::SubresourceRange>,
     {
-        // TODO: clearing strategies
-        //  1.  < GL 3.0 / GL ES 3.0: glClear
-        //  2.  < GL 4.4: glClearBuffer
-        //  3. >= GL 4.4: glClearTexSubImage
-
-        // 2. ClearBuffer
-        // TODO: reset color mask
-        let fbo = self.fbo;
-        let view = match image.kind {
-            n::ImageKind::Surface(id) => n::ImageView::Surface(id),
-            n::ImageKind::Texture(id) => n::ImageView::Texture(id, 0), //TODO
-        };
-        self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, fbo));
-        self.push_cmd(Command::BindTargetView(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, view));
-        self.push_cmd(Command::SetDrawColorBuffers(1));
+        let num_layers = image.dimensions.num_layers();
+        for range in subresource_ranges {
+            let range = range.borrow();
+            if range.levels.end > image.num_levels || range.layers.end > num_layers {
+                error!(
+                    "Clear range of levels {:?} and layers {:?} exceeds the {} levels and {} layers of the image",
+                    range.levels, range.layers, image.num_levels, num_layers,
+                );
+                self.cache.error_state = true;
+                return;
+            }
 
-        match image.channel {
-            ChannelType::Unorm | ChannelType::Inorm | ChannelType::Ufloat |
-            ChannelType::Float | ChannelType::Srgb | ChannelType::Uscaled |
-            ChannelType::Iscaled => self.push_cmd(Command::ClearBufferColorF(0, unsafe { color.float32 })),
-            ChannelType::Uint => self.push_cmd(Command::ClearBufferColorU(0, unsafe { color.uint32 })),
-            ChannelType::Int => self.push_cmd(Command::ClearBufferColorI(0, unsafe { color.int32 })),
+            for level in range.levels.clone() {
+                for layer in range.layers.clone() {
+                    self.push_cmd(Command::ClearImage(*image, unsafe { color.uint32 }, level, layer));
+                }
+            }
         }
     }
 
-    fn clear_attachments<T, U>(&mut self, _: T, _: U)
+    fn clear_attachments<T, U>(&mut self, clears: T, rects: U)
     where
         T: IntoIterator,
         T::Item: Borrow<command::AttachmentClear>,
         U: IntoIterator,
         U::Item: Borrow<pso::ClearRect>,
     {
-        unimplemented!()
+        let clear_cmds = match self.pass_cache {
+            Some(ref state) => {
+                let subpass = &state.render_pass.subpasses[self.cur_subpass];
+                clears
+                    .into_iter()
+                    .filter_map(|clear| match *clear.borrow() {
+                        command::AttachmentClear::Color { index, value } => {
+                            let format = subpass.color_attachments
+                                .get(index)
+                                .and_then(|&id| state.render_pass.attachments[id].format);
+                            let channel = match format {
+                                Some(format) => format.base_format().1,
+                                None => {
+                                    warn!("Skipping clear of missing color attachment {}", index);
+                                    return None;
+                                }
+                            };
+                            let cv = command::ClearColorRaw::from(value);
+                            // Draw buffers are bound in the order of the subpass color attachments.
+                            let cmd = match channel {
+                                ChannelType::Unorm | ChannelType::Inorm | ChannelType::Ufloat |
+                                ChannelType::Float | ChannelType::Srgb | ChannelType::Uscaled |
+                                ChannelType::Iscaled => Command::ClearBufferColorF(index as _, unsafe { cv.float32 }),
+                                ChannelType::Uint => Command::ClearBufferColorU(index as _, unsafe { cv.uint32 }),
+                                ChannelType::Int => Command::ClearBufferColorI(index as _, unsafe { cv.int32 }),
+                            };
+                            Some(cmd)
+                        }
+                        command::AttachmentClear::DepthStencil { depth: None, stencil: None } => None,
+                        command::AttachmentClear::DepthStencil { depth, stencil } => {
+                            Some(Command::ClearBufferDepthStencil(depth, stencil))
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            }
+            None => {
+                error!("No render pass active.");
+                self.cache.error_state = true;
+                return;
+            }
+        };
+
+        // Clears are restricted to the rects via the scissor test,
+        // which is enabled for the whole render pass.
+        // Layered attachments are cleared in all attached layers at once,
+        // clearing a subset of layers beyond the first isn't supported.
+        let scissor = self.cache.scissor;
+        for rect in rects {
+            let rect = rect.borrow();
+            if rect.layers.start != 0 {
+                warn!("Skipping clear of unsupported attachment layers {:?}", rect.layers);
+                continue;
+            }
+            if rect.layers.end > 1 {
+                warn!("Clearing all attached layers instead of layers {:?}", rect.layers);
+            }
+            self.set_scissors(0, &[rect.rect]);
+            for cmd in &clear_cmds {
+                self.push_cmd(cmd.clone());
+            }
+        }
+
+        // Restore the scissor of the render pass.
+        if let Some(scissor) = scissor {
+            self.set_scissors(0, &[scissor]);
+        }
     }
 
     fn resolve_image<T>(
         &mut self,
-        _src: &n::Image,
+        src: &n::Image,
         _src_layout: image::Layout,
-        _dst: &n::Image,
+        dst: &n::Image,
         _dst_layout: image::Layout,
-        _regions: T,
+        regions: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<command::ImageResolve>,
     {
-        unimplemented!()
+        for region in regions {
+            let r = region.borrow();
+            if r.src_subresource.aspects != format::Aspects::COLOR {
+                self.resolve_depth_stencil(src, dst, r, n::DepthResolveMode::SampleZero);
+                continue;
+            }
+            let src_rect = [
+                r.src_offset.x,
+                r.src_offset.y,
+                r.src_offset.x + r.extent.width as i32,
+                r.src_offset.y + r.extent.height as i32,
+            ];
+            let dst_rect = [
+                r.dst_offset.x,
+                r.dst_offset.y,
+                r.dst_offset.x + r.extent.width as i32,
+                r.dst_offset.y + r.extent.height as i32,
+            ];
+            self.blit(src, &r.src_subresource, src_rect, dst, &r.dst_subresource, dst_rect, gl::NEAREST);
+        }
     }
 
     fn blit_image<T>(
         &mut self,
-        _src: &n::Image,
+        src: &n::Image,
         _src_layout: image::Layout,
-        _dst: &n::Image,
+        dst: &n::Image,
         _dst_layout: image::Layout,
-        _filter: image::Filter,
-        _regions: T,
+        filter: image::Filter,
+        regions: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<command::ImageBlit>
     {
-        unimplemented!()
+        let filter = match filter {
+            image::Filter::Nearest => gl::NEAREST,
+            image::Filter::Linear => gl::LINEAR,
+        };
+
+        // `glBlitFramebuffer` can't convert between integer and floating point formats.
+        let fixed_function = channel_class(src.channel) == channel_class(dst.channel);
+
+        for region in regions {
+            let r = region.borrow();
+            if !fixed_function {
+                self.push_cmd(Command::BlitImageShader(*src, *dst, r.clone()));
+                continue;
+            }
+            let src_rect = [r.src_bounds.start.x, r.src_bounds.start.y, r.src_bounds.end.x, r.src_bounds.end.y];
+            let dst_rect = [r.dst_bounds.start.x, r.dst_bounds.start.y, r.dst_bounds.end.x, r.dst_bounds.end.y];
+            self.blit(src, &r.src_subresource, src_rect, dst, &r.dst_subresource, dst_rect, filter);
+        }
     }
 
     fn bind_index_buffer(&mut self, ibv: buffer::IndexBufferView<Backend>) {
@@ -720,37 +1593,11 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         T: IntoIterator,
         T::Item: Borrow<pso::Viewport>,
     {
-        // OpenGL has two functions for setting the viewports.
-        // Configuring the rectangle area and setting the depth bounds are separated.
-        //
-        // We try to store everything into a contiguous block of memory,
-        // which allows us to avoid memory allocations when executing the commands.
-        let mut viewport_ptr = BufferSlice { offset: 0, size: 0 };
-        let mut depth_range_ptr = BufferSlice { offset: 0, size: 0 };
-
-        let mut len = 0;
-        for viewport in viewports {
+        self.push_viewports(first_viewport, viewports.into_iter().map(|viewport| {
             let viewport = viewport.borrow();
-            let viewport_rect = &[viewport.rect.x as f32, viewport.rect.y as f32, viewport.rect.w as f32, viewport.rect.h as f32];
-            viewport_ptr.append(self.add::<f32>(viewport_rect));
-            let depth_range = &[viewport.depth.start as f64, viewport.depth.end as f64];
-            depth_range_ptr.append(self.add::<f64>(depth_range));
-            len += 1;
-        }
-
-        match len {
-            0 => {
-                error!("Number of viewports can not be zero.");
-                self.cache.error_state = true;
-            }
-            n if n + first_viewport as usize <= self.limits.max_viewports => {
-                self.push_cmd(Command::SetViewports { first_viewport, viewport_ptr, depth_range_ptr });
-            }
-            _ => {
-                error!("Number of viewports and first viewport index exceed the number of maximum viewports");
-                self.cache.error_state = true;
-            }
-        }
+            let rect = [viewport.rect.x as f32, viewport.rect.y as f32, viewport.rect.w as f32, viewport.rect.h as f32];
+            (rect, viewport.depth.clone())
+        }));
     }
 
     fn set_scissors<T>(&mut self, first_scissor: u32, scissors: T)
@@ -762,6 +1609,9 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         let mut len = 0;
         for scissor in scissors {
             let scissor = scissor.borrow();
+            if first_scissor == 0 && len == 0 {
+                self.cache.scissor = Some(*scissor);
+            }
             let scissor = &[scissor.x as i32, scissor.y as i32, scissor.w as i32, scissor.h as i32];
             scissors_ptr.append(self.add::<i32>(scissor));
             len += 1;
@@ -783,36 +1633,18 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     }
 
     fn set_stencil_reference(&mut self, faces: pso::Face, value: pso::StencilValue) {
-        assert!(!faces.is_empty());
-
-        let mut front = 0;
-        let mut back = 0;
-
-        if let Some((last_front, last_back)) = self.cache.stencil_ref {
-            front = last_front;
-            back = last_back;
-        }
-
-        if faces.contains(pso::Face::FRONT) {
-            front = value;
-        }
-
-        if faces.contains(pso::Face::BACK) {
-            back = value;
-        }
-
         // Only cache the stencil references values until
         // we assembled all the pieces to set the stencil state
-        // from the pipeline.
-        self.cache.stencil_ref = Some((front, back));
+        // from the pipeline, see `update_stencil`.
+        update_stencil_faces(&mut self.cache.stencil_ref, faces, value, 0);
     }
 
-    fn set_stencil_read_mask(&mut self, _faces: pso::Face, _value: pso::StencilValue) {
-        unimplemented!();
+    fn set_stencil_read_mask(&mut self, faces: pso::Face, value: pso::StencilValue) {
+        update_stencil_faces(&mut self.cache.stencil_read_mask, faces, value, !0);
     }
 
-    fn set_stencil_write_mask(&mut self, _faces: pso::Face, _value: pso::StencilValue) {
-        unimplemented!();
+    fn set_stencil_write_mask(&mut self, faces: pso::Face, value: pso::StencilValue) {
+        update_stencil_faces(&mut self.cache.stencil_write_mask, faces, value, !0);
     }
 
     fn set_blend_constants(&mut self, cv: pso::ColorValue) {
@@ -822,16 +1654,24 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         }
     }
 
-    fn set_depth_bounds(&mut self, _: Range<f32>) {
-        warn!("Depth bounds test is not supported");
+    fn set_depth_bounds(&mut self, bounds: Range<f32>) {
+        if bounds.start < 0.0 || bounds.end > 1.0 || bounds.start > bounds.end {
+            error!("Depth bounds {:?} must be an increasing range within 0..1", bounds);
+            self.cache.error_state = true;
+            return;
+        }
+        self.cache.dynamic_depth_bounds = Some(bounds.clone());
+        if let Some(pso::State::Dynamic) = self.cache.pipeline_depth_bounds {
+            self.update_depth_bounds(Some(bounds));
+        }
     }
 
     fn set_line_width(&mut self, _width: f32) {
         unimplemented!()
     }
 
-    fn set_depth_bias(&mut self, _depth_bias: pso::DepthBias) {
-        unimplemented!()
+    fn set_depth_bias(&mut self, depth_bias: pso::DepthBias) {
+        self.update_depth_bias(Some(depth_bias));
     }
 
     fn bind_graphics_pipeline(&mut self, pipeline: &n::GraphicsPipeline) {
@@ -839,9 +1679,19 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             primitive,
             patch_size,
             program,
+            stage_programs: _,
             ref blend_targets,
             ref attributes,
             ref vertex_buffers,
+            smooth,
+            depth_bias,
+            depth_clamp,
+            ref depth_bounds,
+            depth,
+            stencil,
+            ref uniforms,
+            sample_shading,
+            framebuffer_srgb,
         } = *pipeline;
 
         if self.cache.primitive != Some(primitive) {
@@ -860,7 +1710,54 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             self.push_cmd(Command::BindProgram(program));
         }
 
+        if self.cache.smooth != Some(smooth) {
+            self.cache.smooth = Some(smooth);
+            self.push_cmd(Command::SetSmooth(smooth.0, smooth.1));
+        }
+
+        if self.cache.depth_clamp != Some(depth_clamp) {
+            self.cache.depth_clamp = Some(depth_clamp);
+            self.push_cmd(Command::SetDepthClamp(depth_clamp));
+        }
+
+        match depth_bias {
+            Some(pso::State::Static(bias)) => self.update_depth_bias(Some(bias)),
+            // Set via `set_depth_bias`.
+            Some(pso::State::Dynamic) => (),
+            None => self.update_depth_bias(None),
+        }
+
+        if self.cache.depth != Some(depth) {
+            self.cache.depth = Some(depth);
+            self.push_cmd(Command::SetDepth(depth));
+        }
+
+        // Applied before the next draw, once the dynamic states are known.
+        self.cache.stencil_test = Some(stencil);
+
+        self.cache.pipeline_depth_bounds = depth_bounds.clone();
+        match *depth_bounds {
+            Some(pso::State::Static(ref bounds)) => self.update_depth_bounds(Some(bounds.clone())),
+            // Set via `set_depth_bounds`, possibly before binding the pipeline.
+            Some(pso::State::Dynamic) => {
+                let bounds = self.cache.dynamic_depth_bounds.clone();
+                self.update_depth_bounds(bounds);
+            }
+            None => self.update_depth_bounds(None),
+        }
+
+        if self.cache.sample_shading != Some(sample_shading) {
+            self.cache.sample_shading = Some(sample_shading);
+            self.push_cmd(Command::SetSampleShading(sample_shading));
+        }
+
+        if self.cache.framebuffer_srgb != Some(framebuffer_srgb) {
+            self.cache.framebuffer_srgb = Some(framebuffer_srgb);
+            self.push_cmd(Command::SetFramebufferSrgb(framebuffer_srgb));
+        }
+
         self.cache.attributes = attributes.clone();
+        self.cache.uniforms = uniforms.clone();
 
         self.cache.vertex_buffer_descs = vertex_buffers.clone();
 
@@ -879,76 +1776,37 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         J: IntoIterator,
         J::Item: Borrow<command::DescriptorSetOffset>,
     {
-        assert!(offsets.into_iter().next().is_none()); // TODO: offsets unsupported
-
-        let mut set = first_set as _;
-        let drd = &*layout.desc_remap_data.read().unwrap();
-
-        for desc_set in sets {
-            let desc_set = desc_set.borrow();
-            for new_binding in &*desc_set.bindings.lock().unwrap() {
-                match new_binding {
-                    n::DescSetBindings::Buffer {ty: btype, binding, buffer, offset, size} => {
-                        let btype = match btype {
-                            n::BindingTypes::UniformBuffers => gl::UNIFORM_BUFFER,
-                            n::BindingTypes::Images => panic!("Wrong desc set binding"),
-                        };
-                        for binding in drd.get_binding(n::BindingTypes::UniformBuffers, set, *binding).unwrap() {
-                            self.push_cmd(Command::BindBufferRange(
-                                btype,
-                                *binding,
-                                *buffer,
-                                *offset,
-                                *size,
-                            ))
-                        }
-                    }
-                    n::DescSetBindings::Texture(binding, texture) => {
-                        for binding in drd.get_binding(n::BindingTypes::Images, set, *binding).unwrap() {
-                            self.push_cmd(Command::BindTexture(
-                                *binding,
-                                *texture,
-                            ))
-                        }
-                    }
-                    n::DescSetBindings::Sampler(binding, sampler) => {
-                        for binding in drd.get_binding(n::BindingTypes::Images, set, *binding).unwrap() {
-                            self.push_cmd(Command::BindSampler(
-                                *binding,
-                                *sampler,
-                            ))
-                        }
-                    }
-                }
-            }
-            set += 1;
-        }
+        self.bind_descriptor_sets(layout, first_set, sets, offsets);
     }
 
     fn bind_compute_pipeline(&mut self, pipeline: &n::ComputePipeline) {
         let n::ComputePipeline {
             program,
+            ref uniforms,
         } = *pipeline;
+        let program = n::BoundProgram::Program(program);
 
         if self.cache.program != Some(program) {
             self.cache.program = Some(program);
             self.push_cmd(Command::BindProgram(program));
         }
+        self.cache.uniforms = uniforms.clone();
     }
 
     fn bind_compute_descriptor_sets<I, J>(
         &mut self,
-        _layout: &n::PipelineLayout,
-        _first_set: usize,
-        _sets: I,
-        _offsets: J,
+        layout: &n::PipelineLayout,
+        first_set: usize,
+        sets: I,
+        offsets: J,
     ) where
         I: IntoIterator,
         I::Item: Borrow<n::DescriptorSet>,
         J: IntoIterator,
         J::Item: Borrow<command::DescriptorSetOffset>,
     {
-        // TODO
+        // Graphics and compute share the same binding points in GL.
+        self.bind_descriptor_sets(layout, first_set, sets, offsets);
     }
 
     fn dispatch(&mut self, count: hal::WorkGroupCount) {
@@ -964,17 +1822,22 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         T: IntoIterator,
         T::Item: Borrow<command::BufferCopy>,
     {
-        let old_offset = self.buf.offset;
-
-        for region in regions {
-            let r = region.borrow().clone();
-            let cmd = Command::CopyBufferToBuffer(src.raw, dst.raw, r);
-            self.push_cmd(cmd);
-        }
-
-        if self.buf.offset == old_offset {
+        let regions = regions
+            .into_iter()
+            .map(|region| *region.borrow())
+            .collect::<Vec<_>>();
+        if regions.is_empty() {
             error!("At least one region must be specified");
+            return;
         }
+        if has_overlapping_destinations(&regions) {
+            error!("Destination regions of a buffer copy must not overlap: {:?}", regions);
+            self.cache.error_state = true;
+            return;
+        }
+
+        let regions = self.add(&regions);
+        self.push_cmd(Command::CopyBufferToBuffer(src.raw, dst.raw, regions));
     }
 
     fn copy_image<T>(
@@ -992,11 +1855,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
         for region in regions {
             let r = region.borrow().clone();
-            let cmd = match dst.kind {
-                n::ImageKind::Surface(s) => Command::CopyImageToSurface(src.kind, s, r),
-                n::ImageKind::Texture(t) => Command::CopyImageToTexture(src.kind, t, r),
-            };
-            self.push_cmd(cmd);
+            self.push_cmd(Command::CopyImage(*src, *dst, r));
         }
 
         if self.buf.offset == old_offset {
@@ -1015,12 +1874,44 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
          T::Item: Borrow<command::BufferImageCopy>,
      {
         let old_size = self.buf.size;
+        let desc = dst.format.base_format().0.desc();
 
         for region in regions {
             let r = region.borrow().clone();
             let cmd = match dst.kind {
                 n::ImageKind::Surface(s) => Command::CopyBufferToSurface(src.raw, s, r),
-                n::ImageKind::Texture(t) => Command::CopyBufferToTexture(src.raw, t, r),
+                n::ImageKind::Texture(t, target) if desc.is_compressed() => {
+                    let int_format = match conv::image_format_to_gl(dst.format) {
+                        Some(int_format) => int_format,
+                        None => {
+                            error!("Compressed format {:?} has no GL equivalent", dst.format);
+                            self.cache.error_state = true;
+                            continue;
+                        }
+                    };
+                    match target {
+                        gl::TEXTURE_2D | gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D => (),
+                        _ => {
+                            error!("Compressed uploads to texture target {:#x} are not supported", target);
+                            self.cache.error_state = true;
+                            continue;
+                        }
+                    }
+                    // A buffer width or height of zero means tightly packed.
+                    let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+                    let block_size = desc.bits as u32 / 8;
+                    let blocks_x = (r.image_extent.width + block_width - 1) / block_width;
+                    let pitch_x = (r.buffer_width.max(r.image_extent.width) + block_width - 1) / block_width;
+                    let pitch_y = (r.buffer_height.max(r.image_extent.height) + block_height - 1) / block_height;
+                    let layout = CompressedLayout {
+                        block_height,
+                        row_size: blocks_x * block_size,
+                        row_pitch: pitch_x * block_size,
+                        slice_pitch: pitch_x * pitch_y * block_size,
+                    };
+                    Command::CopyBufferToCompressedTexture(src.raw, t, target, int_format, layout, r)
+                }
+                n::ImageKind::Texture(t, _) => Command::CopyBufferToTexture(src.raw, t, r),
             };
             self.push_cmd(cmd);
         }
@@ -1046,7 +1937,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             let r = region.borrow().clone();
             let cmd = match src.kind {
                 n::ImageKind::Surface(s) => Command::CopySurfaceToBuffer(s, dst.raw, r),
-                n::ImageKind::Texture(t) => Command::CopyTextureToBuffer(t, dst.raw, r),
+                n::ImageKind::Texture(t, _) => Command::CopyTextureToBuffer(t, dst.raw, r),
             };
             self.push_cmd(cmd);
         }
@@ -1062,6 +1953,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         instances: Range<hal::InstanceCount>,
     ) {
         self.bind_attributes();
+        self.update_stencil();
 
         match self.cache.primitive {
             Some(primitive) => {
@@ -1087,6 +1979,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         instances: Range<hal::InstanceCount>,
     ) {
         self.bind_attributes();
+        self.update_stencil();
 
         let (start, index_type) = match self.cache.index_type {
             Some(hal::IndexType::U16) => (indices.start * 2, gl::UNSIGNED_SHORT),
@@ -1139,52 +2032,78 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
     fn begin_query(
         &mut self,
-        _query: query::Query<Backend>,
+        query: query::Query<Backend>,
         _flags: query::QueryControl,
     ) {
-        unimplemented!()
+        let pool = query.pool;
+        let name = match self.query_name(pool, query.id) {
+            Some(name) => name,
+            None => return,
+        };
+        if pool.target == 0 {
+            error!("Timestamp queries can only be written with `write_timestamp`");
+            self.cache.error_state = true;
+            return;
+        }
+        self.push_cmd(Command::BeginQuery(pool.target, name));
     }
 
     fn push_graphics_constants(
         &mut self,
         _layout: &n::PipelineLayout,
         _stages: pso::ShaderStageFlags,
-        _offset: u32,
-        _constants: &[u32],
+        offset: u32,
+        constants: &[u32],
     ) {
-        unimplemented!()
+        // All stages share the uniforms of the program.
+        self.push_constants(offset, constants);
     }
 
     fn end_query(
         &mut self,
-        _query: query::Query<Backend>,
+        query: query::Query<Backend>,
     ) {
-        unimplemented!()
+        if query.pool.target != 0 {
+            self.push_cmd(Command::EndQuery(query.pool.target));
+        }
     }
 
     fn reset_query_pool(
         &mut self,
-        _pool: &(),
-        _queries: Range<query::QueryId>,
+        pool: &n::QueryPool,
+        queries: Range<query::QueryId>,
     ) {
-        unimplemented!()
+        // GL resets queries implicitly when they begin, until then
+        // the queue reports them as unavailable.
+        let names = match pool.queries.get(queries.start as usize .. queries.end as usize) {
+            Some(names) => names,
+            None => {
+                error!("Queries {:?} are out of the range of the pool", queries);
+                self.cache.error_state = true;
+                return;
+            }
+        };
+        let names = self.add(names);
+        self.push_cmd(Command::ResetQueries(names));
     }
 
     fn write_timestamp(
         &mut self,
         _: pso::PipelineStage,
-        _: query::Query<Backend>,
+        query: query::Query<Backend>,
     ) {
-        unimplemented!()
+        if let Some(name) = self.query_name(query.pool, query.id) {
+            self.push_cmd(Command::WriteTimestamp(name));
+        }
     }
 
     fn push_compute_constants(
         &mut self,
         _layout: &n::PipelineLayout,
-        _offset: u32,
-        _constants: &[u32],
+        offset: u32,
+        constants: &[u32],
     ) {
-        unimplemented!()
+        self.push_constants(offset, constants);
     }
 
     fn execute_commands<I>(
@@ -1198,6 +2117,169 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     }
 }
 
+/// Classes of channel types, which can be converted into each other by fixed function copies.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ChannelClass {
+    Float,
+    Uint,
+    Int,
+}
+
+pub(crate) fn channel_class(channel: ChannelType) -> ChannelClass {
+    match channel {
+        ChannelType::Uint => ChannelClass::Uint,
+        ChannelType::Int => ChannelClass::Int,
+        _ => ChannelClass::Float,
+    }
+}
+
+// View of a single mip level (and optionally a single layer) of an image.
+pub(crate) fn image_view(image: &n::Image, level: image::Level, layer: Option<image::Layer>) -> n::ImageView {
+    match (image.kind, layer) {
+        (n::ImageKind::Surface(id), _) => n::ImageView::Surface(id),
+        (n::ImageKind::Texture(id, target), None) => n::ImageView::Texture(id, target, level),
+        (n::ImageKind::Texture(id, target), Some(layer)) => n::ImageView::TextureLayer(id, target, level, layer),
+    }
+}
+
+// Update the (front, back) values of a dynamic stencil state for the given faces.
+fn update_stencil_faces(
+    values: &mut Option<(pso::StencilValue, pso::StencilValue)>,
+    faces: pso::Face,
+    value: pso::StencilValue,
+    default: pso::StencilValue,
+) {
+    assert!(!faces.is_empty());
+    let (mut front, mut back) = values.unwrap_or((default, default));
+    if faces.contains(pso::Face::FRONT) {
+        front = value;
+    }
+    if faces.contains(pso::Face::BACK) {
+        back = value;
+    }
+    *values = Some((front, back));
+}
+
+// Replace the dynamic states of a stencil face, static states of the pipeline are kept.
+fn resolve_stencil_face(
+    face: pso::StencilFace,
+    reference: pso::StencilValue,
+    read_mask: pso::StencilValue,
+    write_mask: pso::StencilValue,
+) -> pso::StencilFace {
+    pso::StencilFace {
+        reference: pso::State::Static(face.reference.static_or(reference)),
+        mask_read: pso::State::Static(face.mask_read.static_or(read_mask)),
+        mask_write: pso::State::Static(face.mask_write.static_or(write_mask)),
+        .. face
+    }
+}
+
+// Check if a render pass loads any aspect of an attachment.
+fn loads_attachment(attachment: &pass::Attachment) -> bool {
+    let format = match attachment.format {
+        Some(format) => format,
+        None => return false,
+    };
+    let load = attachment.ops.load == pass::AttachmentLoadOp::Load;
+    if format.is_color() {
+        return load;
+    }
+    (format.is_depth() && load) ||
+        (format.is_stencil() && attachment.stencil_ops.load == pass::AttachmentLoadOp::Load)
+}
+
+// Check if a render pass stores all aspects of an attachment.
+fn stores_attachment(attachment: &pass::Attachment) -> bool {
+    let format = match attachment.format {
+        Some(format) => format,
+        None => return true,
+    };
+    let store = attachment.ops.store == pass::AttachmentStoreOp::Store;
+    if format.is_color() {
+        return store;
+    }
+    (!format.is_depth() || store) &&
+        (!format.is_stencil() || attachment.stencil_ops.store == pass::AttachmentStoreOp::Store)
+}
+
+// Check if any destination ranges of buffer copy regions overlap.
+fn has_overlapping_destinations(regions: &[command::BufferCopy]) -> bool {
+    let mut ranges = regions
+        .iter()
+        .filter(|r| r.size != 0)
+        .map(|r| (r.dst, r.dst + r.size))
+        .collect::<Vec<_>>();
+    ranges.sort();
+    ranges.windows(2).any(|pair| pair[1].0 < pair[0].1)
+}
+
+// Gather the elements and matrix columns of a uniform from the push constant range
+// into the tightly packed layout of `glUniform*`. Values not pushed yet read as 0.
+fn pack_uniform(uniform: &n::UniformDesc, push_constants: &[u32]) -> Vec<u32> {
+    let columns = conv::attribute_type_columns(uniform.utype);
+    let column_words = conv::uniform_type_size(uniform.utype).unwrap_or(0) / columns / 4;
+    let mut data = Vec::with_capacity((uniform.count as u32 * columns * column_words) as usize);
+    for element in 0 .. uniform.count as u32 {
+        for column in 0 .. columns {
+            let start = (uniform.offset + element * uniform.array_stride + column * uniform.matrix_stride) / 4;
+            data.extend((start .. start + column_words)
+                .map(|word| push_constants.get(word as usize).cloned().unwrap_or(0)));
+        }
+    }
+    data
+}
+
+/// Collect the attachment points whose contents don't need to be stored
+/// at the end of a render pass.
+///
+/// Depth and stencil aspects are treated separately, a packed depth-stencil
+/// attachment only gets invalidated as a whole if both aspects are discarded.
+fn invalidated_attachments<'a, I>(
+    attachments: I,
+    default_framebuffer: bool,
+) -> Vec<gl::types::GLenum>
+where
+    I: IntoIterator<Item = (pass::AttachmentId, &'a pass::Attachment)>,
+{
+    let mut invalidate = Vec::new();
+
+    for (i, attachment) in attachments {
+        let format = match attachment.format {
+            Some(format) => format,
+            None => continue,
+        };
+
+        if format.is_color() {
+            if attachment.ops.store == pass::AttachmentStoreOp::DontCare {
+                invalidate.push(if default_framebuffer {
+                    gl::COLOR
+                } else {
+                    gl::COLOR_ATTACHMENT0 + i as gl::types::GLenum
+                });
+            }
+            continue;
+        }
+
+        let depth = format.is_depth() && attachment.ops.store == pass::AttachmentStoreOp::DontCare;
+        let stencil = format.is_stencil() && attachment.stencil_ops.store == pass::AttachmentStoreOp::DontCare;
+
+        match (depth, stencil) {
+            // The default framebuffer has no combined attachment point.
+            (true, true) if default_framebuffer => {
+                invalidate.push(gl::DEPTH);
+                invalidate.push(gl::STENCIL);
+            }
+            (true, true) => invalidate.push(gl::DEPTH_STENCIL_ATTACHMENT),
+            (true, _) => invalidate.push(if default_framebuffer { gl::DEPTH } else { gl::DEPTH_ATTACHMENT }),
+            (_, true) => invalidate.push(if default_framebuffer { gl::STENCIL } else { gl::STENCIL_ATTACHMENT }),
+            (false, false) => (),
+        }
+    }
+
+    invalidate
+}
+
 /// Avoids creating second mutable borrows of `self` by requiring mutable
 /// references only to the fields it needs. Many functions will simply use
 /// `push_cmd`, but this is needed when the caller would like to perform a
@@ -1222,3 +2304,46 @@ fn push_cmd_internal(id: &u64, memory: &mut Arc<Mutex<BufferMemory>>, buffer: &m
         size: 1,
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::pack_uniform;
+    use gl;
+    use native as n;
+
+    #[test]
+    fn test_pack_uniform_matrix_stride() {
+        // std430 `mat3` at offset 16, columns padded to 16 bytes.
+        let uniform = n::UniformDesc {
+            program: 1,
+            location: 0,
+            utype: gl::FLOAT_MAT3,
+            count: 1,
+            offset: 16,
+            size: 44,
+            array_stride: 48,
+            matrix_stride: 16,
+        };
+        let push_constants = (0 .. 16).collect::<Vec<u32>>();
+        assert_eq!(pack_uniform(&uniform, &push_constants), vec![4, 5, 6, 8, 9, 10, 12, 13, 14]);
+        // Only the first column has been pushed so far.
+        assert_eq!(pack_uniform(&uniform, &push_constants[.. 8]), vec![4, 5, 6, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pack_uniform_array_stride() {
+        // `float[2]` with 16 byte array stride.
+        let uniform = n::UniformDesc {
+            program: 1,
+            location: 0,
+            utype: gl::FLOAT,
+            count: 2,
+            offset: 0,
+            size: 20,
+            array_stride: 16,
+            matrix_stride: 4,
+        };
+        let push_constants = (0 .. 8).collect::<Vec<u32>>();
+        assert_eq!(pack_uniform(&uniform, &push_constants), vec![0, 4]);
+    }
+}
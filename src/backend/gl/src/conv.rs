@@ -1,7 +1,11 @@
+use std::ops::Range;
+
 use gl::{self, types as t};
-use hal::{buffer, image as i, Primitive};
-use hal::format::Format;
-use native::VertexAttribFunction;
+use hal::{buffer, image as i, pso, Primitive};
+use hal::format::{BaseFormat, Format};
+use ext;
+use native::{HintMode, VertexAttribFunction};
+use state;
 
 /*
 pub fn _image_kind_to_gl(kind: i::Kind) -> t::GLenum {
@@ -18,6 +22,98 @@ pub fn _image_kind_to_gl(kind: i::Kind) -> t::GLenum {
     }
 }*/
 
+pub fn view_kind_to_gl(kind: i::ViewKind) -> t::GLenum {
+    match kind {
+        i::ViewKind::D1 => gl::TEXTURE_1D,
+        i::ViewKind::D1Array => gl::TEXTURE_1D_ARRAY,
+        i::ViewKind::D2 => gl::TEXTURE_2D,
+        i::ViewKind::D2Array => gl::TEXTURE_2D_ARRAY,
+        i::ViewKind::D3 => gl::TEXTURE_3D,
+        i::ViewKind::Cube => gl::TEXTURE_CUBE_MAP,
+        i::ViewKind::CubeArray => gl::TEXTURE_CUBE_MAP_ARRAY,
+    }
+}
+
+/// Whether textures of the target have layers which can be attached at once.
+pub fn is_layered_target(target: t::GLenum) -> bool {
+    match target {
+        gl::TEXTURE_1D_ARRAY |
+        gl::TEXTURE_2D_ARRAY |
+        gl::TEXTURE_2D_MULTISAMPLE_ARRAY |
+        gl::TEXTURE_3D |
+        gl::TEXTURE_CUBE_MAP |
+        gl::TEXTURE_CUBE_MAP_ARRAY => true,
+        _ => false,
+    }
+}
+
+pub fn image_format_to_gl(format: Format) -> Option<t::GLenum> {
+    match format {
+        Format::Rgba8Unorm => Some(gl::RGBA8),
+        Format::Rgba8Srgb => Some(gl::SRGB8_ALPHA8),
+        Format::Rgba8Uint => Some(gl::RGBA8UI),
+        Format::Bc7Unorm => Some(gl::COMPRESSED_RGBA_BPTC_UNORM),
+        Format::Bc7Srgb => Some(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM),
+        Format::Bc1RgbUnorm => Some(ext::COMPRESSED_RGB_S3TC_DXT1_EXT),
+        Format::Bc1RgbaUnorm => Some(ext::COMPRESSED_RGBA_S3TC_DXT1_EXT),
+        Format::Bc3Unorm => Some(ext::COMPRESSED_RGBA_S3TC_DXT5_EXT),
+        Format::Etc2R8g8b8Unorm => Some(gl::COMPRESSED_RGB8_ETC2),
+        Format::Etc2R8g8b8Srgb => Some(gl::COMPRESSED_SRGB8_ETC2),
+        Format::Etc2R8g8b8a8Unorm => Some(gl::COMPRESSED_RGBA8_ETC2_EAC),
+        Format::Etc2R8g8b8a8Srgb => Some(gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC),
+        Format::Astc4x4Unorm => Some(ext::COMPRESSED_RGBA_ASTC_4x4_KHR),
+        Format::Astc4x4Srgb => Some(ext::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR),
+        Format::D16Unorm => Some(gl::DEPTH_COMPONENT16),
+        Format::X8D24Unorm => Some(gl::DEPTH_COMPONENT24),
+        Format::D32Float => Some(gl::DEPTH_COMPONENT32F),
+        Format::S8Uint => Some(gl::STENCIL_INDEX8),
+        Format::D24UnormS8Uint => Some(gl::DEPTH24_STENCIL8),
+        Format::D32FloatS8Uint => Some(gl::DEPTH32F_STENCIL8),
+        _ => None,
+    }
+}
+
+/// Sized internal format of buffer textures, which support
+/// only a subset of the formats, e.g. no three-component 8-bit ones.
+pub fn buffer_view_format_to_gl(format: Format) -> Option<t::GLenum> {
+    Some(match format {
+        Format::R8Unorm => gl::R8,
+        Format::R8Uint => gl::R8UI,
+        Format::R8Int => gl::R8I,
+        Format::R16Unorm => gl::R16,
+        Format::R16Uint => gl::R16UI,
+        Format::R16Int => gl::R16I,
+        Format::R16Float => gl::R16F,
+        Format::R32Uint => gl::R32UI,
+        Format::R32Int => gl::R32I,
+        Format::R32Float => gl::R32F,
+        Format::Rg8Unorm => gl::RG8,
+        Format::Rg8Uint => gl::RG8UI,
+        Format::Rg8Int => gl::RG8I,
+        Format::Rg16Unorm => gl::RG16,
+        Format::Rg16Uint => gl::RG16UI,
+        Format::Rg16Int => gl::RG16I,
+        Format::Rg16Float => gl::RG16F,
+        Format::Rg32Uint => gl::RG32UI,
+        Format::Rg32Int => gl::RG32I,
+        Format::Rg32Float => gl::RG32F,
+        Format::Rgb32Uint => gl::RGB32UI,
+        Format::Rgb32Int => gl::RGB32I,
+        Format::Rgb32Float => gl::RGB32F,
+        Format::Rgba8Unorm => gl::RGBA8,
+        Format::Rgba8Uint => gl::RGBA8UI,
+        Format::Rgba8Int => gl::RGBA8I,
+        Format::Rgba16Unorm => gl::RGBA16,
+        Format::Rgba16Uint => gl::RGBA16UI,
+        Format::Rgba16Int => gl::RGBA16I,
+        Format::Rgba16Float => gl::RGBA16F,
+        Format::Rgba32Uint => gl::RGBA32UI,
+        Format::Rgba32Int => gl::RGBA32I,
+        Format::Rgba32Float => gl::RGBA32F,
+        _ => return None,
+    })
+}
+
 pub fn filter_to_gl(mag: i::Filter, min: i::Filter, mip: i::Filter) -> (t::GLenum, t::GLenum) {
     use hal::image::Filter::*;
 
@@ -36,6 +132,23 @@ pub fn filter_to_gl(mag: i::Filter, min: i::Filter, mip: i::Filter) -> (t::GLenu
     (min_filter, mag_filter)
 }
 
+/// Normalized RGBA components of a border color, packed with red in the lowest byte.
+pub fn border_color_to_gl(color: i::PackedColor) -> [t::GLfloat; 4] {
+    let mut components = [0.0; 4];
+    for (i, component) in components.iter_mut().enumerate() {
+        *component = ((color.0 >> (i * 8)) & 0xFF) as t::GLfloat / 255.0;
+    }
+    components
+}
+
+/// Compare mode and function of a sampler, depth comparisons are disabled without a function.
+pub fn sampler_comparison_to_gl(comparison: Option<pso::Comparison>) -> (t::GLenum, Option<t::GLenum>) {
+    match comparison {
+        Some(cmp) => (gl::COMPARE_REF_TO_TEXTURE, Some(state::map_comparison(cmp))),
+        None => (gl::NONE, None),
+    }
+}
+
 pub fn wrap_to_gl(w: i::WrapMode) -> t::GLenum {
     match w {
         i::WrapMode::Tile   => gl::REPEAT,
@@ -56,6 +169,68 @@ pub fn buffer_usage_to_gl_target(usage: buffer::Usage) -> Option<t::GLenum> {
     }
 }
 
+pub fn buffer_access_to_barrier_bits(access: buffer::Access) -> t::GLbitfield {
+    use self::buffer::Access;
+    let mut bits = 0;
+    if access.contains(Access::VERTEX_BUFFER_READ) {
+        bits |= gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT;
+    }
+    if access.contains(Access::INDEX_BUFFER_READ) {
+        bits |= gl::ELEMENT_ARRAY_BARRIER_BIT;
+    }
+    if access.contains(Access::CONSTANT_BUFFER_READ) {
+        bits |= gl::UNIFORM_BARRIER_BIT;
+    }
+    if access.contains(Access::INDIRECT_COMMAND_READ) {
+        bits |= gl::COMMAND_BARRIER_BIT;
+    }
+    if access.intersects(Access::SHADER_READ | Access::SHADER_WRITE) {
+        bits |= gl::SHADER_STORAGE_BARRIER_BIT;
+    }
+    if access.intersects(Access::TRANSFER_READ | Access::TRANSFER_WRITE) {
+        bits |= gl::BUFFER_UPDATE_BARRIER_BIT;
+    }
+    if access.intersects(Access::HOST_READ | Access::HOST_WRITE) {
+        // Mapping the buffer afterwards needs to observe shader writes.
+        bits |= gl::BUFFER_UPDATE_BARRIER_BIT | gl::CLIENT_MAPPED_BUFFER_BARRIER_BIT;
+    }
+    if access.intersects(Access::MEMORY_READ | Access::MEMORY_WRITE) {
+        bits |= gl::ALL_BARRIER_BITS;
+    }
+    bits
+}
+
+pub fn image_access_to_barrier_bits(access: i::Access) -> t::GLbitfield {
+    use self::i::Access;
+    let mut bits = 0;
+    if access.contains(Access::SHADER_READ) {
+        // Sampling may go through either a texture or an image unit.
+        bits |= gl::TEXTURE_FETCH_BARRIER_BIT | gl::SHADER_IMAGE_ACCESS_BARRIER_BIT;
+    }
+    if access.contains(Access::SHADER_WRITE) {
+        bits |= gl::SHADER_IMAGE_ACCESS_BARRIER_BIT;
+    }
+    if access.contains(Access::INPUT_ATTACHMENT_READ) {
+        bits |= gl::TEXTURE_FETCH_BARRIER_BIT;
+    }
+    if access.intersects(
+        Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE |
+        Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::DEPTH_STENCIL_ATTACHMENT_WRITE
+    ) {
+        bits |= gl::FRAMEBUFFER_BARRIER_BIT;
+    }
+    if access.intersects(
+        Access::TRANSFER_READ | Access::TRANSFER_WRITE |
+        Access::HOST_READ | Access::HOST_WRITE
+    ) {
+        bits |= gl::TEXTURE_UPDATE_BARRIER_BIT;
+    }
+    if access.intersects(Access::MEMORY_READ | Access::MEMORY_WRITE) {
+        bits |= gl::ALL_BARRIER_BITS;
+    }
+    bits
+}
+
 pub fn primitive_to_gl_primitive(primitive: Primitive) -> t::GLenum {
     match primitive {
         Primitive::PointList => gl::POINTS,
@@ -71,13 +246,54 @@ pub fn primitive_to_gl_primitive(primitive: Primitive) -> t::GLenum {
     }
 }
 
+pub fn hint_mode_to_gl(mode: HintMode) -> t::GLenum {
+    match mode {
+        HintMode::Fastest => gl::FASTEST,
+        HintMode::Nicest => gl::NICEST,
+        HintMode::DontCare => gl::DONT_CARE,
+    }
+}
+
+/// Primitive mode captured by transform feedback when drawing `primitive`,
+/// `None` for patches, whose captured primitives depend on the tessellation.
+pub fn transform_feedback_primitive(primitive: t::GLenum) -> Option<t::GLenum> {
+    match primitive {
+        gl::POINTS => Some(gl::POINTS),
+        gl::LINES | gl::LINE_STRIP | gl::LINES_ADJACENCY | gl::LINE_STRIP_ADJACENCY => Some(gl::LINES),
+        gl::TRIANGLES | gl::TRIANGLE_STRIP | gl::TRIANGLES_ADJACENCY | gl::TRIANGLE_STRIP_ADJACENCY =>
+            Some(gl::TRIANGLES),
+        _ => None,
+    }
+}
+
+/// Framebuffer attachment point of the render pass attachment at `index`.
+pub fn attachment_point(index: usize, format: Option<Format>) -> t::GLenum {
+    match format {
+        Some(f) if f.is_depth() && f.is_stencil() => gl::DEPTH_STENCIL_ATTACHMENT,
+        Some(f) if f.is_depth() => gl::DEPTH_ATTACHMENT,
+        Some(f) if f.is_stencil() => gl::STENCIL_ATTACHMENT,
+        _ => gl::COLOR_ATTACHMENT0 + index as t::GLenum,
+    }
+}
+
 pub fn format_to_gl_format(format: Format) -> Option<(gl::types::GLint, gl::types::GLenum, VertexAttribFunction)> {
     use hal::format::Format::*;
     use gl::*;
     use native::VertexAttribFunction::*;
-    let _ = Double; //mark as used
     // TODO: Add more formats and error handling for `None`
     let format = match format {
+        R8Unorm | R8Uscaled => (1, UNSIGNED_BYTE, Float),
+        R8Inorm | R8Iscaled => (1, BYTE, Float),
+        Rg8Unorm | Rg8Uscaled => (2, UNSIGNED_BYTE, Float),
+        Rg8Inorm | Rg8Iscaled => (2, BYTE, Float),
+        Rgba8Unorm | Rgba8Uscaled => (4, UNSIGNED_BYTE, Float),
+        Rgba8Inorm | Rgba8Iscaled => (4, BYTE, Float),
+        R16Unorm | R16Uscaled => (1, UNSIGNED_SHORT, Float),
+        R16Inorm | R16Iscaled => (1, SHORT, Float),
+        Rg16Unorm | Rg16Uscaled => (2, UNSIGNED_SHORT, Float),
+        Rg16Inorm | Rg16Iscaled => (2, SHORT, Float),
+        Rgba16Unorm | Rgba16Uscaled => (4, UNSIGNED_SHORT, Float),
+        Rgba16Inorm | Rgba16Iscaled => (4, SHORT, Float),
         R8Uint => (1, UNSIGNED_BYTE, Integer),
         R8Int => (1, BYTE, Integer),
         Rg8Uint => (2, UNSIGNED_BYTE, Integer),
@@ -105,9 +321,335 @@ pub fn format_to_gl_format(format: Format) -> Option<(gl::types::GLint, gl::type
         Rgba32Uint => (4, UNSIGNED_INT, Integer),
         Rgba32Int => (4, INT, Integer),
         Rgba32Float => (4, FLOAT, Float),
+        R64Float => (1, DOUBLE, Double),
+        Rg64Float => (2, DOUBLE, Double),
+        Rgb64Float => (3, DOUBLE, Double),
+        Rgba64Float => (4, DOUBLE, Double),
 
         _ => return None,
     };
 
     Some(format)
 }
+
+/// Format of a framebuffer attachment given the bits of its red, green, blue and
+/// alpha components, the component type and if the color encoding is sRGB.
+pub fn framebuffer_attachment_format(
+    bits: [t::GLint; 4], component_type: t::GLenum, srgb: bool,
+) -> Option<Format> {
+    let format = match (bits, component_type, srgb) {
+        ([8, 8, 8, 8], gl::UNSIGNED_NORMALIZED, true) => Format::Rgba8Srgb,
+        ([8, 8, 8, 8], gl::UNSIGNED_NORMALIZED, false) => Format::Rgba8Unorm,
+        ([8, 8, 8, 0], gl::UNSIGNED_NORMALIZED, true) => Format::Rgb8Srgb,
+        ([8, 8, 8, 0], gl::UNSIGNED_NORMALIZED, false) => Format::Rgb8Unorm,
+        ([10, 10, 10, 2], gl::UNSIGNED_NORMALIZED, false) => Format::A2b10g10r10Unorm,
+        ([5, 6, 5, 0], gl::UNSIGNED_NORMALIZED, false) => Format::R5g6b5Unorm,
+        ([16, 16, 16, 16], gl::FLOAT, _) => Format::Rgba16Float,
+        ([11, 11, 10, 0], gl::FLOAT, _) => Format::B10g11r11Ufloat,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Number of consecutive locations consumed by a vertex attribute type,
+/// one per column of matrix types.
+pub fn attribute_type_columns(atype: t::GLenum) -> u32 {
+    match atype {
+        gl::FLOAT_MAT2 | gl::FLOAT_MAT2x3 | gl::FLOAT_MAT2x4 |
+        gl::DOUBLE_MAT2 | gl::DOUBLE_MAT2x3 | gl::DOUBLE_MAT2x4 => 2,
+        gl::FLOAT_MAT3 | gl::FLOAT_MAT3x2 | gl::FLOAT_MAT3x4 |
+        gl::DOUBLE_MAT3 | gl::DOUBLE_MAT3x2 | gl::DOUBLE_MAT3x4 => 3,
+        gl::FLOAT_MAT4 | gl::FLOAT_MAT4x2 | gl::FLOAT_MAT4x3 |
+        gl::DOUBLE_MAT4 | gl::DOUBLE_MAT4x2 | gl::DOUBLE_MAT4x3 => 4,
+        _ => 1,
+    }
+}
+
+/// Size in bytes of a single component of vertex attribute data.
+pub fn attribute_component_size(format: t::GLenum) -> u32 {
+    match format {
+        gl::BYTE | gl::UNSIGNED_BYTE => 1,
+        gl::SHORT | gl::UNSIGNED_SHORT | gl::HALF_FLOAT => 2,
+        gl::DOUBLE => 8,
+        _ => 4,
+    }
+}
+
+/// Size in bytes of a uniform type settable via push constants,
+/// `None` for opaque types like samplers.
+pub fn uniform_type_size(utype: t::GLenum) -> Option<u32> {
+    let components = match utype {
+        gl::FLOAT | gl::INT | gl::UNSIGNED_INT | gl::BOOL => 1,
+        gl::FLOAT_VEC2 | gl::INT_VEC2 | gl::UNSIGNED_INT_VEC2 | gl::BOOL_VEC2 => 2,
+        gl::FLOAT_VEC3 | gl::INT_VEC3 | gl::UNSIGNED_INT_VEC3 | gl::BOOL_VEC3 => 3,
+        gl::FLOAT_VEC4 | gl::INT_VEC4 | gl::UNSIGNED_INT_VEC4 | gl::BOOL_VEC4 |
+        gl::FLOAT_MAT2 => 4,
+        gl::FLOAT_MAT3 => 9,
+        gl::FLOAT_MAT4 => 16,
+        _ => return None,
+    };
+    Some(components * 4)
+}
+
+/// Vertex attribute function and normalization of an attribute format,
+/// with an optional override of the normalization of integer data.
+pub fn attribute_normalization(
+    format: Format, function: VertexAttribFunction, normalized: Option<bool>,
+) -> (VertexAttribFunction, bool) {
+    use hal::format::ChannelType::*;
+    let default = match format.base_format().1 {
+        Unorm | Inorm | Srgb => true,
+        _ => false,
+    };
+    match (function, normalized) {
+        // Integer data read as floating point.
+        (VertexAttribFunction::Integer, Some(true)) => (VertexAttribFunction::Float, true),
+        (VertexAttribFunction::Float, Some(normalized)) => (function, normalized),
+        (_, _) => (function, default),
+    }
+}
+
+/// Client format and type of tightly packed texels of an uncompressed format,
+/// used for transferring texels between textures and client memory.
+pub fn format_to_pixel_transfer(format: Format) -> Option<(t::GLenum, t::GLenum)> {
+    use hal::format::ChannelType as C;
+    use hal::format::SurfaceType as S;
+
+    let BaseFormat(surface, channel) = format.base_format();
+    let integer = match channel {
+        C::Uint | C::Int => true,
+        _ => false,
+    };
+    let signed = match channel {
+        C::Inorm | C::Iscaled | C::Int => true,
+        _ => false,
+    };
+    let pick = |normalized, integer_format| if integer { integer_format } else { normalized };
+
+    let client_format = match surface {
+        S::R8 | S::R16 | S::R32 => pick(gl::RED, gl::RED_INTEGER),
+        S::R8_G8 | S::R16_G16 | S::R32_G32 => pick(gl::RG, gl::RG_INTEGER),
+        S::R8_G8_B8 | S::R16_G16_B16 | S::R32_G32_B32 => pick(gl::RGB, gl::RGB_INTEGER),
+        S::R8_G8_B8_A8 | S::R16_G16_B16_A16 | S::R32_G32_B32_A32 => pick(gl::RGBA, gl::RGBA_INTEGER),
+        S::B8_G8_R8_A8 => pick(gl::BGRA, gl::BGRA_INTEGER),
+        S::D16 | S::D32 => gl::DEPTH_COMPONENT,
+        _ => return None,
+    };
+    let client_type = match surface.desc().bits / client_components(client_format) {
+        8 if signed => gl::BYTE,
+        8 => gl::UNSIGNED_BYTE,
+        16 if channel == C::Float => gl::HALF_FLOAT,
+        16 if signed => gl::SHORT,
+        16 => gl::UNSIGNED_SHORT,
+        32 if channel == C::Float => gl::FLOAT,
+        32 if signed => gl::INT,
+        32 => gl::UNSIGNED_INT,
+        _ => return None,
+    };
+    Some((client_format, client_type))
+}
+
+fn client_components(client_format: t::GLenum) -> u16 {
+    match client_format {
+        gl::RED | gl::RED_INTEGER | gl::DEPTH_COMPONENT => 1,
+        gl::RG | gl::RG_INTEGER => 2,
+        gl::RGB | gl::RGB_INTEGER => 3,
+        _ => 4,
+    }
+}
+
+/// Number of consecutive attribute locations consumed by a vertex attribute.
+///
+/// Three and four component double-precision attributes use two locations.
+pub fn attribute_locations(format: Format) -> u32 {
+    use hal::format::Format::*;
+    match format {
+        Rgb64Uint | Rgb64Int | Rgb64Float |
+        Rgba64Uint | Rgba64Int | Rgba64Float => 2,
+        _ => 1,
+    }
+}
+
+/// Depth range passed to `glDepthRange` for a viewport depth range.
+///
+/// Shaders always produce depth in the `0..1` clip convention, either natively
+/// via `glClipControl` or by remapping in the vertex shader. In both cases NDC depth
+/// `0` maps to `depth.start` and `1` to `depth.end`, so the range is passed through
+/// without reordering, which keeps reversed ranges (reversed-Z) intact.
+/// Depth clamping clamps to the same range regardless of its orientation.
+pub fn map_depth_range(depth: &Range<f32>) -> [f64; 2] {
+    let clamp = |d: f32| (d as f64).max(0.0).min(1.0);
+    [clamp(depth.start), clamp(depth.end)]
+}
+
+/// Round a range outwards to multiples of the non-coherent atom size,
+/// clamped to the mapped range.
+pub fn align_flush_range(range: Range<u64>, mapped: Range<u64>, atom: u64) -> (u64, u64) {
+    let atom = atom.max(1);
+    let start = range.start / atom * atom;
+    let end = (range.end + atom - 1) / atom * atom;
+    let start = start.max(mapped.start).min(mapped.end);
+    let end = end.min(mapped.end).max(start);
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use gl;
+    use hal::buffer::Access;
+    use hal::format::Format;
+    use native::VertexAttribFunction;
+    use super::{
+        align_flush_range, attribute_normalization, border_color_to_gl, buffer_access_to_barrier_bits,
+        buffer_view_format_to_gl, format_to_pixel_transfer, framebuffer_attachment_format,
+        image_access_to_barrier_bits, map_depth_range, sampler_comparison_to_gl,
+        transform_feedback_primitive, uniform_type_size,
+    };
+
+    // Window depth of a fragment at NDC depth `z` (`0..1` convention),
+    // following the GL viewport transformation and depth clamping.
+    fn window_depth(range: [f64; 2], z: f64, clamp: bool) -> Option<f64> {
+        let (near, far) = (range[0], range[1]);
+        if !clamp && (z < 0.0 || z > 1.0) {
+            return None; // clipped
+        }
+        let depth = near + (far - near) * z;
+        Some(depth.max(near.min(far)).min(near.max(far)))
+    }
+
+    #[test]
+    fn test_depth_range() {
+        assert_eq!(map_depth_range(&(0.0 .. 1.0)), [0.0, 1.0]);
+        assert_eq!(map_depth_range(&(1.0 .. 0.0)), [1.0, 0.0]);
+        assert_eq!(map_depth_range(&(0.25 .. 0.75)), [0.25, 0.75]);
+        assert_eq!(map_depth_range(&(-1.0 .. 2.0)), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_reversed_z_depth_clamp() {
+        let range = map_depth_range(&(1.0 .. 0.0));
+        // Near plane maps to 1, far plane to 0.
+        assert_eq!(window_depth(range, 0.0, false), Some(1.0));
+        assert_eq!(window_depth(range, 1.0, false), Some(0.0));
+        // Geometry in front of the near plane is clamped onto it instead of being clipped.
+        assert_eq!(window_depth(range, -0.5, false), None);
+        assert_eq!(window_depth(range, -0.5, true), Some(1.0));
+        // Geometry behind the far plane is clamped onto the far plane.
+        assert_eq!(window_depth(range, 1.5, true), Some(0.0));
+
+        let range = map_depth_range(&(0.75 .. 0.25));
+        assert_eq!(window_depth(range, -1.0, true), Some(0.75));
+        assert_eq!(window_depth(range, 2.0, true), Some(0.25));
+        assert_eq!(window_depth(range, 0.5, true), Some(0.5));
+    }
+
+    #[test]
+    fn test_transform_feedback_primitive() {
+        assert_eq!(transform_feedback_primitive(gl::TRIANGLE_STRIP), Some(gl::TRIANGLES));
+        assert_eq!(transform_feedback_primitive(gl::LINE_STRIP_ADJACENCY), Some(gl::LINES));
+        assert_eq!(transform_feedback_primitive(gl::PATCHES), None);
+    }
+
+    #[test]
+    fn test_transform_feedback_read_back_bits() {
+        let bits = buffer_access_to_barrier_bits(Access::VERTEX_BUFFER_READ | Access::INDEX_BUFFER_READ);
+        assert_eq!(bits, gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT | gl::ELEMENT_ARRAY_BARRIER_BIT);
+        assert_eq!(buffer_access_to_barrier_bits(Access::CONSTANT_BUFFER_READ), gl::UNIFORM_BARRIER_BIT);
+        assert_eq!(buffer_access_to_barrier_bits(Access::empty()), 0);
+        assert_ne!(buffer_access_to_barrier_bits(Access::HOST_READ) & gl::BUFFER_UPDATE_BARRIER_BIT, 0);
+    }
+
+    #[test]
+    fn test_sampler_comparison() {
+        use hal::pso::Comparison;
+        assert_eq!(sampler_comparison_to_gl(Some(Comparison::Less)), (gl::COMPARE_REF_TO_TEXTURE, Some(gl::LESS)));
+        assert_eq!(
+            sampler_comparison_to_gl(Some(Comparison::GreaterEqual)),
+            (gl::COMPARE_REF_TO_TEXTURE, Some(gl::GEQUAL)),
+        );
+        assert_eq!(sampler_comparison_to_gl(None), (gl::NONE, None));
+    }
+
+    #[test]
+    fn test_border_color() {
+        use hal::image::PackedColor;
+        let red = PackedColor::from([1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(border_color_to_gl(red), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(border_color_to_gl(PackedColor(0x80FF_0000)), [0.0, 0.0, 1.0, 128.0 / 255.0]);
+    }
+
+    #[test]
+    fn test_image_store_then_sample_bits() {
+        use hal::image::Access;
+        assert_eq!(
+            image_access_to_barrier_bits(Access::SHADER_READ),
+            gl::TEXTURE_FETCH_BARRIER_BIT | gl::SHADER_IMAGE_ACCESS_BARRIER_BIT
+        );
+        assert_eq!(image_access_to_barrier_bits(Access::empty()), 0);
+    }
+
+    #[test]
+    fn test_attribute_normalization() {
+        let (function, normalized) = attribute_normalization(Format::Rgba8Unorm, VertexAttribFunction::Float, None);
+        assert!(normalized);
+        let (_, normalized) = attribute_normalization(Format::Rgba8Unorm, VertexAttribFunction::Float, Some(false));
+        assert!(!normalized);
+        let (function2, normalized) = attribute_normalization(Format::Rgba8Uint, VertexAttribFunction::Integer, Some(true));
+        assert!(normalized);
+        match (function, function2) {
+            (VertexAttribFunction::Float, VertexAttribFunction::Float) => (),
+            other => panic!("Unexpected functions {:?}", other),
+        }
+        match attribute_normalization(Format::Rgba8Uint, VertexAttribFunction::Integer, None) {
+            (VertexAttribFunction::Integer, false) => (),
+            other => panic!("Unexpected function {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_framebuffer_attachment_format() {
+        assert_eq!(framebuffer_attachment_format([8, 8, 8, 8], gl::UNSIGNED_NORMALIZED, true), Some(Format::Rgba8Srgb));
+        assert_eq!(framebuffer_attachment_format([10, 10, 10, 2], gl::UNSIGNED_NORMALIZED, false), Some(Format::A2b10g10r10Unorm));
+        assert_eq!(framebuffer_attachment_format([16, 16, 16, 16], gl::FLOAT, false), Some(Format::Rgba16Float));
+        assert_eq!(framebuffer_attachment_format([4, 4, 4, 4], gl::UNSIGNED_NORMALIZED, false), None);
+    }
+
+    #[test]
+    fn test_uniform_type_size() {
+        assert_eq!(uniform_type_size(gl::FLOAT), Some(4));
+        assert_eq!(uniform_type_size(gl::UNSIGNED_INT_VEC3), Some(12));
+        assert_eq!(uniform_type_size(gl::FLOAT_MAT4), Some(64));
+        assert_eq!(uniform_type_size(gl::SAMPLER_2D), None);
+    }
+
+    #[test]
+    fn test_align_flush_range() {
+        assert_eq!(align_flush_range(10 .. 20, 0 .. 256, 64), (0, 64));
+        assert_eq!(align_flush_range(70 .. 130, 0 .. 256, 64), (64, 192));
+        // Clamped to the mapped range.
+        assert_eq!(align_flush_range(200 .. 250, 0 .. 240, 64), (192, 240));
+        assert_eq!(align_flush_range(40 .. 50, 32 .. 256, 64), (32, 64));
+        assert_eq!(align_flush_range(10 .. 20, 0 .. 256, 1), (10, 20));
+    }
+
+    #[test]
+    fn test_buffer_view_format() {
+        assert_eq!(buffer_view_format_to_gl(Format::Rgba8Unorm), Some(gl::RGBA8));
+        assert_eq!(buffer_view_format_to_gl(Format::Rgb32Float), Some(gl::RGB32F));
+        // Three-component formats are only supported with 32-bit components.
+        assert_eq!(buffer_view_format_to_gl(Format::Rgb8Unorm), None);
+        assert_eq!(buffer_view_format_to_gl(Format::Rgb16Float), None);
+        assert_eq!(buffer_view_format_to_gl(Format::Rgba8Srgb), None);
+    }
+
+    #[test]
+    fn test_format_to_pixel_transfer() {
+        assert_eq!(format_to_pixel_transfer(Format::Rgba8Unorm), Some((gl::RGBA, gl::UNSIGNED_BYTE)));
+        assert_eq!(format_to_pixel_transfer(Format::Bgra8Srgb), Some((gl::BGRA, gl::UNSIGNED_BYTE)));
+        assert_eq!(format_to_pixel_transfer(Format::Rg16Float), Some((gl::RG, gl::HALF_FLOAT)));
+        assert_eq!(format_to_pixel_transfer(Format::R32Int), Some((gl::RED_INTEGER, gl::INT)));
+        assert_eq!(format_to_pixel_transfer(Format::Rgba16Inorm), Some((gl::RGBA, gl::SHORT)));
+        assert_eq!(format_to_pixel_transfer(Format::D32Float), Some((gl::DEPTH_COMPONENT, gl::FLOAT)));
+        assert_eq!(format_to_pixel_transfer(Format::Bc7Unorm), None);
+    }
+}
@@ -1,12 +1,13 @@
 use std::borrow::Borrow;
 use std::cell::Cell;
+use std::collections::HashSet;
 use std::iter::repeat;
 use std::ops::Range;
-use std::{ptr, mem, slice};
+use std::{ffi, ptr, mem, slice, str};
 use std::sync::{Arc, Mutex, RwLock};
 
 use gl;
-use gl::types::{GLint, GLenum, GLfloat};
+use gl::types::{GLint, GLenum, GLfloat, GLuint};
 
 use hal::{self as c, device as d, error, image as i, memory, pass, pso, buffer, mapping, query, window};
 use hal::backend::FastHashMap;
@@ -17,10 +18,12 @@ use hal::range::RangeArg;
 
 use spirv_cross::{glsl, spirv, ErrorCode as SpirvErrorCode};
 
-use {Backend as B, Share, Surface, Swapchain, Starc};
-use {conv, native as n, state};
+use {Backend as B, PipelineSettings, Share, Surface, Swapchain, Starc, NON_COHERENT_MEMORY_TYPE};
+use {conv, ext, native as n, state};
 use info::LegacyFeatures;
+use command::Limits;
 use pool::{BufferMemory, OwnedBuffer, RawCommandPool};
+use queue::CommandQueue;
 
 /// Emit error during shader module creation. Used if we don't expect an error
 /// but might panic due to an exception in SPIRV-Cross.
@@ -95,18 +98,114 @@ pub struct UnboundBuffer {
 #[derive(Debug)]
 pub struct UnboundImage {
     image: n::ImageKind,
+    format: Format,
     channel: ChannelType,
+    kind: i::Kind,
+    num_levels: i::Level,
     requirements: memory::Requirements,
 }
 
+/// Error reading back image data.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadbackError {
+    /// The image format is not compressed or not supported for readback.
+    Format(Format),
+    /// The region is not aligned to the compressed blocks or exceeds the level.
+    Region,
+    /// The destination doesn't match the size of the compressed region.
+    Size(usize),
+    /// The image is not backed by a texture or the context can't read it back.
+    Unsupported,
+}
+
+/// Error creating a sampler with unnormalized coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnnormalizedSamplerError {
+    /// The sampler would access mip levels other than the base level.
+    Mipmapping,
+    /// The wrap mode repeats the texture, only clamping is allowed.
+    Wrap(i::WrapMode),
+    /// Rectangle textures or sampler objects aren't supported by the context.
+    Unsupported,
+}
+
+/// Ring of fences limiting the number of frames in flight,
+/// created by `Device::create_frame_fences`.
+///
+/// A frame starts with `begin_frame`, which waits for the frame submitted
+/// `frames_in_flight` frames earlier and recycles its fence, and ends with
+/// `end_frame`, which signals the fence after the submissions of the frame.
+/// The sync objects are deleted when the ring is dropped.
+#[derive(Debug)]
+pub struct FrameFences {
+    share: Starc<Share>,
+    fences: Vec<n::Fence>,
+    current: usize,
+}
+
+impl FrameFences {
+    /// Maximum number of frames in flight.
+    pub fn frames_in_flight(&self) -> usize {
+        self.fences.len()
+    }
+
+    /// Index of the current frame in the ring, e.g. for selecting per-frame command buffers.
+    pub fn frame_index(&self) -> usize {
+        self.current
+    }
+
+    /// Wait until the previous frame using the current slot completed and
+    /// return its fence in the unsignaled state.
+    ///
+    /// Returns `None` if the frame didn't complete within `timeout_ns`.
+    pub fn begin_frame(&mut self, timeout_ns: u64) -> Option<&n::Fence> {
+        let fence = &self.fences[self.current];
+        if self.share.private_caps.sync && !fence.sync.get().is_null() {
+            if !fence.wait(&self.share.context, timeout_ns) {
+                if let Err(err) = self.share.check() {
+                    error!("Error when waiting on frame fence: {:?}", err);
+                }
+                return None;
+            }
+        }
+        reset_fence(&self.share, fence);
+        Some(fence)
+    }
+
+    /// Signal the fence of the current frame once the commands submitted
+    /// to the queue so far completed and advance to the next frame.
+    pub fn end_frame(&mut self, queue: &mut CommandQueue) {
+        queue.signal_fence(&self.fences[self.current]);
+        self.current = (self.current + 1) % self.fences.len();
+    }
+}
+
+impl Drop for FrameFences {
+    fn drop(&mut self) {
+        for fence in &self.fences {
+            reset_fence(&self.share, fence);
+        }
+    }
+}
+
 /// GL device.
 #[derive(Debug)]
 pub struct Device {
-    share: Starc<Share>,
+    pub(crate) share: Starc<Share>,
+    // Separable programs shared between program pipelines, keyed by the GLSL of the stage.
+    separable_programs: Mutex<FastHashMap<(pso::Stage, String), n::Program>>,
+    // Storage block members expected by the application, checked when linking programs.
+    storage_layout: Mutex<Vec<(String, u32, u32)>>,
+    // Programs whose storage block layout didn't match the expected one.
+    invalid_storage_layouts: Mutex<HashSet<n::Program>>,
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
+        let gl = &self.share.context;
+        for (_, program) in self.separable_programs.lock().unwrap().drain() {
+            unsafe { gl.DeleteProgram(program) };
+        }
         self.share.open.set(false);
     }
 }
@@ -116,6 +215,9 @@ impl Device {
     pub(crate) fn new(share: Starc<Share>) -> Self {
         Device {
             share: share,
+            separable_programs: Mutex::new(FastHashMap::default()),
+            storage_layout: Mutex::new(Vec::new()),
+            invalid_storage_layouts: Mutex::new(HashSet::new()),
         }
     }
 
@@ -162,33 +264,674 @@ impl Device {
         }
     }
 
-    fn bind_target_compat(gl: &gl::Gl, point: GLenum, attachment: GLenum, view: &n::ImageView) {
+    /// Create a shader module from GLSL source with additional preprocessor defines.
+    ///
+    /// The `(name, value)` pairs are inserted as `#define` directives right after
+    /// the `#version` directive, allowing to compile multiple variants of a shader.
+    pub fn create_shader_module_from_source_with_defines(
+        &self,
+        data: &[u8],
+        stage: pso::Stage,
+        defines: &[(&str, &str)],
+    ) -> Result<n::ShaderModule, d::ShaderError> {
+        let source = str::from_utf8(data)
+            .map_err(|_| d::ShaderError::CompilationFailed("Shader source is not valid UTF-8".into()))?;
+        let source = inject_glsl_defines(source, defines);
+        self.create_shader_module_from_source(source.as_bytes(), stage)
+    }
+
+    /// Create a ring of `frames_in_flight` fences for pacing frames,
+    /// see `FrameFences`.
+    pub fn create_frame_fences(&self, frames_in_flight: usize) -> FrameFences {
+        assert!(frames_in_flight > 0, "At least one frame has to be in flight");
+        FrameFences {
+            share: self.share.clone(),
+            // Signaled, so the first frames don't wait.
+            fences: (0 .. frames_in_flight).map(|_| n::Fence::new(ptr::null(), true)).collect(),
+            current: 0,
+        }
+    }
+
+    /// Map memory like `map_memory`, requesting only the given access.
+    ///
+    /// `map_memory` maps with all accesses supported by the memory type, while
+    /// a read-only or write-only mapping avoids access flags, which the buffer
+    /// storage may not allow. Fails with `InvalidAccess` if the memory type
+    /// doesn't support the requested access.
+    pub fn map_memory_with_access<R: RangeArg<u64>>(
+        &self, memory: &n::Memory, range: R, access: n::MapAccess,
+    ) -> Result<*mut u8, mapping::Error> {
+        let gl = &self.share.context;
+        let buffer = match memory.first_bound_buffer.get() {
+            0 => panic!("No buffer has been bound yet, can't map memory!"),
+            other => other,
+        };
+
+        let supported = match access {
+            n::MapAccess::Read => memory.can_download(),
+            n::MapAccess::Write => memory.can_upload(),
+            n::MapAccess::ReadWrite => memory.can_upload() || memory.can_download(),
+        };
+        if !supported {
+            return Err(mapping::Error::InvalidAccess);
+        }
+
+        assert!(self.share.private_caps.buffer_role_change);
+        let target = gl::PIXEL_PACK_BUFFER;
+        let access = memory.access_flags(access);
+
+        let offset = *range.start().unwrap_or(&0);
+        let size = *range.end().unwrap_or(&memory.size) - offset;
+
+        if access & gl::MAP_READ_BIT != 0 && self.share.private_caps.memory_barrier {
+            // Make writes of previously submitted shaders visible to the mapping,
+            // the map itself waits for the commands to complete.
+            unsafe { gl.MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT) };
+        }
+
+        if memory.persistent {
+            // Map the whole memory once, later maps return a pointer into the mapping.
+            let mut ptr = memory.persistent_ptr.get();
+            if ptr.is_null() {
+                ptr = state::with_pixel_buffer(gl, target, buffer, || unsafe {
+                    gl.MapBufferRange(target, 0, memory.size as _, memory.map_flags()) as *mut u8
+                });
+                if let Err(err) = self.share.check() {
+                    panic!("Error mapping memory: {:?} for memory {:?}", err, memory);
+                }
+                memory.persistent_ptr.set(ptr);
+                memory.mapped.set(Some((0, memory.size)));
+            }
+            return Ok(unsafe { ptr.offset(offset as isize) });
+        }
+
+        let ptr = state::with_pixel_buffer(gl, target, buffer, || unsafe {
+            gl.MapBufferRange(target, offset as _, size as _, access) as *mut _
+        });
+
+        if let Err(err) = self.share.check() {
+            panic!("Error mapping memory: {:?} for memory {:?}", err, memory);
+        }
+
+        memory.mapped.set(Some((offset, offset + size)));
+        Ok(ptr)
+    }
+
+    /// Create an image like `create_image`, with its storage cleared to zero
+    /// instead of undefined contents, e.g. for the first read of a ping-pong target.
+    ///
+    /// Uses `glClearTexImage` if available, otherwise clears via a framebuffer.
+    pub fn create_zeroed_image(
+        &self,
+        kind: i::Kind,
+        num_levels: i::Level,
+        format: Format,
+        tiling: i::Tiling,
+        usage: i::Usage,
+        flags: i::StorageFlags,
+    ) -> Result<UnboundImage, i::CreationError> {
+        let unbound = d::Device::<B>::create_image(self, kind, num_levels, format, tiling, usage, flags)?;
+        match unbound.image {
+            n::ImageKind::Texture(texture, _) if self.share.private_caps.clear_texture => {
+                let (gl_format, gl_type) = match (format.is_depth(), format.is_stencil()) {
+                    (true, true) if format == Format::D32FloatS8Uint =>
+                        (gl::DEPTH_STENCIL, gl::FLOAT_32_UNSIGNED_INT_24_8_REV),
+                    (true, true) => (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+                    (true, false) => (gl::DEPTH_COMPONENT, gl::FLOAT),
+                    (false, true) => (gl::STENCIL_INDEX, gl::UNSIGNED_BYTE),
+                    (false, false) => match unbound.channel {
+                        ChannelType::Uint | ChannelType::Int => (gl::RGBA_INTEGER, gl::UNSIGNED_BYTE),
+                        _ => (gl::RGBA, gl::UNSIGNED_BYTE),
+                    },
+                };
+                let gl = &self.share.context;
+                for level in 0 .. num_levels {
+                    // Without data the texture is filled with zeros.
+                    unsafe { gl.ClearTexImage(texture, level as _, gl_format, gl_type, ptr::null()) };
+                }
+            }
+            image => self.clear_image_storage(image, kind, format, unbound.channel, num_levels),
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error clearing image: {:?} for kind {:?} of {:?}",
+                err, kind, format);
+        }
+
+        Ok(unbound)
+    }
+
+    // Clear all levels of an image to zero via a temporary framebuffer.
+    fn clear_image_storage(
+        &self, image: n::ImageKind, kind: i::Kind, format: Format, channel: ChannelType, num_levels: i::Level,
+    ) {
+        let gl = &self.share.context;
+        let point = conv::attachment_point(0, Some(format));
+        let fbo = create_fbo_internal(gl);
+        unsafe {
+            // Save the state overridden by the clear.
+            let mut prev_framebuffer = 0;
+            let mut prev_color_mask = [0; 4];
+            let mut prev_depth_mask = 0;
+            let mut prev_stencil_masks = [0; 2];
+            gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut prev_framebuffer);
+            gl.GetBooleanv(gl::COLOR_WRITEMASK, prev_color_mask.as_mut_ptr());
+            gl.GetBooleanv(gl::DEPTH_WRITEMASK, &mut prev_depth_mask);
+            gl.GetIntegerv(gl::STENCIL_WRITEMASK, &mut prev_stencil_masks[0]);
+            gl.GetIntegerv(gl::STENCIL_BACK_WRITEMASK, &mut prev_stencil_masks[1]);
+            let scissor = gl.IsEnabled(gl::SCISSOR_TEST) != 0;
+
+            gl.Disable(gl::SCISSOR_TEST);
+            gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl.DepthMask(gl::TRUE);
+            gl.StencilMask(!0);
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
+
+            let levels = match image {
+                n::ImageKind::Surface(_) => 1,
+                n::ImageKind::Texture(..) => num_levels,
+            };
+            for level in 0 .. levels {
+                // Layers and depth slices are attached and cleared one at a time.
+                let layers = match image {
+                    n::ImageKind::Texture(_, gl::TEXTURE_3D) => kind.level_extent(level).depth.max(1),
+                    n::ImageKind::Texture(_, target) if conv::is_layered_target(target) => kind.num_layers() as u32,
+                    _ => 1,
+                };
+                for layer in 0 .. layers {
+                    match image {
+                        n::ImageKind::Surface(surface) =>
+                            gl.FramebufferRenderbuffer(gl::DRAW_FRAMEBUFFER, point, gl::RENDERBUFFER, surface),
+                        n::ImageKind::Texture(texture, gl::TEXTURE_CUBE_MAP) => gl.FramebufferTexture2D(
+                            gl::DRAW_FRAMEBUFFER, point, gl::TEXTURE_CUBE_MAP_POSITIVE_X + layer,
+                            texture, level as _,
+                        ),
+                        n::ImageKind::Texture(texture, target) if conv::is_layered_target(target) =>
+                            gl.FramebufferTextureLayer(gl::DRAW_FRAMEBUFFER, point, texture, level as _, layer as _),
+                        n::ImageKind::Texture(texture, target) =>
+                            gl.FramebufferTexture2D(gl::DRAW_FRAMEBUFFER, point, target, texture, level as _),
+                    }
+                    match (format.is_depth(), format.is_stencil()) {
+                        (true, true) => gl.ClearBufferfi(gl::DEPTH_STENCIL, 0, 0.0, 0),
+                        (true, false) => gl.ClearBufferfv(gl::DEPTH, 0, &0.0),
+                        (false, true) => gl.ClearBufferiv(gl::STENCIL, 0, &0),
+                        (false, false) => match channel {
+                            ChannelType::Uint => gl.ClearBufferuiv(gl::COLOR, 0, [0; 4].as_ptr()),
+                            ChannelType::Int => gl.ClearBufferiv(gl::COLOR, 0, [0; 4].as_ptr()),
+                            _ => gl.ClearBufferfv(gl::COLOR, 0, [0.0; 4].as_ptr()),
+                        },
+                    }
+                }
+            }
+
+            // Restore the previous state.
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, prev_framebuffer as _);
+            gl.DeleteFramebuffers(1, &fbo);
+            if scissor {
+                gl.Enable(gl::SCISSOR_TEST);
+            }
+            gl.ColorMask(prev_color_mask[0], prev_color_mask[1], prev_color_mask[2], prev_color_mask[3]);
+            gl.DepthMask(prev_depth_mask);
+            gl.StencilMaskSeparate(gl::FRONT, prev_stencil_masks[0] as _);
+            gl.StencilMaskSeparate(gl::BACK, prev_stencil_masks[1] as _);
+        }
+    }
+
+    /// Create a sampled image and upload its initial contents in one call.
+    ///
+    /// `data` contains the texels of all mipmap levels, tightly packed and
+    /// ordered by level, starting with the base level. If `generate_mips` is
+    /// set, only the base level is provided and the other levels are generated.
+    /// Compressed formats are provided as rows of blocks and can't generate mipmaps.
+    pub fn create_image_with_data(
+        &self,
+        kind: i::Kind,
+        num_levels: i::Level,
+        format: Format,
+        usage: i::Usage,
+        data: &[u8],
+        generate_mips: bool,
+    ) -> Result<n::Image, i::CreationError> {
+        if !usage.contains(i::Usage::SAMPLED) {
+            return Err(i::CreationError::Usage(usage));
+        }
+        match kind {
+            i::Kind::D2(_, _, 1, 1) => (),
+            _ => return Err(i::CreationError::Kind),
+        }
+
+        let desc = format.surface_desc();
+        let transfer = if desc.is_compressed() {
+            if generate_mips {
+                return Err(i::CreationError::Format(format));
+            }
+            None
+        } else {
+            match conv::format_to_pixel_transfer(format) {
+                Some(transfer) => Some(transfer),
+                None => return Err(i::CreationError::Format(format)),
+            }
+        };
+        let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+        let level_size = |level| {
+            let extent = kind.level_extent(level);
+            let blocks_x = (extent.width + block_width - 1) / block_width;
+            let blocks_y = (extent.height + block_height - 1) / block_height;
+            blocks_x as usize * blocks_y as usize * (desc.bits / 8) as usize
+        };
+        let num_provided = if generate_mips { 1 } else { num_levels };
+        let size = (0 .. num_provided).map(&level_size).sum::<usize>();
+        if data.len() != size {
+            return Err(i::CreationError::Data(data.len()));
+        }
+
+        let unbound = d::Device::<B>::create_image(
+            self, kind, num_levels, format, i::Tiling::Optimal, usage, i::StorageFlags::empty(),
+        )?;
+        let texture = match unbound.image {
+            n::ImageKind::Texture(texture, _) => texture,
+            n::ImageKind::Surface(_) => unreachable!(),
+        };
+
+        let gl = &self.share.context;
+        unsafe { gl.BindTexture(gl::TEXTURE_2D, texture) };
+        // Upload from client memory, an unpack buffer would turn the pointer into an offset.
+        state::with_pixel_buffer(gl, gl::PIXEL_UNPACK_BUFFER, 0, || {
+            let mut offset = 0;
+            for level in 0 .. num_provided {
+                let extent = kind.level_extent(level);
+                let size = level_size(level);
+                unsafe {
+                    match transfer {
+                        Some((transfer_format, ty)) => gl.TexSubImage2D(
+                            gl::TEXTURE_2D, level as _,
+                            0, 0,
+                            extent.width as _, extent.height as _,
+                            transfer_format, ty,
+                            data[offset ..].as_ptr() as *const _,
+                        ),
+                        None => gl.CompressedTexSubImage2D(
+                            gl::TEXTURE_2D, level as _,
+                            0, 0,
+                            extent.width as _, extent.height as _,
+                            conv::image_format_to_gl(format).unwrap(), size as _,
+                            data[offset ..].as_ptr() as *const _,
+                        ),
+                    }
+                }
+                offset += size;
+            }
+        });
+        if generate_mips && num_levels > 1 {
+            self.apply_mipmap_hint();
+            unsafe { gl.GenerateMipmap(gl::TEXTURE_2D) };
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error uploading image data: {:?} for kind {:?} of {:?}",
+                err, kind, format);
+        }
+
+        Ok(n::Image {
+            kind: unbound.image,
+            format: unbound.format,
+            channel: unbound.channel,
+            dimensions: unbound.kind,
+            num_levels: unbound.num_levels,
+        })
+    }
+
+    /// Read back a mip level and array layer of an uncompressed texture into `data`.
+    ///
+    /// The texels are tightly packed in the client format matching the image format,
+    /// `data` has to match the size of the level exactly.
+    pub fn read_image(
+        &self,
+        image: &n::Image,
+        level: i::Level,
+        layer: i::Layer,
+        data: &mut [u8],
+    ) -> Result<(), ReadbackError> {
+        let gl = &self.share.context;
+        let (texture, target) = match image.kind {
+            n::ImageKind::Texture(texture, target) => (texture, target),
+            n::ImageKind::Surface(_) => return Err(ReadbackError::Unsupported),
+        };
+        if !self.share.private_caps.get_tex_image {
+            return Err(ReadbackError::Unsupported);
+        }
+        let (format, ty) = match conv::format_to_pixel_transfer(image.format) {
+            Some(transfer) => transfer,
+            None => return Err(ReadbackError::Format(image.format)),
+        };
+
+        let extent = self.level_extent(image, level);
+        let mut layers = 1;
+        if conv::is_layered_target(target) && target != gl::TEXTURE_CUBE_MAP {
+            unsafe { gl.GetTexLevelParameteriv(target, level as _, gl::TEXTURE_DEPTH, &mut layers) };
+        } else if target == gl::TEXTURE_CUBE_MAP {
+            layers = 6;
+        }
+        if extent.width == 0 || layer as i32 >= layers {
+            return Err(ReadbackError::Region);
+        }
+        let texel_size = image.format.base_format().0.desc().bits as usize / 8;
+        let size = extent.width as usize * extent.height as usize * texel_size;
+        if data.len() != size {
+            return Err(ReadbackError::Size(data.len()));
+        }
+
+        // Read into client memory, a pack buffer would turn the pointer into an offset.
+        state::with_pixel_buffer(gl, gl::PIXEL_PACK_BUFFER, 0, || unsafe {
+            if self.share.private_caps.get_texture_sub_image {
+                gl.GetTextureSubImage(
+                    texture, level as _,
+                    0, 0, layer as _,
+                    extent.width as _, extent.height as _, 1,
+                    format, ty, size as _, data.as_mut_ptr() as *mut _,
+                );
+            } else if target == gl::TEXTURE_CUBE_MAP {
+                gl.GetTexImage(gl::TEXTURE_CUBE_MAP_POSITIVE_X + layer as GLenum, level as _, format, ty, data.as_mut_ptr() as *mut _);
+            } else if layers > 1 {
+                // Read back all layers and extract the selected one.
+                let mut level_data = vec![0u8; size * layers as usize];
+                gl.GetTexImage(target, level as _, format, ty, level_data.as_mut_ptr() as *mut _);
+                let offset = size * layer as usize;
+                data.copy_from_slice(&level_data[offset .. offset + size]);
+            } else {
+                gl.GetTexImage(target, level as _, format, ty, data.as_mut_ptr() as *mut _);
+            }
+        });
+
+        if let Err(err) = self.share.check() {
+            panic!("Error reading back image: {:?} for level {} and layer {} of {:?}",
+                err, level, layer, image.format);
+        }
+        Ok(())
+    }
+
+    /// Read back a region of a compressed texture into `data`.
+    ///
+    /// The region has to be aligned to the compressed blocks, except where it
+    /// reaches the edge of the level, and `data` has to match its size exactly.
+    pub fn read_compressed_image(
+        &self,
+        image: &n::Image,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+        data: &mut [u8],
+    ) -> Result<(), ReadbackError> {
+        let size = self.compressed_region_size(image, level, offset, extent)?;
+        if data.len() != size {
+            return Err(ReadbackError::Size(data.len()));
+        }
+        if self.share.private_caps.get_texture_sub_image || self.is_full_level(image, level, offset, extent) {
+            self.get_compressed_region(image, level, offset, extent, 0, data.as_mut_ptr(), size)
+        } else {
+            // Read back the whole level and extract the rows of blocks of the region.
+            let desc = image.format.base_format().0.desc();
+            let level_extent = self.level_extent(image, level);
+            let level_size = self.compressed_region_size(image, level, i::Offset { x: 0, y: 0, z: 0 }, level_extent)?;
+            let mut level_data = vec![0u8; level_size];
+            self.get_compressed_region(
+                image, level, i::Offset { x: 0, y: 0, z: 0 }, level_extent,
+                0, level_data.as_mut_ptr(), level_size,
+            )?;
+
+            let (block_width, block_height) = (desc.dim.0 as usize, desc.dim.1 as usize);
+            let block_size = desc.bits as usize / 8;
+            let level_pitch = (level_extent.width as usize + block_width - 1) / block_width * block_size;
+            let pitch = (extent.width as usize + block_width - 1) / block_width * block_size;
+            let rows = (extent.height as usize + block_height - 1) / block_height;
+            let x = offset.x as usize / block_width * block_size;
+            let y = offset.y as usize / block_height;
+            for (row, dst) in data.chunks_mut(pitch).take(rows).enumerate() {
+                let src = (y + row) * level_pitch + x;
+                dst.copy_from_slice(&level_data[src .. src + pitch]);
+            }
+            Ok(())
+        }
+    }
+
+    /// Read back a region of a compressed texture into a buffer at the given offset,
+    /// via `GL_PIXEL_PACK_BUFFER`.
+    ///
+    /// Without `GL_ARB_get_texture_sub_image` only whole levels can be read back.
+    pub fn read_compressed_image_to_buffer(
+        &self,
+        image: &n::Image,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+        buffer: &n::Buffer,
+        buffer_offset: u64,
+    ) -> Result<(), ReadbackError> {
+        let size = self.compressed_region_size(image, level, offset, extent)?;
+        if buffer_offset + size as u64 > buffer.size {
+            return Err(ReadbackError::Size((buffer.size - buffer_offset.min(buffer.size)) as usize));
+        }
+        if !self.share.private_caps.get_texture_sub_image && !self.is_full_level(image, level, offset, extent) {
+            return Err(ReadbackError::Region);
+        }
+        self.get_compressed_region(image, level, offset, extent, buffer.raw, buffer_offset as *mut u8, size)
+    }
+
+    /// Get the GPU address of a buffer for use as pointer in shaders,
+    /// making the buffer resident for reading until it's destroyed.
+    ///
+    /// Returns `None` if `GL_NV_shader_buffer_load` is not supported.
+    pub fn buffer_gpu_address(&self, buffer: &n::Buffer) -> Option<u64> {
+        if !self.share.private_caps.shader_buffer_load {
+            return None;
+        }
+        let mut gpu_address = buffer.gpu_address.lock().unwrap();
+        if *gpu_address != 0 {
+            return Some(*gpu_address);
+        }
+
+        let gl = &self.share.context;
+        let mut address = 0;
+        unsafe {
+            gl.BindBuffer(buffer.target, buffer.raw);
+            self.share.ext.GetBufferParameterui64vNV(buffer.target, ext::BUFFER_GPU_ADDRESS_NV, &mut address);
+            self.share.ext.MakeBufferResidentNV(buffer.target, gl::READ_ONLY);
+            gl.BindBuffer(buffer.target, 0);
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error making buffer resident: {:?} for buffer {:?}", err, buffer);
+        }
+
+        *gpu_address = address;
+        Some(address)
+    }
+
+    fn level_extent(&self, image: &n::Image, level: i::Level) -> i::Extent {
+        let gl = &self.share.context;
+        let (texture, target) = match image.kind {
+            n::ImageKind::Texture(texture, target) => (texture, target),
+            n::ImageKind::Surface(_) => return i::Extent { width: 0, height: 0, depth: 0 },
+        };
+        let (mut width, mut height) = (0, 0);
+        unsafe {
+            gl.BindTexture(target, texture);
+            gl.GetTexLevelParameteriv(target, level as _, gl::TEXTURE_WIDTH, &mut width);
+            gl.GetTexLevelParameteriv(target, level as _, gl::TEXTURE_HEIGHT, &mut height);
+        }
+        i::Extent { width: width as _, height: height as _, depth: 1 }
+    }
+
+    fn is_full_level(&self, image: &n::Image, level: i::Level, offset: i::Offset, extent: i::Extent) -> bool {
+        let level_extent = self.level_extent(image, level);
+        offset.x == 0 && offset.y == 0 &&
+            extent.width == level_extent.width && extent.height == level_extent.height
+    }
+
+    // Size in bytes of a region of compressed blocks, validating the block alignment.
+    fn compressed_region_size(
+        &self,
+        image: &n::Image,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+    ) -> Result<usize, ReadbackError> {
+        let desc = image.format.base_format().0.desc();
+        if desc.dim == (1, 1) || conv::image_format_to_gl(image.format).is_none() {
+            return Err(ReadbackError::Format(image.format));
+        }
+
+        let level_extent = self.level_extent(image, level);
+        let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+        let aligned = |offset: i32, size: u32, level_size: u32, block: u32| {
+            offset >= 0 && offset as u32 % block == 0 &&
+                offset as u32 + size <= level_size &&
+                (size % block == 0 || offset as u32 + size == level_size)
+        };
+        if offset.z != 0 || extent.depth != 1 ||
+            !aligned(offset.x, extent.width, level_extent.width, block_width) ||
+            !aligned(offset.y, extent.height, level_extent.height, block_height)
+        {
+            return Err(ReadbackError::Region);
+        }
+
+        let blocks_x = (extent.width + block_width - 1) / block_width;
+        let blocks_y = (extent.height + block_height - 1) / block_height;
+        Ok(blocks_x as usize * blocks_y as usize * desc.bits as usize / 8)
+    }
+
+    // Read a validated region into client memory, or into `pack_buffer`
+    // at the offset given by `ptr` if it's non-zero.
+    fn get_compressed_region(
+        &self,
+        image: &n::Image,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+        pack_buffer: n::RawBuffer,
+        ptr: *mut u8,
+        size: usize,
+    ) -> Result<(), ReadbackError> {
+        let gl = &self.share.context;
+        let (texture, target) = match image.kind {
+            n::ImageKind::Texture(texture, target) => (texture, target),
+            n::ImageKind::Surface(_) => return Err(ReadbackError::Unsupported),
+        };
+        if !self.share.private_caps.compressed_readback {
+            return Err(ReadbackError::Unsupported);
+        }
+
+        state::with_pixel_buffer(gl, gl::PIXEL_PACK_BUFFER, pack_buffer, || unsafe {
+            if self.share.private_caps.get_texture_sub_image {
+                gl.GetCompressedTextureSubImage(
+                    texture, level as _,
+                    offset.x, offset.y, 0,
+                    extent.width as _, extent.height as _, 1,
+                    size as _, ptr as *mut _,
+                );
+            } else {
+                gl.BindTexture(target, texture);
+                gl.GetCompressedTexImage(target, level as _, ptr as *mut _);
+            }
+        });
+
+        if let Err(err) = self.share.check() {
+            panic!("Error reading back compressed image: {:?} for level {} of {:?}",
+                err, level, image.format);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn bind_target_compat(gl: &gl::Gl, point: GLenum, attachment: GLenum, view: &n::ImageView) {
         match *view {
             n::ImageView::Surface(surface) => unsafe {
                 gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
             },
-            n::ImageView::Texture(texture, level) => unsafe {
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.FramebufferTexture2D(point, attachment, gl::TEXTURE_2D, texture, level as _);
+            n::ImageView::Texture(texture, target, level) if conv::is_layered_target(target) => unsafe {
+                warn!("Layered attachments require glFramebufferTexture, only the first layer is attached");
+                gl.BindTexture(target, texture);
+                if target == gl::TEXTURE_CUBE_MAP {
+                    gl.FramebufferTexture2D(point, attachment, gl::TEXTURE_CUBE_MAP_POSITIVE_X, texture, level as _);
+                } else {
+                    gl.FramebufferTexture3D(point, attachment, target, texture, level as _, 0);
+                }
+            },
+            n::ImageView::Texture(texture, target, level) => unsafe {
+                gl.BindTexture(target, texture);
+                gl.FramebufferTexture2D(point, attachment, target, texture, level as _);
+            },
+            n::ImageView::TextureLayer(texture, target, level, layer) => unsafe {
+                gl.BindTexture(target, texture);
+                gl.FramebufferTexture3D(point, attachment, target, texture, level as _, layer as _);
             },
-            n::ImageView::TextureLayer(texture, level, layer) => unsafe {
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.FramebufferTexture3D(point, attachment, gl::TEXTURE_2D, texture, level as _, layer as _);
+            n::ImageView::TextureLayerRange(texture, target, level, ref layers) => unsafe {
+                warn!("Layered attachments require glFramebufferTexture, only the first layer is attached");
+                gl.BindTexture(target, texture);
+                gl.FramebufferTexture3D(point, attachment, target, texture, level as _, layers.start as _);
+            },
+            n::ImageView::TextureView(texture, target) => unsafe {
+                gl.BindTexture(target, texture);
+                gl.FramebufferTexture2D(point, attachment, target, texture, 0);
+            },
+            n::ImageView::Cube(texture, level) => unsafe {
+                warn!("Layered attachments require glFramebufferTexture, only the first face is attached");
+                gl.BindTexture(gl::TEXTURE_CUBE_MAP, texture);
+                gl.FramebufferTexture2D(point, attachment, gl::TEXTURE_CUBE_MAP_POSITIVE_X, texture, level as _);
+            },
+            n::ImageView::CubeFace(texture, level, face) => unsafe {
+                gl.BindTexture(gl::TEXTURE_CUBE_MAP, texture);
+                gl.FramebufferTexture2D(
+                    point, attachment, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum, texture, level as _,
+                );
+            },
+            n::ImageView::Texture3D(texture, level) => unsafe {
+                warn!("Layered attachments require glFramebufferTexture, only the first slice is attached");
+                gl.BindTexture(gl::TEXTURE_3D, texture);
+                gl.FramebufferTexture3D(point, attachment, gl::TEXTURE_3D, texture, level as _, 0);
+            },
+            n::ImageView::Texture3DSlice(texture, level, slice) => unsafe {
+                gl.BindTexture(gl::TEXTURE_3D, texture);
+                gl.FramebufferTexture3D(point, attachment, gl::TEXTURE_3D, texture, level as _, slice as _);
             },
         }
     }
 
-    fn bind_target(gl: &gl::Gl, point: GLenum, attachment: GLenum, view: &n::ImageView) {
+    pub(crate) fn bind_target(gl: &gl::Gl, point: GLenum, attachment: GLenum, view: &n::ImageView) {
         match *view {
             n::ImageView::Surface(surface) => unsafe {
                 gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
             },
-            n::ImageView::Texture(texture, level) => unsafe {
+            n::ImageView::Texture(texture, _, level) => unsafe {
                 gl.FramebufferTexture(point, attachment, texture, level as _);
             },
-            n::ImageView::TextureLayer(texture, level, layer) => unsafe {
+            n::ImageView::TextureLayer(texture, _, level, layer) => unsafe {
                 gl.FramebufferTextureLayer(point, attachment, texture, level as _, layer as _);
             },
+            n::ImageView::TextureLayerRange(texture, _, level, ref layers) => unsafe {
+                if layers.start == 0 {
+                    gl.FramebufferTexture(point, attachment, texture, level as _);
+                } else {
+                    warn!("Layered attachments can't start at layer {}, only attaching the first layer", layers.start);
+                    gl.FramebufferTextureLayer(point, attachment, texture, level as _, layers.start as _);
+                }
+            },
+            n::ImageView::TextureView(texture, _) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture, 0);
+            },
+            n::ImageView::Cube(texture, level) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture, level as _);
+            },
+            n::ImageView::CubeFace(texture, level, face) => unsafe {
+                gl.FramebufferTexture2D(
+                    point, attachment, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum, texture, level as _,
+                );
+            },
+            n::ImageView::Texture3D(texture, level) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture, level as _);
+            },
+            n::ImageView::Texture3DSlice(texture, level, slice) => unsafe {
+                gl.FramebufferTextureLayer(point, attachment, texture, level as _, slice as _);
+            },
         }
     }
 
@@ -270,6 +1013,8 @@ impl Device {
             other => panic!("GLSL version is not recognized: {:?}", other),
         };
         compile_options.vertex.invert_y = true;
+        // Without clip control the GL clip space depth is `-1..1`.
+        compile_options.vertex.transform_clip_space = !self.share.private_caps.clip_control;
         debug!("SPIR-V options {:?}", compile_options);
 
         ast.set_compiler_options(&compile_options)
@@ -293,6 +1038,31 @@ impl Device {
         let res = ast.get_shader_resources().unwrap();
         self.remap_binding(ast, desc_remap_data, nb_map, &res.sampled_images, n::BindingTypes::Images);
         self.remap_binding(ast, desc_remap_data, nb_map, &res.uniform_buffers, n::BindingTypes::UniformBuffers);
+        self.remap_binding(ast, desc_remap_data, nb_map, &res.storage_buffers, n::BindingTypes::StorageBuffers);
+        self.remap_binding(ast, desc_remap_data, nb_map, &res.storage_images, n::BindingTypes::StorageImages);
+        self.remap_subpass_inputs(ast, nb_map, &res.subpass_inputs);
+    }
+
+    // Subpass inputs are translated to textures fetched at the fragment
+    // coordinates, sampling from the units bound on sub-pass entry.
+    fn remap_subpass_inputs(
+        &self,
+        ast: &mut spirv::Ast<glsl::Target>,
+        nb_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+        all_res: &[spirv::Resource],
+    ) {
+        for res in all_res {
+            let index = ast.get_decoration(res.id, spirv::Decoration::InputAttachmentIndex).unwrap();
+            let unit = self.share.private_caps.input_attachment_unit_base + index;
+
+            if self.share.legacy_features.contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER) {
+                ast.set_decoration(res.id, spirv::Decoration::Binding, unit).unwrap()
+            } else {
+                ast.unset_decoration(res.id, spirv::Decoration::Binding).unwrap();
+                assert!(nb_map.insert(res.name.clone(), unit).is_none());
+            }
+            ast.unset_decoration(res.id, spirv::Decoration::DescriptorSet).unwrap();
+        }
     }
 
     fn remap_binding(
@@ -306,6 +1076,17 @@ impl Device {
         for res in all_res {
             let set = ast.get_decoration(res.id, spirv::Decoration::DescriptorSet).unwrap();
             let binding = ast.get_decoration(res.id, spirv::Decoration::Binding).unwrap();
+            if btype == n::BindingTypes::StorageImages {
+                // Qualifiers of the image, `readonly` and `writeonly` in GLSL.
+                let non_writable = ast.get_decoration(res.id, spirv::Decoration::NonWritable).unwrap() != 0;
+                let non_readable = ast.get_decoration(res.id, spirv::Decoration::NonReadable).unwrap() != 0;
+                let access = match (non_writable, non_readable) {
+                    (true, false) => gl::READ_ONLY,
+                    (false, true) => gl::WRITE_ONLY,
+                    _ => gl::READ_WRITE,
+                };
+                desc_remap_data.insert_image_access(set as _, binding, access);
+            }
             let nbs = desc_remap_data.get_binding(btype, set as _, binding).unwrap();
 
             for nb in nbs {
@@ -343,7 +1124,7 @@ impl Device {
             let (set, binding) = id_map.get(&cis.sampler_id).unwrap();
             desc_remap_data.insert_missing_binding(
                 nb,
-                n::BindingTypes::Images,
+                n::BindingTypes::Samplers,
                 *set,
                 *binding,
             );
@@ -376,1043 +1157,4426 @@ impl Device {
         }
     }
 
-    fn compile_shader(
+    // Assign the texture units to the sampler uniforms of a freshly linked program.
+    //
+    // The assignments are part of the program object state and persist until
+    // the program is linked again, so this is only done once after linking
+    // instead of on every program bind.
+    fn assign_uniform_bindings(
         &self,
-        point: &pso::EntryPoint<B>,
-        stage: pso::Stage,
-        desc_remap_data: &mut n::DescRemapData,
-        name_binding_map: &mut FastHashMap<String, pso::DescriptorBinding>,
-    ) -> n::Shader {
-        assert_eq!(point.entry, "main");
-        match *point.module {
-            n::ShaderModule::Raw(raw) => {
-                debug!("Can't remap bindings for raw shaders. Assuming they are already rebound.");
-                raw
+        program: n::Program,
+        nb_map: &FastHashMap<String, pso::DescriptorBinding>,
+    ) {
+        let gl = &self.share.context;
+        let mut bound = false;
+        for (bname, binding) in nb_map.iter() {
+            let cname = ffi::CString::new(bname.as_str()).unwrap();
+            let loc = unsafe { gl.GetUniformLocation(program, cname.as_ptr()) };
+            if loc < 0 {
+                // Inactive or not a plain uniform (e.g. uniform blocks).
+                continue;
             }
-            n::ShaderModule::Spirv(ref spirv) => {
-                let mut ast = self.parse_spirv(spirv).unwrap();
+            if !bound {
+                unsafe { gl.UseProgram(program) };
+                bound = true;
+            }
+            unsafe { gl.Uniform1i(loc, *binding as _) };
+        }
+    }
 
-                self.specialize_ast(&mut ast, point.specialization).unwrap();
-                self.remap_bindings(&mut ast, desc_remap_data, name_binding_map);
-                self.combine_separate_images_and_samplers(&mut ast, desc_remap_data, name_binding_map);
+    // Query the plain uniforms of the linked programs of a pipeline, which are set by push constants.
+    //
+    // Uniforms translated from push constant blocks take the offsets and strides of
+    // the block members in `layout`. Uniforms of GLSL shaders are laid out by location,
+    // which is queried without name lookups if supported, also covering explicit
+    // `layout(location = N)` declarations. Uniforms of separable programs with the
+    // same location share their push constant offset.
+    fn query_uniforms(&self, programs: &[n::Program], layout: &PushConstantLayout) -> Vec<n::UniformDesc> {
+        let mut named = Vec::new();
+        for &program in programs {
+            self.query_program_uniforms(program, &mut named);
+        }
 
-                let glsl = self.translate_spirv(&mut ast).unwrap();
-                info!("Generated:\n{:?}", glsl);
-                let shader = match self.create_shader_module_from_source(glsl.as_bytes(), stage).unwrap() {
-                    n::ShaderModule::Raw(raw) => raw,
-                    _ => panic!("Unhandled")
-                };
+        if !layout.is_empty() {
+            return named
+                .into_iter()
+                .filter_map(|(name, uniform)| {
+                    // Arrays are reported by the name of their first element.
+                    let member = if name.ends_with("[0]") { &name[.. name.len() - 3] } else { &name[..] };
+                    match layout.get(member) {
+                        Some(member) => Some(layout_push_constant_uniform(uniform, member)),
+                        None => {
+                            warn!("Uniform {} is not a member of a push constant block", name);
+                            None
+                        }
+                    }
+                })
+                .collect();
+        }
 
-                shader
+        let mut uniforms = named.into_iter().map(|(_, uniform)| uniform).collect::<Vec<_>>();
+        uniforms.sort_by_key(|uniform| uniform.location);
+        let mut offset = 0;
+        let mut previous = None;
+        for uniform in &mut uniforms {
+            match previous {
+                Some((location, previous_offset)) if location == uniform.location => {
+                    uniform.offset = previous_offset;
+                }
+                _ => {
+                    uniform.offset = offset;
+                    previous = Some((uniform.location, offset));
+                    offset += uniform.size;
+                }
             }
         }
+        uniforms
     }
-}
 
-impl d::Device<B> for Device {
-    fn allocate_memory(
-        &self, _mem_type: c::MemoryTypeId, size: u64,
-    ) -> Result<n::Memory, d::OutOfMemory> {
-        // TODO
-        Ok(n::Memory {
-            properties: memory::Properties::CPU_VISIBLE | memory::Properties::CPU_CACHED,
-            first_bound_buffer: Cell::new(0),
-            size,
-        })
+    /// Declare the shader storage block layout expected by the application,
+    /// e.g. `std430` offsets computed on the host.
+    ///
+    /// `members` lists buffer variables by the name reported by the program, e.g.
+    /// `"Particles.data[0].position"`, with their expected byte offset and top-level array
+    /// stride, `0` if not an array. Programs linked afterwards are checked once after linking,
+    /// mismatches of the members active in a program are logged as errors.
+    /// Requires `GL_ARB_program_interface_query`, otherwise nothing is checked.
+    pub fn expect_storage_block_layout(&self, members: &[(&str, u32, u32)]) {
+        self.storage_layout
+            .lock()
+            .unwrap()
+            .extend(members.iter().map(|&(name, offset, stride)| (name.to_string(), offset, stride)));
     }
 
-    fn create_command_pool(
-        &self,
-        _family: QueueFamilyId,
-        flags: CommandPoolCreateFlags,
-    ) -> RawCommandPool {
-        let fbo = create_fbo_internal(&self.share.context);
-        let limits = self.share.limits.into();
-        let memory = if flags.contains(CommandPoolCreateFlags::RESET_INDIVIDUAL) {
-            BufferMemory::Individual {
-                storage: FastHashMap::default(),
-                next_buffer_id: 0,
+    /// Check if the programs of a graphics pipeline matched the expected storage block layout
+    /// when linked, see `expect_storage_block_layout`.
+    pub fn check_storage_block_layout(&self, pipeline: &n::GraphicsPipeline) -> bool {
+        let invalid = self.invalid_storage_layouts.lock().unwrap();
+        match pipeline.program {
+            n::BoundProgram::Program(program) => !invalid.contains(&program),
+            n::BoundProgram::Pipeline(_) => pipeline.stage_programs.iter().all(|program| !invalid.contains(program)),
+        }
+    }
+
+    /// Check if the program of a compute pipeline matched the expected storage block layout
+    /// when linked, see `expect_storage_block_layout`.
+    pub fn check_compute_storage_block_layout(&self, pipeline: &n::ComputePipeline) -> bool {
+        !self.invalid_storage_layouts.lock().unwrap().contains(&pipeline.program)
+    }
+
+    // Check the expected storage block members active in a newly linked program.
+    fn check_program_storage_layout(&self, program: n::Program) {
+        let members = self.storage_layout.lock().unwrap();
+        if members.is_empty() || !self.share.private_caps.program_interface_query {
+            return;
+        }
+
+        let gl = &self.share.context;
+        let mut valid = true;
+        for &(ref name, offset, stride) in members.iter() {
+            let cname = ffi::CString::new(name.as_str()).unwrap();
+            let index = unsafe { gl.GetProgramResourceIndex(program, gl::BUFFER_VARIABLE, cname.as_ptr()) };
+            if index == gl::INVALID_INDEX {
+                // Not used by the stages of this program.
+                continue;
             }
-        } else {
-            BufferMemory::Linear(OwnedBuffer::new())
-        };
 
-        // Ignoring `TRANSIENT` hint, unsure how to make use of this.
+            let props = [gl::OFFSET, gl::TOP_LEVEL_ARRAY_STRIDE];
+            let mut values = [0; 2];
+            unsafe {
+                gl.GetProgramResourceiv(
+                    program, gl::BUFFER_VARIABLE, index,
+                    props.len() as _, props.as_ptr(),
+                    values.len() as _, ptr::null_mut(), values.as_mut_ptr(),
+                );
+            }
+            if values[0] as u32 != offset {
+                error!("Storage block member {} has offset {}, expected {}", name, values[0], offset);
+                valid = false;
+            }
+            if values[1] as u32 != stride {
+                error!("Storage block member {} has top-level array stride {}, expected {}", name, values[1], stride);
+                valid = false;
+            }
+        }
 
-        RawCommandPool {
-            fbo,
-            limits,
-            memory: Arc::new(Mutex::new(memory)),
+        if let Err(err) = self.share.check() {
+            panic!("Error querying storage block layout: {:?}", err);
+        }
+        if !valid {
+            self.invalid_storage_layouts.lock().unwrap().insert(program);
         }
     }
 
-    fn destroy_command_pool(&self, pool: RawCommandPool) {
+    /// Generate the mip levels of a texture from its base level.
+    ///
+    /// Renderbuffers and multisampled textures have no mip levels,
+    /// returns `false` for these.
+    pub fn generate_mipmaps(&self, image: &n::Image) -> bool {
+        let (texture, target) = match mipmap_target(image) {
+            Some(target) => target,
+            None => {
+                error!("Mipmaps can't be generated for {:?}", image.kind);
+                return false;
+            }
+        };
+        if image.num_levels <= 1 {
+            return true;
+        }
+
+        self.apply_mipmap_hint();
         let gl = &self.share.context;
         unsafe {
-            gl.DeleteFramebuffers(1, &pool.fbo);
+            gl.BindTexture(target, texture);
+            gl.GenerateMipmap(target);
+            gl.BindTexture(target, 0);
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error {:?} generating {} mip levels of texture {}", err, image.num_levels, texture)
         }
+        true
     }
 
-    fn create_render_pass<'a, IA, IS, ID>(
-        &self, attachments: IA, subpasses: IS, _dependencies: ID
-    ) -> n::RenderPass
-    where
-        IA: IntoIterator,
-        IA::Item: Borrow<pass::Attachment>,
-        IS: IntoIterator,
-        IS::Item: Borrow<pass::SubpassDesc<'a>>,
-        ID: IntoIterator,
-        ID::Item: Borrow<pass::SubpassDependency>,
-    {
-        let subpasses =
-            subpasses
-                .into_iter()
-                .map(|subpass| {
-                    let color_attachments =
-                        subpass
-                            .borrow()
-                            .colors
-                            .iter()
-                            .map(|&(index, _)| index)
-                            .collect();
-
-                    n::SubpassDesc {
-                        color_attachments,
-                    }
-                })
-                .collect();
+    // Apply the `mipmap_hint` option before generating mip levels.
+    fn apply_mipmap_hint(&self) {
+        // Not part of the core profile bindings.
+        const GENERATE_MIPMAP_HINT: GLenum = 0x8192;
 
-        n::RenderPass {
-            attachments: attachments.into_iter().map(|attachment| attachment.borrow().clone()).collect::<Vec<_>>(),
-            subpasses,
+        let mode = match self.share.options.get().mipmap_hint {
+            Some(mode) => mode,
+            None => return,
+        };
+        if self.share.private_caps.generate_mipmap_hint {
+            unsafe { self.share.context.Hint(GENERATE_MIPMAP_HINT, conv::hint_mode_to_gl(mode)) };
+        } else {
+            debug!("Ignoring mipmap hint {:?}, not available in core profiles", mode);
         }
     }
 
-    fn create_pipeline_layout<IS, IR>(&self, layouts: IS, _: IR) -> n::PipelineLayout
-    where
-        IS: IntoIterator,
-        IS::Item: Borrow<n::DescriptorSetLayout>,
-        IR: IntoIterator,
-        IR::Item: Borrow<(pso::ShaderStageFlags, Range<u32>)>,
-    {
-        let mut drd = n::DescRemapData::new();
-
-        layouts
-            .into_iter()
-            .enumerate()
-            .for_each(|(set, layout)| {
-                layout.borrow().iter().for_each(|binding| {
-                    // DescriptorType -> Descriptor
-                    //
-                    // Sampler -> Sampler
-                    // Image -> SampledImage, StorageImage, InputAttachment
-                    // CombinedImageSampler -> CombinedImageSampler
-                    // Buffer -> UniformBuffer, StorageBuffer
-                    // UniformTexel -> UniformTexel
-                    // StorageTexel -> StorageTexel
+    /// Create a single level 2D texture of target `GL_TEXTURE_RECTANGLE`,
+    /// which is sampled with unnormalized texel coordinates.
+    ///
+    /// Has to be sampled with a sampler from `create_unnormalized_sampler`.
+    pub fn create_rectangle_image(
+        &self,
+        width: i::Size,
+        height: i::Size,
+        format: Format,
+        usage: i::Usage,
+    ) -> Result<UnboundImage, i::CreationError> {
+        if !self.share.private_caps.texture_rectangle {
+            return Err(i::CreationError::Kind);
+        }
+        if !usage.contains(i::Usage::SAMPLED) {
+            return Err(i::CreationError::Usage(usage));
+        }
+        let int_format = match conv::image_format_to_gl(format) {
+            Some(int_format) => int_format,
+            None => return Err(i::CreationError::Format(format)),
+        };
 
-                    assert!(!binding.immutable_samplers); //TODO: Implement immutable_samplers
-                    use pso::DescriptorType::*;
-                    match binding.ty {
-                        CombinedImageSampler => {
-                            drd.insert_missing_binding_into_spare(n::BindingTypes::Images, set as _, binding.binding);
-                        }
-                        Sampler | SampledImage => {
-                            // We need to figure out combos once we get the shaders, until then we
-                            // do nothing
-                        }
-                        UniformBuffer => {
-                            drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, set as _, binding.binding);
-                        }
-                        StorageImage
-                        | UniformTexelBuffer
-                        | UniformBufferDynamic
-                        | StorageTexelBuffer
-                        | StorageBufferDynamic
-                        | StorageBuffer
-
-                        | InputAttachment => unimplemented!(), // 6
-                    }
-                })
-            });
+        let gl = &self.share.context;
+        let mut name = 0;
+        unsafe {
+            gl.GenTextures(1, &mut name);
+            gl.BindTexture(gl::TEXTURE_RECTANGLE, name);
+            gl.TexStorage2D(gl::TEXTURE_RECTANGLE, 1, int_format, width as _, height as _);
+        }
 
-        n::PipelineLayout {
-            desc_remap_data: Arc::new(RwLock::new(drd)),
+        if let Err(err) = self.share.check() {
+            panic!("Error creating rectangle image: {:?} of size {}x{} of {:?}",
+                err, width, height, format);
         }
+
+        let kind = i::Kind::D2(width, height, 1, 1);
+        let bytes_per_block = (format.surface_desc().bits / 8).max(1);
+        Ok(UnboundImage {
+            image: n::ImageKind::Texture(name, gl::TEXTURE_RECTANGLE),
+            format,
+            channel: format.base_format().1,
+            kind,
+            num_levels: 1,
+            requirements: memory::Requirements {
+                size: image_storage_size(kind, 1, format),
+                alignment: bytes_per_block as _,
+                type_mask: 0x1,
+            },
+        })
     }
 
-    fn create_graphics_pipeline<'a>(
-        &self, desc: &pso::GraphicsPipelineDesc<'a, B>
-    ) -> Result<n::GraphicsPipeline, pso::CreationError> {
+    /// Create a sampler addressing texels by unnormalized coordinates for
+    /// sampling rectangle textures.
+    ///
+    /// Mip levels beyond the base level and repeating wrap modes are not allowed.
+    pub fn create_unnormalized_sampler(
+        &self,
+        info: i::SamplerInfo,
+    ) -> Result<n::FatSampler, UnnormalizedSamplerError> {
+        if !self.share.private_caps.texture_rectangle ||
+            !self.share.legacy_features.contains(LegacyFeatures::SAMPLER_OBJECTS)
+        {
+            return Err(UnnormalizedSamplerError::Unsupported);
+        }
+        validate_unnormalized_sampler(&info)?;
+
+        let name = match d::Device::<B>::create_sampler(self, info.clone()) {
+            n::FatSampler::Sampler(name) => name,
+            n::FatSampler::Info(_) | n::FatSampler::Unnormalized(_) =>
+                return Err(UnnormalizedSamplerError::Unsupported),
+        };
+        // Rectangle textures have no mip levels to filter between.
+        let min = match info.min_filter {
+            i::Filter::Nearest => gl::NEAREST,
+            i::Filter::Linear => gl::LINEAR,
+        };
         let gl = &self.share.context;
-        let share = &self.share;
-        let desc = desc.borrow();
-        let subpass = {
-            let subpass = desc.subpass;
-            match subpass.main_pass.subpasses.get(subpass.index) {
-                Some(sp) => sp,
-                None => return Err(pso::CreationError::InvalidSubpass(subpass.index)),
+        unsafe { gl.SamplerParameteri(name, gl::TEXTURE_MIN_FILTER, min as GLint) };
+
+        if let Err(err) = self.share.check() {
+            panic!("Error {:?} creating unnormalized sampler {}", err, name)
+        }
+        Ok(n::FatSampler::Unnormalized(name))
+    }
+
+    /// Mirror the texture coordinates of the selected axes (S, T, R) of a sampler once
+    /// and clamp them beyond via `GL_MIRROR_CLAMP_TO_EDGE`, which isn't expressible
+    /// with `WrapMode`. The other axes keep the wrap mode of the sampler info.
+    ///
+    /// Requires GL 4.4 or `GL_ARB_texture_mirror_clamp_to_edge` and sampler objects,
+    /// returns `false` if unsupported.
+    pub fn set_sampler_mirror_clamp(&self, sampler: &n::FatSampler, axes: (bool, bool, bool)) -> bool {
+        let name = match *sampler {
+            n::FatSampler::Sampler(name) if self.share.private_caps.mirror_clamp_to_edge => name,
+            _ => {
+                error!("Mirror clamp to edge wrap mode is not supported");
+                return false;
             }
         };
 
-        let program = {
-            let name = unsafe { gl.CreateProgram() };
-
-            // Attach shaders to program
-            let shaders = [
-                (pso::Stage::Vertex, Some(&desc.shaders.vertex)),
-                (pso::Stage::Hull, desc.shaders.hull.as_ref()),
-                (pso::Stage::Domain, desc.shaders.domain.as_ref()),
-                (pso::Stage::Geometry, desc.shaders.geometry.as_ref()),
-                (pso::Stage::Fragment, desc.shaders.fragment.as_ref()),
-            ];
+        let gl = &self.share.context;
+        let (s, t, r) = axes;
+        for &(pname, enabled) in &[(gl::TEXTURE_WRAP_S, s), (gl::TEXTURE_WRAP_T, t), (gl::TEXTURE_WRAP_R, r)] {
+            if enabled {
+                unsafe { gl.SamplerParameteri(name, pname, gl::MIRROR_CLAMP_TO_EDGE as GLint) };
+            }
+        }
 
-            let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
-            let shader_names = &shaders
-                .iter()
-                .filter_map(|&(stage, point_maybe)| {
-                    point_maybe.map(|point| {
-                        let shader_name = self.compile_shader(
-                            point,
-                            stage,
-                            &mut desc.layout.desc_remap_data.write().unwrap(),
-                            &mut name_binding_map,
-                        );
-                        unsafe { gl.AttachShader(name, shader_name); }
-                        shader_name
-                    })
-                })
-                .collect::<Vec<_>>();
+        if let Err(err) = self.share.check() {
+            panic!("Error {:?} setting the wrap mode of sampler {}", err, name)
+        }
+        true
+    }
 
-            if !share.private_caps.program_interface && share.private_caps.frag_data_location {
-                for i in 0..subpass.color_attachments.len() {
-                    let color_name = format!("Target{}\0", i);
+    // Query the name, location and type of the active attributes of a program.
+    fn query_attributes(&self, program: n::Program) -> Vec<(String, GLuint, GLenum)> {
+        let gl = &self.share.context;
+        let count = get_program_iv(gl, program, gl::ACTIVE_ATTRIBUTES);
+        (0 .. count as GLuint)
+            .filter_map(|index| {
+                let mut name = [0u8; 256];
+                let mut length = 0;
+                let mut size = 0;
+                let mut atype = 0;
+                unsafe {
+                    gl.GetActiveAttrib(
+                        program, index, name.len() as _,
+                        &mut length, &mut size, &mut atype,
+                        name.as_mut_ptr() as *mut _,
+                    );
+                }
+                let location = if self.share.private_caps.program_interface_query {
+                    let props = [gl::LOCATION];
+                    let mut values = [-1];
                     unsafe {
-                        gl.BindFragDataLocation(name, i as u32, (&color_name[..]).as_ptr() as *mut gl::types::GLchar);
+                        gl.GetProgramResourceiv(
+                            program, gl::PROGRAM_INPUT, index,
+                            props.len() as _, props.as_ptr(),
+                            values.len() as _, ptr::null_mut(), values.as_mut_ptr(),
+                        );
                     }
+                    values[0]
+                } else {
+                    unsafe { gl.GetAttribLocation(program, name.as_ptr() as *const _) }
+                };
+                // Built-in inputs like `gl_VertexID` don't have a location.
+                if location < 0 {
+                    return None;
                 }
-            }
+                let name = String::from_utf8_lossy(&name[.. length as usize]).into_owned();
+                Some((name, location as GLuint, atype))
+            })
+            .collect()
+    }
 
-            unsafe { gl.LinkProgram(name) };
-            info!("\tLinked program {}", name);
-            if let Err(err) = share.check() {
-                panic!("Error linking program: {:?}", err);
+    // Select the outputs of a SPIR-V shader captured by transform feedback, before linking
+    // `program`. SPIRV-Cross doesn't emit `xfb_offset` qualifiers for them.
+    fn bind_transform_feedback_varyings(&self, program: n::Program, module: &n::ShaderModule) {
+        let spirv = match *module {
+            n::ShaderModule::Spirv(ref spirv) if self.share.private_caps.transform_feedback => spirv,
+            _ => return,
+        };
+        let varyings = self.reflect_transform_feedback_varyings(spirv);
+        if varyings.is_empty() {
+            return;
+        }
+        let names = varyings
+            .into_iter()
+            .filter_map(|varying| ffi::CString::new(varying).ok())
+            .collect::<Vec<_>>();
+        let pointers = names.iter().map(|name| name.as_ptr()).collect::<Vec<_>>();
+        unsafe {
+            self.share.context.TransformFeedbackVaryings(
+                program, pointers.len() as _, pointers.as_ptr(), gl::INTERLEAVED_ATTRIBS,
+            );
+        }
+    }
+
+    // Outputs of a SPIR-V shader decorated for transform feedback, ordered by buffer
+    // and offset. Outputs of different buffers are separated by `gl_NextBuffer`.
+    fn reflect_transform_feedback_varyings(&self, spirv: &[u8]) -> Vec<String> {
+        let ast = match self.parse_spirv(spirv) {
+            Ok(ast) => ast,
+            Err(_) => return Vec::new(),
+        };
+        let outputs = match ast.get_shader_resources() {
+            Ok(resources) => resources.stage_outputs,
+            Err(err) => {
+                warn!("Failed to reflect the outputs of a shader: {:?}", err);
+                return Vec::new();
+            }
+        };
+        let decoration = |id, decoration| ast.get_decoration(id, decoration).unwrap_or(0);
+        let mut captured = outputs
+            .iter()
+            // Captured outputs always have a buffer stride.
+            .filter(|res| decoration(res.id, spirv::Decoration::XfbStride) != 0)
+            .map(|res| (
+                decoration(res.id, spirv::Decoration::XfbBuffer),
+                decoration(res.id, spirv::Decoration::Offset),
+                res.name.clone(),
+            ))
+            .collect::<Vec<_>>();
+        captured.sort();
+
+        let mut varyings = Vec::new();
+        let mut buffer = captured.first().map_or(0, |&(buffer, _, _)| buffer);
+        for (xfb_buffer, _, name) in captured {
+            while buffer < xfb_buffer {
+                varyings.push("gl_NextBuffer".to_string());
+                buffer += 1;
             }
+            varyings.push(name);
+        }
+        varyings
+    }
 
-            for shader_name in shader_names {
-                unsafe {
-                    gl.DetachShader(name, *shader_name);
-                    gl.DeleteShader(*shader_name);
+    // Reflect the locations of the vertex inputs of a SPIR-V shader by name.
+    fn reflect_vertex_inputs(&self, spirv: &[u8]) -> FastHashMap<String, GLuint> {
+        let mut inputs = FastHashMap::default();
+        let ast = match self.parse_spirv(spirv) {
+            Ok(ast) => ast,
+            Err(_) => return inputs,
+        };
+        let resources = match ast.get_shader_resources() {
+            Ok(resources) => resources,
+            Err(err) => {
+                warn!("Failed to reflect the vertex inputs of a shader: {:?}", err);
+                return inputs;
+            }
+        };
+        for res in &resources.stage_inputs {
+            match ast.get_decoration(res.id, spirv::Decoration::Location) {
+                Ok(location) => {
+                    inputs.insert(res.name.clone(), location);
                 }
+                Err(err) => warn!("Failed to reflect the location of vertex input {}: {:?}", res.name, err),
             }
+        }
+        inputs
+    }
 
-            if !self.share.legacy_features.contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER) {
-                let gl = &self.share.context;
+    // Query the plain uniforms of a program with their names, tightly packed at offset 0.
+    fn query_program_uniforms(&self, program: n::Program, uniforms: &mut Vec<(String, n::UniformDesc)>) {
+        let gl = &self.share.context;
+        let count = get_program_iv(gl, program, gl::ACTIVE_UNIFORMS);
+        for index in 0 .. count as gl::types::GLuint {
+            let mut name = [0u8; 256];
+            let mut length = 0;
+            let mut array_size = 0;
+            let mut utype = 0;
+            unsafe {
+                gl.GetActiveUniform(
+                    program, index, name.len() as _,
+                    &mut length, &mut array_size, &mut utype,
+                    name.as_mut_ptr() as *mut _,
+                );
+            }
+            let location = if self.share.private_caps.program_interface_query {
+                let props = [gl::LOCATION];
+                let mut values = [0];
                 unsafe {
-                    gl.UseProgram(name);
-                    for (bname, binding) in name_binding_map.iter() {
-                        let loc = gl.GetUniformLocation(name, bname.as_ptr() as _);
-                        gl.Uniform1i(loc, *binding as _);
-                    }
+                    gl.GetProgramResourceiv(
+                        program, gl::UNIFORM, index,
+                        props.len() as _, props.as_ptr(),
+                        values.len() as _, ptr::null_mut(), values.as_mut_ptr(),
+                    );
                 }
+                values[0]
+            } else {
+                unsafe { gl.GetUniformLocation(program, name.as_ptr() as *const _) }
+            };
+
+            // Members of uniform blocks don't have a location.
+            if location < 0 {
+                continue;
             }
+            if let Some(size) = conv::uniform_type_size(utype) {
+                let count = array_size.max(1);
+                let name = String::from_utf8_lossy(&name[.. length as usize]).into_owned();
+                uniforms.push((name, n::UniformDesc {
+                    program,
+                    location,
+                    utype,
+                    count,
+                    offset: 0,
+                    size: size * count as u32,
+                    array_stride: size,
+                    matrix_stride: size / conv::attribute_type_columns(utype),
+                }));
+            }
+        }
+    }
 
-            let status = get_program_iv(gl, name, gl::LINK_STATUS);
-            let log = get_program_log(gl, name);
-            if status != 0 {
-                if !log.is_empty() {
-                    warn!("\tLog: {}", log);
-                }
-            } else {
-                return Err(pso::CreationError::Shader(d::ShaderError::CompilationFailed(log)));
+    // Layout of the push constant block members of the SPIR-V shader modules of a pipeline.
+    fn push_constant_layout<'a, I>(&self, modules: I) -> PushConstantLayout
+    where
+        I: IntoIterator<Item = &'a n::ShaderModule>,
+    {
+        let mut layout = PushConstantLayout::default();
+        for module in modules {
+            if let n::ShaderModule::Spirv(ref spirv) = *module {
+                self.reflect_push_constants(spirv, &mut layout);
             }
+        }
+        layout
+    }
 
-            name
+    // Reflect the push constant blocks of a SPIR-V shader, keyed by the names of the
+    // uniforms SPIRV-Cross translates the members to. Unnamed blocks and members are
+    // named like SPIRV-Cross does.
+    fn reflect_push_constants(&self, spirv: &[u8], layout: &mut PushConstantLayout) {
+        let ast = match self.parse_spirv(spirv) {
+            Ok(ast) => ast,
+            Err(_) => return,
         };
-
-        let patch_size = match desc.input_assembler.primitive {
-            c::Primitive::PatchList(size) => Some(size as _),
-            _ => None
+        let resources = match ast.get_shader_resources() {
+            Ok(resources) => resources,
+            Err(err) => {
+                warn!("Failed to reflect the push constants of a shader: {:?}", err);
+                return;
+            }
         };
-
-        let mut vertex_buffers = Vec::new();
-        for vb in &desc.vertex_buffers {
-            while vertex_buffers.len() <= vb.binding as usize {
-                vertex_buffers.push(None);
+        for res in &resources.push_constant_buffers {
+            let name = if res.name.is_empty() {
+                format!("_{}", res.id)
+            } else {
+                res.name.clone()
+            };
+            if let Err(err) = reflect_struct_members(&ast, res.base_type_id, &name, 0, layout) {
+                warn!("Failed to reflect the push constant block {}: {:?}", name, err);
             }
-            vertex_buffers[vb.binding as usize] = Some(*vb);
         }
-
-        Ok(n::GraphicsPipeline {
-            program,
-            primitive: conv::primitive_to_gl_primitive(desc.input_assembler.primitive),
-            patch_size,
-            blend_targets: desc.blender.targets.clone(),
-            vertex_buffers,
-            attributes: desc.attributes
-                .iter()
-                .map(|&a| {
-                    let (size, format, vertex_attrib_fn) = conv::format_to_gl_format(a.element.format).unwrap();
-                    n::AttributeDesc {
-                        location: a.location,
-                        offset: a.element.offset,
-                        binding: a.binding,
-                        size,
-                        format,
-                        vertex_attrib_fn,
-                    }
-                })
-                .collect(),
-        })
     }
 
-    fn create_compute_pipeline<'a>(
-        &self,
-        desc: &pso::ComputePipelineDesc<'a, B>,
-    ) -> Result<n::ComputePipeline, pso::CreationError> {
+    // Internal format of a texture level, required for binding it to an image unit.
+    fn texture_internal_format(&self, texture: n::Texture, target: n::TextureTarget, level: i::Level) -> GLenum {
         let gl = &self.share.context;
-        let share = &self.share;
+        // Levels of cube maps are queried per face.
+        let level_target = match target {
+            gl::TEXTURE_CUBE_MAP => gl::TEXTURE_CUBE_MAP_POSITIVE_X,
+            _ => target,
+        };
+        let mut format = 0;
+        unsafe {
+            gl.BindTexture(target, texture);
+            gl.GetTexLevelParameteriv(level_target, level as _, gl::TEXTURE_INTERNAL_FORMAT, &mut format);
+            gl.BindTexture(target, 0);
+        }
+        format as _
+    }
 
-        let program = {
-            let name = unsafe { gl.CreateProgram() };
+    // Mask of all memory types reported by `PhysicalDevice::memory_properties`.
+    fn memory_type_mask(&self) -> u64 {
+        if self.share.private_caps.map {
+            0xF
+        } else {
+            0x1
+        }
+    }
 
-            let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
-            let shader = self.compile_shader(
-                &desc.shader,
-                pso::Stage::Compute,
-                &mut desc.layout.desc_remap_data.write().unwrap(),
-                &mut name_binding_map,
-            );
-            unsafe { gl.AttachShader(name, shader) };
+    // Check that the flattened bindings of the layout fit into the binding points,
+    // binds beyond the limit would silently fail.
+    fn check_binding_limits(&self, layout: &n::PipelineLayout) -> Result<(), pso::CreationError> {
+        let drd = layout.desc_remap_data.read().unwrap();
+        let limits = [
+            (n::BindingTypes::UniformBuffers, "uniform", self.share.limits.max_uniform_buffer_bindings),
+            (n::BindingTypes::StorageBuffers, "storage", self.share.limits.max_storage_buffer_bindings),
+        ];
+        for &(btype, name, max) in &limits {
+            let count = drd.binding_count(btype) as usize;
+            if count > max {
+                error!("Pipeline layout uses {} {} buffer bindings, exceeding the maximum of {}", count, name, max);
+                return Err(pso::CreationError::Other);
+            }
+        }
+        Ok(())
+    }
 
-            unsafe { gl.LinkProgram(name) };
-            info!("\tLinked program {}", name);
-            if let Err(err) = share.check() {
-                panic!("Error linking program: {:?}", err);
+    fn compile_shader(
+        &self,
+        point: &pso::EntryPoint<B>,
+        stage: pso::Stage,
+        settings: &PipelineSettings,
+        desc_remap_data: &mut n::DescRemapData,
+        name_binding_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+    ) -> n::Shader {
+        assert_eq!(point.entry, "main");
+        match *point.module {
+            n::ShaderModule::Raw(raw) => {
+                debug!("Can't remap bindings for raw shaders. Assuming they are already rebound.");
+                raw
+            }
+            n::ShaderModule::Spirv(ref spirv) => {
+                let glsl = self.translate_entry_point(spirv, point, stage, settings, desc_remap_data, name_binding_map);
+                self.compile_glsl(&glsl, stage)
             }
+        }
+    }
 
-            unsafe {
-                gl.DetachShader(name, shader);
-                gl.DeleteShader(shader);
+    // Translate a SPIR-V entry point to GLSL, remapping the bindings to the flattened layout.
+    fn translate_entry_point(
+        &self,
+        spirv: &[u8],
+        point: &pso::EntryPoint<B>,
+        stage: pso::Stage,
+        settings: &PipelineSettings,
+        desc_remap_data: &mut n::DescRemapData,
+        name_binding_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+    ) -> String {
+        let mut ast = self.parse_spirv(spirv).unwrap();
+
+        self.specialize_ast(&mut ast, point.specialization).unwrap();
+        self.remap_bindings(&mut ast, desc_remap_data, name_binding_map);
+        self.combine_separate_images_and_samplers(&mut ast, desc_remap_data, name_binding_map);
+
+        let mut glsl = self.translate_spirv(&mut ast).unwrap();
+        if let (pso::Stage::Fragment, Some((comparison, reference))) = (stage, settings.alpha_test) {
+            let output = ast
+                .get_shader_resources()
+                .unwrap()
+                .stage_outputs
+                .into_iter()
+                .find(|res| ast.get_decoration(res.id, spirv::Decoration::Location).ok() == Some(0));
+            match output {
+                Some(ref res) if !res.name.is_empty() => {
+                    glsl = inject_alpha_test(&glsl, &res.name, comparison, reference);
+                }
+                _ => warn!("Alpha test requires a named fragment shader output at location 0"),
+            }
+        }
+        if stage == pso::Stage::Fragment && settings.early_fragment_tests {
+            if self.share.private_caps.image_load_store {
+                let version = &self.share.info.shading_language;
+                let extension = !version.is_embedded && version.tuple() < (4, 20);
+                glsl = inject_early_fragment_tests(&glsl, extension);
+            } else {
+                warn!("Early fragment tests are not supported");
             }
+        }
+        info!("Generated:\n{:?}", glsl);
+        glsl
+    }
 
-            if !self.share.legacy_features.contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER) {
-                let gl = &self.share.context;
-                unsafe {
-                    gl.UseProgram(name);
-                    for (bname, binding) in name_binding_map.iter() {
-                        let loc = gl.GetUniformLocation(name, bname.as_ptr() as _);
-                        gl.Uniform1i(loc, *binding as _);
+    fn compile_glsl(&self, glsl: &str, stage: pso::Stage) -> n::Shader {
+        match self.create_shader_module_from_source(glsl.as_bytes(), stage).unwrap() {
+            n::ShaderModule::Raw(raw) => raw,
+            _ => panic!("Unhandled")
+        }
+    }
+
+    // Link a single stage into a separable program for use in program pipelines.
+    //
+    // Returns the program and whether it's owned by the pipeline, programs
+    // shared between pipelines are owned by the device.
+    fn create_separable_program(
+        &self,
+        point: &pso::EntryPoint<B>,
+        stage: pso::Stage,
+        settings: &PipelineSettings,
+        desc_remap_data: &mut n::DescRemapData,
+        color_attachments: usize,
+    ) -> Result<(n::Program, bool), pso::CreationError> {
+        let gl = &self.share.context;
+        let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
+        // Without explicit layouts the binding points are assigned after linking
+        // and depend on the pipeline layout, so the program can't be shared.
+        let explicit_layouts = self.share.legacy_features.contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER);
+
+        // Raw shaders are owned by their module.
+        let (shader, key, owned_shader) = match *point.module {
+            n::ShaderModule::Raw(raw) => (raw, None, false),
+            n::ShaderModule::Spirv(ref spirv) => {
+                assert_eq!(point.entry, "main");
+                let mut glsl = self.translate_entry_point(spirv, point, stage, settings, desc_remap_data, &mut name_binding_map);
+                let version = &self.share.info.shading_language;
+                if !version.is_embedded && version.tuple() >= (1, 50) {
+                    glsl = inject_per_vertex(&glsl, stage);
+                }
+                if explicit_layouts {
+                    if let Some(&program) = self.separable_programs.lock().unwrap().get(&(stage, glsl.clone())) {
+                        return Ok((program, false));
                     }
                 }
+                let shader = self.compile_glsl(&glsl, stage);
+                (shader, if explicit_layouts { Some((stage, glsl)) } else { None }, true)
             }
+        };
 
-            let status = get_program_iv(gl, name, gl::LINK_STATUS);
-            let log = get_program_log(gl, name);
-            if status != 0 {
-                if !log.is_empty() {
-                    warn!("\tLog: {}", log);
+        let name = unsafe { gl.CreateProgram() };
+        unsafe {
+            gl.ProgramParameteri(name, gl::PROGRAM_SEPARABLE, gl::TRUE as _);
+            gl.AttachShader(name, shader);
+        }
+        if stage != pso::Stage::Fragment {
+            self.bind_transform_feedback_varyings(name, point.module);
+        }
+        if stage == pso::Stage::Fragment && !self.share.private_caps.program_interface &&
+            self.share.private_caps.frag_data_location
+        {
+            for i in 0 .. color_attachments {
+                let color_name = format!("Target{}\0", i);
+                unsafe {
+                    gl.BindFragDataLocation(name, i as u32, (&color_name[..]).as_ptr() as *mut gl::types::GLchar);
                 }
-            } else {
-                return Err(pso::CreationError::Other);
             }
+        }
 
-            name
-        };
+        unsafe { gl.LinkProgram(name) };
+        info!("\tLinked separable program {}", name);
+        if let Err(err) = self.share.check() {
+            panic!("Error linking program: {:?}", err);
+        }
 
-        Ok(n::ComputePipeline {
-            program,
-        })
+        unsafe { gl.DetachShader(name, shader) };
+        if owned_shader {
+            unsafe { gl.DeleteShader(shader) };
+        }
+
+        if !explicit_layouts {
+            self.assign_uniform_bindings(name, &name_binding_map);
+        }
+
+        let status = get_program_iv(gl, name, gl::LINK_STATUS);
+        let log = get_program_log(gl, name);
+        if status != 0 {
+            if !log.is_empty() {
+                warn!("\tLog: {}", log);
+            }
+        } else {
+            unsafe { gl.DeleteProgram(name) };
+            return Err(pso::CreationError::Shader(d::ShaderError::CompilationFailed(log)));
+        }
+        self.check_program_storage_layout(name);
+
+        match key {
+            Some(key) => {
+                self.separable_programs.lock().unwrap().insert(key, name);
+                Ok((name, false))
+            }
+            None => Ok((name, true)),
+        }
     }
 
-    fn create_framebuffer<I>(
+    // Combine separable programs of the stages in a program pipeline,
+    // validating that the interfaces between the stages match.
+    fn create_program_pipeline(
         &self,
-        pass: &n::RenderPass,
-        attachments: I,
-        _extent: i::Extent,
-    ) -> Result<n::FrameBuffer, d::FramebufferError>
-    where
-        I: IntoIterator,
-        I::Item: Borrow<n::ImageView>,
-    {
-        if !self.share.private_caps.framebuffer {
-            return Err(d::FramebufferError);
+        programs: &[(pso::Stage, n::Program)],
+    ) -> Result<n::ProgramPipeline, pso::CreationError> {
+        let gl = &self.share.context;
+        let mut pipeline = 0;
+        unsafe {
+            gl.GenProgramPipelines(1, &mut pipeline);
+            for &(stage, program) in programs {
+                let stage_bit = match stage {
+                    pso::Stage::Vertex => gl::VERTEX_SHADER_BIT,
+                    pso::Stage::Hull => gl::TESS_CONTROL_SHADER_BIT,
+                    pso::Stage::Domain => gl::TESS_EVALUATION_SHADER_BIT,
+                    pso::Stage::Geometry => gl::GEOMETRY_SHADER_BIT,
+                    pso::Stage::Fragment => gl::FRAGMENT_SHADER_BIT,
+                    pso::Stage::Compute => gl::COMPUTE_SHADER_BIT,
+                };
+                gl.UseProgramStages(pipeline, stage_bit, program);
+            }
+            gl.ValidateProgramPipeline(pipeline);
         }
 
-        let gl = &self.share.context;
-        let target = gl::DRAW_FRAMEBUFFER;
-        let mut name = 0;
+        let mut status = 0;
+        let mut length = 0;
         unsafe {
-            gl.GenFramebuffers(1, &mut name);
-            gl.BindFramebuffer(target, name);
+            gl.GetProgramPipelineiv(pipeline, gl::VALIDATE_STATUS, &mut status);
+            gl.GetProgramPipelineiv(pipeline, gl::INFO_LOG_LENGTH, &mut length);
+        }
+        let mut log = String::new();
+        if length > 0 {
+            log.extend(repeat('\0').take(length as usize));
+            unsafe {
+                gl.GetProgramPipelineInfoLog(pipeline, length, &mut length,
+                    (&log[..]).as_ptr() as *mut gl::types::GLchar);
+            }
+            log.truncate(length as usize);
         }
 
-        let att_points = [
-            gl::COLOR_ATTACHMENT0,
-            gl::COLOR_ATTACHMENT1,
-            gl::COLOR_ATTACHMENT2,
-            gl::COLOR_ATTACHMENT3,
-        ];
+        if status == 0 {
+            error!("Stages of the program pipeline don't match: {}", log);
+            unsafe { gl.DeleteProgramPipelines(1, &pipeline) };
+            return Err(pso::CreationError::Shader(d::ShaderError::InterfaceMismatch(log)));
+        }
+        if !log.is_empty() {
+            warn!("\tLog: {}", log);
+        }
+        Ok(pipeline)
+    }
 
-        let mut attachments_len = 0;
-        //TODO: exclude depth/stencil attachments from here
-        for (&att_point, view) in att_points.iter().zip(attachments.into_iter()) {
-            attachments_len += 1;
-            if self.share.private_caps.framebuffer_texture {
-                Self::bind_target(gl, target, att_point, view.borrow());
-            } else {
-                Self::bind_target_compat(gl, target, att_point, view.borrow());
+    /// Create a pipeline layout using the binding numbers of the descriptor set layouts
+    /// directly as GL binding points, matching shaders with explicit bindings.
+    ///
+    /// Binding numbers reused by multiple sets are only assigned to the first set,
+    /// the others fall back to automatically assigned spare binding points.
+    pub fn create_pipeline_layout_with_explicit_bindings<IS>(&self, layouts: IS) -> n::PipelineLayout
+    where
+        IS: IntoIterator,
+        IS::Item: Borrow<n::DescriptorSetLayout>,
+    {
+        self.flatten_pipeline_layout(layouts, true)
+    }
+
+    // Flatten the bindings of the descriptor set layouts into GL binding points.
+    fn flatten_pipeline_layout<IS>(&self, layouts: IS, explicit_bindings: bool) -> n::PipelineLayout
+    where
+        IS: IntoIterator,
+        IS::Item: Borrow<n::DescriptorSetLayout>,
+    {
+        let mut drd = n::DescRemapData::new(self.share.private_caps.input_attachment_unit_base);
+        let owned_layouts = layouts.into_iter().collect::<Vec<_>>();
+        let layouts = owned_layouts.iter().map(|layout| layout.borrow()).collect::<Vec<&n::DescriptorSetLayout>>();
+
+        // Claim the binding points of explicitly numbered bindings first,
+        // bindings colliding with them fall back to spare binding points.
+        let mut explicit = FastHashMap::default();
+        if explicit_bindings {
+            for (set, layout) in layouts.iter().enumerate() {
+                for binding in layout.iter() {
+                    let btype = match binding.ty {
+                        pso::DescriptorType::CombinedImageSampler |
+                        pso::DescriptorType::UniformTexelBuffer => n::BindingTypes::Images,
+                        pso::DescriptorType::UniformBuffer => n::BindingTypes::UniformBuffers,
+                        pso::DescriptorType::StorageBuffer => n::BindingTypes::StorageBuffers,
+                        pso::DescriptorType::StorageImage |
+                        pso::DescriptorType::StorageTexelBuffer => n::BindingTypes::StorageImages,
+                        _ => continue,
+                    };
+                    if drd.insert_explicit_binding(btype, set as _, binding.binding) {
+                        if binding.ty == pso::DescriptorType::CombinedImageSampler {
+                            drd.insert_missing_binding(binding.binding, n::BindingTypes::Samplers, set as _, binding.binding);
+                        }
+                        explicit.insert((set, binding.binding), ());
+                    } else {
+                        warn!("Binding {} of set {} collides with another set, assigning a spare binding point",
+                            binding.binding, set);
+                    }
+                }
             }
         }
-        assert_eq!(attachments_len, pass.attachments.len());
-        // attachments_len actually equals min(attachments.len(), att_points.len()) until the next assert
 
-        unsafe {
-            assert!(pass.attachments.len() <= att_points.len());
-            gl.DrawBuffers(attachments_len as _, att_points.as_ptr());
-            let status = gl.CheckFramebufferStatus(target);
-            gl.BindFramebuffer(target, 0);
+        layouts
+            .iter()
+            .enumerate()
+            .for_each(|(set, layout)| {
+                layout.iter().for_each(|binding| {
+                    // DescriptorType -> Descriptor
+                    //
+                    // Sampler -> Sampler
+                    // Image -> SampledImage, StorageImage, InputAttachment
+                    // CombinedImageSampler -> CombinedImageSampler
+                    // Buffer -> UniformBuffer, StorageBuffer
+                    // UniformTexel -> UniformTexel
+                    // StorageTexel -> StorageTexel
+
+                    assert!(!binding.immutable_samplers); //TODO: Implement immutable_samplers
+                    if explicit.contains_key(&(set, binding.binding)) {
+                        return;
+                    }
+                    use pso::DescriptorType::*;
+                    match binding.ty {
+                        CombinedImageSampler => {
+                            drd.insert_combined_binding_into_spare(set as _, binding.binding);
+                        }
+                        // Buffer views are sampled as buffer textures.
+                        UniformTexelBuffer => {
+                            drd.insert_missing_binding_into_spare(n::BindingTypes::Images, set as _, binding.binding);
+                        }
+                        Sampler | SampledImage => {
+                            // We need to figure out combos once we get the shaders, until then we
+                            // do nothing
+                        }
+                        UniformBuffer => {
+                            drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, set as _, binding.binding);
+                        }
+                        StorageBuffer => {
+                            drd.insert_missing_binding_into_spare(n::BindingTypes::StorageBuffers, set as _, binding.binding);
+                        }
+                        StorageImage | StorageTexelBuffer => {
+                            drd.insert_missing_binding_into_spare(n::BindingTypes::StorageImages, set as _, binding.binding);
+                        }
+                        InputAttachment => {
+                            // Bound to fixed texture units when entering a sub-pass,
+                            // see `n::MAX_INPUT_ATTACHMENTS`.
+                        }
+                        UniformBufferDynamic
+                        | StorageBufferDynamic => unimplemented!(), // 4
+                    }
+                })
+            });
+
+        n::PipelineLayout {
+            desc_remap_data: Arc::new(RwLock::new(drd)),
         }
-        if let Err(err) = self.share.check() {
-            //TODO: attachments have been consumed
-            panic!("Error creating FBO: {:?} for {:?}"/* with attachments {:?}"*/,
-               err, pass/*, attachments*/);
+    }
+
+    /// Create a graphics pipeline with backend specific settings,
+    /// see `PipelineSettings`.
+    pub fn create_graphics_pipeline_with_settings<'a>(
+        &self, desc: &pso::GraphicsPipelineDesc<'a, B>, settings: PipelineSettings,
+    ) -> Result<n::GraphicsPipeline, pso::CreationError> {
+        let gl = &self.share.context;
+        let share = &self.share;
+        let desc = desc.borrow();
+        let subpass = {
+            let subpass = desc.subpass;
+            match subpass.main_pass.subpasses.get(subpass.index) {
+                Some(sp) => sp,
+                None => return Err(pso::CreationError::InvalidSubpass(subpass.index)),
+            }
+        };
+        self.check_binding_limits(desc.layout)?;
+
+        // Attributes exceeding the limits would be silently dropped by the driver.
+        let max_attributes = share.limits.max_vertex_input_attributes as u32;
+        let max_bindings = share.limits.max_vertex_input_bindings as u32;
+        for attribute in &desc.attributes {
+            let end = attribute.location + conv::attribute_locations(attribute.element.format);
+            if end > max_attributes {
+                error!(
+                    "Vertex attribute at location {} ({:?}) exceeds the maximum of {} attribute locations",
+                    attribute.location, attribute.element.format, max_attributes,
+                );
+                return Err(pso::CreationError::Other);
+            }
+            if attribute.binding >= max_bindings {
+                error!(
+                    "Vertex attribute at location {} uses binding {}, exceeding the maximum of {} bindings",
+                    attribute.location, attribute.binding, max_bindings,
+                );
+                return Err(pso::CreationError::Other);
+            }
+        }
+        for vb in &desc.vertex_buffers {
+            if vb.binding >= max_bindings {
+                error!(
+                    "Vertex buffer binding {} exceeds the maximum of {} bindings",
+                    vb.binding, max_bindings,
+                );
+                return Err(pso::CreationError::Other);
+            }
         }
 
-        Ok(name)
+        let shaders = [
+            (pso::Stage::Vertex, Some(&desc.shaders.vertex)),
+            (pso::Stage::Hull, desc.shaders.hull.as_ref()),
+            (pso::Stage::Domain, desc.shaders.domain.as_ref()),
+            (pso::Stage::Geometry, desc.shaders.geometry.as_ref()),
+            (pso::Stage::Fragment, desc.shaders.fragment.as_ref()),
+        ];
+
+        let separate = share.options.get().separate_shader_objects;
+        if separate && !share.private_caps.separate_shader_objects {
+            warn!("Separate shader objects are not supported, linking a monolithic program");
+        }
+
+        let (program, stage_programs, uniforms, vertex_program) = if separate && share.private_caps.separate_shader_objects {
+            let mut programs = Vec::new();
+            let mut owned = Vec::new();
+            let mut result = Ok(());
+            for &(stage, point_maybe) in &shaders {
+                if let Some(point) = point_maybe {
+                    match self.create_separable_program(
+                        point,
+                        stage,
+                        &settings,
+                        &mut desc.layout.desc_remap_data.write().unwrap(),
+                        subpass.color_attachments.len(),
+                    ) {
+                        Ok((program, is_owned)) => {
+                            programs.push((stage, program));
+                            if is_owned {
+                                owned.push(program);
+                            }
+                        }
+                        Err(err) => {
+                            result = Err(err);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            let pipeline = result.and_then(|()| self.create_program_pipeline(&programs));
+            let pipeline = match pipeline {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    for program in owned {
+                        unsafe { gl.DeleteProgram(program) };
+                    }
+                    return Err(err);
+                }
+            };
+            let uniforms = self.query_uniforms(
+                &programs.iter().map(|&(_, program)| program).collect::<Vec<_>>(),
+                &self.push_constant_layout(shaders.iter().filter_map(|&(_, point)| point.map(|point| point.module))),
+            );
+            let vertex_program = programs
+                .iter()
+                .find(|&&(stage, _)| stage == pso::Stage::Vertex)
+                .map(|&(_, program)| program);
+            (n::BoundProgram::Pipeline(pipeline), owned, uniforms, vertex_program)
+        } else {
+            let name = unsafe { gl.CreateProgram() };
+
+            // Attach shaders to program
+            let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
+            let shader_names = &shaders
+                .iter()
+                .filter_map(|&(stage, point_maybe)| {
+                    point_maybe.map(|point| {
+                        let shader_name = self.compile_shader(
+                            point,
+                            stage,
+                            &settings,
+                            &mut desc.layout.desc_remap_data.write().unwrap(),
+                            &mut name_binding_map,
+                        );
+                        unsafe { gl.AttachShader(name, shader_name); }
+                        shader_name
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // Outputs of the last vertex processing stage are captured.
+            let last_vertex_stage = shaders
+                .iter()
+                .filter(|&&(stage, _)| stage != pso::Stage::Fragment)
+                .filter_map(|&(_, point)| point)
+                .last();
+            if let Some(point) = last_vertex_stage {
+                self.bind_transform_feedback_varyings(name, point.module);
+            }
+
+            if !share.private_caps.program_interface && share.private_caps.frag_data_location {
+                for i in 0..subpass.color_attachments.len() {
+                    let color_name = format!("Target{}\0", i);
+                    unsafe {
+                        gl.BindFragDataLocation(name, i as u32, (&color_name[..]).as_ptr() as *mut gl::types::GLchar);
+                    }
+                }
+            }
+
+            unsafe { gl.LinkProgram(name) };
+            info!("\tLinked program {}", name);
+            if let Err(err) = share.check() {
+                panic!("Error linking program: {:?}", err);
+            }
+
+            for shader_name in shader_names {
+                unsafe {
+                    gl.DetachShader(name, *shader_name);
+                    gl.DeleteShader(*shader_name);
+                }
+            }
+
+            if !self.share.legacy_features.contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER) {
+                self.assign_uniform_bindings(name, &name_binding_map);
+            }
+
+            let status = get_program_iv(gl, name, gl::LINK_STATUS);
+            let log = get_program_log(gl, name);
+            if status != 0 {
+                if !log.is_empty() {
+                    warn!("\tLog: {}", log);
+                }
+            } else {
+                return Err(pso::CreationError::Shader(d::ShaderError::CompilationFailed(log)));
+            }
+            self.check_program_storage_layout(name);
+
+            let layout = self.push_constant_layout(shaders.iter().filter_map(|&(_, point)| point.map(|point| point.module)));
+            (n::BoundProgram::Program(name), Vec::new(), self.query_uniforms(&[name], &layout), Some(name))
+        };
+
+        let patch_size = match desc.input_assembler.primitive {
+            c::Primitive::PatchList(size) => Some(size as _),
+            _ => None
+        };
+
+        let mut vertex_buffers = Vec::new();
+        for vb in &desc.vertex_buffers {
+            while vertex_buffers.len() <= vb.binding as usize {
+                vertex_buffers.push(None);
+            }
+            vertex_buffers[vb.binding as usize] = Some(*vb);
+        }
+
+        let options = share.options.get();
+        let mut smooth = (options.smooth_lines, options.smooth_polygons);
+        if smooth != (false, false) && !share.private_caps.primitive_smooth {
+            warn!("Line and polygon smoothing are not supported, consider using multisampling instead");
+            smooth = (false, false);
+        }
+
+        let depth_clamp = desc.rasterizer.depth_clamping && share.private_caps.depth_clamp;
+        if desc.rasterizer.depth_clamping && !depth_clamp {
+            warn!("Depth clamping is not supported");
+        }
+
+        let depth_bounds = if !desc.depth_stencil.depth_bounds {
+            None
+        } else if !share.private_caps.depth_bounds_test {
+            warn!("Depth bounds test is not supported");
+            None
+        } else {
+            match desc.baked_states.depth_bounds {
+                Some(ref bounds) => Some(pso::State::Static(bounds.clone())),
+                None => Some(pso::State::Dynamic),
+            }
+        };
+
+        let sample_shading = match desc.multisampling {
+            Some(pso::Multisampling { rasterization_samples, sample_shading: Some(rate), .. })
+                if rasterization_samples > 1 =>
+            {
+                if share.private_caps.sample_shading {
+                    Some(rate.max(0.0).min(1.0))
+                } else {
+                    warn!("Sample shading is not supported");
+                    None
+                }
+            }
+            // Single sampled targets shade once per fragment anyway.
+            _ => None,
+        };
+
+        let mut blend_targets = desc.blender.targets.clone();
+        if smooth != (false, false) {
+            // Smoothing writes the coverage into the alpha channel
+            // which needs to be blended into the target.
+            for target in &mut blend_targets {
+                if target.1 == pso::BlendState::Off {
+                    target.1 = pso::BlendState::ALPHA;
+                }
+            }
+        }
+
+        let mut attributes = desc.attributes
+            .iter()
+            .map(|&a| {
+                let (size, format, vertex_attrib_fn) = conv::format_to_gl_format(a.element.format).unwrap();
+                let (vertex_attrib_fn, normalized) =
+                    conv::attribute_normalization(a.element.format, vertex_attrib_fn, a.normalized);
+                n::AttributeDesc {
+                    location: a.location,
+                    offset: a.element.offset,
+                    binding: a.binding,
+                    size,
+                    format,
+                    vertex_attrib_fn,
+                    normalized,
+                    divisor: attribute_divisor(&vertex_buffers, a.binding),
+                }
+            })
+            .collect::<Vec<_>>();
+        if let Some(vertex_program) = vertex_program {
+            let active = self.query_attributes(vertex_program);
+            // Locations of GLSL shaders are taken as given, vertex inputs of SPIR-V shaders
+            // may be assigned other locations by the linker if explicit locations are unsupported.
+            if let n::ShaderModule::Spirv(ref spirv) = *desc.shaders.vertex.module {
+                let reflected = self.reflect_vertex_inputs(spirv);
+                remap_attribute_locations(&mut attributes, &active, &reflected);
+            }
+            let matrices = active
+                .iter()
+                .map(|&(_, location, atype)| (location, conv::attribute_type_columns(atype)))
+                .filter(|&(_, columns)| columns > 1)
+                .collect::<Vec<_>>();
+            attributes = expand_matrix_attributes(attributes, &matrices);
+        }
+
+        Ok(n::GraphicsPipeline {
+            program,
+            stage_programs,
+            primitive: conv::primitive_to_gl_primitive(desc.input_assembler.primitive),
+            patch_size,
+            blend_targets,
+            vertex_buffers,
+            attributes,
+            smooth,
+            depth_bias: desc.rasterizer.depth_bias,
+            depth_clamp,
+            depth_bounds,
+            depth: desc.depth_stencil.depth,
+            stencil: desc.depth_stencil.stencil,
+            uniforms,
+            sample_shading,
+            framebuffer_srgb: None,
+        })
+    }
+}
+
+impl d::Device<B> for Device {
+    fn allocate_memory(
+        &self, mem_type: c::MemoryTypeId, size: u64,
+    ) -> Result<n::Memory, d::OutOfMemory> {
+        // TODO
+        let properties = if mem_type.0 == NON_COHERENT_MEMORY_TYPE {
+            memory::Properties::CPU_VISIBLE
+        } else {
+            memory::Properties::CPU_VISIBLE | memory::Properties::CPU_CACHED | memory::Properties::COHERENT
+        };
+        Ok(n::Memory {
+            properties,
+            first_bound_buffer: Cell::new(0),
+            size,
+            mapped: Cell::new(None),
+            persistent: self.share.private_caps.buffer_storage,
+            persistent_ptr: Cell::new(ptr::null_mut()),
+        })
+    }
+
+    fn create_command_pool(
+        &self,
+        _family: QueueFamilyId,
+        flags: CommandPoolCreateFlags,
+    ) -> RawCommandPool {
+        let limits = Limits::new(&self.share.limits, &self.share.private_caps);
+        let memory = if flags.contains(CommandPoolCreateFlags::RESET_INDIVIDUAL) {
+            BufferMemory::Individual {
+                storage: FastHashMap::default(),
+                next_buffer_id: 0,
+            }
+        } else {
+            BufferMemory::Linear(OwnedBuffer::new())
+        };
+
+        // Ignoring `TRANSIENT` hint, unsure how to make use of this.
+
+        RawCommandPool {
+            limits,
+            memory: Arc::new(Mutex::new(memory)),
+        }
+    }
+
+    fn destroy_command_pool(&self, _pool: RawCommandPool) {
+        // Nothing to do
+    }
+
+    fn create_render_pass<'a, IA, IS, ID>(
+        &self, attachments: IA, subpasses: IS, _dependencies: ID
+    ) -> n::RenderPass
+    where
+        IA: IntoIterator,
+        IA::Item: Borrow<pass::Attachment>,
+        IS: IntoIterator,
+        IS::Item: Borrow<pass::SubpassDesc<'a>>,
+        ID: IntoIterator,
+        ID::Item: Borrow<pass::SubpassDependency>,
+    {
+        let subpasses =
+            subpasses
+                .into_iter()
+                .map(|subpass| convert_subpass_desc(subpass.borrow()))
+                .collect();
+
+        n::RenderPass {
+            attachments: attachments.into_iter().map(|attachment| attachment.borrow().clone()).collect::<Vec<_>>(),
+            subpasses,
+        }
+    }
+
+    fn create_pipeline_layout<IS, IR>(&self, layouts: IS, _: IR) -> n::PipelineLayout
+    where
+        IS: IntoIterator,
+        IS::Item: Borrow<n::DescriptorSetLayout>,
+        IR: IntoIterator,
+        IR::Item: Borrow<(pso::ShaderStageFlags, Range<u32>)>,
+    {
+        self.flatten_pipeline_layout(layouts, false)
+    }
+
+    fn create_graphics_pipeline<'a>(
+        &self, desc: &pso::GraphicsPipelineDesc<'a, B>
+    ) -> Result<n::GraphicsPipeline, pso::CreationError> {
+        self.create_graphics_pipeline_with_settings(desc, PipelineSettings::default())
+    }
+
+    fn create_compute_pipeline<'a>(
+        &self,
+        desc: &pso::ComputePipelineDesc<'a, B>,
+    ) -> Result<n::ComputePipeline, pso::CreationError> {
+        let gl = &self.share.context;
+        let share = &self.share;
+        self.check_binding_limits(desc.layout)?;
+
+        let program = {
+            let name = unsafe { gl.CreateProgram() };
+
+            let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
+            let shader = self.compile_shader(
+                &desc.shader,
+                pso::Stage::Compute,
+                &PipelineSettings::default(),
+                &mut desc.layout.desc_remap_data.write().unwrap(),
+                &mut name_binding_map,
+            );
+            unsafe { gl.AttachShader(name, shader) };
+
+            unsafe { gl.LinkProgram(name) };
+            info!("\tLinked program {}", name);
+            if let Err(err) = share.check() {
+                panic!("Error linking program: {:?}", err);
+            }
+
+            unsafe {
+                gl.DetachShader(name, shader);
+                gl.DeleteShader(shader);
+            }
+
+            if !self.share.legacy_features.contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER) {
+                self.assign_uniform_bindings(name, &name_binding_map);
+            }
+
+            let status = get_program_iv(gl, name, gl::LINK_STATUS);
+            let log = get_program_log(gl, name);
+            if status != 0 {
+                if !log.is_empty() {
+                    warn!("\tLog: {}", log);
+                }
+            } else {
+                return Err(pso::CreationError::Other);
+            }
+            self.check_program_storage_layout(name);
+
+            name
+        };
+        let uniforms = self.query_uniforms(&[program], &self.push_constant_layout(Some(desc.shader.module)));
+
+        Ok(n::ComputePipeline {
+            program,
+            uniforms,
+        })
+    }
+
+    fn create_framebuffer<I>(
+        &self,
+        pass: &n::RenderPass,
+        attachments: I,
+        _extent: i::Extent,
+    ) -> Result<n::FrameBuffer, d::FramebufferError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<n::ImageView>,
+    {
+        if !self.share.private_caps.framebuffer {
+            return Err(d::FramebufferError);
+        }
+
+        let gl = &self.share.context;
+        let target = gl::DRAW_FRAMEBUFFER;
+        let mut name = 0;
+        unsafe {
+            gl.GenFramebuffers(1, &mut name);
+            gl.BindFramebuffer(target, name);
+        }
+
+        let mut views = Vec::new();
+        let mut draw_buffers = Vec::new();
+        let mut resolves = Vec::new();
+        for (i, (attachment, view)) in pass.attachments.iter().zip(attachments.into_iter()).enumerate() {
+            views.push(view.borrow().clone());
+            let att_point = conv::attachment_point(i, attachment.format);
+            // Framebuffers with attachments of different sample counts are incomplete,
+            // resolve targets are attached to separate framebuffer objects.
+            if (0 .. pass.subpasses.len()).any(|subpass| pass.is_resolve_target(subpass, i)) {
+                resolves.push((att_point, view.borrow().clone()));
+                if pass.is_resolve_only(i) {
+                    continue;
+                }
+            }
+            if self.share.private_caps.framebuffer_texture {
+                Self::bind_target(gl, target, att_point, view.borrow());
+            } else {
+                Self::bind_target_compat(gl, target, att_point, view.borrow());
+            }
+            if attachment.format.map_or(true, |f| f.is_color()) {
+                draw_buffers.push(att_point);
+            }
+        }
+        assert_eq!(views.len(), pass.attachments.len());
+
+        unsafe {
+            gl.DrawBuffers(draw_buffers.len() as _, draw_buffers.as_ptr());
+            let status = gl.CheckFramebufferStatus(target);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                error!("Framebuffer {} is incomplete: {:#x}", name, status);
+            }
+            gl.BindFramebuffer(target, 0);
+        }
+
+        if !resolves.is_empty() {
+            let resolve = n::ResolveFrameBuffer {
+                attachments: resolves,
+                objects: FastHashMap::default(),
+            };
+            self.share.resolve_framebuffers.borrow_mut().insert(name, resolve);
+        }
+        self.share.framebuffer_attachments.borrow_mut().insert(name, views);
+
+        if let Err(err) = self.share.check() {
+            //TODO: attachments have been consumed
+            panic!("Error creating FBO: {:?} for {:?}"/* with attachments {:?}"*/,
+               err, pass/*, attachments*/);
+        }
+
+        Ok(name)
+    }
+
+    fn create_shader_module(
+        &self,
+        raw_data: &[u8],
+    ) -> Result<n::ShaderModule, d::ShaderError> {
+        Ok(n::ShaderModule::Spirv(raw_data.into()))
+    }
+
+    fn create_sampler(&self, info: i::SamplerInfo) -> n::FatSampler {
+        if !self.share.legacy_features.contains(LegacyFeatures::SAMPLER_OBJECTS) {
+            return n::FatSampler::Info(info);
+        }
+
+        let gl = &self.share.context;
+        let mut name = 0 as n::Sampler;
+
+        let (min, mag) = conv::filter_to_gl(info.mag_filter, info.min_filter, info.mip_filter);
+
+        unsafe {
+            gl.GenSamplers(1, &mut name);
+
+            if self.share.features.contains(c::Features::SAMPLER_ANISOTROPY) {
+                // `GL_TEXTURE_MAX_ANISOTROPY` of GL 4.6 shares the value of the EXT enum.
+                let anisotropy = clamp_anisotropy(info.anisotropic, self.share.private_caps.max_texture_anisotropy);
+                gl.SamplerParameterf(name, gl::TEXTURE_MAX_ANISOTROPY_EXT, anisotropy);
+            }
+
+            gl.SamplerParameteri(name, gl::TEXTURE_MIN_FILTER, min as GLint);
+            gl.SamplerParameteri(name, gl::TEXTURE_MAG_FILTER, mag as GLint);
+
+            let (s, t, r) = info.wrap_mode;
+            gl.SamplerParameteri(name, gl::TEXTURE_WRAP_S, wrap_to_gl(self.share.legacy_features, s) as GLint);
+            gl.SamplerParameteri(name, gl::TEXTURE_WRAP_T, wrap_to_gl(self.share.legacy_features, t) as GLint);
+            gl.SamplerParameteri(name, gl::TEXTURE_WRAP_R, wrap_to_gl(self.share.legacy_features, r) as GLint);
+
+            if self.share.legacy_features.contains(LegacyFeatures::SAMPLER_LOD_BIAS) {
+                gl.SamplerParameterf(name, gl::TEXTURE_LOD_BIAS, info.lod_bias.into());
+            }
+            if self.share.legacy_features.contains(LegacyFeatures::SAMPLER_BORDER_COLOR) {
+                let border = conv::border_color_to_gl(info.border);
+                gl.SamplerParameterfv(name, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+            }
+
+            gl.SamplerParameterf(name, gl::TEXTURE_MIN_LOD, info.lod_range.start.into());
+            gl.SamplerParameterf(name, gl::TEXTURE_MAX_LOD, info.lod_range.end.into());
+
+            let (compare_mode, compare_func) = conv::sampler_comparison_to_gl(info.comparison);
+            gl.SamplerParameteri(name, gl::TEXTURE_COMPARE_MODE, compare_mode as GLint);
+            if let Some(func) = compare_func {
+                gl.SamplerParameteri(name, gl::TEXTURE_COMPARE_FUNC, func as GLint);
+            }
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error {:?} creating sampler: {:?}", err, info)
+        }
+
+        n::FatSampler::Sampler(name)
+    }
+
+    fn create_buffer(
+        &self, size: u64, usage: buffer::Usage,
+    ) -> Result<UnboundBuffer, buffer::CreationError> {
+        if !self.share.legacy_features.contains(LegacyFeatures::CONSTANT_BUFFER) &&
+            usage.contains(buffer::Usage::UNIFORM) {
+            return Err(buffer::CreationError::UnsupportedUsage { usage });
+        }
+
+        let target = if self.share.private_caps.buffer_role_change {
+            gl::ARRAY_BUFFER
+        } else {
+            match conv::buffer_usage_to_gl_target(usage) {
+                Some(target) => target,
+                None => return Err(buffer::CreationError::UnsupportedUsage { usage }),
+            }
+        };
+
+        let gl = &self.share.context;
+        let mut name = 0;
+        unsafe {
+            gl.GenBuffers(1, &mut name);
+        }
+
+        Ok(UnboundBuffer {
+            name,
+            target,
+            requirements: memory::Requirements {
+                size,
+                alignment: buffer_alignment(&self.share.limits, usage),
+                type_mask: self.memory_type_mask(),
+            },
+        })
+    }
+
+    fn get_buffer_requirements(&self, unbound: &UnboundBuffer) -> memory::Requirements {
+        unbound.requirements
+    }
+
+    fn bind_buffer_memory(
+        &self, memory: &n::Memory, offset: u64, unbound: UnboundBuffer,
+    ) -> Result<n::Buffer, d::BindError> {
+        let gl = &self.share.context;
+        let target = unbound.target;
+
+        if offset == 0 {
+            memory.first_bound_buffer.set(unbound.name);
+        } else {
+            assert_ne!(0, memory.first_bound_buffer.get());
+        }
+
+        let cpu_can_read = memory.can_download();
+        let cpu_can_write = memory.can_upload();
+
+        if self.share.private_caps.buffer_storage {
+            // Explicit flushes are only requested when mapping. `update_buffer`
+            // writes via `glBufferSubData`, which requires dynamic storage.
+            let flags = (memory.map_flags() & !gl::MAP_FLUSH_EXPLICIT_BIT) | gl::DYNAMIC_STORAGE_BIT;
+            //TODO: use *Named calls to avoid binding
+            unsafe {
+                gl.BindBuffer(target, unbound.name);
+                gl.BufferStorage(target,
+                    unbound.requirements.size as _,
+                    ptr::null(),
+                    flags,
+                );
+                gl.BindBuffer(target, 0);
+            }
+        }
+        else {
+            let flags = if cpu_can_read && cpu_can_write {
+                gl::DYNAMIC_DRAW
+            } else if cpu_can_write {
+                gl::STREAM_DRAW
+            } else if cpu_can_read {
+                gl::STREAM_READ
+            } else {
+                gl::STATIC_DRAW
+            };
+            unsafe {
+                gl.BindBuffer(target, unbound.name);
+                gl.BufferData(target,
+                    unbound.requirements.size as _,
+                    ptr::null(),
+                    flags,
+                );
+                gl.BindBuffer(target, 0);
+            }
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error {:?} initializing buffer {:?}, memory {:?}",
+                err, unbound, memory.properties);
+        }
+
+        Ok(n::Buffer {
+            raw: unbound.name,
+            target,
+            size: unbound.requirements.size,
+            gpu_address: Mutex::new(0),
+        })
+    }
+
+    fn map_memory<R: RangeArg<u64>>(
+        &self, memory: &n::Memory, range: R
+    ) -> Result<*mut u8, mapping::Error> {
+        self.map_memory_with_access(memory, range, n::MapAccess::ReadWrite)
+    }
+
+    fn unmap_memory(&self, memory: &n::Memory) {
+        if memory.persistent {
+            // Kept mapped until the memory is freed.
+            return;
+        }
+        let gl = &self.share.context;
+        let buffer = match memory.first_bound_buffer.get() {
+            0 => panic!("No buffer has been bound yet, can't map memory!"),
+            other => other,
+        };
+        let target = gl::PIXEL_PACK_BUFFER;
+
+        state::with_pixel_buffer(gl, target, buffer, || unsafe {
+            gl.UnmapBuffer(target);
+        });
+        memory.mapped.set(None);
+
+        if let Err(err) = self.share.check() {
+            panic!("Error unmapping memory: {:?} for memory {:?}",
+                err, memory);
+        }
+    }
+
+    fn flush_mapped_memory_ranges<'a, I, R>(&self, ranges: I)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<(&'a n::Memory, R)>,
+        R: RangeArg<u64>,
+    {
+        let gl = &self.share.context;
+        let atom = self.share.limits.non_coherent_atom_size as u64;
+
+        for range in ranges {
+            let (memory, ref range) = *range.borrow();
+            if memory.map_flags() & gl::MAP_FLUSH_EXPLICIT_BIT == 0 {
+                // Coherent memory, nothing to flush.
+                continue;
+            }
+            let (start, end) = match memory.mapped.get() {
+                Some(mapped) => (*range.start().unwrap_or(&mapped.0), *range.end().unwrap_or(&mapped.1)),
+                None => (*range.start().unwrap_or(&0), *range.end().unwrap_or(&memory.size)),
+            };
+            memory.flush_range(gl, start, end.saturating_sub(start), atom);
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error flushing mapped memory: {:?}", err);
+        }
+    }
+
+    fn invalidate_mapped_memory_ranges<'a, I, R>(&self, _ranges: I)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<(&'a n::Memory, R)>,
+        R: RangeArg<u64>,
+    {
+        // Non-persistent mappings are up to date once mapped,
+        // persistent ones of coherent memory are kept up to date.
+    }
+
+    fn create_buffer_view<R: RangeArg<u64>>(
+        &self, buffer: &n::Buffer, format: Option<Format>, range: R
+    ) -> Result<n::BufferView, buffer::ViewCreationError> {
+        let gl = &self.share.context;
+        let int_format = match format.and_then(conv::buffer_view_format_to_gl) {
+            Some(int_format) if self.share.private_caps.texture_buffer => int_format,
+            _ => return Err(buffer::ViewCreationError::UnsupportedFormat { format }),
+        };
+        let format = format.unwrap();
+
+        let start = *range.start().unwrap_or(&0);
+        let end = *range.end().unwrap_or(&buffer.size);
+        if start > end || end > buffer.size {
+            error!("Buffer view range {}..{} exceeds the buffer size {}", start, end, buffer.size);
+            return Err(buffer::ViewCreationError::OutOfBounds);
+        }
+        let whole = start == 0 && end == buffer.size;
+        if !whole {
+            if !self.share.private_caps.texture_buffer_range {
+                error!("Buffer views of a sub-range require GL_ARB_texture_buffer_range");
+                return Err(buffer::ViewCreationError::UnsupportedRange);
+            }
+            let alignment = self.share.limits.min_texel_buffer_offset_alignment;
+            if start % alignment != 0 {
+                error!("Buffer view offset {} is not aligned to {}", start, alignment);
+                return Err(buffer::ViewCreationError::UnalignedOffset { offset: start });
+            }
+        }
+
+        let mut texture = 0;
+        unsafe {
+            gl.GenTextures(1, &mut texture);
+            gl.BindTexture(gl::TEXTURE_BUFFER, texture);
+            if whole {
+                gl.TexBuffer(gl::TEXTURE_BUFFER, int_format, buffer.raw);
+            } else {
+                gl.TexBufferRange(gl::TEXTURE_BUFFER, int_format, buffer.raw, start as _, (end - start) as _);
+            }
+            gl.BindTexture(gl::TEXTURE_BUFFER, 0);
+        }
+
+        if let Err(err) = self.share.check() {
+            panic!("Error creating buffer view: {:?} for format {:?}", err, format);
+        }
+
+        Ok(n::BufferView {
+            texture,
+            format: int_format,
+        })
+    }
+
+    fn create_image(
+        &self,
+        kind: i::Kind,
+        num_levels: i::Level,
+        format: Format,
+        _tiling: i::Tiling,
+        usage: i::Usage,
+        flags: i::StorageFlags,
+    ) -> Result<UnboundImage, i::CreationError> {
+        let gl = &self.share.context;
+
+        let int_format = match conv::image_format_to_gl(format) {
+            Some(int_format) => int_format,
+            None => {
+                error!("Image format {:?} is not supported", format);
+                return Err(i::CreationError::Format(format));
+            }
+        };
+
+        let channel = format.base_format().1;
+
+        let samples = kind.num_samples();
+        // The limits are bitmasks of the supported sample counts.
+        let sample_mask = if format.is_depth() || format.is_stencil() {
+            self.share.limits.framebuffer_depth_samples_count
+        } else {
+            self.share.limits.framebuffer_color_samples_count
+        };
+        if !samples.is_power_of_two() || samples & (sample_mask | 1) == 0 {
+            return Err(i::CreationError::Samples(samples));
+        }
+        match kind {
+            i::Kind::D1(_, layers) | i::Kind::D2(_, _, layers, _)
+                if layers as usize > self.share.limits.max_image_array_layers =>
+            {
+                error!("{} array layers exceed the maximum of {}", layers, self.share.limits.max_image_array_layers);
+                return Err(i::CreationError::Size(layers as _));
+            }
+            i::Kind::D3(w, h, d) if w.max(h).max(d) as usize > self.share.limits.max_image_3d_size => {
+                error!("3D image size {:?} exceeds the maximum of {}", (w, h, d), self.share.limits.max_image_3d_size);
+                return Err(i::CreationError::Size(w.max(h).max(d)));
+            }
+            _ => (),
+        }
+
+        // Single layer 2D images which are only rendered to are stored in renderbuffers.
+        let renderbuffer = match kind {
+            i::Kind::D2(_, _, 1, _) => {
+                num_levels == 1 &&
+                !usage.contains(i::Usage::STORAGE) &&
+                !usage.contains(i::Usage::SAMPLED)
+            }
+            _ => false,
+        };
+
+        let image = if !renderbuffer {
+            let cube = flags.contains(i::StorageFlags::CUBE_VIEW);
+            let target = match kind {
+                i::Kind::D2(_, _, 1, 1) => gl::TEXTURE_2D,
+                i::Kind::D2(w, h, 6, 1) if cube && w == h => gl::TEXTURE_CUBE_MAP,
+                i::Kind::D2(_, _, _, 1) => gl::TEXTURE_2D_ARRAY,
+                i::Kind::D2(_, _, 1, _) => gl::TEXTURE_2D_MULTISAMPLE,
+                i::Kind::D2(..) => gl::TEXTURE_2D_MULTISAMPLE_ARRAY,
+                i::Kind::D3(..) => gl::TEXTURE_3D,
+                i::Kind::D1(..) => {
+                    error!("1D images are not supported");
+                    return Err(i::CreationError::Kind);
+                }
+            };
+
+            let mut name = 0;
+            unsafe { gl.GenTextures(1, &mut name) };
+            unsafe { gl.BindTexture(target, name) };
+            match kind {
+                // Cube maps are allocated like 2D textures, covering all faces.
+                i::Kind::D2(w, h, _, 1) if target != gl::TEXTURE_2D_ARRAY => unsafe {
+                    gl.TexStorage2D(target, num_levels as _, int_format, w as _, h as _);
+                }
+                i::Kind::D2(w, h, layers, 1) => unsafe {
+                    gl.TexStorage3D(target, num_levels as _, int_format, w as _, h as _, layers as _);
+                }
+                i::Kind::D2(w, h, 1, samples) => unsafe {
+                    gl.TexImage2DMultisample(target, samples as _, int_format, w as _, h as _, gl::TRUE);
+                }
+                i::Kind::D2(w, h, layers, samples) => unsafe {
+                    gl.TexImage3DMultisample(
+                        target, samples as _, int_format, w as _, h as _, layers as _, gl::TRUE,
+                    );
+                }
+                i::Kind::D3(w, h, d) => unsafe {
+                    gl.TexStorage3D(target, num_levels as _, int_format, w as _, h as _, d as _);
+                }
+                i::Kind::D1(..) => unreachable!(),
+            };
+            n::ImageKind::Texture(name, target)
+        } else {
+            let mut name = 0;
+            unsafe { gl.GenRenderbuffers(1, &mut name) };
+            match kind {
+                i::Kind::D2(w, h, 1, 1) => unsafe {
+                    gl.BindRenderbuffer(gl::RENDERBUFFER, name);
+                    gl.RenderbufferStorage(gl::RENDERBUFFER, int_format, w as _, h as _);
+                }
+                i::Kind::D2(w, h, 1, samples) => unsafe {
+                    gl.BindRenderbuffer(gl::RENDERBUFFER, name);
+                    gl.RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as _, int_format, w as _, h as _);
+                }
+                _ => unreachable!(),
+            };
+            n::ImageKind::Surface(name)
+        };
+
+        let size = image_storage_size(kind, num_levels, format);
+        let bytes_per_block = (format.surface_desc().bits / 8).max(1);
+
+        if let Err(err) = self.share.check() {
+            panic!("Error creating image: {:?} for kind {:?} of {:?}",
+                err, kind, format);
+        }
+
+        Ok(UnboundImage {
+            image,
+            format,
+            channel,
+            kind,
+            num_levels,
+            requirements: memory::Requirements {
+                size,
+                alignment: bytes_per_block as _,
+                // Image contents aren't accessible through the bound memory.
+                type_mask: 0x1,
+            }
+        })
+    }
+
+    fn get_image_requirements(&self, unbound: &UnboundImage) -> memory::Requirements {
+        unbound.requirements
+    }
+
+    fn get_image_subresource_footprint(
+        &self, _image: &n::Image, _sub: i::Subresource
+    ) -> i::SubresourceFootprint {
+        unimplemented!()
+    }
+
+    fn bind_image_memory(
+        &self, _memory: &n::Memory, _offset: u64, unbound: UnboundImage
+    ) -> Result<n::Image, d::BindError> {
+        Ok(n::Image {
+            kind: unbound.image,
+            format: unbound.format,
+            channel: unbound.channel,
+            dimensions: unbound.kind,
+            num_levels: unbound.num_levels,
+        })
+    }
+
+    fn create_image_view(
+        &self,
+        image: &n::Image,
+        kind: i::ViewKind,
+        format: Format,
+        swizzle: Swizzle,
+        range: i::SubresourceRange,
+    ) -> Result<n::ImageView, i::ViewError> {
+        //TODO: check if `layers.end` covers all the layers
+        let level = range.levels.start;
+        //assert_eq!(format, image.format);
+        assert_eq!(swizzle, Swizzle::NO);
+        //TODO: check format
+        match image.kind {
+            n::ImageKind::Surface(surface) => {
+                if range.levels.start == 0 && range.layers.start == 0 {
+                    Ok(n::ImageView::Surface(surface))
+                } else if level != 0 {
+                    Err(i::ViewError::Level(level)) //TODO
+                } else {
+                    Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers)))
+                }
+            }
+            n::ImageKind::Texture(texture, target) => {
+                //TODO: check that `level` exists
+                let layered_view = match kind {
+                    i::ViewKind::D1Array | i::ViewKind::D2Array |
+                    i::ViewKind::Cube | i::ViewKind::CubeArray => true,
+                    i::ViewKind::D1 | i::ViewKind::D2 | i::ViewKind::D3 => false,
+                };
+                let num_layers = image.dimensions.num_layers();
+                // `glTextureView` requires immutable storage, which multisample
+                // textures lack. Cube maps and 3D textures have dedicated views.
+                let viewable = target == gl::TEXTURE_2D || target == gl::TEXTURE_2D_ARRAY;
+                let partial = range.levels.start != 0 || range.layers.start != 0 ||
+                    (layered_view && range.layers.end < num_layers);
+                if partial && viewable {
+                    if range.layers.start >= range.layers.end || range.layers.end > num_layers {
+                        return Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers)));
+                    }
+                    if self.share.private_caps.texture_view {
+                        // Sampling has to see the sub-range as a standalone
+                        // texture, so alias it with a new texture object.
+                        let gl = &self.share.context;
+                        let int_format = conv::image_format_to_gl(format)
+                            .or(conv::image_format_to_gl(image.format))
+                            .unwrap();
+                        let view_target = conv::view_kind_to_gl(kind);
+                        let mut name = 0;
+                        unsafe {
+                            gl.GenTextures(1, &mut name);
+                            gl.TextureView(
+                                name,
+                                view_target,
+                                texture,
+                                int_format,
+                                range.levels.start as _,
+                                (range.levels.end - range.levels.start) as _,
+                                range.layers.start as _,
+                                (range.layers.end - range.layers.start) as _,
+                            );
+                        }
+                        if let Err(err) = self.share.check() {
+                            panic!("Error creating texture view: {:?} for range {:?}", err, range);
+                        }
+                        return Ok(n::ImageView::TextureView(name, view_target));
+                    }
+                    warn!("Texture views are not supported, sampling {:?} will bind the whole texture", range);
+                }
+                if target == gl::TEXTURE_CUBE_MAP {
+                    return if range.layers.start == 0 && layered_view {
+                        Ok(n::ImageView::Cube(texture, level))
+                    } else if range.layers.start + 1 == range.layers.end {
+                        n::ImageView::cube_face(texture, level, range.layers.start)
+                    } else {
+                        Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers)))
+                    };
+                }
+                if target == gl::TEXTURE_3D {
+                    let depth = match image.dimensions {
+                        i::Kind::D3(_, _, depth) => (depth >> level).max(1),
+                        _ => 1,
+                    };
+                    return match kind {
+                        i::ViewKind::D3 if range.layers.start == 0 => Ok(n::ImageView::Texture3D(texture, level)),
+                        // Depth slices are selected through the layer range of 2D views.
+                        i::ViewKind::D2 if range.layers.start + 1 == range.layers.end =>
+                            n::ImageView::texture_3d_slice(texture, level, range.layers.start as _, depth),
+                        _ => Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers))),
+                    };
+                }
+                let whole = range.layers.start == 0 && range.layers.end >= num_layers;
+                if whole && (layered_view || !conv::is_layered_target(target)) {
+                    // Attached with `glFramebufferTexture`, covering all the layers.
+                    Ok(n::ImageView::Texture(texture, target, level))
+                } else if range.layers.start + 1 == range.layers.end {
+                    // A single layer of an array has to be attached on its own,
+                    // otherwise the framebuffer would be layered.
+                    Ok(n::ImageView::TextureLayer(texture, target, level, range.layers.start))
+                } else if layered_view {
+                    n::ImageView::texture_layer_range(texture, target, level, range.layers, num_layers)
+                } else {
+                    Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers)))
+                }
+            }
+        }
+    }
+
+    fn create_descriptor_pool<I>(&self, _: usize, _: I) -> n::DescriptorPool
+    where
+        I: IntoIterator,
+        I::Item: Borrow<pso::DescriptorRangeDesc>,
+    {
+        n::DescriptorPool { }
+    }
+
+    fn create_descriptor_set_layout<I, J>(&self, layout: I, _: J) -> n::DescriptorSetLayout
+    where
+        I: IntoIterator,
+        I::Item: Borrow<pso::DescriptorSetLayoutBinding>,
+        J: IntoIterator,
+        J::Item: Borrow<n::FatSampler>,
+    {
+        // Just return it
+        layout.into_iter().map(|l| l.borrow().clone()).collect()
+    }
+
+    fn write_descriptor_sets<'a, I, J>(&self, writes: I)
+    where
+        I: IntoIterator<Item = pso::DescriptorSetWrite<'a, B, J>>,
+        J: IntoIterator,
+        J::Item: Borrow<pso::Descriptor<'a, B>>,
+    {
+        for mut write in writes {
+            let set = &mut write.set;
+            let mut bindings = set.bindings.lock().unwrap();
+            let binding = write.binding;
+
+            let ty = set.layout
+                .iter()
+                .find(|b| b.binding == binding)
+                .map(|b| b.ty);
+            // Input attachments are taken from the framebuffer of the render pass.
+            if ty == Some(pso::DescriptorType::InputAttachment) {
+                continue;
+            }
+            let buffer_type = match ty {
+                Some(pso::DescriptorType::StorageBuffer) => n::BindingTypes::StorageBuffers,
+                _ => n::BindingTypes::UniformBuffers,
+            };
+
+            for descriptor in write.descriptors {
+                match descriptor.borrow() {
+                    pso::Descriptor::Buffer(buffer, ref range) => {
+                        let start = range.start.unwrap_or(0);
+                        let end = range.end.unwrap_or(buffer.size);
+                        let size = (end - start) as _;
+
+                        bindings
+                            .push(n::DescSetBindings::Buffer {
+                                ty: buffer_type,
+                                binding,
+                                buffer: buffer.raw,
+                                offset: start as _,
+                                size,
+                            });
+                    },
+                    pso::Descriptor::CombinedImageSampler(view, _layout, sampler) => {
+                        match combined_image_sampler_bindings(binding, view, sampler) {
+                            Some(pair) => bindings.extend_from_slice(&pair),
+                            None => error!("Image view {:?} can't be sampled with sampler {:?}",
+                                view, sampler),
+                        }
+                    }
+                    pso::Descriptor::Image(view, _layout) if ty == Some(pso::DescriptorType::StorageImage) => {
+                        let format = match **view {
+                            n::ImageView::Texture(tex, target, level)
+                            | n::ImageView::TextureLayer(tex, target, level, _)
+                            | n::ImageView::TextureLayerRange(tex, target, level, _) =>
+                                self.texture_internal_format(tex, target, level),
+                            n::ImageView::TextureView(tex, target) =>
+                                self.texture_internal_format(tex, target, 0),
+                            n::ImageView::Cube(tex, level)
+                            | n::ImageView::CubeFace(tex, level, _) =>
+                                self.texture_internal_format(tex, gl::TEXTURE_CUBE_MAP, level),
+                            n::ImageView::Texture3D(tex, level)
+                            | n::ImageView::Texture3DSlice(tex, level, _) =>
+                                self.texture_internal_format(tex, gl::TEXTURE_3D, level),
+                            n::ImageView::Surface(_) => {
+                                error!("Renderbuffer view {:?} can't be bound as storage image", view);
+                                continue;
+                            }
+                        };
+                        bindings.extend(storage_image_binding(binding, view, format));
+                    }
+                    pso::Descriptor::Image(view, _layout) => {
+                        match view_texture(view) {
+                            Some((tex, target)) =>
+                                bindings
+                                .push(n::DescSetBindings::Texture(binding, tex, target)),
+                            None => error!("Renderbuffer view {:?} can't be sampled", view),
+                        }
+                    }
+                    pso::Descriptor::Sampler(sampler) => {
+                        match sampler {
+                            n::FatSampler::Sampler(sampler) |
+                            n::FatSampler::Unnormalized(sampler) =>
+                                bindings
+                                .push(n::DescSetBindings::Sampler(binding, *sampler)),
+                            n::FatSampler::Info(ref info) =>
+                                bindings
+                                .push(n::DescSetBindings::SamplerInfo(binding, info.clone())),
+                        }
+                    }
+                    pso::Descriptor::UniformTexelBuffer(view) => {
+                        bindings
+                            .push(n::DescSetBindings::Texture(binding, view.texture, gl::TEXTURE_BUFFER));
+                    }
+                    pso::Descriptor::StorageTexelBuffer(view) => {
+                        bindings.push(n::DescSetBindings::Image {
+                            binding,
+                            texture: view.texture,
+                            level: 0,
+                            layered: false,
+                            layer: 0,
+                            format: view.format,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn copy_descriptor_sets<'a, I>(&self, copies: I)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<pso::DescriptorSetCopy<'a, B>>,
+    {
+        for _copy in copies {
+            unimplemented!()
+        }
+    }
+
+    fn create_semaphore(&self) -> n::Semaphore {
+        n::Semaphore::new()
+    }
+
+    fn create_fence(&self, signalled: bool) -> n::Fence {
+        // Signaled fences don't need a sync object, waits return immediately.
+        n::Fence::new(ptr::null(), signalled)
+    }
+
+    fn reset_fences<I>(&self, fences: I)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<n::Fence>,
+    {
+        for fence in fences {
+            reset_fence(&self.share, fence.borrow());
+        }
+    }
+
+    fn wait_for_fence(&self, fence: &n::Fence, timeout_ns: u64) -> bool {
+        if !self.share.private_caps.sync || fence.signaled.get() {
+            return true;
+        }
+        if fence.wait(&self.share.context, timeout_ns) {
+            return true;
+        }
+        if let Err(err) = self.share.check() {
+            error!("Error when waiting on fence: {:?}", err);
+        }
+        false
+    }
+
+    fn get_fence_status(&self, fence: &n::Fence) -> bool {
+        if !self.share.private_caps.sync {
+            return true;
+        }
+        fence.get_status(&self.share.context)
+    }
+
+    fn free_memory(&self, memory: n::Memory) {
+        if memory.persistent_ptr.get().is_null() {
+            return;
+        }
+        // Persistent mappings are released with their buffer if it's been destroyed already.
+        let gl = &self.share.context;
+        let buffer = memory.first_bound_buffer.get();
+        unsafe {
+            if gl.IsBuffer(buffer) == gl::TRUE {
+                state::with_pixel_buffer(gl, gl::PIXEL_PACK_BUFFER, buffer, || {
+                    gl.UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+                });
+            }
+        }
+    }
+
+    fn create_query_pool(&self, ty: query::QueryType, count: u32) -> n::QueryPool {
+        let target = match ty {
+            query::QueryType::Occlusion => gl::SAMPLES_PASSED,
+            query::QueryType::Timestamp => 0,
+            query::QueryType::PipelineStatistics(_) => {
+                // Query pools can't fail to be created, recording queries of the empty pool fails instead.
+                error!("Pipeline statistics queries are not supported");
+                return n::QueryPool { target: 0, queries: Vec::new() };
+            }
+        };
+
+        let gl = &self.share.context;
+        let mut queries = vec![0; count as usize];
+        unsafe { gl.GenQueries(count as _, queries.as_mut_ptr()) };
+        if let Err(err) = self.share.check() {
+            panic!("Error creating query pool: {:?}", err);
+        }
+
+        n::QueryPool { target, queries }
+    }
+
+    fn destroy_query_pool(&self, pool: n::QueryPool) {
+        let gl = &self.share.context;
+        unsafe { gl.DeleteQueries(pool.queries.len() as _, pool.queries.as_ptr()) };
+    }
+
+    fn destroy_shader_module(&self, _: n::ShaderModule) {
+        // Assumes compiled shaders are managed internally
+    }
+
+    fn destroy_render_pass(&self, _: n::RenderPass) {
+        // Nothing to do
+    }
+
+    fn destroy_pipeline_layout(&self, layout: n::PipelineLayout) {
+        layout.desc_remap_data.write().unwrap().clear();
+    }
+
+    fn destroy_graphics_pipeline(&self, pipeline: n::GraphicsPipeline) {
+        let gl = &self.share.context;
+        let mut invalid_storage_layouts = self.invalid_storage_layouts.lock().unwrap();
+        unsafe {
+            match pipeline.program {
+                n::BoundProgram::Program(program) => {
+                    gl.DeleteProgram(program);
+                    invalid_storage_layouts.remove(&program);
+                }
+                n::BoundProgram::Pipeline(program_pipeline) => gl.DeleteProgramPipelines(1, &program_pipeline),
+            }
+            for program in pipeline.stage_programs {
+                gl.DeleteProgram(program);
+                invalid_storage_layouts.remove(&program);
+            }
+        }
+    }
+
+    fn destroy_compute_pipeline(&self, pipeline: n::ComputePipeline) {
+        unsafe {
+            self.share.context.DeleteProgram(pipeline.program);
+        }
+        self.invalid_storage_layouts.lock().unwrap().remove(&pipeline.program);
+    }
+
+    fn destroy_framebuffer(&self, frame_buffer: n::FrameBuffer) {
+        let gl = &self.share.context;
+        unsafe { gl.DeleteFramebuffers(1, &frame_buffer); }
+        self.share.framebuffer_attachments.borrow_mut().remove(&frame_buffer);
+        if let Some(resolve) = self.share.resolve_framebuffers.borrow_mut().remove(&frame_buffer) {
+            // Objects of other contexts are released with their context.
+            if let Some(object) = resolve.objects.get(&self.share.current_window.get()) {
+                unsafe { gl.DeleteFramebuffers(1, object); }
+            }
+        }
+    }
+
+    fn destroy_buffer(&self, buffer: n::Buffer) {
+        let gl = &self.share.context;
+        unsafe {
+            if *buffer.gpu_address.lock().unwrap() != 0 {
+                gl.BindBuffer(buffer.target, buffer.raw);
+                self.share.ext.MakeBufferNonResidentNV(buffer.target);
+                gl.BindBuffer(buffer.target, 0);
+            }
+            gl.DeleteBuffers(1, &buffer.raw);
+        }
+    }
+    fn destroy_buffer_view(&self, view: n::BufferView) {
+        unsafe {
+            self.share.context.DeleteTextures(1, &view.texture);
+        }
+    }
+
+    fn destroy_image(&self, image: n::Image) {
+        let gl = &self.share.context;
+        match image.kind {
+            n::ImageKind::Surface(rb) => unsafe { gl.DeleteRenderbuffers(1, &rb) },
+            n::ImageKind::Texture(t, _) => unsafe { gl.DeleteTextures(1, &t) },
+        }
+    }
+
+    fn destroy_image_view(&self, image_view: n::ImageView) {
+        match image_view {
+            n::ImageView::TextureView(texture, _) => unsafe {
+                self.share.context.DeleteTextures(1, &texture);
+            },
+            // The other views don't own their textures.
+            _ => (),
+        }
+    }
+
+    fn destroy_sampler(&self, sampler: n::FatSampler) {
+        let gl = &self.share.context;
+        match sampler {
+            n::FatSampler::Sampler(s) |
+            n::FatSampler::Unnormalized(s) => unsafe { gl.DeleteSamplers(1, &s) },
+            _ => (),
+        }
+    }
+
+    fn destroy_descriptor_pool(&self, _: n::DescriptorPool) {
+        // Nothing to do
+    }
+
+    fn destroy_descriptor_set_layout(&self, _: n::DescriptorSetLayout) {
+        // Nothing to do
+    }
+
+    fn destroy_fence(&self, fence: n::Fence) {
+        if self.share.private_caps.sync {
+            fence.destroy(&self.share.context);
+        }
+    }
+
+    fn destroy_semaphore(&self, semaphore: n::Semaphore) {
+        if self.share.private_caps.sync {
+            semaphore.destroy(&self.share.context);
+        }
+    }
+
+    fn create_swapchain(
+        &self,
+        surface: &mut Surface,
+        config: c::SwapchainConfig,
+        _old_swapchain: Option<Swapchain>,
+        _extent: &window::Extent2D,
+    ) -> (Swapchain, c::Backbuffer<B>) {
+        self.create_swapchain_impl(surface, config)
+    }
+
+    fn destroy_swapchain(&self, swapchain: Swapchain) {
+        if let Some(target) = *swapchain.resolve_target.lock().unwrap() {
+            let gl = &self.share.context;
+            unsafe {
+                gl.DeleteRenderbuffers(1, &target.surface);
+                // The framebuffer object is released with the context of the window otherwise.
+                if swapchain.is_current() {
+                    gl.DeleteFramebuffers(1, &target.fbo);
+                }
+            }
+        }
+        self.destroy_fence(swapchain.frame_fence);
+        self.destroy_fence(swapchain.pacing_fence);
+    }
+
+    fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
+        unsafe { self.share.context.Finish(); }
+        Ok(())
+    }
+}
+
+/// Byte layout of a push constant block member.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PushConstantMember {
+    offset: u32,
+    // `0` if not an array.
+    array_stride: u32,
+    // `0` if not a matrix.
+    matrix_stride: u32,
+}
+
+/// Push constant block members keyed by the name of their uniform, e.g. `"pc.color"`.
+type PushConstantLayout = FastHashMap<String, PushConstantMember>;
+
+// Add the members of a push constant block struct to the layout, flattening nested structs.
+fn reflect_struct_members(
+    ast: &spirv::Ast<glsl::Target>,
+    type_id: u32,
+    prefix: &str,
+    base: u32,
+    layout: &mut PushConstantLayout,
+) -> Result<(), SpirvErrorCode> {
+    let member_types = match ast.get_type(type_id)? {
+        spirv::Type::Struct { member_types, .. } => member_types,
+        _ => return Ok(()),
+    };
+    for (index, &member_type) in member_types.iter().enumerate() {
+        let index = index as u32;
+        let name = match ast.get_member_name(type_id, index)? {
+            ref name if name.is_empty() => format!("{}._m{}", prefix, index),
+            name => format!("{}.{}", prefix, name),
+        };
+        let offset = base + ast.get_member_decoration(type_id, index, spirv::Decoration::Offset)?;
+        match ast.get_type(member_type)? {
+            spirv::Type::Struct { ref array, .. } if array.is_empty() => {
+                reflect_struct_members(ast, member_type, &name, offset, layout)?;
+            }
+            spirv::Type::Struct { .. } => {
+                warn!("Arrays of structs in push constant blocks are not supported: {}", name);
+            }
+            _ => {
+                let member = PushConstantMember {
+                    offset,
+                    array_stride: ast.get_decoration(member_type, spirv::Decoration::ArrayStride)?,
+                    matrix_stride: ast.get_member_decoration(type_id, index, spirv::Decoration::MatrixStride)?,
+                };
+                layout.insert(name, member);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Place a tightly packed uniform at the offset and strides of its push constant block member.
+fn layout_push_constant_uniform(mut uniform: n::UniformDesc, member: &PushConstantMember) -> n::UniformDesc {
+    let columns = conv::attribute_type_columns(uniform.utype);
+    let column_size = uniform.matrix_stride;
+    if member.matrix_stride != 0 {
+        uniform.matrix_stride = member.matrix_stride;
+    }
+    let element_size = uniform.matrix_stride * (columns - 1) + column_size;
+    uniform.array_stride = if member.array_stride != 0 { member.array_stride } else { element_size };
+    uniform.offset = member.offset;
+    uniform.size = uniform.array_stride * (uniform.count as u32 - 1) + element_size;
+    uniform
+}
+
+fn convert_subpass_desc(subpass: &pass::SubpassDesc) -> n::SubpassDesc {
+    n::SubpassDesc {
+        color_attachments: subpass.colors.iter().map(|&(index, _)| index).collect(),
+        input_attachments: subpass.inputs.iter().map(|&(index, _)| index).collect(),
+        resolve_attachments: subpass.resolves.iter().map(|&(index, _)| index).collect(),
+        preserve_attachments: subpass.preserves.to_vec(),
+    }
+}
+
+/// Remap attribute locations to the locations assigned by the linker
+/// to vertex inputs without explicit location.
+///
+/// `active` holds the name, location and type of the active attributes and
+/// `reflected` the SPIR-V locations of the vertex inputs by name. Attributes
+/// of matrix columns keep their column offset to the matrix location.
+fn remap_attribute_locations(
+    attributes: &mut [n::AttributeDesc],
+    active: &[(String, GLuint, GLenum)],
+    reflected: &FastHashMap<String, GLuint>,
+) {
+    let remap = active
+        .iter()
+        .filter_map(|&(ref name, location, atype)| {
+            reflected
+                .get(name)
+                .map(|&spirv_location| (spirv_location, location, conv::attribute_type_columns(atype)))
+        })
+        .collect::<Vec<_>>();
+
+    for attribute in attributes {
+        let target = remap
+            .iter()
+            .find(|&&(spirv_location, _, columns)| {
+                attribute.location >= spirv_location && attribute.location < spirv_location + columns
+            })
+            .map(|&(spirv_location, location, _)| location + attribute.location - spirv_location);
+        match target {
+            Some(location) if location != attribute.location => {
+                debug!("Remapping vertex attribute location {} to {}", attribute.location, location);
+                attribute.location = location;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Instance divisor of attributes read from the vertex buffer at `binding`,
+/// the input rate of the buffer, `0` if no buffer is described.
+fn attribute_divisor(vertex_buffers: &[Option<pso::VertexBufferDesc>], binding: u32) -> GLuint {
+    vertex_buffers
+        .get(binding as usize)
+        .and_then(|vb| vb.as_ref())
+        .map_or(0, |vb| vb.rate as GLuint)
+}
+
+/// Expand attributes bound to matrix inputs into one attribute per column.
+///
+/// `matrices` holds the location and number of columns of the matrix inputs.
+/// The columns are read from consecutive locations, each column is stored
+/// directly after the previous one in the vertex buffer. Matrices already
+/// described by an attribute per column are left alone.
+fn expand_matrix_attributes(attributes: Vec<n::AttributeDesc>, matrices: &[(GLuint, u32)]) -> Vec<n::AttributeDesc> {
+    let columns = attributes
+        .iter()
+        .map(|attribute| {
+            let columns = matrices
+                .iter()
+                .find(|&&(location, _)| location == attribute.location)
+                .map_or(1, |&(_, columns)| columns);
+            let split = attributes
+                .iter()
+                .any(|a| a.location > attribute.location && a.location < attribute.location + columns);
+            if split { 1 } else { columns }
+        })
+        .collect::<Vec<_>>();
+
+    let mut expanded = Vec::with_capacity(attributes.len());
+    for (attribute, columns) in attributes.into_iter().zip(columns) {
+        let stride = attribute.size as u32 * conv::attribute_component_size(attribute.format);
+        for column in 0 .. columns {
+            expanded.push(n::AttributeDesc {
+                location: attribute.location + column,
+                offset: attribute.offset + column * stride,
+                .. attribute
+            });
+        }
     }
+    expanded
+}
 
-    fn create_shader_module(
-        &self,
-        raw_data: &[u8],
-    ) -> Result<n::ShaderModule, d::ShaderError> {
-        Ok(n::ShaderModule::Spirv(raw_data.into()))
+/// Convert a wrap mode, clamping to the edge if border colors aren't supported.
+pub(crate) fn wrap_to_gl(legacy_features: LegacyFeatures, mode: i::WrapMode) -> GLenum {
+    if mode == i::WrapMode::Border && !legacy_features.contains(LegacyFeatures::SAMPLER_BORDER_COLOR) {
+        warn!("Border wrap mode is not supported, clamping to the edge instead");
+        return gl::CLAMP_TO_EDGE;
     }
+    conv::wrap_to_gl(mode)
+}
 
-    fn create_sampler(&self, info: i::SamplerInfo) -> n::FatSampler {
-        if !self.share.legacy_features.contains(LegacyFeatures::SAMPLER_OBJECTS) {
-            return n::FatSampler::Info(info);
-        }
+/// Texture and target sampled through an image view.
+///
+/// Returns `None` for renderbuffers, which can't be sampled.
+fn view_texture(view: &n::ImageView) -> Option<(n::Texture, n::TextureTarget)> {
+    match *view {
+        n::ImageView::Texture(tex, target, _)
+        | n::ImageView::TextureLayer(tex, target, _, _)
+        | n::ImageView::TextureLayerRange(tex, target, _, _)
+        | n::ImageView::TextureView(tex, target) => Some((tex, target)),
+        n::ImageView::Cube(tex, _)
+        | n::ImageView::CubeFace(tex, _, _) => Some((tex, gl::TEXTURE_CUBE_MAP)),
+        n::ImageView::Texture3D(tex, _)
+        | n::ImageView::Texture3DSlice(tex, _, _) => Some((tex, gl::TEXTURE_3D)),
+        n::ImageView::Surface(_) => None,
+    }
+}
 
-        let gl = &self.share.context;
-        let mut name = 0 as n::Sampler;
+/// Texture and sampler bindings of a combined image sampler,
+/// bound to the same texture unit.
+///
+/// Returns `None` for renderbuffers or if only one of the texture and
+/// the sampler uses unnormalized coordinates.
+fn combined_image_sampler_bindings(
+    binding: pso::DescriptorBinding,
+    view: &n::ImageView,
+    sampler: &n::FatSampler,
+) -> Option<[n::DescSetBindings; 2]> {
+    let (tex, target) = view_texture(view)?;
+    let rectangle = target == gl::TEXTURE_RECTANGLE;
+    let sampler = match *sampler {
+        n::FatSampler::Sampler(sampler) if !rectangle => n::DescSetBindings::Sampler(binding, sampler),
+        n::FatSampler::Unnormalized(sampler) if rectangle => n::DescSetBindings::Sampler(binding, sampler),
+        n::FatSampler::Sampler(_) | n::FatSampler::Unnormalized(_) => return None,
+        // Without sampler objects the sampler state is applied to the texture when binding.
+        n::FatSampler::Info(ref info) => n::DescSetBindings::SamplerInfo(binding, info.clone()),
+    };
+    Some([
+        n::DescSetBindings::Texture(binding, tex, target),
+        sampler,
+    ])
+}
 
-        let (min, mag) = conv::filter_to_gl(info.mag_filter, info.min_filter, info.mip_filter);
+/// Check the requirements of unnormalized coordinates on a sampler.
+fn validate_unnormalized_sampler(info: &i::SamplerInfo) -> Result<(), UnnormalizedSamplerError> {
+    let (min_lod, max_lod): (f32, f32) = (info.lod_range.start.into(), info.lod_range.end.into());
+    if min_lod != 0.0 || max_lod != 0.0 {
+        return Err(UnnormalizedSamplerError::Mipmapping);
+    }
+    let (s, t, r) = info.wrap_mode;
+    for &mode in &[s, t, r] {
+        match mode {
+            i::WrapMode::Clamp | i::WrapMode::Border => (),
+            i::WrapMode::Tile | i::WrapMode::Mirror => return Err(UnnormalizedSamplerError::Wrap(mode)),
+        }
+    }
+    Ok(())
+}
 
-        unsafe {
-            gl.GenSamplers(1, &mut name);
+/// Image unit binding of a storage image view for loads and stores.
+///
+/// Views of whole array, cube and 3D textures bind all layers.
+/// Returns `None` for renderbuffers, which can't be bound to image units.
+fn storage_image_binding(
+    binding: pso::DescriptorBinding,
+    view: &n::ImageView,
+    format: GLenum,
+) -> Option<n::DescSetBindings> {
+    let (texture, level, layered, layer) = match *view {
+        n::ImageView::Texture(tex, target, level) => (tex, level, conv::is_layered_target(target), 0),
+        n::ImageView::TextureLayer(tex, _, level, layer) => (tex, level, false, layer),
+        // Image units bind either a single or all layers.
+        n::ImageView::TextureLayerRange(tex, _, level, _) => (tex, level, true, 0),
+        n::ImageView::TextureView(tex, target) => (tex, 0, conv::is_layered_target(target), 0),
+        n::ImageView::Cube(tex, level) => (tex, level, true, 0),
+        n::ImageView::CubeFace(tex, level, face) => (tex, level, false, face as i::Layer),
+        n::ImageView::Texture3D(tex, level) => (tex, level, true, 0),
+        n::ImageView::Texture3DSlice(tex, level, slice) => (tex, level, false, slice as i::Layer),
+        n::ImageView::Surface(_) => return None,
+    };
+    Some(n::DescSetBindings::Image {
+        binding,
+        texture,
+        level,
+        layered,
+        layer,
+        format,
+    })
+}
 
-            match info.anisotropic {
-                i::Anisotropic::On(fac) if fac > 1 => {
-                    if self.share.private_caps.sampler_anisotropy_ext {
-                        gl.SamplerParameterf(name, gl::TEXTURE_MAX_ANISOTROPY_EXT, fac as GLfloat);
-                    } else if self.share.features.contains(c::Features::SAMPLER_ANISOTROPY) {
-                        // TODO: Uncomment once `gfx_gl` supports GL 4.6
-                        // gl.SamplerParameterf(name, gl::TEXTURE_MAX_ANISOTROPY, fac as GLfloat);
-                    }
-                }
-                _ => ()
-            }
+/// Alignment of buffers with the given usage, required for binding
+/// their memory with offsets.
+fn buffer_alignment(limits: &c::Limits, usage: buffer::Usage) -> u64 {
+    let mut alignment = 1;
+    if usage.contains(buffer::Usage::UNIFORM) {
+        alignment = alignment.max(limits.min_uniform_buffer_offset_alignment);
+    }
+    if usage.contains(buffer::Usage::STORAGE) {
+        alignment = alignment.max(limits.min_storage_buffer_offset_alignment);
+    }
+    if usage.intersects(buffer::Usage::UNIFORM_TEXEL | buffer::Usage::STORAGE_TEXEL) {
+        alignment = alignment.max(limits.min_texel_buffer_offset_alignment);
+    }
+    alignment
+}
 
-            gl.SamplerParameteri(name, gl::TEXTURE_MIN_FILTER, min as GLint);
-            gl.SamplerParameteri(name, gl::TEXTURE_MAG_FILTER, mag as GLint);
+/// Size of an image including all mip levels, array layers and samples.
+///
+/// Rows are tightly packed, matching the pixel pack and unpack alignment of 1.
+fn image_storage_size(kind: i::Kind, num_levels: i::Level, format: Format) -> u64 {
+    let desc = format.surface_desc();
+    let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+    let bytes_per_block = (desc.bits / 8) as u64;
+    let samples = match kind {
+        i::Kind::D2(_, _, _, samples) => samples as u64,
+        _ => 1,
+    };
 
-            let (s, t, r) = info.wrap_mode;
-            gl.SamplerParameteri(name, gl::TEXTURE_WRAP_S, conv::wrap_to_gl(s) as GLint);
-            gl.SamplerParameteri(name, gl::TEXTURE_WRAP_T, conv::wrap_to_gl(t) as GLint);
-            gl.SamplerParameteri(name, gl::TEXTURE_WRAP_R, conv::wrap_to_gl(r) as GLint);
+    let level_size = (0 .. num_levels)
+        .map(|level| {
+            let extent = kind.level_extent(level);
+            let blocks_x = (extent.width + block_width - 1) / block_width;
+            let blocks_y = (extent.height + block_height - 1) / block_height;
+            blocks_x as u64 * blocks_y as u64 * extent.depth as u64 * bytes_per_block
+        })
+        .sum::<u64>();
+    level_size * kind.num_layers() as u64 * samples
+}
 
-            if self.share.legacy_features.contains(LegacyFeatures::SAMPLER_LOD_BIAS) {
-                gl.SamplerParameterf(name, gl::TEXTURE_LOD_BIAS, info.lod_bias.into());
-            }
-            if self.share.legacy_features.contains(LegacyFeatures::SAMPLER_BORDER_COLOR) {
-                let border: [f32; 4] = info.border.into();
-                gl.SamplerParameterfv(name, gl::TEXTURE_BORDER_COLOR, &border[0]);
-            }
+/// Insert `#define` directives after the `#version` directive of a GLSL source,
+/// or at the start if there is none.
+fn inject_glsl_defines(source: &str, defines: &[(&str, &str)]) -> String {
+    let mut start = 0;
+    let mut insert_at = 0;
+    while start < source.len() {
+        let end = source[start ..].find('\n').map_or(source.len(), |i| start + i + 1);
+        let line = source[start .. end].trim_start();
+        if line.starts_with('#') && line[1 ..].trim_start().starts_with("version") {
+            insert_at = end;
+            break;
+        }
+        start = end;
+    }
 
-            gl.SamplerParameterf(name, gl::TEXTURE_MIN_LOD, info.lod_range.start.into());
-            gl.SamplerParameterf(name, gl::TEXTURE_MAX_LOD, info.lod_range.end.into());
+    let mut result = String::with_capacity(source.len());
+    result.push_str(&source[.. insert_at]);
+    if insert_at != 0 && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    for &(name, value) in defines {
+        result.push_str(&format!("#define {} {}\n", name, value));
+    }
+    result.push_str(&source[insert_at ..]);
+    result
+}
 
-            match info.comparison {
-                None => gl.SamplerParameteri(name, gl::TEXTURE_COMPARE_MODE, gl::NONE as GLint),
-                Some(cmp) => {
-                    gl.SamplerParameteri(name, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
-                    gl.SamplerParameteri(name, gl::TEXTURE_COMPARE_FUNC, state::map_comparison(cmp) as GLint);
-                }
-            }
+// Emulate the alpha test by wrapping the entry point of a fragment shader,
+// discarding the fragment if the alpha of `output` fails the comparison.
+fn inject_alpha_test(source: &str, output: &str, comparison: pso::Comparison, reference: f32) -> String {
+    use pso::Comparison::*;
+    let condition = match comparison {
+        Never => "true".to_string(),
+        Always => return source.to_string(),
+        Less => format!("!({}.a < {:?})", output, reference),
+        Equal => format!("!({}.a == {:?})", output, reference),
+        LessEqual => format!("!({}.a <= {:?})", output, reference),
+        Greater => format!("!({}.a > {:?})", output, reference),
+        NotEqual => format!("!({}.a != {:?})", output, reference),
+        GreaterEqual => format!("!({}.a >= {:?})", output, reference),
+    };
+    let main = match find_entry_point(source) {
+        Some(main) => main,
+        None => {
+            warn!("Entry point of the fragment shader not found, skipping the alpha test");
+            return source.to_string();
         }
+    };
+    let mut result = String::with_capacity(source.len());
+    result.push_str(&source[.. main.start]);
+    result.push_str("alpha_test_main");
+    result.push_str(&source[main.end ..]);
+    result.push_str(&format!(
+        "\nvoid main()\n{{\n    alpha_test_main();\n    if ({})\n    {{\n        discard;\n    }}\n}}\n",
+        condition,
+    ));
+    result
+}
 
-        if let Err(err) = self.share.check() {
-            panic!("Error {:?} creating sampler: {:?}", err, info)
+// Byte range of the `main` identifier in the definition of the entry point,
+// allowing any whitespace between `void`, `main` and the parameter list.
+fn find_entry_point(source: &str) -> Option<Range<usize>> {
+    let bytes = source.as_bytes();
+    let is_identifier = |c: u8| c == b'_' || c.is_ascii_alphanumeric();
+    let mut from = 0;
+    while let Some(i) = source[from ..].find("main") {
+        let (start, end) = (from + i, from + i + 4);
+        from = end;
+        if (start > 0 && is_identifier(bytes[start - 1])) || (end < bytes.len() && is_identifier(bytes[end])) {
+            continue;
+        }
+        let before = source[.. start].trim_end();
+        let void_start = match before.len().checked_sub(4) {
+            Some(void_start) if before.ends_with("void") => void_start,
+            _ => continue,
+        };
+        if void_start > 0 && is_identifier(bytes[void_start - 1]) {
+            continue;
         }
+        if source[end ..].trim_start().starts_with('(') {
+            return Some(start .. end);
+        }
+    }
+    None
+}
 
-        n::FatSampler::Sampler(name)
+// Force early fragment tests in a fragment shader, unless already declared.
+// The layout is declared after the leading preprocessor directives.
+fn inject_early_fragment_tests(source: &str, extension: bool) -> String {
+    if source.contains("early_fragment_tests") {
+        return source.to_string();
     }
+    let insert_at = directives_end(source);
 
-    fn create_buffer(
-        &self, size: u64, usage: buffer::Usage,
-    ) -> Result<UnboundBuffer, buffer::CreationError> {
-        if !self.share.legacy_features.contains(LegacyFeatures::CONSTANT_BUFFER) &&
-            usage.contains(buffer::Usage::UNIFORM) {
-            return Err(buffer::CreationError::UnsupportedUsage { usage });
-        }
+    let mut result = String::with_capacity(source.len());
+    result.push_str(&source[.. insert_at]);
+    if insert_at != 0 && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    if extension {
+        result.push_str("#extension GL_ARB_shader_image_load_store : require\n");
+    }
+    result.push_str("layout(early_fragment_tests) in;\n");
+    result.push_str(&source[insert_at ..]);
+    result
+}
 
-        let target = if self.share.private_caps.buffer_role_change {
-            gl::ARRAY_BUFFER
-        } else {
-            match conv::buffer_usage_to_gl_target(usage) {
-                Some(target) => target,
-                None => return Err(buffer::CreationError::UnsupportedUsage { usage }),
-            }
-        };
+// Redeclare the `gl_PerVertex` blocks of a stage linked into a separable program,
+// which core profiles require for using the built-in outputs across program pipelines.
+// Clip distances are only declared if used, sources declaring the blocks are kept.
+fn inject_per_vertex(source: &str, stage: pso::Stage) -> String {
+    if source.contains("gl_PerVertex") {
+        return source.to_string();
+    }
+    let mut members = "{\n    vec4 gl_Position;\n    float gl_PointSize;\n".to_string();
+    if source.contains("gl_ClipDistance") {
+        members.push_str("    float gl_ClipDistance[];\n");
+    }
+    members.push('}');
+    let blocks = match stage {
+        pso::Stage::Vertex => format!("out gl_PerVertex\n{};\n", members),
+        pso::Stage::Hull => format!(
+            "in gl_PerVertex\n{} gl_in[gl_MaxPatchVertices];\nout gl_PerVertex\n{} gl_out[];\n",
+            members, members,
+        ),
+        pso::Stage::Domain => format!(
+            "in gl_PerVertex\n{} gl_in[gl_MaxPatchVertices];\nout gl_PerVertex\n{};\n",
+            members, members,
+        ),
+        pso::Stage::Geometry => format!(
+            "in gl_PerVertex\n{} gl_in[];\nout gl_PerVertex\n{};\n",
+            members, members,
+        ),
+        pso::Stage::Fragment | pso::Stage::Compute => return source.to_string(),
+    };
+    let insert_at = directives_end(source);
 
-        let gl = &self.share.context;
-        let mut name = 0;
-        unsafe {
-            gl.GenBuffers(1, &mut name);
+    let mut result = String::with_capacity(source.len() + blocks.len());
+    result.push_str(&source[.. insert_at]);
+    if insert_at != 0 && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(&blocks);
+    result.push_str(&source[insert_at ..]);
+    result
+}
+
+// Offset after the leading preprocessor directives of a GLSL source,
+// which include the version and extension directives.
+fn directives_end(source: &str) -> usize {
+    let mut start = 0;
+    let mut insert_at = 0;
+    while start < source.len() {
+        let end = source[start ..].find('\n').map_or(source.len(), |i| start + i + 1);
+        let line = source[start .. end].trim();
+        if line.starts_with('#') {
+            insert_at = end;
+        } else if !line.is_empty() && !line.starts_with("//") {
+            break;
         }
+        start = end;
+    }
+    insert_at
+}
 
-        Ok(UnboundBuffer {
-            name,
-            target,
-            requirements: memory::Requirements {
-                size,
-                alignment: 1, // TODO: do we need specific alignment for any use-case?
-                type_mask: 0x7,
-            },
-        })
+/// Degree of anisotropic filtering, clamped to the supported maximum.
+pub(crate) fn clamp_anisotropy(anisotropic: i::Anisotropic, max: GLfloat) -> GLfloat {
+    match anisotropic {
+        i::Anisotropic::On(degree) => (degree as GLfloat).min(max).max(1.0),
+        i::Anisotropic::Off => 1.0,
     }
+}
 
-    fn get_buffer_requirements(&self, unbound: &UnboundBuffer) -> memory::Requirements {
-        unbound.requirements
+/// Texture and target to generate the mip levels of an image with, if it has any.
+fn mipmap_target(image: &n::Image) -> Option<(n::Texture, GLenum)> {
+    match image.kind {
+        n::ImageKind::Texture(_, gl::TEXTURE_2D_MULTISAMPLE) |
+        n::ImageKind::Texture(_, gl::TEXTURE_2D_MULTISAMPLE_ARRAY) |
+        n::ImageKind::Surface(_) => None,
+        n::ImageKind::Texture(texture, target) => Some((texture, target)),
     }
+}
 
-    fn bind_buffer_memory(
-        &self, memory: &n::Memory, offset: u64, unbound: UnboundBuffer,
-    ) -> Result<n::Buffer, d::BindError> {
-        let gl = &self.share.context;
-        let target = unbound.target;
+/// Return the fence to the unsignaled state, deleting its sync object.
+fn reset_fence(share: &Share, fence: &n::Fence) {
+    if share.private_caps.sync {
+        fence.reset(&share.context);
+    } else {
+        fence.signaled.set(false);
+    }
+}
 
-        if offset == 0 {
-            memory.first_bound_buffer.set(unbound.name);
-        } else {
-            assert_ne!(0, memory.first_bound_buffer.get());
+#[cfg(test)]
+mod tests {
+    use super::{
+        attribute_divisor, buffer_alignment, clamp_anisotropy,
+        combined_image_sampler_bindings, expand_matrix_attributes, image_storage_size,
+        inject_alpha_test, inject_early_fragment_tests, inject_glsl_defines, inject_per_vertex,
+        mipmap_target, remap_attribute_locations, storage_image_binding,
+        validate_unnormalized_sampler, Device, UnnormalizedSamplerError,
+    };
+    use ext;
+    use gl;
+    use hal;
+    use hal::{buffer, format, image as i, pass, pso, Limits};
+    use native as n;
+    use queue::CommandQueue;
+    use std::ptr;
+    use std::sync::atomic::Ordering;
+    use test_utils;
+
+    // Input attachment units of a context with the minimum of 32 combined texture units (GLES 3.0).
+    const INPUT_ATTACHMENT_UNIT_BASE: u32 = 32 - n::MAX_INPUT_ATTACHMENTS;
+
+    // Attribute of a `vec4` of floats in vertex buffer 0.
+    fn vec4_attribute(location: u32, offset: u32) -> n::AttributeDesc {
+        n::AttributeDesc {
+            location,
+            offset,
+            binding: 0,
+            size: 4,
+            format: gl::FLOAT,
+            vertex_attrib_fn: n::VertexAttribFunction::Float,
+            normalized: false,
+            divisor: 0,
         }
+    }
 
-        let cpu_can_read = memory.can_download();
-        let cpu_can_write = memory.can_upload();
+    #[test]
+    fn test_input_attachment_bindings() {
+        let render_pass = n::RenderPass::fixture(&[1, 1], &[
+            (&[0], &[], &[], &[]),
+            (&[1], &[0], &[], &[]),
+        ]);
+
+        assert!(render_pass.input_attachment_bindings(0, INPUT_ATTACHMENT_UNIT_BASE).is_empty());
+        assert_eq!(
+            render_pass.input_attachment_bindings(1, INPUT_ATTACHMENT_UNIT_BASE),
+            vec![(INPUT_ATTACHMENT_UNIT_BASE, 0)],
+        );
+        assert!(render_pass.subpasses[1].is_using(0));
+    }
 
-        if self.share.private_caps.buffer_storage {
-            //TODO: gl::DYNAMIC_STORAGE_BIT | gl::MAP_PERSISTENT_BIT
-            let flags = memory.map_flags();
-            //TODO: use *Named calls to avoid binding
-            unsafe {
-                gl.BindBuffer(target, unbound.name);
-                gl.BufferStorage(target,
-                    unbound.requirements.size as _,
-                    ptr::null(),
-                    flags,
-                );
-                gl.BindBuffer(target, 0);
-            }
-        }
-        else {
-            let flags = if cpu_can_read && cpu_can_write {
-                gl::DYNAMIC_DRAW
-            } else if cpu_can_write {
-                gl::STREAM_DRAW
-            } else if cpu_can_read {
-                gl::STREAM_READ
-            } else {
-                gl::STATIC_DRAW
-            };
-            unsafe {
-                gl.BindBuffer(target, unbound.name);
-                gl.BufferData(target,
-                    unbound.requirements.size as _,
-                    ptr::null(),
-                    flags,
-                );
-                gl.BindBuffer(target, 0);
-            }
-        }
+    #[test]
+    fn test_resolve_blits() {
+        let render_pass = n::RenderPass::fixture(&[4, 1, 1], &[
+            (&[0], &[], &[1], &[]),
+            (&[0], &[], &[n::ATTACHMENT_UNUSED], &[]),
+            (&[2], &[], &[], &[]),
+        ]);
+
+        assert_eq!(render_pass.resolve_blits(0), vec![(0, 1)]);
+        assert!(render_pass.resolve_blits(1).is_empty());
+        assert!(render_pass.is_resolve_target(0, 1));
+        assert!(!render_pass.is_resolve_target(1, 1));
+        assert!(!render_pass.is_resolve_target(0, 0));
+        assert!(render_pass.is_resolve_only(1));
+    }
 
-        if let Err(err) = self.share.check() {
-            panic!("Error {:?} initializing buffer {:?}, memory {:?}",
-                err, unbound, memory.properties);
-        }
+    #[test]
+    fn test_resolve_target_used_by_later_subpass() {
+        // The resolve target of the first sub-pass is rendered to by the second one.
+        let render_pass = n::RenderPass::fixture(&[4, 1], &[
+            (&[0], &[], &[1], &[]),
+            (&[1], &[], &[], &[]),
+        ]);
+
+        assert!(render_pass.is_resolve_target(0, 1));
+        assert!(!render_pass.is_resolve_target(1, 1));
+        assert!(!render_pass.is_resolve_only(1));
+    }
 
-        Ok(n::Buffer {
-            raw: unbound.name,
-            target,
-            size: unbound.requirements.size,
-        })
+    #[test]
+    fn test_inject_glsl_defines() {
+        let defines = [("USE_FOG", "1"), ("LIGHTS", "4")];
+        assert_eq!(
+            inject_glsl_defines("#version 330 core\nvoid main() {}\n", &defines),
+            "#version 330 core\n#define USE_FOG 1\n#define LIGHTS 4\nvoid main() {}\n",
+        );
+        // Comments may precede the version directive.
+        assert_eq!(
+            inject_glsl_defines("// shader\n  # version 300 es", &defines[.. 1]),
+            "// shader\n  # version 300 es\n#define USE_FOG 1\n",
+        );
+        assert_eq!(
+            inject_glsl_defines("void main() {}\n", &defines[.. 1]),
+            "#define USE_FOG 1\nvoid main() {}\n",
+        );
     }
 
-    fn map_memory<R: RangeArg<u64>>(
-        &self, memory: &n::Memory, range: R
-    ) -> Result<*mut u8, mapping::Error> {
-        let gl = &self.share.context;
-        let buffer = match memory.first_bound_buffer.get() {
-            0 => panic!("No buffer has been bound yet, can't map memory!"),
-            other => other,
-        };
+    #[test]
+    fn test_discarded_attachments() {
+        let render_pass = n::RenderPass::fixture(&[1, 1, 1], &[
+            (&[0, 1], &[], &[], &[]),
+            (&[2], &[], &[], &[1]),
+        ]);
 
-        assert!(self.share.private_caps.buffer_role_change);
-        let target = gl::PIXEL_PACK_BUFFER;
-        let access = memory.map_flags();
+        // The preserved attachment 1 must not be invalidated.
+        assert_eq!(render_pass.discarded_attachments(0), vec![0]);
+        assert_eq!(render_pass.discarded_attachments(1), vec![2]);
+    }
 
-        let offset = *range.start().unwrap_or(&0);
-        let size = *range.end().unwrap_or(&memory.size) - offset;
+    #[test]
+    fn test_max_texture_descriptors() {
+        test_utils::set_integer(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS, 48);
+        let adapter = test_utils::adapter();
+        // The last units are reserved for input attachments.
+        assert_eq!(adapter.physical_device.max_texture_descriptors(), 48 - n::MAX_INPUT_ATTACHMENTS as usize);
+        assert_eq!(adapter.physical_device.0.private_caps.input_attachment_unit_base, 40);
+    }
+
+    #[test]
+    fn test_input_attachment_units_reserved() {
+        let mut drd = n::DescRemapData::new(INPUT_ATTACHMENT_UNIT_BASE);
+        let units = (0 .. INPUT_ATTACHMENT_UNIT_BASE)
+            .map(|binding| drd.insert_combined_binding_into_spare(0, binding))
+            .collect::<Vec<_>>();
+        // Spare texture units end at the units of input attachments.
+        assert_eq!(units, (0 .. INPUT_ATTACHMENT_UNIT_BASE).collect::<Vec<_>>());
+        assert!(!drd.insert_explicit_binding(n::BindingTypes::Images, 1, INPUT_ATTACHMENT_UNIT_BASE));
+        assert!(!drd.insert_explicit_binding(n::BindingTypes::Samplers, 1, INPUT_ATTACHMENT_UNIT_BASE + n::MAX_INPUT_ATTACHMENTS));
+        assert!(drd.insert_explicit_binding(n::BindingTypes::UniformBuffers, 1, INPUT_ATTACHMENT_UNIT_BASE));
+    }
+
+    #[test]
+    fn test_storage_buffer_bindings() {
+        let mut drd = n::DescRemapData::new(INPUT_ATTACHMENT_UNIT_BASE);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 0, 0);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::StorageBuffers, 0, 1);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::StorageBuffers, 1, 1);
+
+        // Storage buffers use their own binding points, independent of uniform buffers.
+        assert_eq!(drd.get_binding(n::BindingTypes::StorageBuffers, 0, 1), Some(&[0][..]));
+        assert_eq!(drd.get_binding(n::BindingTypes::StorageBuffers, 1, 1), Some(&[1][..]));
+        assert_eq!(drd.get_binding(n::BindingTypes::UniformBuffers, 0, 0), Some(&[0][..]));
+        assert_eq!(drd.binding_count(n::BindingTypes::StorageBuffers), 2);
+    }
 
-        let ptr = unsafe {
-            gl.BindBuffer(target, buffer);
-            let ptr = gl.MapBufferRange(target, offset as _, size as _, access);
-            gl.BindBuffer(target, 0);
-            ptr as *mut _
+    #[test]
+    fn test_buffer_alignment() {
+        let limits = Limits {
+            min_uniform_buffer_offset_alignment: 256,
+            min_storage_buffer_offset_alignment: 32,
+            min_texel_buffer_offset_alignment: 16,
+            .. Limits::default()
         };
+        assert_eq!(buffer_alignment(&limits, buffer::Usage::VERTEX), 1);
+        assert_eq!(buffer_alignment(&limits, buffer::Usage::UNIFORM | buffer::Usage::STORAGE), 256);
+        assert_eq!(buffer_alignment(&limits, buffer::Usage::STORAGE_TEXEL), 16);
+    }
 
-        if let Err(err) = self.share.check() {
-            panic!("Error mapping memory: {:?} for memory {:?}", err, memory);
+    #[test]
+    fn test_image_storage_size() {
+        let rgba = format::Format::Rgba8Unorm;
+        assert_eq!(image_storage_size(i::Kind::D2(4, 4, 1, 1), 1, rgba), 64);
+        // 4x4 + 2x2 + 1x1 texels in 6 layers.
+        assert_eq!(image_storage_size(i::Kind::D2(4, 4, 6, 1), 3, rgba), 21 * 4 * 6);
+        assert_eq!(image_storage_size(i::Kind::D2(4, 4, 1, 4), 1, rgba), 256);
+        // Partial 4x4 blocks of 8 bytes each.
+        assert_eq!(image_storage_size(i::Kind::D2(6, 6, 1, 1), 1, format::Format::Bc1RgbUnorm), 32);
+    }
+
+    #[test]
+    fn test_storage_image_binding() {
+        let mut drd = n::DescRemapData::new(INPUT_ATTACHMENT_UNIT_BASE);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::Images, 0, 0);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::StorageImages, 0, 1);
+        // Image units are assigned independently of texture units.
+        assert_eq!(drd.get_binding(n::BindingTypes::StorageImages, 0, 1), Some(&[0][..]));
+
+        let view = n::ImageView::TextureLayer(7, gl::TEXTURE_2D_ARRAY, 2, 3);
+        assert_eq!(
+            storage_image_binding(1, &view, gl::RGBA8),
+            Some(n::DescSetBindings::Image {
+                binding: 1,
+                texture: 7,
+                level: 2,
+                layered: false,
+                layer: 3,
+                format: gl::RGBA8,
+            }),
+        );
+        let view = n::ImageView::Texture(7, gl::TEXTURE_2D_ARRAY, 0);
+        match storage_image_binding(1, &view, gl::RGBA8) {
+            Some(n::DescSetBindings::Image { layered, .. }) => assert!(layered),
+            _ => unreachable!(),
         }
+        assert_eq!(storage_image_binding(1, &n::ImageView::Surface(4), gl::RGBA8), None);
+
+        // Shaders accessing the image differently widen the access.
+        assert_eq!(drd.get_image_access(0, 1), gl::READ_WRITE);
+        drd.insert_image_access(0, 1, gl::READ_ONLY);
+        assert_eq!(drd.get_image_access(0, 1), gl::READ_ONLY);
+        drd.insert_image_access(0, 1, gl::WRITE_ONLY);
+        assert_eq!(drd.get_image_access(0, 1), gl::READ_WRITE);
+        drd.remove_set(0);
+        assert_eq!(drd.get_image_access(0, 1), gl::READ_WRITE);
+    }
 
-        Ok(ptr)
+    #[test]
+    fn test_combined_image_sampler_bindings() {
+        let view = n::ImageView::Texture(3, gl::TEXTURE_2D, 0);
+        let sampler = n::FatSampler::Sampler(5);
+        assert_eq!(
+            combined_image_sampler_bindings(2, &view, &sampler),
+            Some([n::DescSetBindings::Texture(2, 3, gl::TEXTURE_2D), n::DescSetBindings::Sampler(2, 5)]),
+        );
+        // Rectangle textures require unnormalized coordinates.
+        let rectangle = n::ImageView::Texture(4, gl::TEXTURE_RECTANGLE, 0);
+        assert_eq!(combined_image_sampler_bindings(2, &rectangle, &sampler), None);
+        let unnormalized = n::FatSampler::Unnormalized(6);
+        assert_eq!(
+            combined_image_sampler_bindings(2, &rectangle, &unnormalized),
+            Some([n::DescSetBindings::Texture(2, 4, gl::TEXTURE_RECTANGLE), n::DescSetBindings::Sampler(2, 6)]),
+        );
+        assert_eq!(combined_image_sampler_bindings(2, &view, &unnormalized), None);
+
+        let mut drd = n::DescRemapData::new(INPUT_ATTACHMENT_UNIT_BASE);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::Images, 0, 0);
+        let unit = drd.insert_combined_binding_into_spare(0, 2);
+        // The sampler is bound to the unit of the texture.
+        assert_eq!(unit, 1);
+        assert_eq!(drd.get_binding(n::BindingTypes::Images, 0, 2), Some(&[1][..]));
+        assert_eq!(drd.get_sampler_binding(0, 2), Some(&[1][..]));
+        // Bindings with texture units only keep binding their samplers to them.
+        assert_eq!(drd.get_sampler_binding(0, 0), Some(&[0][..]));
     }
 
-    fn unmap_memory(&self, memory: &n::Memory) {
-        let gl = &self.share.context;
-        let buffer = match memory.first_bound_buffer.get() {
-            0 => panic!("No buffer has been bound yet, can't map memory!"),
-            other => other,
-        };
-        let target = gl::PIXEL_PACK_BUFFER;
+    #[test]
+    fn test_remap_attribute_locations() {
+        let reflected = [("position", 0), ("normal", 3), ("uv", 5)]
+            .iter()
+            .map(|&(name, location)| (name.to_string(), location))
+            .collect();
+        // Active attributes in an order unrelated to their locations.
+        let active = vec![
+            ("uv".to_string(), 1, gl::FLOAT_VEC2),
+            ("position".to_string(), 0, gl::FLOAT_VEC4),
+            ("normal".to_string(), 2, gl::FLOAT_VEC3),
+        ];
+        let mut attributes = vec![vec4_attribute(0, 0), vec4_attribute(3, 0), vec4_attribute(5, 0)];
+        remap_attribute_locations(&mut attributes, &active, &reflected);
+        let locations = attributes.iter().map(|a| a.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![0, 2, 1]);
+
+        // Unused attributes keep their location.
+        let mut attributes = vec![vec4_attribute(0, 0), vec4_attribute(1, 0)];
+        remap_attribute_locations(&mut attributes, &active[1 ..], &reflected);
+        let locations = attributes.iter().map(|a| a.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![0, 1]);
+    }
 
-        unsafe {
-            gl.BindBuffer(target, buffer);
-            gl.UnmapBuffer(target);
-            gl.BindBuffer(target, 0);
-        }
+    #[test]
+    fn test_remap_matrix_column_locations() {
+        // A `mat4` at SPIR-V location 1 described per column, moved to location 2.
+        let reflected = [("position", 0), ("model", 1)]
+            .iter()
+            .map(|&(name, location)| (name.to_string(), location))
+            .collect();
+        let active = vec![
+            ("model".to_string(), 2, gl::FLOAT_MAT4),
+            ("position".to_string(), 6, gl::FLOAT_VEC4),
+        ];
+        let mut attributes = (0 .. 5).map(|i| vec4_attribute(i, 0)).collect::<Vec<_>>();
+        remap_attribute_locations(&mut attributes, &active, &reflected);
+        let locations = attributes.iter().map(|a| a.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![6, 2, 3, 4, 5]);
+    }
 
-        if let Err(err) = self.share.check() {
-            panic!("Error unmapping memory: {:?} for memory {:?}",
-                err, memory);
-        }
+    #[test]
+    fn test_attribute_divisor() {
+        let vertex_buffers = [
+            Some(pso::VertexBufferDesc { binding: 0, stride: 16, rate: 0 }),
+            None,
+            Some(pso::VertexBufferDesc { binding: 2, stride: 64, rate: 1 }),
+        ];
+        assert_eq!(attribute_divisor(&vertex_buffers, 0), 0);
+        assert_eq!(attribute_divisor(&vertex_buffers, 2), 1);
+        // Attributes without described vertex buffer default to per vertex.
+        assert_eq!(attribute_divisor(&vertex_buffers, 1), 0);
+        assert_eq!(attribute_divisor(&vertex_buffers, 3), 0);
     }
 
-    fn flush_mapped_memory_ranges<'a, I, R>(&self, _: I)
-    where
-        I: IntoIterator,
-        I::Item: Borrow<(&'a n::Memory, R)>,
-        R: RangeArg<u64>,
-    {
-        // unimplemented!()
-        warn!("memory range invalidation not implemented!");
+    #[test]
+    fn test_expand_matrix_attributes() {
+        // A `mat4` at location 1 after a `vec4` at location 0.
+        let attributes = vec![vec4_attribute(0, 0), vec4_attribute(1, 16)];
+        let expanded = expand_matrix_attributes(attributes, &[(1, 4)]);
+        let columns = expanded.iter().map(|a| (a.location, a.offset)).collect::<Vec<_>>();
+        assert_eq!(columns, vec![(0, 0), (1, 16), (2, 32), (3, 48), (4, 64)]);
+        assert!(expanded.iter().all(|a| a.size == 4 && a.format == gl::FLOAT));
     }
 
-    fn invalidate_mapped_memory_ranges<'a, I, R>(&self, _ranges: I)
-    where
-        I: IntoIterator,
-        I::Item: Borrow<(&'a n::Memory, R)>,
-        R: RangeArg<u64>,
-    {
-        unimplemented!()
+    #[test]
+    fn test_expand_split_matrix_attributes() {
+        // The columns of the `mat4` at location 1 are already described separately.
+        let attributes = (0 .. 5).map(|i| vec4_attribute(i, i * 16)).collect::<Vec<_>>();
+        let expanded = expand_matrix_attributes(attributes, &[(1, 4)]);
+        let columns = expanded.iter().map(|a| (a.location, a.offset)).collect::<Vec<_>>();
+        assert_eq!(columns, vec![(0, 0), (1, 16), (2, 32), (3, 48), (4, 64)]);
     }
 
-    fn create_buffer_view<R: RangeArg<u64>>(
-        &self, _: &n::Buffer, _: Option<Format>, _: R
-    ) -> Result<n::BufferView, buffer::ViewCreationError> {
-        unimplemented!()
+    #[test]
+    fn test_remove_desc_remap_set() {
+        let mut drd = n::DescRemapData::new(INPUT_ATTACHMENT_UNIT_BASE);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 0, 0);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 1, 0);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 1, 1);
+        drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 2, 0);
+
+        drd.remove_set(1);
+        assert_eq!(drd.get_binding(n::BindingTypes::UniformBuffers, 1, 0), None);
+        // The binding points of set 1 are reused, skipping the ones still in use.
+        assert_eq!(drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 3, 0), &[1]);
+        assert_eq!(drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 3, 1), &[2]);
+        assert_eq!(drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 3, 2), &[4]);
+
+        drd.clear();
+        assert_eq!(drd.binding_count(n::BindingTypes::UniformBuffers), 0);
+        assert_eq!(drd.insert_missing_binding_into_spare(n::BindingTypes::UniformBuffers, 0, 0), &[0]);
     }
 
-    fn create_image(
-        &self,
-        kind: i::Kind,
-        num_levels: i::Level,
-        format: Format,
-        _tiling: i::Tiling,
-        usage: i::Usage,
-        _flags: i::StorageFlags,
-    ) -> Result<UnboundImage, i::CreationError> {
-        let gl = &self.share.context;
+    // Occlusion query pool and a buffer for copying its results.
+    fn query_pool_and_buffer(device: &Device, count: u32) -> (n::QueryPool, n::Buffer) {
+        let pool = hal::Device::create_query_pool(device, hal::query::QueryType::Occlusion, count);
+        let unbound = hal::Device::create_buffer(device, 256, buffer::Usage::TRANSFER_DST).unwrap();
+        let memory = hal::Device::allocate_memory(device, hal::MemoryTypeId(0), 256).unwrap();
+        let buffer = hal::Device::bind_buffer_memory(device, &memory, 0, unbound).unwrap();
+        (pool, buffer)
+    }
 
-        let int_format = match format {
-            Format::Rgba8Unorm => gl::RGBA8,
-            Format::Rgba8Srgb => gl::SRGB8_ALPHA8,
-            _ => unimplemented!()
+    #[test]
+    fn test_reset_query_unavailable_until_begun() {
+        use hal::command::RawCommandBuffer;
+        use hal::query;
+
+        let (device, mut queue) = test_utils::open();
+        let (pool, buffer) = query_pool_and_buffer(&device, 1);
+        let query = query::Query { pool: &pool, id: 0 };
+        let copy = |commands: &mut ::command::RawCommandBuffer, flags| {
+            commands.copy_query_pool_results(&pool, 0 .. 1, &buffer, 16, 8, flags);
         };
 
-        let channel = format.base_format().1;
+        // Reset in one submission, copied in the next.
+        let mut commands = test_utils::command_buffer(&device);
+        commands.reset_query_pool(&pool, 0 .. 1);
+        test_utils::submit(&mut queue, commands, None);
+        test_utils::clear_calls();
+        let mut commands = test_utils::command_buffer(&device);
+        copy(&mut commands, n::QueryResultFlags::empty());
+        copy(&mut commands, n::QueryResultFlags::PARTIAL);
+        test_utils::submit(&mut queue, commands, None);
+        // Only partial results are written, as zero.
+        assert!(test_utils::calls("GetQueryObjectuiv").is_empty());
+        assert_eq!(test_utils::calls("BufferSubData").len(), 1);
+        assert_eq!(test_utils::calls("BufferSubData")[0][.. 3], [gl::QUERY_BUFFER as i64, 16, 4]);
+
+        // Available again once the query ran.
+        let mut commands = test_utils::command_buffer(&device);
+        commands.begin_query(query, query::QueryControl::empty());
+        commands.end_query(query::Query { pool: &pool, id: 0 });
+        copy(&mut commands, n::QueryResultFlags::WAIT);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+        assert_eq!(
+            test_utils::calls("GetQueryObjectuiv"),
+            vec![vec![pool.queries[0] as i64, gl::QUERY_RESULT as i64, 16]],
+        );
+        assert!(test_utils::calls("BufferSubData").is_empty());
+    }
 
-        let image = if num_levels > 1 ||
-            usage.contains(i::Usage::STORAGE) ||
-            usage.contains(i::Usage::SAMPLED)
-        {
-            let mut name = 0;
-            unsafe { gl.GenTextures(1, &mut name) };
-            match kind {
-                i::Kind::D2(w, h, 1, 1) => unsafe {
-                    gl.BindTexture(gl::TEXTURE_2D, name);
-                    gl.TexStorage2D(gl::TEXTURE_2D, num_levels as _, int_format, w as _, h as _);
-                }
-                _ => unimplemented!(),
-            };
-            n::ImageKind::Texture(name)
-        } else {
-            let mut name = 0;
-            unsafe { gl.GenRenderbuffers(1, &mut name) };
-            match kind {
-                i::Kind::D2(w, h, 1, 1) => unsafe {
-                    gl.BindRenderbuffer(gl::RENDERBUFFER, name);
-                    gl.RenderbufferStorage(gl::RENDERBUFFER, int_format, w as _, h as _);
-                }
-                _ => unimplemented!(),
-            };
-            n::ImageKind::Surface(name)
-        };
+    #[test]
+    fn test_copy_reset_query_with_availability() {
+        use hal::command::RawCommandBuffer;
+
+        let (device, mut queue) = test_utils::open();
+        let (pool, buffer) = query_pool_and_buffer(&device, 1);
+        let mut commands = test_utils::command_buffer(&device);
+        commands.reset_query_pool(&pool, 0 .. 1);
+        let flags = n::QueryResultFlags::WITH_AVAILABILITY | n::QueryResultFlags::BITS_64;
+        commands.copy_query_pool_results(&pool, 0 .. 1, &buffer, 0, 16, flags);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        // Never begun, only the availability is written, as zero after the result.
+        assert!(test_utils::calls("GetQueryObjectui64v").is_empty());
+        let writes = test_utils::calls("BufferSubData");
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0][.. 3], [gl::QUERY_BUFFER as i64, 8, 8]);
+    }
 
-        let surface_desc = format.base_format().0.desc();
-        let bytes_per_texel  = surface_desc.bits / 8;
-        let ext = kind.extent();
-        let size = (ext.width * ext.height * ext.depth) as u64 * bytes_per_texel as u64;
+    #[test]
+    fn test_copy_partially_available_queries() {
+        use hal::command::RawCommandBuffer;
+        use hal::query;
+
+        let (device, mut queue) = test_utils::open();
+        let (pool, buffer) = query_pool_and_buffer(&device, 2);
+        let mut commands = test_utils::command_buffer(&device);
+        commands.reset_query_pool(&pool, 0 .. 2);
+        commands.begin_query(query::Query { pool: &pool, id: 0 }, query::QueryControl::empty());
+        commands.end_query(query::Query { pool: &pool, id: 0 });
+        // Without `WAIT`, the results are copied whether they are available or not.
+        commands.copy_query_pool_results(&pool, 0 .. 2, &buffer, 0, 8, n::QueryResultFlags::WITH_AVAILABILITY);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        // The availability of the finished query is queried before its result,
+        // so a stale result is never marked as available.
+        assert_eq!(test_utils::calls("GetQueryObjectuiv"), vec![
+            vec![pool.queries[0] as i64, gl::QUERY_RESULT_AVAILABLE as i64, 4],
+            vec![pool.queries[0] as i64, gl::QUERY_RESULT_NO_WAIT as i64, 0],
+        ]);
+        // The reset query is unavailable.
+        let writes = test_utils::calls("BufferSubData");
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0][.. 3], [gl::QUERY_BUFFER as i64, 12, 4]);
+    }
 
-        if let Err(err) = self.share.check() {
-            panic!("Error creating image: {:?} for kind {:?} of {:?}",
-                err, kind, format);
-        }
+    #[test]
+    fn test_pipeline_statistics_query_unsupported() {
+        use hal::query;
+
+        let (device, mut queue) = test_utils::open();
+        test_utils::clear_calls();
+        let pool = hal::Device::create_query_pool(
+            &device,
+            query::QueryType::PipelineStatistics(query::PipelineStatistic::VERTEX_SHADER_INVOCATIONS),
+            1,
+        );
+        assert!(test_utils::calls("GenQueries").is_empty());
+
+        let mut buffer = test_utils::command_buffer(&device);
+        let query = query::Query { pool: &pool, id: 0 };
+        hal::command::RawCommandBuffer::begin_query(&mut buffer, query, query::QueryControl::empty());
+        hal::command::RawCommandBuffer::end_query(&mut buffer, query::Query { pool: &pool, id: 0 });
+        test_utils::submit(&mut queue, buffer, None);
+        assert!(test_utils::calls("BeginQuery").is_empty());
+    }
 
-        Ok(UnboundImage {
-            image,
-            channel,
-            requirements: memory::Requirements {
-                size,
-                alignment: 1,
-                type_mask: 0x7,
-            }
-        })
+    #[test]
+    fn test_fence_status_without_sync() {
+        // Fences without a sync object don't call into GL.
+        let gl = gl::Gl::load_with(|_| ptr::null());
+        assert!(!n::Fence::new(ptr::null(), false).get_status(&gl));
+        assert!(n::Fence::new(ptr::null(), true).get_status(&gl));
     }
 
-    fn get_image_requirements(&self, unbound: &UnboundImage) -> memory::Requirements {
-        unbound.requirements
+    #[test]
+    fn test_fence_signaled_after_submit() {
+        let (device, mut queue) = test_utils::open();
+        let fence = hal::Device::create_fence(&device, false);
+        assert!(fence.sync.get().is_null());
+        test_utils::submit(&mut queue, test_utils::command_buffer(&device), Some(&fence));
+
+        let sync = fence.sync.get();
+        assert!(!sync.is_null());
+        assert!(hal::Device::wait_for_fence(&device, &fence, 1_000_000_000));
+        assert_eq!(test_utils::calls("ClientWaitSync").last(), Some(&vec![sync as i64]));
+        assert!(hal::Device::get_fence_status(&device, &fence));
     }
 
-    fn get_image_subresource_footprint(
-        &self, _image: &n::Image, _sub: i::Subresource
-    ) -> i::SubresourceFootprint {
-        unimplemented!()
+    #[test]
+    fn test_fence_reset_and_reuse() {
+        let (device, mut queue) = test_utils::open();
+        let fence = hal::Device::create_fence(&device, false);
+        test_utils::submit(&mut queue, test_utils::command_buffer(&device), Some(&fence));
+        let first = fence.sync.get();
+        assert!(hal::Device::wait_for_fence(&device, &fence, 1_000_000_000));
+
+        // Resetting deletes the sync object, resetting again does nothing.
+        test_utils::clear_calls();
+        hal::Device::reset_fence(&device, &fence);
+        hal::Device::reset_fence(&device, &fence);
+        assert!(fence.sync.get().is_null());
+        assert_eq!(test_utils::calls("DeleteSync"), vec![vec![first as i64]]);
+
+        test_utils::submit(&mut queue, test_utils::command_buffer(&device), Some(&fence));
+        let second = fence.sync.get();
+        assert!(!second.is_null() && second != first);
+        assert!(hal::Device::wait_for_fence(&device, &fence, 1_000_000_000));
+        assert_eq!(test_utils::calls("ClientWaitSync").last(), Some(&vec![second as i64]));
     }
 
-    fn bind_image_memory(
-        &self, _memory: &n::Memory, _offset: u64, unbound: UnboundImage
-    ) -> Result<n::Image, d::BindError> {
-        Ok(n::Image {
-            kind: unbound.image,
-            channel: unbound.channel,
-        })
+    #[test]
+    fn test_wait_for_unsubmitted_fence() {
+        let (device, _queue) = test_utils::open();
+        let fence = hal::Device::create_fence(&device, false);
+        test_utils::clear_calls();
+        assert!(!hal::Device::wait_for_fence(&device, &fence, 1_000_000_000));
+        assert!(!hal::Device::wait_for_fences(&device, Some(&fence), hal::device::WaitFor::All, 0));
+        assert!(test_utils::calls("ClientWaitSync").is_empty());
     }
 
-    fn create_image_view(
-        &self,
-        image: &n::Image,
-        _kind: i::ViewKind,
-        _format: Format,
-        swizzle: Swizzle,
-        range: i::SubresourceRange,
-    ) -> Result<n::ImageView, i::ViewError> {
-        //TODO: check if `layers.end` covers all the layers
-        let level = range.levels.start;
-        assert_eq!(level + 1, range.levels.end);
-        //assert_eq!(format, image.format);
-        assert_eq!(swizzle, Swizzle::NO);
-        //TODO: check format
-        match image.kind {
-            n::ImageKind::Surface(surface) => {
-                if range.levels.start == 0 && range.layers.start == 0 {
-                    Ok(n::ImageView::Surface(surface))
-                } else if level != 0 {
-                    Err(i::ViewError::Level(level)) //TODO
-                } else {
-                    Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers)))
-                }
-            }
-            n::ImageKind::Texture(texture) => {
-                //TODO: check that `level` exists
-                if range.layers.start == 0 {
-                    Ok(n::ImageView::Texture(texture, level))
-                } else if range.layers.start + 1 == range.layers.end {
-                    Ok(n::ImageView::TextureLayer(texture, level, range.layers.start))
-                } else {
-                    Err(i::ViewError::Layer(i::LayerError::OutOfBounds(range.layers)))
-                }
-            }
-        }
+    #[test]
+    fn test_destroy_unsubmitted_fence() {
+        let (device, mut queue) = test_utils::open();
+        test_utils::clear_calls();
+        // Neither unsignaled nor signaled fences have a sync object before their submission.
+        hal::Device::destroy_fence(&device, hal::Device::create_fence(&device, false));
+        hal::Device::destroy_fence(&device, hal::Device::create_fence(&device, true));
+        assert!(test_utils::calls("DeleteSync").is_empty());
+
+        let fence = hal::Device::create_fence(&device, false);
+        test_utils::submit(&mut queue, test_utils::command_buffer(&device), Some(&fence));
+        let sync = fence.sync.get();
+        hal::Device::destroy_fence(&device, fence);
+        assert_eq!(test_utils::calls("DeleteSync"), vec![vec![sync as i64]]);
     }
 
-    fn create_descriptor_pool<I>(&self, _: usize, _: I) -> n::DescriptorPool
-    where
-        I: IntoIterator,
-        I::Item: Borrow<pso::DescriptorRangeDesc>,
-    {
-        n::DescriptorPool { }
+    #[test]
+    fn test_semaphore_signal() {
+        let gl = test_utils::fake_gl();
+        let semaphore = n::Semaphore::new();
+        assert!(semaphore.sync.load(Ordering::Acquire).is_null());
+        semaphore.signal(&gl);
+        assert!(!semaphore.sync.load(Ordering::Acquire).is_null());
+        // The sync object is flushed for waits of other contexts.
+        assert_eq!(test_utils::call_names().last(), Some(&"Flush"));
+        semaphore.wait(&gl);
+        assert!(semaphore.sync.load(Ordering::Acquire).is_null());
+        assert_eq!(test_utils::calls("WaitSync").len(), 1);
     }
 
-    fn create_descriptor_set_layout<I, J>(&self, layout: I, _: J) -> n::DescriptorSetLayout
-    where
-        I: IntoIterator,
-        I::Item: Borrow<pso::DescriptorSetLayoutBinding>,
-        J: IntoIterator,
-        J::Item: Borrow<n::FatSampler>,
-    {
-        // Just return it
-        layout.into_iter().map(|l| l.borrow().clone()).collect()
+    // Submit an empty command buffer signaling `semaphore`.
+    fn submit_signaling(device: &Device, queue: &mut CommandQueue, semaphore: &n::Semaphore) {
+        let mut buffer = test_utils::command_buffer(device);
+        hal::command::RawCommandBuffer::finish(&mut buffer);
+        let submission = hal::queue::RawSubmission {
+            cmd_buffers: Some(&buffer),
+            wait_semaphores: &[],
+            signal_semaphores: &[semaphore],
+        };
+        unsafe { hal::queue::RawCommandQueue::submit_raw(queue, submission, None) };
     }
 
-    fn write_descriptor_sets<'a, I, J>(&self, writes: I)
-    where
-        I: IntoIterator<Item = pso::DescriptorSetWrite<'a, B, J>>,
-        J: IntoIterator,
-        J::Item: Borrow<pso::Descriptor<'a, B>>,
-    {
-        for mut write in writes {
-            let set = &mut write.set;
-            let mut bindings = set.bindings.lock().unwrap();
-            let binding = write.binding;
-            let mut offset = write.array_offset as _;
+    #[test]
+    fn test_semaphore_signaled_by_single_context_submit() {
+        let (device, mut queue) = test_utils::open();
+        let semaphore = hal::Device::create_semaphore(&device);
+        test_utils::clear_calls();
+        submit_signaling(&device, &mut queue, &semaphore);
+        // Commands of a single context execute in order, nothing to synchronize.
+        assert!(semaphore.sync.load(Ordering::Acquire).is_null());
+        assert!(test_utils::calls("FenceSync").is_empty());
+        assert!(test_utils::calls("Flush").is_empty());
+    }
 
-            for descriptor in write.descriptors {
-                match descriptor.borrow() {
-                    pso::Descriptor::Buffer(buffer, ref range) => {
-                        let start = range.start.unwrap_or(0);
-                        let end = range.end.unwrap_or(buffer.size);
-                        let size = (end - start) as _;
+    #[test]
+    fn test_semaphore_signaled_for_other_context_submit() {
+        let (device, mut queue) = test_utils::open();
+        // Simulate the context of a second window, e.g. of another swapchain.
+        device.share.register_context(1, Box::new(|| true));
+        let semaphore = hal::Device::create_semaphore(&device);
+        submit_signaling(&device, &mut queue, &semaphore);
+        assert!(!semaphore.sync.load(Ordering::Acquire).is_null());
+        assert_eq!(test_utils::call_names().last(), Some(&"Flush"));
+    }
 
-                        bindings
-                            .push(n::DescSetBindings::Buffer {
-                                ty: n::BindingTypes::UniformBuffers,
-                                binding,
-                                buffer: buffer.raw,
-                                offset,
-                                size,
-                            });
+    #[test]
+    fn test_pace_frame() {
+        use std::sync::Mutex;
+        use std::time::{Duration, Instant};
+
+        let (_device, queue) = test_utils::open();
+        let fence = n::Fence::new(ptr::null(), true);
+        let last_present = Mutex::new(None);
+        let frame_time = Duration::from_millis(20);
+        test_utils::clear_calls();
+
+        // The first frame isn't delayed.
+        queue.pace_frame(&fence, &last_present, frame_time);
+        assert!(test_utils::calls("ClientWaitSync").is_empty());
+        assert_eq!(test_utils::calls("FenceSync").len(), 1);
+
+        // Following frames wait for the previous one and are spaced by the frame time.
+        let start = Instant::now();
+        queue.pace_frame(&fence, &last_present, frame_time);
+        queue.pace_frame(&fence, &last_present, frame_time);
+        assert!(start.elapsed() >= frame_time * 2);
+        assert_eq!(test_utils::calls("ClientWaitSync").len(), 2);
+        assert_eq!(test_utils::calls("FenceSync").len(), 3);
+    }
 
-                        offset += size;
-                    },
-                    pso::Descriptor::CombinedImageSampler(view, _layout, sampler) => {
-                        match view {
-                            n::ImageView::Texture(tex, _)
-                            | n::ImageView::TextureLayer(tex, _, _) =>
-                                bindings
-                                .push(n::DescSetBindings::Texture(binding, *tex)),
-                            n::ImageView::Surface(_) => unimplemented!(),
-                        }
-                        match sampler {
-                            n::FatSampler::Sampler(sampler) =>
-                                bindings
-                                .push(n::DescSetBindings::Sampler(binding, *sampler)),
-                            n::FatSampler::Info(_) => unimplemented!(),
-                        }
-                    }
-                    pso::Descriptor::Image(view, _layout) => {
-                        match view {
-                            n::ImageView::Texture(tex, _)
-                            | n::ImageView::TextureLayer(tex, _, _) =>
-                                bindings
-                                .push(n::DescSetBindings::Texture(binding, *tex)),
-                            n::ImageView::Surface(_) => unimplemented!(),
-                        }
-                    }
-                    pso::Descriptor::Sampler(sampler) => {
-                        match sampler {
-                            n::FatSampler::Sampler(sampler) =>
-                                bindings
-                                .push(n::DescSetBindings::Sampler(binding, *sampler)),
-                            n::FatSampler::Info(_) => unimplemented!(),
-                        }
-                    }
-                    pso::Descriptor::UniformTexelBuffer(_view) => unimplemented!(),
-                    pso::Descriptor::StorageTexelBuffer(_view) => unimplemented!(),
-                }
-            }
-        }
+    #[test]
+    fn test_inject_alpha_test() {
+        let source = "#version 330\nout vec4 color;\nvoid main()\n{\n    color = vec4(1.0);\n}\n";
+        let injected = inject_alpha_test(source, "color", pso::Comparison::GreaterEqual, 0.5);
+        assert!(injected.contains("void alpha_test_main()\n{\n    color = vec4(1.0);\n}\n"));
+        assert!(injected.ends_with("void main()\n{\n    alpha_test_main();\n    if (!(color.a >= 0.5))\n    {\n        discard;\n    }\n}\n"));
+        assert_eq!(inject_alpha_test(source, "color", pso::Comparison::Always, 0.5), source);
+
+        // The entry point is found regardless of the whitespace around it.
+        let source = "#version 330\nout vec4 color;\nvoid remain() {}\nvoid\tmain ( void )\n{\n}\n";
+        let injected = inject_alpha_test(source, "color", pso::Comparison::Less, 0.5);
+        assert!(injected.contains("void remain() {}\nvoid\talpha_test_main ( void )\n"));
+        let source = "#version 330\nvoid start() {}\n";
+        assert_eq!(inject_alpha_test(source, "color", pso::Comparison::Less, 0.5), source);
     }
 
-    fn copy_descriptor_sets<'a, I>(&self, copies: I)
-    where
-        I: IntoIterator,
-        I::Item: Borrow<pso::DescriptorSetCopy<'a, B>>,
-    {
-        for _copy in copies {
-            unimplemented!()
-        }
+    #[test]
+    fn test_persistent_map_flags() {
+        use hal::memory::Properties;
+        use std::cell::Cell;
+
+        let memory = |properties, persistent| n::Memory {
+            properties,
+            first_bound_buffer: Cell::new(0),
+            size: 256,
+            mapped: Cell::new(None),
+            persistent,
+            persistent_ptr: Cell::new(ptr::null_mut()),
+        };
+        let coherent = Properties::CPU_VISIBLE | Properties::CPU_CACHED | Properties::COHERENT;
+
+        assert_eq!(memory(coherent, false).map_flags(), gl::MAP_READ_BIT | gl::MAP_WRITE_BIT);
+        assert_eq!(memory(Properties::CPU_VISIBLE, false).map_flags(), gl::MAP_WRITE_BIT | gl::MAP_FLUSH_EXPLICIT_BIT);
+        assert_eq!(
+            memory(coherent, true).map_flags(),
+            gl::MAP_READ_BIT | gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT,
+        );
+        assert_eq!(
+            memory(coherent, true).access_flags(n::MapAccess::Write),
+            gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT,
+        );
+        assert_eq!(
+            memory(Properties::CPU_VISIBLE, true).map_flags(),
+            gl::MAP_WRITE_BIT | gl::MAP_FLUSH_EXPLICIT_BIT | gl::MAP_PERSISTENT_BIT,
+        );
     }
 
-    fn create_semaphore(&self) -> n::Semaphore {
-        n::Semaphore
+    #[test]
+    fn test_flush_out_of_range() {
+        use hal::memory::Properties;
+        use std::cell::Cell;
+
+        // Rejected ranges don't call into GL.
+        let gl = gl::Gl::load_with(|_| ptr::null());
+        let memory = n::Memory {
+            properties: Properties::CPU_VISIBLE,
+            first_bound_buffer: Cell::new(1),
+            size: 256,
+            mapped: Cell::new(Some((64, 256))),
+            persistent: false,
+            persistent_ptr: Cell::new(ptr::null_mut()),
+        };
+        assert!(!memory.flush_range(&gl, 128, 256, 1));
+        assert!(!memory.flush_range(&gl, 0, 64, 1));
+        assert!(!memory.flush_range(&gl, !0, 2, 1));
+        memory.mapped.set(None);
+        assert!(!memory.flush_range(&gl, 64, 16, 1));
     }
 
-    fn create_fence(&self, signalled: bool) -> n::Fence {
-        let sync = if signalled && self.share.private_caps.sync {
-            let gl = &self.share.context;
-            unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) }
-        } else {
-            ptr::null()
+    #[test]
+    fn test_mipmap_target() {
+        let image = |kind| n::Image {
+            kind,
+            format: format::Format::Rgba8Unorm,
+            channel: format::ChannelType::Unorm,
+            dimensions: i::Kind::D2(64, 64, 1, 1),
+            num_levels: 4,
         };
-        n::Fence::new(sync)
+        let texture = image(n::ImageKind::Texture(3, gl::TEXTURE_2D));
+        assert_eq!(mipmap_target(&texture), Some((3, gl::TEXTURE_2D)));
+        let renderbuffer = image(n::ImageKind::Surface(5));
+        assert_eq!(mipmap_target(&renderbuffer), None);
     }
 
-    fn reset_fences<I>(&self, fences: I)
-    where
-        I: IntoIterator,
-        I::Item: Borrow<n::Fence>,
-    {
-        if !self.share.private_caps.sync {
-            return
+    #[test]
+    fn test_validate_unnormalized_sampler() {
+        let mut info = i::SamplerInfo::new(i::Filter::Nearest, i::WrapMode::Clamp);
+        info.lod_range = i::Lod::from(0.0) .. i::Lod::from(0.0);
+        assert_eq!(validate_unnormalized_sampler(&info), Ok(()));
+        info.wrap_mode.1 = i::WrapMode::Tile;
+        assert_eq!(validate_unnormalized_sampler(&info), Err(UnnormalizedSamplerError::Wrap(i::WrapMode::Tile)));
+        info.wrap_mode.1 = i::WrapMode::Clamp;
+        info.lod_range.end = i::Lod::from(4.0);
+        assert_eq!(validate_unnormalized_sampler(&info), Err(UnnormalizedSamplerError::Mipmapping));
+    }
+
+    #[test]
+    fn test_cube_face_view() {
+        assert_eq!(n::ImageView::cube_face(3, 1, 5), Ok(n::ImageView::CubeFace(3, 1, 5)));
+        assert_eq!(
+            n::ImageView::cube_face(3, 1, 6),
+            Err(i::ViewError::Layer(i::LayerError::OutOfBounds(6 .. 7))),
+        );
+    }
+
+    #[test]
+    fn test_create_cube_and_depth_images() {
+        let (device, _queue) = test_utils::open();
+        let cube = test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 6, 1), 1, format::Format::Rgba8Unorm,
+            i::Usage::SAMPLED | i::Usage::COLOR_ATTACHMENT, i::StorageFlags::CUBE_VIEW,
+        ).unwrap();
+        assert_eq!(test_utils::calls("TexStorage2D"), vec![vec![gl::TEXTURE_CUBE_MAP as i64, 1, gl::RGBA8 as i64, 16, 16]]);
+        let range = i::SubresourceRange { aspects: format::Aspects::COLOR, levels: 0 .. 1, layers: 3 .. 4 };
+        let face = hal::Device::create_image_view(
+            &device, &cube, i::ViewKind::D2, format::Format::Rgba8Unorm, format::Swizzle::NO, range,
+        );
+        match (face, cube.kind) {
+            (Ok(n::ImageView::CubeFace(texture, 0, 3)), n::ImageKind::Texture(name, _)) => assert_eq!(texture, name),
+            other => panic!("Unexpected cube face view {:?}", other),
         }
 
-        let gl = &self.share.context;
-        for fence in fences {
-            let fence = fence.borrow();
-            let sync = fence.0.get();
-            unsafe {
-                if gl.IsSync(sync) == gl::TRUE {
-                    gl.DeleteSync(sync);
-                }
-            }
-            fence.0.set(ptr::null())
+        test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 1, 1), 1, format::Format::D32Float,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT, i::StorageFlags::empty(),
+        ).unwrap();
+        let storage = test_utils::calls("RenderbufferStorage");
+        assert_eq!(storage, vec![vec![gl::RENDERBUFFER as i64, gl::DEPTH_COMPONENT32F as i64, 16, 16]]);
+
+        assert_eq!(
+            test_utils::create_image(
+                &device, i::Kind::D1(16, 1), 1, format::Format::Rgba8Unorm,
+                i::Usage::SAMPLED, i::StorageFlags::empty(),
+            ).err(),
+            Some(i::CreationError::Kind),
+        );
+    }
+
+    #[test]
+    fn test_texture_3d_slice_attachment() {
+        let gl = test_utils::fake_gl();
+        assert!(n::ImageView::texture_3d_slice(2, 0, 4, 4).is_err());
+        let view = n::ImageView::texture_3d_slice(2, 0, 3, 4).unwrap();
+        Device::bind_target_compat(&gl, gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, &view);
+        let calls = test_utils::calls("FramebufferTexture3D");
+        assert_eq!(calls.len(), 1);
+        assert_eq!((calls[0][2], calls[0][5]), (gl::TEXTURE_3D as i64, 3));
+    }
+
+    #[test]
+    fn test_create_texture_3d_slice_view() {
+        let (device, _queue) = test_utils::open();
+        let image = test_utils::create_image(
+            &device, i::Kind::D3(8, 8, 4), 1, format::Format::Rgba8Unorm,
+            i::Usage::SAMPLED | i::Usage::COLOR_ATTACHMENT, i::StorageFlags::empty(),
+        ).unwrap();
+        assert_eq!(test_utils::calls("TexStorage3D"), vec![vec![gl::TEXTURE_3D as i64, 1, gl::RGBA8 as i64, 8, 8, 4]]);
+
+        let view = |layers| hal::Device::create_image_view(
+            &device, &image, i::ViewKind::D2, format::Format::Rgba8Unorm, format::Swizzle::NO,
+            i::SubresourceRange { aspects: format::Aspects::COLOR, levels: 0 .. 1, layers },
+        );
+        match (view(2 .. 3), image.kind) {
+            (Ok(n::ImageView::Texture3DSlice(texture, 0, 2)), n::ImageKind::Texture(name, _)) => assert_eq!(texture, name),
+            other => panic!("Unexpected slice view {:?}", other),
         }
+        assert!(view(4 .. 5).is_err());
     }
 
-    fn wait_for_fence(&self, fence: &n::Fence, timeout_ns: u64) -> bool {
-        if !self.share.private_caps.sync {
-            return true;
+    #[test]
+    fn test_create_array_layer_range_view() {
+        let (device, _queue) = test_utils::open();
+        let image = test_utils::create_image(
+            &device, i::Kind::D2(8, 8, 6, 1), 1, format::Format::Rgba8Unorm,
+            i::Usage::SAMPLED | i::Usage::COLOR_ATTACHMENT, i::StorageFlags::empty(),
+        ).unwrap();
+        assert_eq!(
+            test_utils::calls("TexStorage3D"),
+            vec![vec![gl::TEXTURE_2D_ARRAY as i64, 1, gl::RGBA8 as i64, 8, 8, 6]],
+        );
+        let texture = match image.kind {
+            n::ImageKind::Texture(texture, _) => texture,
+            n::ImageKind::Surface(_) => panic!("Array images are stored in textures"),
+        };
+
+        let view = |layers| hal::Device::create_image_view(
+            &device, &image, i::ViewKind::D2Array, format::Format::Rgba8Unorm, format::Swizzle::NO,
+            i::SubresourceRange { aspects: format::Aspects::COLOR, levels: 0 .. 1, layers },
+        ).unwrap();
+        assert_eq!(view(0 .. 6), n::ImageView::Texture(texture, gl::TEXTURE_2D_ARRAY, 0));
+        // Sub-ranges alias the layers with a new texture, for both sampling and attaching.
+        match view(2 .. 5) {
+            n::ImageView::TextureView(_, gl::TEXTURE_2D_ARRAY) => (),
+            other => panic!("Unexpected layer range view {:?}", other),
         }
-        match wait_fence(fence, &self.share.context, timeout_ns) {
-            gl::TIMEOUT_EXPIRED => false,
-            gl::WAIT_FAILED => {
-                if let Err(err) = self.share.check() {
-                    error!("Error when waiting on fence: {:?}", err);
-                }
-                false
-            }
-            _ => true,
+        assert_eq!(
+            test_utils::calls("TextureView").last().map(|args| args[1 ..].to_vec()),
+            Some(vec![gl::TEXTURE_2D_ARRAY as i64, texture as i64, gl::RGBA8 as i64, 0, 1, 2, 3]),
+        );
+    }
+
+    #[test]
+    fn test_layered_cube_attachment() {
+        let (device, _queue) = test_utils::open();
+        let format = format::Format::Rgba8Unorm;
+        let image = test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 6, 1), 1, format,
+            i::Usage::SAMPLED | i::Usage::COLOR_ATTACHMENT, i::StorageFlags::CUBE_VIEW,
+        ).unwrap();
+        let texture = match image.kind {
+            n::ImageKind::Texture(texture, gl::TEXTURE_CUBE_MAP) => texture,
+            other => panic!("Unexpected cube image {:?}", other),
+        };
+        let view = hal::Device::create_image_view(
+            &device, &image, i::ViewKind::Cube, format, format::Swizzle::NO,
+            i::SubresourceRange { aspects: format::Aspects::COLOR, levels: 0 .. 1, layers: 0 .. 6 },
+        ).unwrap();
+        assert_eq!(view, n::ImageView::Cube(texture, 0));
+
+        let attachment = pass::Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: pass::AttachmentOps::DONT_CARE,
+            stencil_ops: pass::AttachmentOps::DONT_CARE,
+            layouts: i::Layout::Undefined .. i::Layout::ShaderReadOnlyOptimal,
+        };
+        let subpass = pass::SubpassDesc {
+            colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+            depth_stencil: None,
+            inputs: &[],
+            resolves: &[],
+            preserves: &[],
+        };
+        let render_pass = hal::Device::create_render_pass(
+            &device, &[attachment], &[subpass], Vec::<pass::SubpassDependency>::new(),
+        );
+        hal::Device::create_framebuffer(
+            &device, &render_pass, Some(&view), i::Extent { width: 16, height: 16, depth: 1 },
+        ).unwrap();
+        // All faces are attached at once, geometry shaders select them via `gl_Layer`.
+        assert_eq!(
+            test_utils::calls("FramebufferTexture"),
+            vec![vec![gl::DRAW_FRAMEBUFFER as i64, gl::COLOR_ATTACHMENT0 as i64, texture as i64, 0]],
+        );
+    }
+
+    #[test]
+    fn test_create_mip_range_view() {
+        let (device, _queue) = test_utils::open();
+        let image = test_utils::create_image(
+            &device, i::Kind::D2(64, 64, 1, 1), 4, format::Format::Rgba8Srgb,
+            i::Usage::SAMPLED, i::StorageFlags::empty(),
+        ).unwrap();
+        assert_eq!(test_utils::calls("TexStorage2D"), vec![vec![gl::TEXTURE_2D as i64, 4, gl::SRGB8_ALPHA8 as i64, 64, 64]]);
+        let texture = match image.kind {
+            n::ImageKind::Texture(texture, _) => texture,
+            n::ImageKind::Surface(_) => panic!("Sampled images are stored in textures"),
+        };
+
+        let view = hal::Device::create_image_view(
+            &device, &image, i::ViewKind::D2, format::Format::Rgba8Srgb, format::Swizzle::NO,
+            i::SubresourceRange { aspects: format::Aspects::COLOR, levels: 2 .. 4, layers: 0 .. 1 },
+        ).unwrap();
+        match view {
+            n::ImageView::TextureView(_, gl::TEXTURE_2D) => (),
+            other => panic!("Unexpected mip range view {:?}", other),
         }
+        assert_eq!(
+            test_utils::calls("TextureView").last().map(|args| args[1 ..].to_vec()),
+            Some(vec![gl::TEXTURE_2D as i64, texture as i64, gl::SRGB8_ALPHA8 as i64, 2, 2, 0, 1]),
+        );
     }
 
-    fn get_fence_status(&self, _: &n::Fence) -> bool {
-        unimplemented!()
+    #[test]
+    fn test_create_image_sample_counts() {
+        test_utils::set_integer(gl::MAX_COLOR_TEXTURE_SAMPLES, 4);
+        let (device, _queue) = test_utils::open();
+        let create = |layers, samples| test_utils::create_image(
+            &device, i::Kind::D2(16, 16, layers, samples), 1, format::Format::Rgba8Unorm,
+            i::Usage::SAMPLED | i::Usage::COLOR_ATTACHMENT, i::StorageFlags::empty(),
+        ).err();
+        assert_eq!(create(2, 4), None);
+        assert_eq!(create(2, 8), Some(i::CreationError::Samples(8)));
+        assert_eq!(create(2, 3), Some(i::CreationError::Samples(3)));
+        assert_eq!(
+            test_utils::calls("TexImage3DMultisample"),
+            vec![vec![gl::TEXTURE_2D_MULTISAMPLE_ARRAY as i64, 4, gl::RGBA8 as i64, 16, 16, 2, gl::TRUE as i64]],
+        );
     }
 
-    fn free_memory(&self, _memory: n::Memory) {
-        // Nothing to do
+    #[test]
+    fn test_create_image_with_data_formats() {
+        let (device, _queue) = test_utils::open();
+        let kind = i::Kind::D2(8, 8, 1, 1);
+        let create = |format, data: &[u8], generate_mips| device
+            .create_image_with_data(kind, 1, format, i::Usage::SAMPLED, data, generate_mips)
+            .err();
+
+        assert_eq!(create(format::Format::D32Float, &[0; 8 * 8 * 4], false), None);
+        assert_eq!(
+            test_utils::calls("TexSubImage2D").last().map(|args| args[6 .. 8].to_vec()),
+            Some(vec![gl::DEPTH_COMPONENT as i64, gl::FLOAT as i64]),
+        );
+
+        // Blocks of 4x4 texels with 8 bytes each.
+        assert_eq!(create(format::Format::Bc1RgbaUnorm, &[0; 2 * 2 * 8], false), None);
+        assert_eq!(
+            test_utils::calls("CompressedTexSubImage2D").last().map(|args| args[1 .. 8].to_vec()),
+            Some(vec![0, 0, 0, 8, 8, ext::COMPRESSED_RGBA_S3TC_DXT1_EXT as i64, 32]),
+        );
+        assert_eq!(create(format::Format::Bc1RgbaUnorm, &[0; 8 * 8 * 4], false), Some(i::CreationError::Data(256)));
+        assert_eq!(
+            create(format::Format::Bc1RgbaUnorm, &[0; 2 * 2 * 8], true),
+            Some(i::CreationError::Format(format::Format::Bc1RgbaUnorm)),
+        );
     }
 
-    fn create_query_pool(&self, _ty: query::QueryType, _count: u32) -> () {
-        unimplemented!()
+    #[test]
+    fn test_storage_block_layout_checked_when_linking() {
+        test_utils::set_integer(gl::OFFSET, 16);
+        test_utils::set_integer(gl::TOP_LEVEL_ARRAY_STRIDE, 0);
+        let (device, _queue) = test_utils::open();
+        let no_push_constants: &[(pso::ShaderStageFlags, std::ops::Range<u32>)] = &[];
+        let layout = hal::Device::create_pipeline_layout(&device, None::<&n::DescriptorSetLayout>, no_push_constants);
+        let module = n::ShaderModule::Raw(1);
+        let create = || hal::Device::create_compute_pipeline(&device, &pso::ComputePipelineDesc::new(
+            pso::EntryPoint { entry: "main", module: &module, specialization: &[] },
+            &layout,
+        )).unwrap();
+
+        // Members which aren't active in the program are ignored.
+        device.expect_storage_block_layout(&[("Particles.count", 16, 0), ("Unused.count", 4, 0)]);
+        let pipeline = create();
+        assert!(device.check_compute_storage_block_layout(&pipeline));
+
+        device.expect_storage_block_layout(&[("Particles.data[0]", 32, 16)]);
+        let pipeline = create();
+        assert!(!device.check_compute_storage_block_layout(&pipeline));
+        hal::Device::destroy_compute_pipeline(&device, pipeline);
     }
 
-    fn destroy_query_pool(&self, _: ()) {
-        unimplemented!()
+    #[test]
+    fn test_create_zeroed_image() {
+        let create = |device: &Device, kind, format| device.create_zeroed_image(
+            kind, 1, format, i::Tiling::Optimal, i::Usage::SAMPLED | i::Usage::DEPTH_STENCIL_ATTACHMENT,
+            i::StorageFlags::empty(),
+        ).unwrap();
+
+        let (device, _queue) = test_utils::open();
+        test_utils::clear_calls();
+        create(&device, i::Kind::D2(4, 4, 1, 1), format::Format::D32FloatS8Uint);
+        assert_eq!(
+            test_utils::calls("ClearTexImage").last().map(|args| args[2 .. 4].to_vec()),
+            Some(vec![gl::DEPTH_STENCIL as i64, gl::FLOAT_32_UNSIGNED_INT_24_8_REV as i64]),
+        );
+
+        // Without `glClearTexImage` each layer is attached to a framebuffer and cleared.
+        test_utils::set_version(b"4.3.0 Fake\0");
+        test_utils::set_integer(gl::STENCIL_WRITEMASK, 0x0F);
+        test_utils::set_integer(gl::STENCIL_BACK_WRITEMASK, 0xF0);
+        let (device, _queue) = test_utils::open();
+        test_utils::clear_calls();
+        let image = create(&device, i::Kind::D2(4, 4, 2, 1), format::Format::D24UnormS8Uint);
+        let texture = match image.image {
+            n::ImageKind::Texture(texture, gl::TEXTURE_2D_ARRAY) => texture as i64,
+            other => panic!("Unexpected image {:?}", other),
+        };
+        let attachment = gl::DEPTH_STENCIL_ATTACHMENT as i64;
+        assert_eq!(
+            test_utils::calls("FramebufferTextureLayer"),
+            vec![
+                vec![gl::DRAW_FRAMEBUFFER as i64, attachment, texture, 0, 0],
+                vec![gl::DRAW_FRAMEBUFFER as i64, attachment, texture, 0, 1],
+            ],
+        );
+        assert!(test_utils::calls("FramebufferTexture").is_empty());
+        assert_eq!(test_utils::calls("ClearBufferfi").len(), 2);
+        assert_eq!(
+            test_utils::calls("StencilMaskSeparate"),
+            vec![vec![gl::FRONT as i64, 0x0F], vec![gl::BACK as i64, 0xF0]],
+        );
     }
 
-    fn destroy_shader_module(&self, _: n::ShaderModule) {
-        // Assumes compiled shaders are managed internally
+    #[test]
+    fn test_copy_buffer_to_compressed_image_pitch() {
+        use hal::command::{BufferImageCopy, RawCommandBuffer};
+
+        let (device, mut queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 1024, buffer::Usage::TRANSFER_SRC).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 1024).unwrap();
+        let staging = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        let mut copy = |image: &n::Image, buffer_width, layers| {
+            let mut commands = test_utils::command_buffer(&device);
+            commands.copy_buffer_to_image(&staging, image, i::Layout::TransferDstOptimal, Some(BufferImageCopy {
+                buffer_offset: 16,
+                buffer_width,
+                buffer_height: 0,
+                image_layers: i::SubresourceLayers { aspects: format::Aspects::COLOR, level: 0, layers },
+                image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                image_extent: i::Extent { width: 8, height: 6, depth: 1 },
+            }));
+            test_utils::clear_calls();
+            test_utils::submit(&mut queue, commands, None);
+        };
+
+        // Blocks of 4x4 texels with 8 bytes each, uploaded at once when tightly packed.
+        let image = test_utils::create_image(
+            &device, i::Kind::D2(8, 8, 1, 1), 1, format::Format::Bc1RgbaUnorm,
+            i::Usage::SAMPLED | i::Usage::TRANSFER_DST, i::StorageFlags::empty(),
+        ).unwrap();
+        copy(&image, 0, 0 .. 1);
+        assert_eq!(
+            test_utils::calls("CompressedTexSubImage2D"),
+            vec![vec![gl::TEXTURE_2D as i64, 0, 0, 0, 8, 6, ext::COMPRESSED_RGBA_S3TC_DXT1_EXT as i64, 32, 16]],
+        );
+
+        // Rows of 16 texels, of which the first 8 are uploaded per row of blocks.
+        copy(&image, 16, 0 .. 1);
+        assert_eq!(
+            test_utils::calls("CompressedTexSubImage2D"),
+            vec![
+                vec![gl::TEXTURE_2D as i64, 0, 0, 0, 8, 4, ext::COMPRESSED_RGBA_S3TC_DXT1_EXT as i64, 16, 16],
+                vec![gl::TEXTURE_2D as i64, 0, 0, 4, 8, 2, ext::COMPRESSED_RGBA_S3TC_DXT1_EXT as i64, 16, 48],
+            ],
+        );
+
+        // Layers follow each other after two rows of blocks.
+        let array = test_utils::create_image(
+            &device, i::Kind::D2(8, 8, 3, 1), 1, format::Format::Bc1RgbaUnorm,
+            i::Usage::SAMPLED | i::Usage::TRANSFER_DST, i::StorageFlags::empty(),
+        ).unwrap();
+        copy(&array, 0, 1 .. 3);
+        assert!(test_utils::calls("CompressedTexSubImage2D").is_empty());
+        assert_eq!(
+            test_utils::calls("CompressedTexSubImage3D"),
+            vec![
+                vec![gl::TEXTURE_2D_ARRAY as i64, 0, 0, 0, 1, 8, 6, 1, ext::COMPRESSED_RGBA_S3TC_DXT1_EXT as i64, 32, 16],
+                vec![gl::TEXTURE_2D_ARRAY as i64, 0, 0, 0, 2, 8, 6, 1, ext::COMPRESSED_RGBA_S3TC_DXT1_EXT as i64, 32, 48],
+            ],
+        );
     }
 
-    fn destroy_render_pass(&self, _: n::RenderPass) {
-        // Nothing to do
+    #[test]
+    fn test_depth_resolve_modes() {
+        test_utils::set_version(b"3.1.0 Fake\0");
+        let adapter = test_utils::adapter();
+        assert!(adapter.physical_device.supports_depth_resolve_mode(n::DepthResolveMode::SampleZero));
+        // Shader resolves require multisample textures.
+        assert!(!adapter.physical_device.supports_depth_resolve_mode(n::DepthResolveMode::Min));
+
+        test_utils::set_version(b"3.2.0 Fake\0");
+        let adapter = test_utils::adapter();
+        assert!(adapter.physical_device.supports_depth_resolve_mode(n::DepthResolveMode::Min));
+        assert!(adapter.physical_device.supports_depth_resolve_mode(n::DepthResolveMode::Max));
     }
 
-    fn destroy_pipeline_layout(&self, _: n::PipelineLayout) {
-        // Nothing to do
+    #[test]
+    fn test_min_depth_resolve() {
+        let (device, mut queue) = test_utils::open();
+        let src = test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 1, 4), 1, format::Format::D32Float,
+            i::Usage::SAMPLED | i::Usage::DEPTH_STENCIL_ATTACHMENT, i::StorageFlags::empty(),
+        ).unwrap();
+        let dst = test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 1, 1), 1, format::Format::D32Float,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT, i::StorageFlags::empty(),
+        ).unwrap();
+        assert_eq!(
+            test_utils::calls("TexImage2DMultisample"),
+            vec![vec![gl::TEXTURE_2D_MULTISAMPLE as i64, 4, gl::DEPTH_COMPONENT32F as i64, 16, 16, gl::TRUE as i64]],
+        );
+
+        let layers = i::SubresourceLayers { aspects: format::Aspects::DEPTH, level: 0, layers: 0 .. 1 };
+        let region = hal::command::ImageResolve {
+            src_subresource: layers.clone(),
+            src_offset: i::Offset::ZERO,
+            dst_subresource: layers,
+            dst_offset: i::Offset::ZERO,
+            extent: i::Extent { width: 16, height: 16, depth: 1 },
+        };
+        let mut buffer = test_utils::command_buffer(&device);
+        buffer.resolve_depth_stencil_image(&src, &dst, n::DepthResolveMode::Min, Some(region));
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, buffer, None);
+
+        // The depth is written by a shader pass instead of a blit.
+        assert!(test_utils::calls("BlitFramebuffer").is_empty());
+        assert!(test_utils::calls("DrawBuffer").is_empty());
+        assert_eq!(test_utils::calls("DrawArrays"), vec![vec![gl::TRIANGLES as i64, 0, 3]]);
+        assert!(test_utils::calls("Uniform1i").contains(&vec![-1, 4]));
+        assert!(test_utils::calls("DepthFunc").contains(&vec![gl::ALWAYS as i64]));
     }
 
-    fn destroy_graphics_pipeline(&self, pipeline: n::GraphicsPipeline) {
-        unsafe {
-            self.share.context.DeleteProgram(pipeline.program);
-        }
+    #[test]
+    fn test_buffer_dynamic_storage() {
+        let (device, _queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 256, buffer::Usage::UNIFORM | buffer::Usage::TRANSFER_DST).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 256).unwrap();
+        hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        let storage = test_utils::calls("BufferStorage");
+        assert_eq!(storage.len(), 1);
+        assert_ne!(storage[0][3] & gl::DYNAMIC_STORAGE_BIT as i64, 0);
+        assert_eq!(storage[0][3] & gl::MAP_FLUSH_EXPLICIT_BIT as i64, 0);
     }
 
-    fn destroy_compute_pipeline(&self, pipeline: n::ComputePipeline) {
-        unsafe {
-            self.share.context.DeleteProgram(pipeline.program);
-        }
+    #[test]
+    fn test_buffer_view_errors() {
+        let (device, _queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 256, buffer::Usage::UNIFORM_TEXEL).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 256).unwrap();
+        let buffer = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        let format = Some(format::Format::Rgba8Unorm);
+
+        assert_eq!(
+            hal::Device::create_buffer_view(&device, &buffer, format, 128 .. 512).unwrap_err(),
+            buffer::ViewCreationError::OutOfBounds,
+        );
+        assert_eq!(
+            hal::Device::create_buffer_view(&device, &buffer, format, 4 .. 128).unwrap_err(),
+            buffer::ViewCreationError::UnalignedOffset { offset: 4 },
+        );
+        let view = hal::Device::create_buffer_view(&device, &buffer, format, 64 .. 128).unwrap();
+        assert_eq!(view.format, gl::RGBA8);
     }
 
-    fn destroy_framebuffer(&self, frame_buffer: n::FrameBuffer) {
-        let gl = &self.share.context;
-        unsafe { gl.DeleteFramebuffers(1, &frame_buffer); }
+    #[test]
+    fn test_non_coherent_atom_size() {
+        use hal::PhysicalDevice;
+
+        test_utils::set_integer(gl::MIN_MAP_BUFFER_ALIGNMENT, 256);
+        let adapter = test_utils::adapter();
+        assert_eq!(adapter.physical_device.limits().non_coherent_atom_size, 256);
+
+        // Drivers reporting less than the spec minimum are rounded up.
+        test_utils::set_integer(gl::MIN_MAP_BUFFER_ALIGNMENT, 16);
+        let adapter = test_utils::adapter();
+        assert_eq!(adapter.physical_device.limits().non_coherent_atom_size, 64);
     }
 
-    fn destroy_buffer(&self, buffer: n::Buffer) {
-        unsafe {
-            self.share.context.DeleteBuffers(1, &buffer.raw);
-        }
+    #[test]
+    fn test_pixel_buffer_binding_restored() {
+        use hal::command::RawCommandBuffer;
+
+        let (device, mut queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 256, buffer::Usage::TRANSFER_SRC | buffer::Usage::TRANSFER_DST).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(3), 256).unwrap();
+        let buffer = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        test_utils::set_integer(gl::PIXEL_PACK_BUFFER_BINDING, 7);
+        test_utils::set_integer(gl::PIXEL_UNPACK_BUFFER_BINDING, 8);
+        test_utils::clear_calls();
+
+        hal::Device::map_memory(&device, &memory, 0 .. 256).unwrap();
+        hal::Device::flush_mapped_memory_ranges(&device, Some((&memory, 0 .. 64)));
+        let mut commands = test_utils::command_buffer(&device);
+        commands.copy_buffer(&buffer, &buffer, &[hal::command::BufferCopy { src: 0, dst: 128, size: 64 }]);
+        test_utils::submit(&mut queue, commands, None);
+
+        // Buffers bound by the application aren't replaced by `0`.
+        let bindings = test_utils::calls("BindBuffer");
+        let last = |target: gl::types::GLenum| bindings.iter().rev().find(|call| call[0] == target as i64).cloned();
+        assert_eq!(last(gl::PIXEL_PACK_BUFFER), Some(vec![gl::PIXEL_PACK_BUFFER as i64, 7]));
+        assert_eq!(last(gl::PIXEL_UNPACK_BUFFER), Some(vec![gl::PIXEL_UNPACK_BUFFER as i64, 8]));
+        let pixel_targets = [gl::PIXEL_PACK_BUFFER as i64, gl::PIXEL_UNPACK_BUFFER as i64];
+        assert!(!bindings.iter().any(|call| pixel_targets.contains(&call[0]) && call[1] == 0));
     }
-    fn destroy_buffer_view(&self, _: n::BufferView) {
-        // Nothing to do
+
+    #[test]
+    fn test_flush_unaligned_range() {
+        test_utils::set_integer(gl::MIN_MAP_BUFFER_ALIGNMENT, 64);
+        let (device, _queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 240, buffer::Usage::TRANSFER_SRC).unwrap();
+        // Visible but not coherent, mapped with `GL_MAP_FLUSH_EXPLICIT_BIT`.
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(3), 240).unwrap();
+        hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        hal::Device::map_memory(&device, &memory, 0 .. 240).unwrap();
+        test_utils::clear_calls();
+
+        // Rounded outwards to the atom size.
+        hal::Device::flush_mapped_memory_ranges(&device, Some((&memory, 70 .. 130)));
+        // Clamped to the end of the mapping, which isn't a multiple of the atom size.
+        hal::Device::flush_mapped_memory_ranges(&device, Some((&memory, 200 .. 239)));
+        assert_eq!(test_utils::calls("FlushMappedBufferRange"), vec![
+            vec![gl::PIXEL_PACK_BUFFER as i64, 64, 128],
+            vec![gl::PIXEL_PACK_BUFFER as i64, 192, 48],
+        ]);
     }
 
-    fn destroy_image(&self, image: n::Image) {
-        let gl = &self.share.context;
-        match image.kind {
-            n::ImageKind::Surface(rb) => unsafe { gl.DeleteRenderbuffers(1, &rb) },
-            n::ImageKind::Texture(t) => unsafe { gl.DeleteTextures(1, &t) },
-        }
+    #[test]
+    fn test_bind_pipeline_depth_write() {
+        use hal::command::RawCommandBuffer;
+
+        let (device, mut queue) = test_utils::open();
+        let pipeline = n::GraphicsPipeline {
+            depth: pso::DepthTest::On { fun: pso::Comparison::Less, write: false },
+            .. test_utils::graphics_pipeline()
+        };
+        let mut buffer = test_utils::command_buffer(&device);
+        buffer.bind_graphics_pipeline(&pipeline);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, buffer, None);
+
+        assert!(test_utils::calls("Enable").contains(&vec![gl::DEPTH_TEST as i64]));
+        assert_eq!(test_utils::calls("DepthMask").last(), Some(&vec![gl::FALSE as i64]));
     }
 
-    fn destroy_image_view(&self, _image_view: n::ImageView) {
-        // Nothing to do
+    #[test]
+    fn test_bind_pipeline_smooth_unsupported() {
+        use hal::command::RawCommandBuffer;
+
+        // Line and polygon smoothing aren't available on GLES.
+        test_utils::set_version(b"OpenGL ES 3.2 Fake\0");
+        let (device, mut queue) = test_utils::open();
+        let mut buffer = test_utils::command_buffer(&device);
+        buffer.bind_graphics_pipeline(&test_utils::graphics_pipeline());
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, buffer, None);
+
+        let smooth = [gl::LINE_SMOOTH as i64, gl::POLYGON_SMOOTH as i64];
+        assert!(test_utils::calls("Disable").iter().all(|args| !smooth.contains(&args[0])));
+        assert!(test_utils::calls("Enable").iter().all(|args| !smooth.contains(&args[0])));
     }
 
-    fn destroy_sampler(&self, sampler: n::FatSampler) {
-        let gl = &self.share.context;
-        match sampler {
-            n::FatSampler::Sampler(s) => unsafe { gl.DeleteSamplers(1, &s) },
-            _ => (),
+    #[test]
+    fn test_default_framebuffer_status_cached() {
+        use hal::command::{ClearValueRaw, RawCommandBuffer, SubpassContents};
+
+        let (device, mut queue) = test_utils::open();
+        let render_pass = n::RenderPass::fixture(&[1], &[(&[0], &[], &[], &[])]);
+        let area = pso::Rect { x: 0, y: 0, w: 16, h: 16 };
+        let mut commands = test_utils::command_buffer(&device);
+        for _ in 0 .. 2 {
+            commands.begin_render_pass(
+                &render_pass, &n::DEFAULT_FRAMEBUFFER, area, &[] as &[ClearValueRaw], SubpassContents::Inline,
+            );
+            commands.end_render_pass();
         }
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+        assert_eq!(test_utils::calls("CheckFramebufferStatus").len(), 1);
+
+        // Framebuffer objects are checked when created.
+        let mut commands = test_utils::command_buffer(&device);
+        commands.begin_render_pass(&render_pass, &1, area, &[] as &[ClearValueRaw], SubpassContents::Inline);
+        commands.end_render_pass();
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+        assert!(test_utils::calls("CheckFramebufferStatus").is_empty());
     }
 
-    fn destroy_descriptor_pool(&self, _: n::DescriptorPool) {
-        // Nothing to do
+    #[test]
+    fn test_clear_attachments_skipped() {
+        use hal::command::{AttachmentClear, ClearColor, ClearValueRaw, RawCommandBuffer, SubpassContents};
+
+        let (device, mut queue) = test_utils::open();
+        let render_pass = n::RenderPass::fixture(&[1], &[(&[0], &[], &[], &[])]);
+        let area = pso::Rect { x: 0, y: 0, w: 16, h: 16 };
+        let rect = pso::Rect { x: 4, y: 4, w: 8, h: 8 };
+        let mut commands = test_utils::command_buffer(&device);
+        commands.begin_render_pass(&render_pass, &1, area, &[] as &[ClearValueRaw], SubpassContents::Inline);
+        // The subpass has a single color attachment.
+        commands.clear_attachments(
+            &[AttachmentClear::Color { index: 1, value: ClearColor::Float([1.0; 4]) }],
+            &[pso::ClearRect { rect, layers: 0 .. 1 }],
+        );
+        // Layers beyond the first can't be cleared on their own.
+        commands.clear_attachments(
+            &[AttachmentClear::Color { index: 0, value: ClearColor::Float([1.0; 4]) }],
+            &[pso::ClearRect { rect, layers: 1 .. 2 }, pso::ClearRect { rect, layers: 0 .. 2 }],
+        );
+        commands.end_render_pass();
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        assert_eq!(test_utils::calls("ClearBufferfv").len(), 1);
     }
 
-    fn destroy_descriptor_set_layout(&self, _: n::DescriptorSetLayout) {
-        // Nothing to do
+    #[test]
+    fn test_clear_attachments_unmasked() {
+        use hal::command::{AttachmentClear, ClearColor, ClearValueRaw, RawCommandBuffer, SubpassContents};
+
+        let (device, mut queue) = test_utils::open();
+        let render_pass = n::RenderPass::fixture(&[1], &[(&[0], &[], &[], &[])]);
+        let area = pso::Rect { x: 0, y: 0, w: 16, h: 16 };
+        let mut commands = test_utils::command_buffer(&device);
+        commands.begin_render_pass(&render_pass, &1, area, &[] as &[ClearValueRaw], SubpassContents::Inline);
+        commands.clear_attachments(
+            &[AttachmentClear::Color { index: 0, value: ClearColor::Float([1.0; 4]) }],
+            &[pso::ClearRect { rect: pso::Rect { x: 4, y: 4, w: 8, h: 8 }, layers: 0 .. 1 }],
+        );
+        commands.end_render_pass();
+        // Color writes are disabled by the bound pipeline.
+        test_utils::set_integer(gl::COLOR_WRITEMASK, 0);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        let names = test_utils::call_names()
+            .into_iter()
+            .filter(|&name| name == "ColorMaski" || name == "ClearBufferfv")
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["ColorMaski", "ClearBufferfv", "ColorMaski"]);
+        assert_eq!(test_utils::calls("ColorMaski"), vec![vec![0, 1, 1, 1, 1], vec![0, 0, 0, 0, 0]]);
+        // Only the scissor of the first viewport is changed and restored.
+        assert!(test_utils::calls("Scissor").is_empty());
+        let scissors = test_utils::calls("ScissorArrayv");
+        assert_eq!(scissors.len(), 3);
+        assert!(scissors.iter().all(|args| args[.. 2] == [0, 1]));
     }
 
-    fn destroy_fence(&self, fence: n::Fence) {
-        unsafe {
-            self.share.context.DeleteSync(fence.0.get());
+    #[test]
+    fn test_transform_feedback_pause() {
+        use hal::command::RawCommandBuffer;
+
+        let (device, mut queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 256, buffer::Usage::STORAGE).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 256).unwrap();
+        let buffer = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+
+        let mut commands = test_utils::command_buffer(&device);
+        commands.bind_graphics_pipeline(&test_utils::graphics_pipeline());
+        commands.begin_transform_feedback(&[(&buffer, 0 .. 256)]);
+        commands.pause_transform_feedback();
+        commands.resume_transform_feedback();
+        commands.end_transform_feedback();
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        // Resuming keeps the bound buffer range, appending at the write offset of the pause.
+        let names = test_utils::call_names()
+            .into_iter()
+            .filter(|name| name.contains("TransformFeedback") || *name == "BindBufferRange")
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![
+            "BindBufferRange", "BeginTransformFeedback", "PauseTransformFeedback",
+            "ResumeTransformFeedback", "EndTransformFeedback",
+        ]);
+    }
+
+    #[test]
+    fn test_transform_feedback_pause_unsupported() {
+        use hal::command::RawCommandBuffer;
+
+        test_utils::set_version(b"3.3.0 Fake\0");
+        let (device, _queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 256, buffer::Usage::STORAGE).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 256).unwrap();
+        let buffer = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+
+        let mut commands = test_utils::command_buffer(&device);
+        commands.bind_graphics_pipeline(&test_utils::graphics_pipeline());
+        commands.begin_transform_feedback(&[(&buffer, 0 .. 256)]);
+        let recorded = commands.buf.size;
+        // Rejected while recording instead of being skipped on submission.
+        commands.pause_transform_feedback();
+        assert_eq!(commands.buf.size, recorded);
+    }
+
+    #[test]
+    fn test_sampler_info_applied_on_bind() {
+        use hal::command::RawCommandBuffer;
+
+        // Sampler objects require GL 3.3.
+        test_utils::set_version(b"3.2.0 Fake\0");
+        let (device, mut queue) = test_utils::open();
+        test_utils::clear_calls();
+        let layout = hal::Device::create_descriptor_set_layout(&device, &[pso::DescriptorSetLayoutBinding {
+            binding: 0,
+            ty: pso::DescriptorType::CombinedImageSampler,
+            count: 1,
+            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        }], Vec::<n::FatSampler>::new());
+        let no_push_constants: &[(pso::ShaderStageFlags, std::ops::Range<u32>)] = &[];
+        let pipeline_layout = hal::Device::create_pipeline_layout(&device, Some(&layout), no_push_constants);
+        let view = n::ImageView::Texture(3, gl::TEXTURE_2D, 0);
+
+        for &(comparison, mode) in &[
+            (Some(pso::Comparison::Less), gl::COMPARE_REF_TO_TEXTURE),
+            (None, gl::NONE),
+        ] {
+            let sampler = hal::Device::create_sampler(&device, i::SamplerInfo {
+                comparison,
+                .. i::SamplerInfo::new(i::Filter::Linear, i::WrapMode::Clamp)
+            });
+            match sampler {
+                n::FatSampler::Info(_) => (),
+                _ => panic!("Expected the sampler info without sampler objects"),
+            }
+            let set = pso::DescriptorPool::allocate_set(&mut n::DescriptorPool {}, &layout).unwrap();
+            hal::Device::write_descriptor_sets(&device, Some(pso::DescriptorSetWrite {
+                set: &set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::CombinedImageSampler(&view, i::Layout::General, &sampler)),
+            }));
+            // Nothing is applied to the texture until drawing with the set bound.
+            assert!(test_utils::calls("TexParameteri").is_empty());
+
+            let mut commands = test_utils::command_buffer(&device);
+            commands.bind_graphics_pipeline(&test_utils::graphics_pipeline());
+            commands.bind_graphics_descriptor_sets(&pipeline_layout, 0, Some(&set), None::<hal::command::DescriptorSetOffset>);
+            commands.draw(0 .. 3, 0 .. 1);
+            test_utils::submit(&mut queue, commands, None);
+            let params = test_utils::calls("TexParameteri");
+            assert!(params.contains(&vec![gl::TEXTURE_2D as i64, gl::TEXTURE_COMPARE_MODE as i64, mode as i64]));
+            assert_eq!(
+                comparison.is_some(),
+                params.contains(&vec![gl::TEXTURE_2D as i64, gl::TEXTURE_COMPARE_FUNC as i64, gl::LESS as i64]),
+            );
+            // The texture stays bound to its unit.
+            assert_eq!(test_utils::calls("BindTexture").last(), Some(&vec![gl::TEXTURE_2D as i64, 3]));
+            test_utils::clear_calls();
         }
     }
 
-    fn destroy_semaphore(&self, _: n::Semaphore) {
-        // Nothing to do
+    #[test]
+    fn test_incomplete_texture_levels_clamped() {
+        use hal::command::{BufferImageCopy, RawCommandBuffer};
+
+        test_utils::set_integer(gl::TEXTURE_IMMUTABLE_FORMAT, gl::TRUE as _);
+        test_utils::set_integer(gl::TEXTURE_IMMUTABLE_LEVELS, 4);
+        test_utils::set_integer(gl::TEXTURE_MAX_LEVEL, 1000);
+        let adapter = test_utils::adapter();
+        adapter.physical_device.set_options(::Options { fix_incomplete_textures: true, .. Default::default() });
+        let (device, mut queue) = test_utils::open_adapter(&adapter);
+
+        let unbound = hal::Device::create_buffer(&device, 1024, buffer::Usage::TRANSFER_SRC).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 1024).unwrap();
+        let staging = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        let image = test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 1, 1), 4, format::Format::Rgba8Unorm,
+            i::Usage::SAMPLED | i::Usage::TRANSFER_DST, i::StorageFlags::empty(),
+        ).unwrap();
+        let texture = match image.kind {
+            n::ImageKind::Texture(texture, _) => texture,
+            n::ImageKind::Surface(_) => unreachable!(),
+        };
+
+        let layout = hal::Device::create_descriptor_set_layout(&device, &[pso::DescriptorSetLayoutBinding {
+            binding: 0,
+            ty: pso::DescriptorType::CombinedImageSampler,
+            count: 1,
+            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        }], Vec::<n::FatSampler>::new());
+        let no_push_constants: &[(pso::ShaderStageFlags, std::ops::Range<u32>)] = &[];
+        let pipeline_layout = hal::Device::create_pipeline_layout(&device, Some(&layout), no_push_constants);
+        let sampler = hal::Device::create_sampler(&device, i::SamplerInfo::new(i::Filter::Linear, i::WrapMode::Clamp));
+        let view = n::ImageView::Texture(texture, gl::TEXTURE_2D, 0);
+        let set = pso::DescriptorPool::allocate_set(&mut n::DescriptorPool {}, &layout).unwrap();
+        hal::Device::write_descriptor_sets(&device, Some(pso::DescriptorSetWrite {
+            set: &set,
+            binding: 0,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::CombinedImageSampler(&view, i::Layout::General, &sampler)),
+        }));
+
+        // Only the base level of the four levels is uploaded.
+        let mut commands = test_utils::command_buffer(&device);
+        commands.copy_buffer_to_image(&staging, &image, i::Layout::TransferDstOptimal, Some(BufferImageCopy {
+            buffer_offset: 0,
+            buffer_width: 16,
+            buffer_height: 16,
+            image_layers: i::SubresourceLayers { aspects: format::Aspects::COLOR, level: 0, layers: 0 .. 1 },
+            image_offset: i::Offset { x: 0, y: 0, z: 0 },
+            image_extent: i::Extent { width: 16, height: 16, depth: 1 },
+        }));
+        commands.bind_graphics_pipeline(&test_utils::graphics_pipeline());
+        commands.bind_graphics_descriptor_sets(&pipeline_layout, 0, Some(&set), None::<hal::command::DescriptorSetOffset>);
+        commands.draw(0 .. 3, 0 .. 1);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        assert!(test_utils::calls("TexParameteri").contains(&vec![gl::TEXTURE_2D as i64, gl::TEXTURE_MAX_LEVEL as i64, 0]));
     }
 
-    fn create_swapchain(
-        &self,
-        surface: &mut Surface,
-        config: c::SwapchainConfig,
-        _old_swapchain: Option<Swapchain>,
-        _extent: &window::Extent2D,
-    ) -> (Swapchain, c::Backbuffer<B>) {
-        self.create_swapchain_impl(surface, config)
+    #[test]
+    fn test_write_buffer_descriptor_range() {
+        use hal::command::RawCommandBuffer;
+
+        let (device, mut queue) = test_utils::open();
+        let unbound = hal::Device::create_buffer(&device, 256, buffer::Usage::STORAGE).unwrap();
+        let memory = hal::Device::allocate_memory(&device, hal::MemoryTypeId(0), 256).unwrap();
+        let buffer = hal::Device::bind_buffer_memory(&device, &memory, 0, unbound).unwrap();
+        let layout = hal::Device::create_descriptor_set_layout(&device, &[pso::DescriptorSetLayoutBinding {
+            binding: 0,
+            ty: pso::DescriptorType::StorageBuffer,
+            count: 1,
+            stage_flags: pso::ShaderStageFlags::COMPUTE,
+            immutable_samplers: false,
+        }], Vec::<n::FatSampler>::new());
+        let no_push_constants: &[(pso::ShaderStageFlags, std::ops::Range<u32>)] = &[];
+        let pipeline_layout = hal::Device::create_pipeline_layout(&device, Some(&layout), no_push_constants);
+        let set = pso::DescriptorPool::allocate_set(&mut n::DescriptorPool {}, &layout).unwrap();
+        hal::Device::write_descriptor_sets(&device, Some(pso::DescriptorSetWrite {
+            set: &set,
+            binding: 0,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::Buffer(&buffer, Some(64) .. Some(192))),
+        }));
+
+        let mut commands = test_utils::command_buffer(&device);
+        commands.bind_compute_descriptor_sets(&pipeline_layout, 0, Some(&set), None::<hal::command::DescriptorSetOffset>);
+        test_utils::clear_calls();
+        test_utils::submit(&mut queue, commands, None);
+
+        // The range starts at the offset of the descriptor.
+        assert_eq!(
+            test_utils::calls("BindBufferRange"),
+            vec![vec![gl::SHADER_STORAGE_BUFFER as i64, 0, buffer.raw as i64, 64, 128]],
+        );
     }
 
-    fn destroy_swapchain(&self, _swapchain: Swapchain) {
-        // Nothing to do
+    #[test]
+    fn test_sampler_border_color() {
+        let (device, _queue) = test_utils::open();
+        test_utils::clear_calls();
+        let sampler = hal::Device::create_sampler(&device, i::SamplerInfo {
+            border: i::PackedColor(0xFF00_00FF),
+            .. i::SamplerInfo::new(i::Filter::Linear, i::WrapMode::Border)
+        });
+        let name = match sampler {
+            n::FatSampler::Sampler(name) => name,
+            _ => panic!("Expected a sampler object"),
+        };
+        let red = [1.0f32, 0.0, 0.0, 1.0];
+        let mut expected = vec![name as i64, gl::TEXTURE_BORDER_COLOR as i64];
+        expected.extend(red.iter().map(|c| c.to_bits() as i64));
+        assert_eq!(test_utils::calls("SamplerParameterfv"), vec![expected]);
     }
 
-    fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
-        unsafe { self.share.context.Finish(); }
-        Ok(())
+    #[test]
+    fn test_blit_image_shader_restores_bindings() {
+        use hal::command::RawCommandBuffer;
+
+        let (device, mut queue) = test_utils::open();
+        let create = |format| test_utils::create_image(
+            &device, i::Kind::D2(16, 16, 1, 1), 1, format,
+            i::Usage::SAMPLED | i::Usage::COLOR_ATTACHMENT, i::StorageFlags::empty(),
+        ).unwrap();
+        // Normalized to integer formats can't be converted by `glBlitFramebuffer`.
+        let src = create(format::Format::Rgba8Unorm);
+        let dst = create(format::Format::Rgba8Uint);
+        let subresource = i::SubresourceLayers {
+            aspects: format::Aspects::COLOR,
+            level: 0,
+            layers: 0 .. 1,
+        };
+        let bounds = i::Offset { x: 0, y: 0, z: 0 } .. i::Offset { x: 16, y: 16, z: 1 };
+
+        test_utils::set_integer(gl::ACTIVE_TEXTURE, gl::TEXTURE3 as _);
+        test_utils::set_integer(gl::TEXTURE_BINDING_2D, 9);
+        test_utils::set_integer(gl::SAMPLER_BINDING, 4);
+        test_utils::clear_calls();
+        let mut commands = test_utils::command_buffer(&device);
+        commands.blit_image(&src, i::Layout::General, &dst, i::Layout::General, i::Filter::Nearest, &[
+            hal::command::ImageBlit {
+                src_subresource: subresource.clone(),
+                src_bounds: bounds.clone(),
+                dst_subresource: subresource,
+                dst_bounds: bounds,
+            },
+        ]);
+        test_utils::submit(&mut queue, commands, None);
+
+        assert_eq!(test_utils::calls("DrawArrays").len(), 1);
+        // Attached without `glFramebufferTexture`, which requires GL 3.2.
+        assert!(test_utils::calls("FramebufferTexture").is_empty());
+        assert_eq!(test_utils::calls("FramebufferTexture2D").len(), 1);
+        assert!(test_utils::calls("DrawBuffer").is_empty());
+        assert_eq!(test_utils::calls("DrawBuffers").len(), 1);
+        assert_eq!(test_utils::calls("BindTexture").last(), Some(&vec![gl::TEXTURE_2D as i64, 9]));
+        assert_eq!(test_utils::calls("BindSampler").last(), Some(&vec![0, 4]));
+        assert_eq!(test_utils::calls("ActiveTexture").last(), Some(&vec![gl::TEXTURE3 as i64]));
+    }
+
+    #[test]
+    fn test_inject_early_fragment_tests() {
+        let source = "#version 450\n#extension GL_ARB_separate_shader_objects : enable\n\nlayout(location = 0) out vec4 color;\n";
+        assert_eq!(
+            inject_early_fragment_tests(source, false),
+            "#version 450\n#extension GL_ARB_separate_shader_objects : enable\n\
+             layout(early_fragment_tests) in;\n\nlayout(location = 0) out vec4 color;\n",
+        );
+        assert_eq!(
+            inject_early_fragment_tests("#version 330\nvoid main() {}\n", true),
+            "#version 330\n#extension GL_ARB_shader_image_load_store : require\n\
+             layout(early_fragment_tests) in;\nvoid main() {}\n",
+        );
+        // Shaders declaring it already are left untouched.
+        let declared = "#version 450\nlayout(early_fragment_tests) in;\nvoid main() {}\n";
+        assert_eq!(inject_early_fragment_tests(declared, false), declared);
+    }
+
+    #[test]
+    fn test_inject_per_vertex() {
+        let source = "#version 450\nlayout(location = 0) in vec4 a_Pos;\nvoid main() { gl_Position = a_Pos; }\n";
+        assert_eq!(
+            inject_per_vertex(source, pso::Stage::Vertex),
+            "#version 450\nout gl_PerVertex\n{\n    vec4 gl_Position;\n    float gl_PointSize;\n};\n\
+             layout(location = 0) in vec4 a_Pos;\nvoid main() { gl_Position = a_Pos; }\n",
+        );
+        let geometry = inject_per_vertex("#version 450\nvoid main() { gl_ClipDistance[0] = 0.0; }\n", pso::Stage::Geometry);
+        assert!(geometry.contains("    float gl_ClipDistance[];\n} gl_in[];\nout gl_PerVertex\n"));
+        // Fragment shaders and sources declaring the blocks are left untouched.
+        assert_eq!(inject_per_vertex(source, pso::Stage::Fragment), source);
+        let declared = "#version 450\nout gl_PerVertex { vec4 gl_Position; };\nvoid main() {}\n";
+        assert_eq!(inject_per_vertex(declared, pso::Stage::Vertex), declared);
+    }
+
+    #[test]
+    fn test_texture_layer_range_view() {
+        assert_eq!(
+            n::ImageView::texture_layer_range(3, gl::TEXTURE_2D_ARRAY, 0, 2 .. 5, 6),
+            Ok(n::ImageView::TextureLayerRange(3, gl::TEXTURE_2D_ARRAY, 0, 2 .. 5)),
+        );
+        assert_eq!(
+            n::ImageView::texture_layer_range(3, gl::TEXTURE_2D_ARRAY, 0, 2 .. 2, 6),
+            Err(i::ViewError::Layer(i::LayerError::OutOfBounds(2 .. 2))),
+        );
+        assert!(n::ImageView::texture_layer_range(3, gl::TEXTURE_2D_ARRAY, 0, 4 .. 7, 6).is_err());
     }
-}
 
-pub fn wait_fence(fence: &n::Fence, gl: &gl::Gl, timeout_ns: u64) -> GLenum {
-    // TODO:
-    // This can be called by multiple objects wanting to ensure they have exclusive
-    // access to a resource. How much does this call costs ? The status of the fence
-    // could be cached to avoid calling this more than once (in core or in the backend ?).
-    unsafe { gl.ClientWaitSync(fence.0.get(), gl::SYNC_FLUSH_COMMANDS_BIT, timeout_ns) }
+    #[test]
+    fn test_clamp_anisotropy() {
+        assert_eq!(clamp_anisotropy(i::Anisotropic::On(16), 8.0), 8.0);
+        assert_eq!(clamp_anisotropy(i::Anisotropic::On(4), 16.0), 4.0);
+        // Unsupported anisotropic filtering reports a maximum of 1.
+        assert_eq!(clamp_anisotropy(i::Anisotropic::On(16), 1.0), 1.0);
+        assert_eq!(clamp_anisotropy(i::Anisotropic::Off, 16.0), 1.0);
+    }
 }
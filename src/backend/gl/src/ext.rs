@@ -0,0 +1,80 @@
+//! Enums and entry points of extensions missing from the generated bindings.
+
+use gl::types::*;
+use std::mem;
+use std::os::raw::c_void;
+
+// GL_ARB_sample_locations, GL_NV_sample_locations
+pub const FRAMEBUFFER_PROGRAMMABLE_SAMPLE_LOCATIONS_ARB: GLenum = 0x9342;
+
+// GL_EXT_texture_compression_s3tc
+pub const COMPRESSED_RGB_S3TC_DXT1_EXT: GLenum = 0x83F0;
+pub const COMPRESSED_RGBA_S3TC_DXT1_EXT: GLenum = 0x83F1;
+pub const COMPRESSED_RGBA_S3TC_DXT5_EXT: GLenum = 0x83F3;
+
+// GL_KHR_texture_compression_astc_ldr
+#[allow(non_upper_case_globals)]
+pub const COMPRESSED_RGBA_ASTC_4x4_KHR: GLenum = 0x93B0;
+#[allow(non_upper_case_globals)]
+pub const COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR: GLenum = 0x93D0;
+
+// GL_EXT_depth_bounds_test
+pub const DEPTH_BOUNDS_TEST_EXT: GLenum = 0x8890;
+
+// GL_NV_shader_buffer_load
+pub const BUFFER_GPU_ADDRESS_NV: GLenum = 0x8F1D;
+
+macro_rules! functions {
+    ($($name:ident($($arg:ident: $ty:ty),*);)*) => {
+        /// Function table of the extension entry points,
+        /// loaded with the same loader as the core functions.
+        #[allow(non_snake_case)]
+        pub struct ExtGl {
+            $($name: Option<extern "system" fn($($ty),*)>,)*
+        }
+
+        impl ExtGl {
+            pub fn load_with<F>(mut loadfn: F) -> Self
+            where
+                F: FnMut(&'static str) -> *const c_void,
+            {
+                ExtGl {
+                    $($name: unsafe { load(&mut loadfn, concat!("gl", stringify!($name))) },)*
+                }
+            }
+
+            $(
+                #[allow(non_snake_case)]
+                pub unsafe fn $name(&self, $($arg: $ty),*) {
+                    match self.$name {
+                        Some(function) => function($($arg),*),
+                        None => panic!(concat!("gl", stringify!($name), " is not loaded")),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+functions! {
+    DepthBoundsEXT(zmin: GLclampd, zmax: GLclampd);
+    FramebufferSampleLocationsfvARB(target: GLenum, start: GLuint, count: GLsizei, v: *const GLfloat);
+    FramebufferSampleLocationsfvNV(target: GLenum, start: GLuint, count: GLsizei, v: *const GLfloat);
+    GetBufferParameterui64vNV(target: GLenum, pname: GLenum, params: *mut GLuint64);
+    MakeBufferResidentNV(target: GLenum, access: GLenum);
+    MakeBufferNonResidentNV(target: GLenum);
+}
+
+// Function pointer of a symbol, `None` if the loader doesn't know it.
+unsafe fn load<F, T>(loadfn: &mut F, symbol: &'static str) -> Option<T>
+where
+    F: FnMut(&'static str) -> *const c_void,
+    T: Copy,
+{
+    let ptr = loadfn(symbol);
+    if ptr.is_null() {
+        None
+    } else {
+        Some(mem::transmute_copy(&ptr))
+    }
+}
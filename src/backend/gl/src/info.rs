@@ -2,6 +2,8 @@ use std::collections::HashSet;
 use std::{ffi, fmt, mem, str};
 use gl;
 use hal::{Features, Limits};
+use hal::image::NumSamples;
+use native::MAX_INPUT_ATTACHMENTS;
 
 /// A version number for a specific component of an OpenGL implementation
 #[derive(Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
@@ -130,6 +132,13 @@ fn get_usize(gl: &gl::Gl, name: gl::types::GLenum) -> usize {
     value as usize
 }
 
+/// Bitmask of the supported sample counts, with a bit for each power of two up to `max`.
+fn sample_count_mask(max: usize) -> NumSamples {
+    let max = max.max(1).min(128);
+    let highest = if max.is_power_of_two() { max } else { max.next_power_of_two() >> 1 };
+    ((highest << 1) - 1) as _
+}
+
 unsafe fn c_str_as_static_str(c_str: *const i8) -> &'static str {
     //TODO: avoid transmuting
     mem::transmute(str::from_utf8(ffi::CStr::from_ptr(c_str as *const _).to_bytes()).unwrap())
@@ -151,6 +160,27 @@ impl PlatformName {
             renderer: get_string(gl, gl::RENDERER),
         }
     }
+
+    /// PCI vendor id of known vendor strings, `0` if unknown.
+    pub fn vendor_id(&self) -> usize {
+        match self.vendor {
+            "NVIDIA Corporation" => 0x10DE,
+            "ATI Technologies Inc." | "Advanced Micro Devices, Inc." | "AMD" => 0x1002,
+            "Intel" | "Intel Inc." | "Intel Corporation" | "Intel Open Source Technology Center" => 0x8086,
+            "ARM" => 0x13B5,
+            "Qualcomm" => 0x5143,
+            "Imagination Technologies" => 0x1010,
+            _ => 0,
+        }
+    }
+
+    /// Check if the renderer is a software rasterizer.
+    pub fn is_software_renderer(&self) -> bool {
+        let renderer = self.renderer.to_lowercase();
+        ["llvmpipe", "softpipe", "swiftshader", "software rasterizer", "gdi generic", "basic render"]
+            .iter()
+            .any(|name| renderer.contains(name))
+    }
 }
 
 /// Private capabilities that don't need to be exposed.
@@ -175,8 +205,80 @@ pub struct PrivateCaps {
     pub sync: bool,
     /// Can map memory
     pub map: bool,
-    /// Indicates if we only have support via the EXT.
-    pub sampler_anisotropy_ext: bool,
+    /// Can discard framebuffer attachment contents via `glInvalidateFramebuffer`
+    pub framebuffer_invalidate: bool,
+    /// Supports `GL_LINE_SMOOTH` and `GL_POLYGON_SMOOTH`.
+    /// Not available on GLES and deprecated in core profiles.
+    pub primitive_smooth: bool,
+    /// Supports `GL_GENERATE_MIPMAP_HINT`, removed from core profiles.
+    pub generate_mipmap_hint: bool,
+    /// Can write query results into buffer objects.
+    pub query_buffer: bool,
+    /// Can switch the clip space depth to `0..1` via `glClipControl`.
+    pub clip_control: bool,
+    /// Supports `GL_DEPTH_CLAMP`.
+    pub depth_clamp: bool,
+    /// Can copy between images via `glCopyImageSubData`.
+    pub copy_image: bool,
+    /// Can alias a sub-range of a texture via `glTextureView`.
+    pub texture_view: bool,
+    /// Can order memory accesses via `glMemoryBarrier`.
+    pub memory_barrier: bool,
+    /// Can read back compressed textures via `glGetCompressedTexImage`.
+    pub compressed_readback: bool,
+    /// Can read back a region of a texture via `glGetCompressedTextureSubImage`.
+    pub get_texture_sub_image: bool,
+    /// Can program custom sample locations of framebuffers.
+    pub sample_locations: bool,
+    /// Indicates if we only have support via the NV extension.
+    pub sample_locations_nv: bool,
+    /// Can query GPU addresses of resident buffers via `GL_NV_shader_buffer_load`.
+    pub shader_buffer_load: bool,
+    /// Can set viewports with floating point bounds via `glViewportIndexedf`.
+    pub viewport_array: bool,
+    /// Can clear textures without a framebuffer via `glClearTexImage`.
+    pub clear_texture: bool,
+    /// Can discard fragments outside of depth bounds via `GL_EXT_depth_bounds_test`.
+    pub depth_bounds_test: bool,
+    /// Can forward driver messages to a callback via `glDebugMessageCallback`.
+    pub debug_output: bool,
+    /// Can query uniform locations without names via `glGetProgramResourceiv`,
+    /// which includes explicit `layout(location = N)` locations.
+    pub program_interface_query: bool,
+    /// Can shade multiple samples per fragment via `glMinSampleShading`.
+    pub sample_shading: bool,
+    /// Can create multisample textures and fetch their samples in shaders.
+    pub texture_multisample: bool,
+    /// Can blend each draw buffer independently via `glEnablei` and `glBlendFuncSeparatei`.
+    pub draw_buffers_blend: bool,
+    /// Can query the support of internal formats via `glGetInternalformativ`
+    /// with `GL_INTERNALFORMAT_SUPPORTED`.
+    pub internalformat_query2: bool,
+    /// Can sample buffers as textures of target `GL_TEXTURE_BUFFER`.
+    pub texture_buffer: bool,
+    /// Can restrict buffer textures to a range of the buffer via `glTexBufferRange`.
+    pub texture_buffer_range: bool,
+    /// Can combine separable programs of individual stages via program pipeline objects.
+    pub separate_shader_objects: bool,
+    /// Can read back textures via `glGetTexImage`, not available on GLES.
+    pub get_tex_image: bool,
+    /// Can bind textures to image units for load/store via `glBindImageTexture`.
+    pub image_load_store: bool,
+    /// Can mirror textures once and clamp beyond via `GL_MIRROR_CLAMP_TO_EDGE`.
+    pub mirror_clamp_to_edge: bool,
+    /// Can capture vertex outputs into buffers via transform feedback.
+    pub transform_feedback: bool,
+    /// Can pause and resume transform feedback.
+    pub transform_feedback_pause: bool,
+    /// Maximum degree of anisotropic filtering, `1.0` if unsupported.
+    pub max_texture_anisotropy: f32,
+    /// First texture unit reserved for input attachments, which is also the number
+    /// of texture units available to descriptors, see `native::MAX_INPUT_ATTACHMENTS`.
+    pub input_attachment_unit_base: u32,
+    /// Can sample rectangle textures with unnormalized coordinates.
+    pub texture_rectangle: bool,
+    /// Can query the number of levels of immutable textures via `GL_TEXTURE_IMMUTABLE_LEVELS`.
+    pub texture_immutable_levels: bool,
 }
 
 /// OpenGL implementation information
@@ -302,11 +404,12 @@ pub fn query_all(gl: &gl::Gl) -> (Info, Features, LegacyFeatures, Limits, Privat
     let mut limits = Limits {
         max_texture_size: get_usize(gl, gl::MAX_TEXTURE_SIZE),
         max_viewports: 1,
+        max_image_array_layers: 1,
         min_buffer_copy_offset_alignment: 1,
         min_buffer_copy_pitch_alignment: 1,
-        min_texel_buffer_offset_alignment: 1, // TODO
-        min_uniform_buffer_offset_alignment: 1, // TODO
-        min_storage_buffer_offset_alignment: 1, // TODO
+        min_texel_buffer_offset_alignment: 1,
+        min_uniform_buffer_offset_alignment: 1,
+        min_storage_buffer_offset_alignment: 1,
         .. Limits::default()
     };
 
@@ -316,11 +419,88 @@ pub fn query_all(gl: &gl::Gl) -> (Info, Features, LegacyFeatures, Limits, Privat
     ]) {
         limits.max_patch_size = get_usize(gl, gl::MAX_PATCH_VERTICES) as _;
     }
+    if info.is_supported(&[
+        Core(3,0),
+        Es(3,0),
+        Ext("GL_EXT_texture_array"),
+    ]) {
+        limits.max_image_array_layers = get_usize(gl, gl::MAX_ARRAY_TEXTURE_LAYERS);
+    }
+    if info.is_supported(&[
+        Core(3,1),
+        Es(3,0),
+        Ext("GL_ARB_uniform_buffer_object"),
+    ]) {
+        limits.max_uniform_buffer_bindings = get_usize(gl, gl::MAX_UNIFORM_BUFFER_BINDINGS);
+        limits.min_uniform_buffer_offset_alignment = get_usize(gl, gl::UNIFORM_BUFFER_OFFSET_ALIGNMENT).max(1) as _;
+    }
+    if info.is_supported(&[
+        Core(4,3),
+        Es(3,1),
+        Ext("GL_ARB_shader_storage_buffer_object"),
+    ]) {
+        limits.max_storage_buffer_bindings = get_usize(gl, gl::MAX_SHADER_STORAGE_BUFFER_BINDINGS);
+        limits.min_storage_buffer_offset_alignment = get_usize(gl, gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT).max(1) as _;
+    }
+    if info.is_supported(&[
+        Core(1,2),
+        Es(3,0),
+    ]) {
+        limits.max_image_3d_size = get_usize(gl, gl::MAX_3D_TEXTURE_SIZE);
+    }
+    if info.is_supported(&[
+        Core(4,3),
+        Es(3,2),
+        Ext("GL_ARB_texture_buffer_range"),
+    ]) {
+        limits.min_texel_buffer_offset_alignment = get_usize(gl, gl::TEXTURE_BUFFER_OFFSET_ALIGNMENT).max(1) as _;
+    }
     if info.is_supported(&[Core(4,1)]) { // TODO: extension
         limits.max_viewports = get_usize(gl, gl::MAX_VIEWPORTS);
+        limits.viewport_sub_pixel_bits = get_usize(gl, gl::VIEWPORT_SUBPIXEL_BITS) as _;
     }
+    limits.sub_pixel_precision_bits = get_usize(gl, gl::SUBPIXEL_BITS) as _;
+    let mut viewport_dims = [0 as gl::types::GLint; 2];
+    unsafe { gl.GetIntegerv(gl::MAX_VIEWPORT_DIMS, viewport_dims.as_mut_ptr()) };
+    limits.max_viewport_dimensions = [viewport_dims[0] as _, viewport_dims[1] as _];
+
+    if info.is_supported(&[
+        Core(3,2),
+        Es(3,1),
+        Ext("GL_ARB_texture_multisample"),
+    ]) {
+        let color_samples = sample_count_mask(get_usize(gl, gl::MAX_COLOR_TEXTURE_SAMPLES));
+        let depth_samples = sample_count_mask(get_usize(gl, gl::MAX_DEPTH_TEXTURE_SAMPLES));
+        limits.framebuffer_color_samples_count = color_samples;
+        limits.framebuffer_depth_samples_count = depth_samples;
+        limits.framebuffer_stencil_samples_count = depth_samples;
+    }
+
+    // Mapped pointers are aligned to `GL_MIN_MAP_BUFFER_ALIGNMENT`, flushing
+    // whole multiples of it keeps the flushed ranges within the cache lines
+    // touched by the host. 64 bytes is the minimum required by the spec.
+    limits.non_coherent_atom_size = if info.is_supported(&[
+        Core(4,2),
+        Ext("GL_ARB_map_buffer_alignment"),
+    ]) {
+        get_usize(gl, gl::MIN_MAP_BUFFER_ALIGNMENT).max(64)
+    } else {
+        64
+    };
 
-    if false && info.is_supported(&[ //TODO: enable when compute is implemented
+    limits.max_vertex_input_attributes = get_usize(gl, gl::MAX_VERTEX_ATTRIBS);
+    limits.max_vertex_input_bindings = if info.is_supported(&[
+        Core(4,3),
+        Es(3,1),
+        Ext("GL_ARB_vertex_attrib_binding"),
+    ]) {
+        get_usize(gl, gl::MAX_VERTEX_ATTRIB_BINDINGS)
+    } else {
+        // Each attribute is sourced from its own buffer binding.
+        limits.max_vertex_input_attributes
+    };
+
+    if info.is_supported(&[
         Core(4, 3),
         Ext("GL_ARB_compute_shader"),
     ]) {
@@ -361,6 +541,16 @@ pub fn query_all(gl: &gl::Gl) -> (Info, Features, LegacyFeatures, Limits, Privat
     ]) {
         features |= Features::INSTANCE_RATE;
     }
+    if info.is_supported(&[Ext("GL_EXT_depth_bounds_test")]) {
+        features |= Features::DEPTH_BOUNDS;
+    }
+    if info.is_supported(&[
+        Core(4, 0),
+        Es(3, 2),
+        Ext("GL_ARB_sample_shading"),
+    ]) {
+        features |= Features::SAMPLE_RATE_SHADING;
+    }
 
     if info.is_supported(&[Core(4, 3), Es(3, 1)]) { // TODO: extension
         legacy |= LegacyFeatures::INDIRECT_EXECUTION;
@@ -435,6 +625,19 @@ pub fn query_all(gl: &gl::Gl) -> (Info, Features, LegacyFeatures, Limits, Privat
         legacy |= LegacyFeatures::SAMPLER_BORDER_COLOR;
     }
 
+    // Legacy functionality is only guaranteed to work in compatibility profiles.
+    let core_profile = info.is_supported(&[Core(3,2)]) &&
+        get_usize(gl, gl::CONTEXT_PROFILE_MASK) & gl::CONTEXT_CORE_PROFILE_BIT as usize != 0;
+
+    let max_texture_anisotropy = if features.contains(Features::SAMPLER_ANISOTROPY) {
+        // `GL_MAX_TEXTURE_MAX_ANISOTROPY` shares the value of the EXT enum.
+        let mut value = 1.0;
+        unsafe { gl.GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY_EXT, &mut value) };
+        value.max(1.0)
+    } else {
+        1.0
+    };
+
     let private = PrivateCaps {
         vertex_array:                       info.is_supported(&[Core(3,0),
                                                                 Es  (3,0),
@@ -457,9 +660,84 @@ pub fn query_all(gl: &gl::Gl) -> (Info, Features, LegacyFeatures, Limits, Privat
                                                                 Es  (3,0),
                                                                 Ext ("GL_ARB_sync")]),
         map:                                !info.version.is_embedded, //TODO: OES extension
-        sampler_anisotropy_ext:             !info.is_supported(&[Core(4,6),
-                                                                Ext ("GL_ARB_texture_filter_anisotropic")]) &&
-                                            info.is_supported(&[Ext ("GL_EXT_texture_filter_anisotropic")]),
+        framebuffer_invalidate:             info.is_supported(&[Core(4,3),
+                                                                Es  (3,0),
+                                                                Ext ("GL_ARB_invalidate_subdata")]),
+        primitive_smooth:                   !info.version.is_embedded && !core_profile,
+        generate_mipmap_hint:               info.version.is_embedded || !core_profile,
+        query_buffer:                       info.is_supported(&[Core(4,4),
+                                                                Ext ("GL_ARB_query_buffer_object")]),
+        clip_control:                       info.is_supported(&[Core(4,5),
+                                                                Ext ("GL_ARB_clip_control")]),
+        depth_clamp:                        info.is_supported(&[Core(3,2),
+                                                                Ext ("GL_ARB_depth_clamp")]),
+        copy_image:                         info.is_supported(&[Core(4,3),
+                                                                Es  (3,2),
+                                                                Ext ("GL_ARB_copy_image")]),
+        texture_view:                       info.is_supported(&[Core(4,3),
+                                                                Ext ("GL_ARB_texture_view")]),
+        memory_barrier:                     info.is_supported(&[Core(4,2),
+                                                                Es  (3,1),
+                                                                Ext ("GL_ARB_shader_image_load_store")]),
+        compressed_readback:                !info.version.is_embedded,
+        get_texture_sub_image:              info.is_supported(&[Core(4,5),
+                                                                Ext ("GL_ARB_get_texture_sub_image")]),
+        sample_locations:                   info.is_supported(&[Ext ("GL_ARB_sample_locations"),
+                                                                Ext ("GL_NV_sample_locations")]),
+        sample_locations_nv:                !info.is_supported(&[Ext ("GL_ARB_sample_locations")]) &&
+                                            info.is_supported(&[Ext ("GL_NV_sample_locations")]),
+        shader_buffer_load:                 info.is_supported(&[Ext ("GL_NV_shader_buffer_load")]),
+        viewport_array:                     info.is_supported(&[Core(4,1),
+                                                                Ext ("GL_ARB_viewport_array")]),
+        clear_texture:                      info.is_supported(&[Core(4,4),
+                                                                Ext ("GL_ARB_clear_texture")]),
+        depth_bounds_test:                  info.is_supported(&[Ext ("GL_EXT_depth_bounds_test")]),
+        debug_output:                       info.is_supported(&[Core(4,3),
+                                                                Es  (3,2),
+                                                                Ext ("GL_KHR_debug")]),
+        program_interface_query:            info.is_supported(&[Core(4,3),
+                                                                Es  (3,1),
+                                                                Ext ("GL_ARB_program_interface_query")]),
+        sample_shading:                     info.is_supported(&[Core(4,0),
+                                                                Es  (3,2),
+                                                                Ext ("GL_ARB_sample_shading")]),
+        texture_multisample:                info.is_supported(&[Core(3,2),
+                                                                Es  (3,1),
+                                                                Ext ("GL_ARB_texture_multisample")]),
+        draw_buffers_blend:                 info.is_supported(&[Core(4,0),
+                                                                Es  (3,2),
+                                                                Ext ("GL_ARB_draw_buffers_blend")]),
+        internalformat_query2:              info.is_supported(&[Core(4,3),
+                                                                Ext ("GL_ARB_internalformat_query2")]),
+        texture_buffer:                     info.is_supported(&[Core(3,1),
+                                                                Es  (3,2),
+                                                                Ext ("GL_ARB_texture_buffer_object")]),
+        texture_buffer_range:               info.is_supported(&[Core(4,3),
+                                                                Es  (3,2),
+                                                                Ext ("GL_ARB_texture_buffer_range")]),
+        separate_shader_objects:            info.is_supported(&[Core(4,1),
+                                                                Es  (3,1),
+                                                                Ext ("GL_ARB_separate_shader_objects")]),
+        get_tex_image:                      !info.version.is_embedded,
+        image_load_store:                   info.is_supported(&[Core(4,2),
+                                                                Es  (3,1),
+                                                                Ext ("GL_ARB_shader_image_load_store")]),
+        mirror_clamp_to_edge:               info.is_supported(&[Core(4,4),
+                                                                Ext ("GL_ARB_texture_mirror_clamp_to_edge"),
+                                                                Ext ("GL_EXT_texture_mirror_clamp")]),
+        texture_rectangle:                  info.is_supported(&[Core(3,1),
+                                                                Ext ("GL_ARB_texture_rectangle")]),
+        texture_immutable_levels:           info.is_supported(&[Core(4,3),
+                                                                Es  (3,0)]),
+        transform_feedback:                 info.is_supported(&[Core(3,0),
+                                                                Es  (3,0),
+                                                                Ext ("GL_EXT_transform_feedback")]),
+        transform_feedback_pause:           info.is_supported(&[Core(4,0),
+                                                                Es  (3,0),
+                                                                Ext ("GL_ARB_transform_feedback2")]),
+        max_texture_anisotropy,
+        input_attachment_unit_base: (get_usize(gl, gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS) as u32)
+            .saturating_sub(MAX_INPUT_ATTACHMENTS),
     };
 
     (info, features, legacy, limits, private)
@@ -467,7 +745,22 @@ pub fn query_all(gl: &gl::Gl) -> (Info, Features, LegacyFeatures, Limits, Privat
 
 #[cfg(test)]
 mod tests {
-    use super::Version;
+    use super::{sample_count_mask, PlatformName, Version};
+
+    #[test]
+    fn test_platform_name() {
+        let name = PlatformName { vendor: "NVIDIA Corporation", renderer: "GeForce GTX 1060/PCIe/SSE2" };
+        assert_eq!(name.vendor_id(), 0x10DE);
+        assert!(!name.is_software_renderer());
+        let name = PlatformName { vendor: "Intel Open Source Technology Center", renderer: "Mesa DRI Intel(R) HD Graphics 620" };
+        assert_eq!(name.vendor_id(), 0x8086);
+        // Vendors are only recognized by their exact vendor string, not by the renderer.
+        let name = PlatformName { vendor: "X.Org", renderer: "AMD Radeon RX 580 (POLARIS10, DRM 3.27.0)" };
+        assert_eq!(name.vendor_id(), 0);
+        let name = PlatformName { vendor: "VMware, Inc.", renderer: "llvmpipe (LLVM 6.0, 256 bits)" };
+        assert_eq!(name.vendor_id(), 0);
+        assert!(name.is_software_renderer());
+    }
 
     #[test]
     fn test_version_parse() {
@@ -486,4 +779,14 @@ mod tests {
         assert_eq!(Version::parse("OpenGL ES 2.0 Google Nexus"), Ok(Version::new_embedded(2, 0, "Google Nexus")));
         assert_eq!(Version::parse("GLSL ES 1.1"), Ok(Version::new_embedded(1, 1, "")));
     }
+
+    #[test]
+    fn test_sample_count_mask() {
+        assert_eq!(sample_count_mask(0), 0b1);
+        assert_eq!(sample_count_mask(1), 0b1);
+        assert_eq!(sample_count_mask(8), 0b1111);
+        // Only powers of two are valid sample counts.
+        assert_eq!(sample_count_mask(6), 0b111);
+        assert_eq!(sample_count_mask(1024), 0xFF);
+    }
 }
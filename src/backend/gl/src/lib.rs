@@ -1,5 +1,23 @@
 //! OpenGL implementation of a device, striving to support OpenGL 2.0 with at
 //! least VAOs, but using newer extensions when available.
+//!
+//! # Multiple GPUs
+//!
+//! OpenGL has no adapter enumeration, the single adapter is the GPU the context
+//! is created on. On systems with an integrated and a discrete GPU the driver picks
+//! it when the process starts, which the application can influence only outside
+//! of this crate:
+//!
+//! - Linux and BSD: start the application with `DRI_PRIME=1` for PRIME offloading.
+//! - Windows: export the hints of the NVIDIA Optimus and AMD PowerXpress drivers
+//!   from the executable, exports of libraries are ignored:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub static NvOptimusEnablement: u32 = 1;
+//! #[no_mangle]
+//! pub static AmdPowerXpressRequestHighPerformance: i32 = 1;
+//! ```
 
 #![allow(missing_docs, missing_copy_implementations)]
 
@@ -14,30 +32,36 @@ extern crate spirv_cross;
 #[cfg(feature = "glutin")]
 pub extern crate glutin;
 
-use std::cell::Cell;
-use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::{ffi, fmt, ptr};
+use std::os::raw::c_void;
 use std::sync::Arc;
 use std::ops::Deref;
 use std::thread::{self, ThreadId};
 
 use hal::{error, image, pso};
+use hal::backend::FastHashMap;
 use hal::queue::{Queues, QueueFamilyId};
 
-pub use self::device::Device;
+pub use self::device::{Device, FrameFences, ReadbackError, UnnormalizedSamplerError};
 pub use self::info::{Info, PlatformName, Version};
+pub use self::native::{DepthResolveMode, HintMode, MapAccess, QueryResultFlags, ATTACHMENT_UNUSED};
 
 mod command;
 mod conv;
 mod device;
+mod ext;
 mod info;
 mod native;
 mod pool;
 mod queue;
 mod state;
+#[cfg(test)]
+mod test_utils;
 mod window;
 
 #[cfg(feature = "glutin")]
-pub use window::glutin::{config_context, Headless, Surface, Swapchain};
+pub use window::glutin::{config_context, Headless, PresentSync, Surface, Swapchain};
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Backend {}
@@ -76,7 +100,7 @@ impl hal::Backend for Backend {
 
     type Fence = native::Fence;
     type Semaphore = native::Semaphore;
-    type QueryPool = ();
+    type QueryPool = native::QueryPool;
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -104,9 +128,119 @@ impl Error {
     }
 }
 
+/// Backend specific options.
+///
+/// Can be configured via `PhysicalDevice::set_options`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Clamp `GL_TEXTURE_MAX_LEVEL` of textures, which are sampled without having
+    /// all mipmap levels uploaded, instead of only emitting a warning.
+    /// The check is only done in debug builds.
+    pub fix_incomplete_textures: bool,
+    /// Anti-alias lines of graphics pipelines created afterwards via `GL_LINE_SMOOTH`.
+    /// Only supported on compatibility profiles, consider multisampling otherwise.
+    pub smooth_lines: bool,
+    /// Anti-alias polygons of graphics pipelines created afterwards via `GL_POLYGON_SMOOTH`.
+    /// Only supported on compatibility profiles, consider multisampling otherwise.
+    pub smooth_polygons: bool,
+    /// Forward messages of the driver's debug output to the log via `glDebugMessageCallback`,
+    /// requires a debug context for most drivers. Applied when opening the device.
+    pub debug_output: bool,
+    /// Emit debug messages synchronously (`GL_DEBUG_OUTPUT_SYNCHRONOUS`) from within the
+    /// offending call, so backtraces of the callback point at the culprit.
+    /// Applied when opening the device, asynchronous output is faster.
+    pub synchronous_debug_output: bool,
+    /// Link each stage of graphics pipelines created afterwards into its own separable
+    /// program, combined via program pipeline objects (`GL_ARB_separate_shader_objects`).
+    ///
+    /// Stages translating to the same GLSL are only linked once and shared between
+    /// pipelines, which requires explicit layouts in shaders (GL 4.2). Push constants
+    /// are matched between the stages by uniform location.
+    pub separate_shader_objects: bool,
+    /// Quality of the mip levels generated afterwards via `glGenerateMipmap`,
+    /// applied as `GL_GENERATE_MIPMAP_HINT`. `None` keeps the driver default.
+    ///
+    /// The hint was removed from core profiles, where this has no effect.
+    pub mipmap_hint: Option<HintMode>,
+    /// Restrict the limits reported by `PhysicalDevice::limits` for testing purposes.
+    ///
+    /// The reported limits are never more capable than the actual ones,
+    /// the behavior of the device itself is not affected. Usually created by
+    /// lowering some of the values returned by `PhysicalDevice::limits`.
+    pub limits: Option<hal::Limits>,
+}
+
+/// Backend specific settings of a graphics pipeline.
+///
+/// Passed to `Device::create_graphics_pipeline_with_settings`,
+/// `create_graphics_pipeline` uses the defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PipelineSettings {
+    /// Emulate the fixed function alpha test in the fragment shader, discarding
+    /// fragments whose alpha of color output 0 fails the comparison with the
+    /// reference value. Only applied to SPIR-V shaders.
+    pub alpha_test: Option<(pso::Comparison, f32)>,
+    /// Force early depth and stencil tests (`layout(early_fragment_tests)`) in the
+    /// fragment shader, even if it discards fragments or writes depth. Only applied
+    /// to SPIR-V shaders, requires GL 4.2, ES 3.1 or `GL_ARB_shader_image_load_store`.
+    pub early_fragment_tests: bool,
+}
+
+// Combine the limits of the device with the user provided restrictions,
+// selecting the more restrictive value for each limit.
+fn restrict_limits(limits: hal::Limits, max: &hal::Limits) -> hal::Limits {
+    use std::cmp::{max as maximum, min};
+    let mut compute_count = limits.max_compute_group_count;
+    let mut compute_size = limits.max_compute_group_size;
+    for i in 0 .. 3 {
+        compute_count[i] = min(compute_count[i], max.max_compute_group_count[i]);
+        compute_size[i] = min(compute_size[i], max.max_compute_group_size[i]);
+    }
+
+    hal::Limits {
+        max_texture_size: min(limits.max_texture_size, max.max_texture_size),
+        max_image_array_layers: min(limits.max_image_array_layers, max.max_image_array_layers),
+        max_image_3d_size: min(limits.max_image_3d_size, max.max_image_3d_size),
+        max_uniform_buffer_bindings: min(limits.max_uniform_buffer_bindings, max.max_uniform_buffer_bindings),
+        max_storage_buffer_bindings: min(limits.max_storage_buffer_bindings, max.max_storage_buffer_bindings),
+        max_patch_size: min(limits.max_patch_size, max.max_patch_size),
+        max_viewports: min(limits.max_viewports, max.max_viewports),
+        max_viewport_dimensions: [
+            min(limits.max_viewport_dimensions[0], max.max_viewport_dimensions[0]),
+            min(limits.max_viewport_dimensions[1], max.max_viewport_dimensions[1]),
+        ],
+        sub_pixel_precision_bits: min(limits.sub_pixel_precision_bits, max.sub_pixel_precision_bits),
+        viewport_sub_pixel_bits: min(limits.viewport_sub_pixel_bits, max.viewport_sub_pixel_bits),
+        max_compute_group_count: compute_count,
+        max_compute_group_size: compute_size,
+        max_vertex_input_attributes: min(limits.max_vertex_input_attributes, max.max_vertex_input_attributes),
+        max_vertex_input_bindings: min(limits.max_vertex_input_bindings, max.max_vertex_input_bindings),
+        max_vertex_input_attribute_offset: min(limits.max_vertex_input_attribute_offset, max.max_vertex_input_attribute_offset),
+        max_vertex_input_binding_stride: min(limits.max_vertex_input_binding_stride, max.max_vertex_input_binding_stride),
+        max_vertex_output_components: min(limits.max_vertex_output_components, max.max_vertex_output_components),
+        // Alignments are more restrictive the larger they are.
+        min_buffer_copy_offset_alignment: maximum(limits.min_buffer_copy_offset_alignment, max.min_buffer_copy_offset_alignment),
+        min_buffer_copy_pitch_alignment: maximum(limits.min_buffer_copy_pitch_alignment, max.min_buffer_copy_pitch_alignment),
+        min_texel_buffer_offset_alignment: maximum(limits.min_texel_buffer_offset_alignment, max.min_texel_buffer_offset_alignment),
+        min_uniform_buffer_offset_alignment: maximum(limits.min_uniform_buffer_offset_alignment, max.min_uniform_buffer_offset_alignment),
+        min_storage_buffer_offset_alignment: maximum(limits.min_storage_buffer_offset_alignment, max.min_storage_buffer_offset_alignment),
+        // Sample counts are bitmasks, only the counts supported by both remain.
+        framebuffer_color_samples_count: limits.framebuffer_color_samples_count & max.framebuffer_color_samples_count,
+        framebuffer_depth_samples_count: limits.framebuffer_depth_samples_count & max.framebuffer_depth_samples_count,
+        framebuffer_stencil_samples_count: limits.framebuffer_stencil_samples_count & max.framebuffer_stencil_samples_count,
+        max_color_attachments: min(limits.max_color_attachments, max.max_color_attachments),
+        non_coherent_atom_size: maximum(limits.non_coherent_atom_size, max.non_coherent_atom_size),
+    }
+}
+
+/// Index of the memory type requiring explicit flushes of mapped ranges.
+const NON_COHERENT_MEMORY_TYPE: usize = 3;
+
 /// Internal struct of shared data between the physical and logical device.
 struct Share {
     context: gl::Gl,
+    // Entry points of extensions missing from `context`.
+    ext: ext::ExtGl,
     info: Info,
     features: hal::Features,
     legacy_features: info::LegacyFeatures,
@@ -114,12 +248,99 @@ struct Share {
     private_caps: info::PrivateCaps,
     // Indicates if there is an active logical device.
     open: Cell<bool>,
+    options: Cell<Options>,
+    // Queries of the contexts sharing their objects with the device context,
+    // keyed by window.
+    contexts: RefCell<FastHashMap<usize, ContextQuery>>,
+    // Key of the window whose context is current, `0` if unknown.
+    // Container objects like VAOs and FBOs are not shared between contexts.
+    current_window: Cell<usize>,
+    // Resolve attachments of a framebuffer, attached to separate framebuffer objects.
+    resolve_framebuffers: RefCell<FastHashMap<native::FrameBuffer, native::ResolveFrameBuffer>>,
+    // Attachments of a framebuffer, for binding them as input attachments.
+    framebuffer_attachments: RefCell<FastHashMap<native::FrameBuffer, Vec<native::ImageView>>>,
 }
 
+/// Returns `true` if a context is current on the calling thread.
+type ContextQuery = Box<dyn Fn() -> bool>;
+
 impl Share {
+    /// Panics during a debug build if none of the contexts of this device
+    /// is current on the calling thread.
+    ///
+    /// Only the contexts of windows are known, the check is skipped otherwise.
+    fn check_current(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let contexts = self.contexts.borrow();
+        if !contexts.is_empty() && !contexts.values().any(|is_current| is_current()) {
+            panic!(
+                "The GL context of this device is not current on thread {:?}. \
+                Make it current before using the device.",
+                thread::current().id(),
+            );
+        }
+    }
+
+    /// Record the context of a window as sharing its objects with the device context.
+    fn register_context(&self, key: usize, is_current: ContextQuery) {
+        self.contexts.borrow_mut().entry(key).or_insert(is_current);
+    }
+
+    /// Returns `true` if contexts of other windows than the current one
+    /// share their objects with the device context.
+    fn has_other_contexts(&self) -> bool {
+        let current = self.current_window.get();
+        self.contexts.borrow().keys().any(|&key| key != current)
+    }
+
+    /// Initialize the permanent states of the current context.
+    fn init_context(&self) {
+        let gl = &self.context;
+        if self.legacy_features.contains(info::LegacyFeatures::SRGB_COLOR) {
+            unsafe {
+                gl.Enable(gl::FRAMEBUFFER_SRGB);
+            }
+        }
+        if self.private_caps.clip_control {
+            // Match the `0..1` clip space depth of the shaders.
+            unsafe {
+                gl.ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+            }
+        }
+        let options = self.options.get();
+        if options.debug_output || options.synchronous_debug_output {
+            if self.private_caps.debug_output {
+                unsafe {
+                    if options.debug_output {
+                        gl.Enable(gl::DEBUG_OUTPUT);
+                        gl.DebugMessageCallback(debug_message_callback, ptr::null());
+                    }
+                    if options.synchronous_debug_output {
+                        gl.Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+                    } else {
+                        gl.Disable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+                    }
+                }
+            } else {
+                warn!("Debug output is not supported");
+            }
+        }
+        unsafe {
+            gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+
+            if !self.info.version.is_embedded {
+                gl.Enable(gl::PROGRAM_POINT_SIZE);
+            }
+        }
+    }
+
     /// Fails during a debug build if the implementation's error flag was set.
     fn check(&self) -> Result<(), Error> {
         if cfg!(debug_assertions) {
+            self.check_current();
             let gl = &self.context;
             let err = Error::from_error_code(unsafe { gl.GetError() });
             if err != Error::NoError {
@@ -130,6 +351,25 @@ impl Share {
     }
 }
 
+// Forward messages of the debug output to the log, by severity.
+extern "system" fn debug_message_callback(
+    _source: gl::types::GLenum,
+    _type: gl::types::GLenum,
+    id: gl::types::GLuint,
+    severity: gl::types::GLenum,
+    _length: gl::types::GLsizei,
+    message: *const gl::types::GLchar,
+    _user_param: *mut c_void,
+) {
+    let message = unsafe { ffi::CStr::from_ptr(message) }.to_string_lossy();
+    match severity {
+        gl::DEBUG_SEVERITY_HIGH => error!("GL debug message {}: {}", id, message),
+        gl::DEBUG_SEVERITY_MEDIUM => warn!("GL debug message {}: {}", id, message),
+        gl::DEBUG_SEVERITY_LOW => info!("GL debug message {}: {}", id, message),
+        _ => debug!("GL debug message {}: {}", id, message),
+    }
+}
+
 /// Single-threaded `Arc`.
 /// Wrapper for `Arc` that allows you to `Send` it even if `T: !Sync`.
 /// Yet internal data cannot be accessed outside of the thread where it was created.
@@ -177,10 +417,11 @@ impl<T: ?Sized> Deref for Starc<T> {
 pub struct PhysicalDevice(Starc<Share>);
 
 impl PhysicalDevice {
-    fn new_adapter<F>(fn_proc: F) -> hal::Adapter<Backend>
-    where F: FnMut(&str) -> *const std::os::raw::c_void
+    fn new_adapter<F>(mut fn_proc: F) -> hal::Adapter<Backend>
+    where F: FnMut(&str) -> *const c_void
     {
-        let gl = gl::Gl::load_with(fn_proc);
+        let gl = gl::Gl::load_with(|symbol| fn_proc(symbol));
+        let ext = ext::ExtGl::load_with(|symbol| fn_proc(symbol));
         // query information
         let (info, features, legacy_features, limits, private_caps) = info::query_all(&gl);
         info!("Vendor: {:?}", info.platform_name.vendor);
@@ -194,16 +435,24 @@ impl PhysicalDevice {
             debug!("- {}", *extension);
         }
         let name = info.platform_name.renderer.into();
+        let vendor = info.platform_name.vendor_id();
+        let software_rendering = info.platform_name.is_software_renderer();
 
         // create the shared context
         let share = Share {
             context: gl,
+            ext,
             info,
             features,
             legacy_features,
             limits,
             private_caps,
             open: Cell::new(false),
+            options: Cell::new(Options::default()),
+            contexts: RefCell::new(FastHashMap::default()),
+            current_window: Cell::new(0),
+            resolve_framebuffers: RefCell::new(FastHashMap::default()),
+            framebuffer_attachments: RefCell::new(FastHashMap::default()),
         };
         if let Err(err) = share.check() {
             panic!("Error querying info: {:?}", err);
@@ -212,9 +461,11 @@ impl PhysicalDevice {
         hal::Adapter {
             info: hal::AdapterInfo {
                 name,
-                vendor: 0, // TODO
+                vendor,
                 device: 0, // TODO
-                software_rendering: false, // not always true ..
+                // The adapter is whichever GPU the context was created on,
+                // see the crate documentation on multiple GPUs.
+                software_rendering,
             },
             physical_device: PhysicalDevice(Starc::new(share)),
             queue_families: vec![QueueFamily],
@@ -225,6 +476,69 @@ impl PhysicalDevice {
     pub fn legacy_features(&self) -> &info::LegacyFeatures {
         &self.0.legacy_features
     }
+
+    /// Maximum number of texture units bound by descriptors at once, summed over all
+    /// descriptor sets and stages. The remaining units of the context are reserved
+    /// for emulating input attachments.
+    pub fn max_texture_descriptors(&self) -> usize {
+        self.0.private_caps.input_attachment_unit_base as usize
+    }
+
+    /// Check if custom sample locations can be programmed via
+    /// `RawCommandBuffer::set_sample_locations`.
+    pub fn supports_sample_locations(&self) -> bool {
+        self.0.private_caps.sample_locations
+    }
+
+    /// Check if multisampled depth images can be resolved with the mode via
+    /// `RawCommandBuffer::resolve_depth_stencil_image`.
+    ///
+    /// Resolving the minimum or maximum depth samples the source image in a shader,
+    /// which requires multisample textures.
+    pub fn supports_depth_resolve_mode(&self, mode: DepthResolveMode) -> bool {
+        let caps = &self.0.private_caps;
+        match mode {
+            DepthResolveMode::SampleZero => caps.framebuffer,
+            DepthResolveMode::Min | DepthResolveMode::Max => caps.framebuffer && caps.texture_multisample,
+        }
+    }
+
+    /// Check if the context accepts compressed textures of the format,
+    /// e.g. for selecting the target format of a transcoder.
+    ///
+    /// Equivalent to checking the `SAMPLED` feature of the format properties.
+    pub fn supports_compressed_format(&self, format: hal::format::Format) -> bool {
+        let int_format = match conv::image_format_to_gl(format) {
+            Some(int_format) if format.base_format().0.desc().is_compressed() => int_format,
+            _ => return false,
+        };
+        let gl = &self.0.context;
+        if self.0.private_caps.internalformat_query2 {
+            let mut supported = 0;
+            unsafe {
+                gl.GetInternalformativ(gl::TEXTURE_2D, int_format, gl::INTERNALFORMAT_SUPPORTED, 1, &mut supported);
+            }
+            supported == gl::TRUE as gl::types::GLint
+        } else {
+            let mut count = 0;
+            unsafe { gl.GetIntegerv(gl::NUM_COMPRESSED_TEXTURE_FORMATS, &mut count) };
+            let mut formats = vec![0; count as usize];
+            if count > 0 {
+                unsafe { gl.GetIntegerv(gl::COMPRESSED_TEXTURE_FORMATS, formats.as_mut_ptr()) };
+            }
+            formats.contains(&(int_format as gl::types::GLint))
+        }
+    }
+
+    /// Get the backend specific options.
+    pub fn options(&self) -> Options {
+        self.0.options.get()
+    }
+
+    /// Set the backend specific options.
+    pub fn set_options(&self, options: Options) {
+        self.0.options.set(options);
+    }
 }
 
 impl hal::PhysicalDevice<Backend> for PhysicalDevice {
@@ -237,23 +551,13 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             return Err(error::DeviceCreationError::TooManyObjects);
         }
         self.0.open.set(true);
+        self.0.check_current();
 
         // initialize permanent states
-        let gl = &self.0.context;
-        if self.0.legacy_features.contains(info::LegacyFeatures::SRGB_COLOR) {
-            unsafe {
-                gl.Enable(gl::FRAMEBUFFER_SRGB);
-            }
-        }
-        unsafe {
-            gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-
-            if !self.0.info.version.is_embedded {
-                gl.Enable(gl::PROGRAM_POINT_SIZE);
-            }
-        }
+        self.0.init_context();
 
         // create main VAO and bind it
+        let gl = &self.0.context;
         let mut vao = 0;
         if self.0.private_caps.vertex_array {
             unsafe {
@@ -281,8 +585,24 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
         })
     }
 
-    fn format_properties(&self, _: Option<hal::format::Format>) -> hal::format::Properties {
-        unimplemented!()
+    fn format_properties(&self, format: Option<hal::format::Format>) -> hal::format::Properties {
+        use hal::format::{ImageFeature, Properties};
+
+        match format {
+            Some(format) if format.base_format().0.desc().is_compressed() => {
+                let optimal_tiling = if self.supports_compressed_format(format) {
+                    ImageFeature::SAMPLED | ImageFeature::SAMPLED_LINEAR | ImageFeature::BLIT_SRC
+                } else {
+                    ImageFeature::empty()
+                };
+                Properties {
+                    optimal_tiling,
+                    .. Properties::default()
+                }
+            }
+            // Features of uncompressed formats aren't reported yet.
+            _ => Properties::default(),
+        }
     }
 
     fn image_format_properties(
@@ -311,6 +631,10 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
                     properties: Properties::CPU_VISIBLE | Properties::COHERENT | Properties::CPU_CACHED,
                     heap_index: 0,
                 },
+                hal::MemoryType { // upload with explicit flushes
+                    properties: Properties::CPU_VISIBLE,
+                    heap_index: 0,
+                },
             ]
         } else {
             vec![
@@ -332,7 +656,10 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
     }
 
     fn limits(&self) -> hal::Limits {
-        self.0.limits
+        match self.0.options.get().limits {
+            Some(ref max) => restrict_limits(self.0.limits, max),
+            None => self.0.limits,
+        }
     }
 }
 
@@ -1,44 +1,135 @@
 use std::cell::Cell;
+use std::ptr;
+use std::ops::Range;
 use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicPtr, Ordering};
 
 use hal::{format, image as i, pass, pso};
 use hal::memory::Properties;
 use hal::backend::FastHashMap;
 
 use gl;
+use conv::align_flush_range;
+use state;
 use Backend;
 use std::borrow::Borrow;
 
 pub type RawBuffer   = gl::types::GLuint;
 pub type Shader      = gl::types::GLuint;
 pub type Program     = gl::types::GLuint;
+pub type ProgramPipeline = gl::types::GLuint;
 pub type FrameBuffer = gl::types::GLuint;
 pub type Surface     = gl::types::GLuint;
 pub type Texture     = gl::types::GLuint;
+pub type TextureTarget = gl::types::GLenum;
 pub type Sampler     = gl::types::GLuint;
 
 pub type DescriptorSetLayout = Vec<pso::DescriptorSetLayoutBinding>;
 
 pub const DEFAULT_FRAMEBUFFER: FrameBuffer = 0;
 
+/// Number of texture units reserved for emulating input attachments,
+/// the last ones of `GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS`.
+///
+/// Input attachment `i` of a sub-pass is bound to the texture unit
+/// `PrivateCaps::input_attachment_unit_base + i` when entering the sub-pass.
+/// Descriptors are never assigned to these.
+pub const MAX_INPUT_ATTACHMENTS: gl::types::GLuint = 8;
+
+/// Attachment index of an unused attachment reference, e.g. for color
+/// attachments of a sub-pass which shouldn't be resolved.
+pub const ATTACHMENT_UNUSED: pass::AttachmentId = !0;
+
 #[derive(Debug)]
 pub struct Buffer {
     pub(crate) raw: RawBuffer,
     pub(crate) target: gl::types::GLenum,
     pub(crate) size: u64,
+    /// GPU address of the buffer once made resident, `0` otherwise.
+    pub(crate) gpu_address: Mutex<u64>,
 }
 
 #[derive(Debug)]
-pub struct BufferView;
+pub struct BufferView {
+    /// Texture of target `GL_TEXTURE_BUFFER` sourcing its texels from the range.
+    pub(crate) texture: Texture,
+    /// Internal format of the texels, for binding the view as storage texel buffer.
+    pub(crate) format: gl::types::GLenum,
+}
 
 #[derive(Debug)]
-pub struct Fence(pub(crate) Cell<gl::types::GLsync>);
+pub struct Fence {
+    pub(crate) sync: Cell<gl::types::GLsync>,
+    /// Fence created in the signaled state, without a sync object,
+    /// complete until it's reset.
+    pub(crate) signaled: Cell<bool>,
+}
 unsafe impl Send for Fence {}
 unsafe impl Sync for Fence {}
 
 impl Fence {
-    pub(crate) fn new(sync: gl::types::GLsync) -> Self {
-        Fence(Cell::new(sync))
+    pub(crate) fn new(sync: gl::types::GLsync, signaled: bool) -> Self {
+        Fence {
+            sync: Cell::new(sync),
+            signaled: Cell::new(signaled),
+        }
+    }
+
+    /// Check if the fence is in the signaled state without a pending sync object.
+    pub fn is_signaled(&self) -> bool {
+        self.signaled.get()
+    }
+
+    /// Check if the fence is signaled without blocking,
+    /// fences which haven't been submitted yet are unsignaled.
+    pub(crate) fn get_status(&self, gl: &gl::Gl) -> bool {
+        if self.signaled.get() {
+            return true;
+        }
+        let sync = self.sync.get();
+        if sync.is_null() {
+            return false;
+        }
+        let mut status = 0;
+        unsafe {
+            gl.GetSynciv(sync, gl::SYNC_STATUS, 1, ptr::null_mut(), &mut status);
+        }
+        status == gl::SIGNALED as gl::types::GLint
+    }
+
+    /// Delete the sync object and return to the unsignaled state,
+    /// the next signal creates a new sync object. Resetting twice has no effect.
+    pub(crate) fn reset(&self, gl: &gl::Gl) {
+        self.signaled.set(false);
+        self.destroy(gl);
+    }
+
+    pub(crate) fn destroy(&self, gl: &gl::Gl) {
+        let sync = self.sync.replace(ptr::null());
+        if !sync.is_null() {
+            unsafe { gl.DeleteSync(sync) };
+        }
+    }
+
+    /// Wait for the sync object to be signaled, flushing pending commands first.
+    ///
+    /// Returns `false` if the timeout expired or the wait failed,
+    /// a timeout of `u64::MAX` waits without a limit. Fences which haven't been
+    /// submitted yet can't be signaled by the GPU, `false` is returned right away.
+    pub(crate) fn wait(&self, gl: &gl::Gl, timeout_ns: u64) -> bool {
+        let sync = self.sync.get();
+        if sync.is_null() {
+            return false;
+        }
+        let timeout = if timeout_ns == u64::max_value() { gl::TIMEOUT_IGNORED } else { timeout_ns };
+        // TODO:
+        // This can be called by multiple objects wanting to ensure they have exclusive
+        // access to a resource. How much does this call costs ? The status of the fence
+        // could be cached to avoid calling this more than once (in core or in the backend ?).
+        match unsafe { gl.ClientWaitSync(sync, gl::SYNC_FLUSH_COMMANDS_BIT, timeout) } {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => true,
+            _ => false,
+        }
     }
 }
 
@@ -46,6 +137,11 @@ impl Fence {
 pub enum BindingTypes {
     Images,
     UniformBuffers,
+    StorageBuffers,
+    /// Image units for load/store, separate from the texture units of `Images`.
+    StorageImages,
+    /// Texture units of sampler objects, sharing the unit of the texture they sample.
+    Samplers,
 }
 
 #[derive(Clone, Debug)]
@@ -53,17 +149,29 @@ pub struct DescRemapData {
     bindings: FastHashMap<(BindingTypes, pso::DescriptorSetIndex, pso::DescriptorBinding), Vec<pso::DescriptorBinding>>,
     names: FastHashMap<String, (BindingTypes, pso::DescriptorSetIndex, pso::DescriptorBinding)>,
     next_binding: FastHashMap<BindingTypes, pso::DescriptorBinding>,
+    // Number of bindings assigned to each binding point.
+    used: FastHashMap<(BindingTypes, pso::DescriptorBinding), usize>,
+    // Bindings used directly as binding points, skipped by automatic assignments.
+    explicit: FastHashMap<(BindingTypes, pso::DescriptorBinding), pso::DescriptorSetIndex>,
+    // Access of the shaders to storage images, merged over all pipelines of the layout.
+    image_access: FastHashMap<(pso::DescriptorSetIndex, pso::DescriptorBinding), gl::types::GLenum>,
+    // First texture unit of input attachments, the units of descriptors end there.
+    input_attachment_unit_base: pso::DescriptorBinding,
 }
 
 /// Stores where the descriptor bindings have been remaped too.
 ///
 /// OpenGL doesn't support sets, so we have to flatten out the bindings.
 impl DescRemapData {
-    pub fn new() -> Self {
+    pub fn new(input_attachment_unit_base: pso::DescriptorBinding) -> Self {
         DescRemapData {
             bindings: FastHashMap::default(),
             names: FastHashMap::default(),
             next_binding: FastHashMap::default(),
+            used: FastHashMap::default(),
+            explicit: FastHashMap::default(),
+            image_access: FastHashMap::default(),
+            input_attachment_unit_base,
         }
     }
 
@@ -73,17 +181,138 @@ impl DescRemapData {
         set: pso::DescriptorSetIndex,
         binding: pso::DescriptorBinding,
     ) -> &[pso::DescriptorBinding] {
-        let nb = self.next_binding.entry(btype).or_insert(0);
-        let val = self.bindings.entry((btype, set, binding)).or_insert(Vec::new());
-        val.push(*nb);
-        *nb += 1;
-        &*val
+        let nb = self.reserve_binding(btype);
+        self.insert_missing_binding(nb, btype, set, binding)
+    }
+
+    /// Use the binding number directly as binding point.
+    ///
+    /// Returns `false` if the binding point is already taken by another set.
+    pub fn insert_explicit_binding(
+        &mut self,
+        btype: BindingTypes,
+        set: pso::DescriptorSetIndex,
+        binding: pso::DescriptorBinding,
+    ) -> bool {
+        if self.is_reserved(btype, binding) {
+            return false;
+        }
+        if *self.explicit.entry((btype, binding)).or_insert(set) != set {
+            return false;
+        }
+        self.insert_missing_binding(binding, btype, set, binding);
+        true
     }
 
     pub fn reserve_binding(&mut self, btype: BindingTypes) -> pso::DescriptorBinding {
-        let nb = self.next_binding.entry(btype).or_insert(0);
-        *nb += 1;
-        *nb - 1
+        let mut nb = *self.next_binding.get(&btype).unwrap_or(&0);
+        // Binding points freed by `remove_set` are reused.
+        while self.explicit.contains_key(&(btype, nb)) || self.is_binding_used(btype, nb) {
+            nb += 1;
+        }
+        if self.is_reserved(btype, nb) {
+            error!("Out of texture units for descriptors, units {}.. are reserved for input attachments",
+                self.input_attachment_unit_base);
+        }
+        self.next_binding.insert(btype, nb + 1);
+        nb
+    }
+
+    // Texture units of input attachments and beyond, see `MAX_INPUT_ATTACHMENTS`.
+    fn is_reserved(&self, btype: BindingTypes, nb: pso::DescriptorBinding) -> bool {
+        match btype {
+            BindingTypes::Images | BindingTypes::Samplers => nb >= self.input_attachment_unit_base,
+            _ => false,
+        }
+    }
+
+    fn is_binding_used(&self, btype: BindingTypes, nb: pso::DescriptorBinding) -> bool {
+        self.used.contains_key(&(btype, nb))
+    }
+
+    /// Remove the bindings of a descriptor set, freeing their binding points.
+    ///
+    /// Layouts are immutable in hal, so only whole layouts are released, see `clear`.
+    #[allow(dead_code)]
+    pub fn remove_set(&mut self, set: pso::DescriptorSetIndex) {
+        let mut freed = Vec::new();
+        let used = &mut self.used;
+        self.bindings.retain(|&(btype, s, _), nbs| {
+            if s == set {
+                for &nb in nbs.iter() {
+                    let remove = match used.get_mut(&(btype, nb)) {
+                        Some(count) => {
+                            *count -= 1;
+                            *count == 0
+                        }
+                        None => false,
+                    };
+                    if remove {
+                        used.remove(&(btype, nb));
+                    }
+                }
+                freed.push(btype);
+            }
+            s != set
+        });
+        self.names.retain(|_, &mut (_, s, _)| s != set);
+        self.image_access.retain(|&(s, _), _| s != set);
+        self.explicit.retain(|&(btype, _), s| {
+            if *s == set {
+                freed.push(btype);
+            }
+            *s != set
+        });
+        // Restart the search for spare binding points from the start.
+        for btype in freed {
+            self.next_binding.remove(&btype);
+        }
+    }
+
+    /// Remove all bindings.
+    pub fn clear(&mut self) {
+        self.bindings.clear();
+        self.names.clear();
+        self.next_binding.clear();
+        self.used.clear();
+        self.explicit.clear();
+        self.image_access.clear();
+    }
+
+    /// Record the access of a shader to a storage image binding,
+    /// widened to `GL_READ_WRITE` if other shaders access it differently.
+    pub fn insert_image_access(
+        &mut self,
+        set: pso::DescriptorSetIndex,
+        binding: pso::DescriptorBinding,
+        access: gl::types::GLenum,
+    ) {
+        let merged = self.image_access.entry((set, binding)).or_insert(access);
+        if *merged != access {
+            *merged = gl::READ_WRITE;
+        }
+    }
+
+    /// Access of the shaders to a storage image binding, `GL_READ_WRITE` if unknown.
+    pub fn get_image_access(
+        &self,
+        set: pso::DescriptorSetIndex,
+        binding: pso::DescriptorBinding,
+    ) -> gl::types::GLenum {
+        self.image_access.get(&(set, binding)).cloned().unwrap_or(gl::READ_WRITE)
+    }
+
+    /// Reserve a texture unit for a combined image sampler, binding
+    /// both the texture and the sampler to it.
+    pub fn insert_combined_binding_into_spare(
+        &mut self,
+        set: pso::DescriptorSetIndex,
+        binding: pso::DescriptorBinding,
+    ) -> pso::DescriptorBinding {
+        let nb = self.reserve_binding(BindingTypes::Images);
+        self.insert_missing_binding(nb, BindingTypes::Images, set, binding);
+        self.insert_missing_binding(nb, BindingTypes::Samplers, set, binding);
+        nb
     }
 
     pub fn insert_missing_binding(
@@ -93,11 +322,24 @@ impl DescRemapData {
         set: pso::DescriptorSetIndex,
         binding: pso::DescriptorBinding,
     ) -> &[pso::DescriptorBinding] {
+        *self.used.entry((btype, nb)).or_insert(0) += 1;
         let val = self.bindings.entry((btype, set, binding)).or_insert(Vec::new());
         val.push(nb);
         &*val
     }
 
+    /// Number of binding points of the type in use, including the unused ones below
+    /// the highest assigned binding point.
+    pub fn binding_count(&self, btype: BindingTypes) -> pso::DescriptorBinding {
+        self.bindings
+            .iter()
+            .filter(|&(&(ty, _, _), _)| ty == btype)
+            .flat_map(|(_, nbs)| nbs.iter())
+            .map(|nb| nb + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
     pub fn get_binding(
         &self,
         btype: BindingTypes,
@@ -106,34 +348,109 @@ impl DescRemapData {
     ) -> Option<&[pso::DescriptorBinding]> {
         self.bindings.get(&(btype, set, binding)).map(AsRef::as_ref)
     }
+
+    /// Texture units of a sampler binding.
+    ///
+    /// Falls back to the texture units of `Images` for bindings
+    /// which only have been assigned texture units.
+    pub fn get_sampler_binding(
+        &self,
+        set: pso::DescriptorSetIndex,
+        binding: pso::DescriptorBinding,
+    ) -> Option<&[pso::DescriptorBinding]> {
+        self.get_binding(BindingTypes::Samplers, set, binding)
+            .or_else(|| self.get_binding(BindingTypes::Images, set, binding))
+    }
+}
+
+/// Program of a pipeline, either a monolithic program or a program pipeline
+/// object combining separable programs of the individual stages.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum BoundProgram {
+    Program(Program),
+    Pipeline(ProgramPipeline),
 }
 
 #[derive(Clone, Debug)]
 pub struct GraphicsPipeline {
-    pub(crate) program: Program,
+    pub(crate) program: BoundProgram,
+    // Separable programs owned by the pipeline, the ones shared
+    // between pipelines are owned by the device.
+    pub(crate) stage_programs: Vec<Program>,
     pub(crate) primitive: gl::types::GLenum,
     pub(crate) patch_size: Option<gl::types::GLint>,
     pub(crate) blend_targets: Vec<pso::ColorBlendDesc>,
     pub(crate) attributes: Vec<AttributeDesc>,
     pub(crate) vertex_buffers: Vec<Option<pso::VertexBufferDesc>>,
+    // Line and polygon smoothing.
+    pub(crate) smooth: (bool, bool),
+    pub(crate) depth_bias: Option<pso::State<pso::DepthBias>>,
+    pub(crate) depth_clamp: bool,
+    pub(crate) depth_bounds: Option<pso::State<Range<f32>>>,
+    pub(crate) depth: pso::DepthTest,
+    pub(crate) stencil: pso::StencilTest,
+    pub(crate) uniforms: Vec<UniformDesc>,
+    // Minimum fraction of samples shaded per fragment, only for multisampled pipelines.
+    pub(crate) sample_shading: Option<f32>,
+    // Forced state of `GL_FRAMEBUFFER_SRGB`, `None` to convert based on the attachment format.
+    pub(crate) framebuffer_srgb: Option<bool>,
+}
+
+impl GraphicsPipeline {
+    /// Force the sRGB conversion of color writes on or off while the pipeline is bound.
+    ///
+    /// By default (`None`) values written to attachments with `ChannelType::Srgb`
+    /// are converted from linear to sRGB. Disabling the conversion allows writing
+    /// already encoded values to sRGB targets.
+    pub fn set_framebuffer_srgb(&mut self, srgb: Option<bool>) {
+        self.framebuffer_srgb = srgb;
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ComputePipeline {
     pub(crate) program: Program,
+    pub(crate) uniforms: Vec<UniformDesc>,
+}
+
+/// Plain uniform of a program, updated by push constants.
+///
+/// Uniforms translated from the push constant blocks of SPIR-V shaders use the
+/// offsets and strides of the block members. Uniforms of GLSL shaders are laid out
+/// over the push constant range ordered by location, tightly packed.
+#[derive(Clone, Copy, Debug)]
+pub struct UniformDesc {
+    // Program of the uniform, made active via `glActiveShaderProgram` for program pipelines.
+    pub(crate) program: Program,
+    pub(crate) location: gl::types::GLint,
+    pub(crate) utype: gl::types::GLenum,
+    pub(crate) count: gl::types::GLsizei,
+    // Byte offset in the push constant range.
+    pub(crate) offset: u32,
+    // Size in bytes of all array elements in the push constant range.
+    pub(crate) size: u32,
+    // Byte stride of the array elements in the push constant range.
+    pub(crate) array_stride: u32,
+    // Byte stride of the matrix columns in the push constant range.
+    pub(crate) matrix_stride: u32,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Image {
     pub(crate) kind: ImageKind,
+    // Required for copies between images
+    pub(crate) format: format::Format,
     // Required for clearing operations
     pub(crate) channel: format::ChannelType,
+    // Required for validating and clearing subresource ranges
+    pub(crate) dimensions: i::Kind,
+    pub(crate) num_levels: i::Level,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ImageKind {
     Surface(Surface),
-    Texture(Texture),
+    Texture(Texture, TextureTarget),
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -142,16 +459,76 @@ pub enum ImageKind {
 pub enum FatSampler {
     Sampler(Sampler),
     Info(i::SamplerInfo),
+    /// Sampler object addressing texels by unnormalized coordinates,
+    /// only valid for rectangle textures.
+    Unnormalized(Sampler),
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ImageView {
     Surface(Surface),
-    Texture(Texture, i::Level),
-    TextureLayer(Texture, i::Level, i::Layer),
+    /// Whole texture, attached as a layered attachment for array and cube
+    /// textures, where geometry shaders select the layer via `gl_Layer`.
+    Texture(Texture, TextureTarget, i::Level),
+    TextureLayer(Texture, TextureTarget, i::Level, i::Layer),
+    /// Contiguous range of layers of an array texture. Attached as a layered
+    /// attachment covering all layers if the range starts at the first layer,
+    /// where geometry shaders are responsible for staying within the range,
+    /// otherwise only the first layer of the range is attached.
+    TextureLayerRange(Texture, TextureTarget, i::Level, Range<i::Layer>),
+    /// Texture created with `glTextureView` over a sub-range of mips and
+    /// layers, owned by the view.
+    TextureView(Texture, TextureTarget),
+    /// Whole cube map, attached as a layered attachment of the six faces.
+    Cube(Texture, i::Level),
+    /// Single face of a cube map, in the order of `GL_TEXTURE_CUBE_MAP_POSITIVE_X`
+    /// and following targets.
+    CubeFace(Texture, i::Level, u8),
+    /// Whole 3D texture, attached as a layered attachment of all depth slices.
+    Texture3D(Texture, i::Level),
+    /// Single depth slice of a 3D texture.
+    Texture3DSlice(Texture, i::Level, u32),
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+impl ImageView {
+    /// View a range of layers of an array texture level with `num_layers` layers,
+    /// failing for empty ranges or ranges exceeding the array.
+    pub fn texture_layer_range(
+        texture: Texture,
+        target: TextureTarget,
+        level: i::Level,
+        layers: Range<i::Layer>,
+        num_layers: i::Layer,
+    ) -> Result<Self, i::ViewError> {
+        if layers.start < layers.end && layers.end <= num_layers {
+            Ok(ImageView::TextureLayerRange(texture, target, level, layers))
+        } else {
+            Err(i::ViewError::Layer(i::LayerError::OutOfBounds(layers)))
+        }
+    }
+
+    /// View a single face of a cube map, failing for faces outside of `0 .. 6`.
+    pub fn cube_face(texture: Texture, level: i::Level, face: i::Layer) -> Result<Self, i::ViewError> {
+        if face < 6 {
+            Ok(ImageView::CubeFace(texture, level, face as u8))
+        } else {
+            Err(i::ViewError::Layer(i::LayerError::OutOfBounds(face .. face + 1)))
+        }
+    }
+
+    /// View a single depth slice of a 3D texture level with `depth` slices,
+    /// failing for slices outside of the level.
+    pub fn texture_3d_slice(texture: Texture, level: i::Level, slice: u32, depth: u32) -> Result<Self, i::ViewError> {
+        if slice < depth {
+            Ok(ImageView::Texture3DSlice(texture, level, slice))
+        } else {
+            let slice = slice as i::Layer;
+            Err(i::ViewError::Layer(i::LayerError::OutOfBounds(slice .. slice + 1)))
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub(crate) enum DescSetBindings {
     Buffer {
         ty: BindingTypes,
@@ -160,13 +537,25 @@ pub(crate) enum DescSetBindings {
         offset: gl::types::GLintptr,
         size: gl::types::GLsizeiptr
     },
-    Texture(pso::DescriptorBinding, Texture),
+    Texture(pso::DescriptorBinding, Texture, TextureTarget),
     Sampler(pso::DescriptorBinding, Sampler),
+    /// Sampler state applied to the texture of the unit, without sampler objects.
+    SamplerInfo(pso::DescriptorBinding, i::SamplerInfo),
+    /// Texture level bound to an image unit.
+    Image {
+        binding: pso::DescriptorBinding,
+        texture: Texture,
+        level: i::Level,
+        // Bind all layers or only `layer` of array, cube and 3D textures.
+        layered: bool,
+        layer: i::Layer,
+        format: gl::types::GLenum,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub struct DescriptorSet {
-    layout: DescriptorSetLayout,
+    pub(crate) layout: DescriptorSetLayout,
     pub(crate) bindings: Arc<Mutex<Vec<DescSetBindings>>>,
 }
 
@@ -205,6 +594,13 @@ pub struct Memory {
     pub(crate) first_bound_buffer: Cell<RawBuffer>,
     /// Allocation size
     pub(crate) size: u64,
+    /// Start and end of the currently mapped range, flushes are relative to its start.
+    pub(crate) mapped: Cell<Option<(u64, u64)>>,
+    /// Backed by buffers created with `glBufferStorage`, mapped once via `GL_MAP_PERSISTENT_BIT`
+    /// and kept mapped until the memory is freed.
+    pub(crate) persistent: bool,
+    /// Start of the persistent mapping, null until first mapped.
+    pub(crate) persistent_ptr: Cell<*mut u8>,
 }
 
 unsafe impl Send for Memory {}
@@ -219,13 +615,59 @@ impl Memory {
         self.properties.contains(Properties::CPU_VISIBLE | Properties::CPU_CACHED)
     }
 
+    /// Flush writes to a range of the mapped memory, given relative to the start of the memory,
+    /// expanded to multiples of `atom` within the mapping.
+    ///
+    /// Returns `false` without flushing if the memory isn't mapped or the range exceeds it.
+    pub(crate) fn flush_range(&self, gl: &gl::Gl, offset: u64, size: u64, atom: u64) -> bool {
+        let mapped = match self.mapped.get() {
+            Some(mapped) => mapped,
+            None => {
+                error!("Flushing unmapped memory {:?}", self);
+                return false;
+            }
+        };
+        let end = match offset.checked_add(size) {
+            Some(end) if offset >= mapped.0 && end <= mapped.1 && end <= self.size => end,
+            _ => {
+                error!("Flushed range {}+{} exceeds the mapped range {:?}", offset, size, mapped);
+                return false;
+            }
+        };
+        let (start, end) = align_flush_range(offset .. end, mapped.0 .. mapped.1, atom);
+        if start == end {
+            return true;
+        }
+
+        state::with_pixel_buffer(gl, gl::PIXEL_PACK_BUFFER, self.first_bound_buffer.get(), || unsafe {
+            // The range is relative to the start of the mapping.
+            gl.FlushMappedBufferRange(gl::PIXEL_PACK_BUFFER, (start - mapped.0) as _, (end - start) as _);
+        });
+        true
+    }
+
     pub fn map_flags(&self) -> gl::types::GLenum {
+        self.access_flags(MapAccess::ReadWrite)
+    }
+
+    /// Map flags for the given access, restricted to the ones supported by the memory.
+    pub fn access_flags(&self, access: MapAccess) -> gl::types::GLenum {
         let mut flags = 0;
-        if self.can_download() {
+        if access != MapAccess::Write && self.can_download() {
             flags |= gl::MAP_READ_BIT;
         }
-        if self.can_upload() {
+        if access != MapAccess::Read && self.can_upload() {
             flags |= gl::MAP_WRITE_BIT;
+            if !self.properties.contains(Properties::COHERENT) {
+                flags |= gl::MAP_FLUSH_EXPLICIT_BIT;
+            }
+        }
+        if self.persistent && flags != 0 {
+            flags |= gl::MAP_PERSISTENT_BIT;
+            // Coherent mappings require persistent ones.
+            if self.properties.contains(Properties::COHERENT) {
+                flags |= gl::MAP_COHERENT_BIT;
+            }
         }
         flags
     }
@@ -240,14 +682,150 @@ pub struct RenderPass {
 #[derive(Clone, Debug)]
 pub struct SubpassDesc {
     pub(crate) color_attachments: Vec<usize>,
+    pub(crate) input_attachments: Vec<usize>,
+    // Resolve targets of the color attachments, empty if not resolving.
+    pub(crate) resolve_attachments: Vec<usize>,
+    // Attachments not used by this sub-pass whose contents must be kept.
+    pub(crate) preserve_attachments: Vec<usize>,
 }
 
 impl SubpassDesc {
     /// Check if an attachment is used by this sub-pass.
     pub(crate) fn is_using(&self, at_id: pass::AttachmentId) -> bool {
         self.color_attachments.iter()
+            .chain(self.input_attachments.iter())
             .any(|id| *id == at_id)
     }
+
+    /// Check if an attachment is used or preserved by this sub-pass.
+    pub(crate) fn is_referencing(&self, at_id: pass::AttachmentId) -> bool {
+        self.is_using(at_id) || self.preserve_attachments.contains(&at_id)
+    }
+}
+
+impl RenderPass {
+    /// Texture units and attachments to bind for reading the input
+    /// attachments of a sub-pass, up to `MAX_INPUT_ATTACHMENTS` from `unit_base`.
+    pub(crate) fn input_attachment_bindings(
+        &self,
+        subpass: pass::SubpassId,
+        unit_base: gl::types::GLuint,
+    ) -> Vec<(gl::types::GLuint, pass::AttachmentId)> {
+        let inputs = &self.subpasses[subpass].input_attachments;
+        if inputs.len() > MAX_INPUT_ATTACHMENTS as usize {
+            error!("Sub-pass {} reads {} input attachments, at most {} are supported",
+                subpass, inputs.len(), MAX_INPUT_ATTACHMENTS);
+        }
+        inputs
+            .iter()
+            .take(MAX_INPUT_ATTACHMENTS as usize)
+            .enumerate()
+            .map(|(i, &id)| (unit_base + i as gl::types::GLuint, id))
+            .collect()
+    }
+
+    /// Color and resolve attachment pairs to resolve at the end of a sub-pass.
+    ///
+    /// Unused resolve attachments are skipped, as well as resolve attachments
+    /// with a format different from their color attachment.
+    pub(crate) fn resolve_blits(
+        &self,
+        subpass: pass::SubpassId,
+    ) -> Vec<(pass::AttachmentId, pass::AttachmentId)> {
+        let desc = &self.subpasses[subpass];
+        desc.color_attachments
+            .iter()
+            .zip(desc.resolve_attachments.iter())
+            .filter(|&(&src, &dst)| {
+                if dst == ATTACHMENT_UNUSED {
+                    return false;
+                }
+                let (src_format, dst_format) = (self.attachments[src].format, self.attachments[dst].format);
+                if src_format != dst_format {
+                    error!(
+                        "Format {:?} of resolve attachment {} doesn't match format {:?} of color attachment {}",
+                        dst_format, dst, src_format, src,
+                    );
+                    return false;
+                }
+                true
+            })
+            .map(|(&src, &dst)| (src, dst))
+            .collect()
+    }
+
+    /// Attachments used by a sub-pass which aren't referenced by any following
+    /// sub-pass, allowing to discard their contents at the end of the sub-pass
+    /// if they are not stored.
+    pub(crate) fn discarded_attachments(&self, subpass: pass::SubpassId) -> Vec<pass::AttachmentId> {
+        let following = &self.subpasses[subpass + 1 ..];
+        (0 .. self.attachments.len())
+            .filter(|&id| {
+                self.subpasses[subpass].is_using(id) &&
+                    !following.iter().any(|desc| desc.is_referencing(id))
+            })
+            .collect()
+    }
+
+    /// Check if an attachment is a resolve target of a sub-pass.
+    pub(crate) fn is_resolve_target(&self, subpass: pass::SubpassId, at_id: pass::AttachmentId) -> bool {
+        self.subpasses[subpass].resolve_attachments.contains(&at_id)
+    }
+
+    /// Check if an attachment is only written by resolves and not used by any sub-pass.
+    pub(crate) fn is_resolve_only(&self, at_id: pass::AttachmentId) -> bool {
+        (0 .. self.subpasses.len()).any(|subpass| self.is_resolve_target(subpass, at_id)) &&
+            !self.subpasses.iter().any(|desc| desc.is_using(at_id))
+    }
+}
+
+/// Color, input, resolve and preserve attachments of a sub-pass fixture.
+#[cfg(test)]
+pub(crate) type SubpassFixture<'a> = (
+    &'a [pass::AttachmentId],
+    &'a [pass::AttachmentId],
+    &'a [pass::AttachmentId],
+    &'a [pass::AttachmentId],
+);
+
+#[cfg(test)]
+impl RenderPass {
+    /// Render pass with `Rgba8Unorm` attachments of the given sample counts.
+    pub(crate) fn fixture(samples: &[i::NumSamples], subpasses: &[SubpassFixture]) -> Self {
+        RenderPass {
+            attachments: samples
+                .iter()
+                .map(|&samples| pass::Attachment {
+                    format: Some(format::Format::Rgba8Unorm),
+                    samples,
+                    ops: pass::AttachmentOps::DONT_CARE,
+                    stencil_ops: pass::AttachmentOps::DONT_CARE,
+                    layouts: i::Layout::Undefined .. i::Layout::ShaderReadOnlyOptimal,
+                })
+                .collect(),
+            subpasses: subpasses
+                .iter()
+                .map(|&(colors, inputs, resolves, preserves)| SubpassDesc {
+                    color_attachments: colors.to_vec(),
+                    input_attachments: inputs.to_vec(),
+                    resolve_attachments: resolves.to_vec(),
+                    preserve_attachments: preserves.to_vec(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Framebuffer objects holding the resolve attachments of a framebuffer.
+///
+/// Framebuffers are not shared between contexts, the objects are created
+/// on first use in the context of each window.
+#[derive(Debug)]
+pub struct ResolveFrameBuffer {
+    // Resolve targets with their attachment points.
+    pub(crate) attachments: Vec<(gl::types::GLenum, ImageView)>,
+    // Framebuffer objects keyed by window.
+    pub(crate) objects: FastHashMap<usize, FrameBuffer>,
 }
 
 #[derive(Debug)]
@@ -255,9 +833,111 @@ pub struct PipelineLayout {
     pub(crate) desc_remap_data: Arc<RwLock<DescRemapData>>,
 }
 
+/// Commands of a single context execute in order, the sync object of a semaphore
+/// orders submissions of different contexts, e.g. presenting windows of contexts
+/// other than the one rendering.
+#[derive(Debug)]
+pub struct Semaphore {
+    pub(crate) sync: AtomicPtr<gl::types::__GLsync>,
+}
+
+impl Semaphore {
+    pub(crate) fn new() -> Self {
+        Semaphore {
+            sync: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Insert a sync object after the commands submitted to the current context,
+    /// replacing a previous signal which hasn't been waited on.
+    pub(crate) fn signal(&self, gl: &gl::Gl) {
+        let sync = unsafe {
+            let sync = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            // Waits of other contexts never complete if the sync object isn't submitted.
+            gl.Flush();
+            sync
+        };
+        let previous = self.sync.swap(sync as *mut _, Ordering::AcqRel);
+        if !previous.is_null() {
+            unsafe { gl.DeleteSync(previous) };
+        }
+    }
+
+    /// Make the GPU wait for the signal before executing further commands
+    /// of the current context, consuming the signal.
+    pub(crate) fn wait(&self, gl: &gl::Gl) {
+        let sync = self.sync.swap(ptr::null_mut(), Ordering::AcqRel);
+        if !sync.is_null() {
+            unsafe {
+                gl.WaitSync(sync, 0, gl::TIMEOUT_IGNORED);
+                gl.DeleteSync(sync);
+            }
+        }
+    }
+
+    pub(crate) fn destroy(&self, gl: &gl::Gl) {
+        let sync = self.sync.swap(ptr::null_mut(), Ordering::AcqRel);
+        if !sync.is_null() {
+            unsafe { gl.DeleteSync(sync) };
+        }
+    }
+}
+
 #[derive(Debug)]
-// No inter-queue synchronization required for GL.
-pub struct Semaphore;
+pub struct QueryPool {
+    // Query target of the pool, zero for timestamp queries.
+    pub(crate) target: gl::types::GLenum,
+    pub(crate) queries: Vec<gl::types::GLuint>,
+}
+
+bitflags! {
+    /// Flags controlling how query results are written by
+    /// `RawCommandBuffer::copy_query_pool_results`.
+    pub struct QueryResultFlags: u32 {
+        /// Results are written as 64-bit integers instead of 32-bit ones.
+        const BITS_64 = 0x1;
+        /// Wait for the results to become available.
+        const WAIT = 0x2;
+        /// Write an additional availability value after each result.
+        const WITH_AVAILABILITY = 0x4;
+        /// Write partial results of unfinished queries.
+        const PARTIAL = 0x8;
+    }
+}
+
+/// Intended access of a memory mapping, see `Device::map_memory_with_access`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MapAccess {
+    /// Only read from the mapping.
+    Read,
+    /// Only write to the mapping.
+    Write,
+    /// Read and write, as far as supported by the memory type.
+    ReadWrite,
+}
+
+/// Implementation-specific quality trade-off requested via `glHint`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HintMode {
+    /// Prefer the most efficient option (`GL_FASTEST`).
+    Fastest,
+    /// Prefer the highest quality option (`GL_NICEST`).
+    Nicest,
+    /// No preference (`GL_DONT_CARE`).
+    DontCare,
+}
+
+/// Selection of the depth value when resolving a multisampled depth image,
+/// see `RawCommandBuffer::resolve_depth_stencil_image`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DepthResolveMode {
+    /// Take the value of the first sample, as done by `glBlitFramebuffer`.
+    SampleZero,
+    /// Take the minimum depth of all samples.
+    Min,
+    /// Take the maximum depth of all samples.
+    Max,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct AttributeDesc {
@@ -267,6 +947,10 @@ pub struct AttributeDesc {
     pub(crate) size: gl::types::GLint,
     pub(crate) format: gl::types::GLenum,
     pub(crate) vertex_attrib_fn: VertexAttribFunction,
+    // Normalize integer data, only used by `glVertexAttribPointer`.
+    pub(crate) normalized: bool,
+    // Instance divisor, `0` advances per vertex, `n` every `n` instances.
+    pub(crate) divisor: gl::types::GLuint,
 }
 
 #[derive(Debug, Clone, Copy)]
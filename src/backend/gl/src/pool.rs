@@ -1,7 +1,6 @@
 use hal::{self, pool};
 use hal::backend::FastHashMap;
 use command::{self, Command, RawCommandBuffer};
-use native as n;
 use Backend;
 
 use std::sync::{Arc, Mutex};
@@ -54,7 +53,6 @@ pub enum BufferMemory {
 
 
 pub struct RawCommandPool {
-    pub(crate) fbo: n::FrameBuffer,
     pub(crate) limits: command::Limits,
     pub(crate) memory: Arc<Mutex<BufferMemory>>,
 }
@@ -83,7 +81,6 @@ impl pool::RawCommandPool<Backend> for RawCommandPool {
     ) -> Vec<RawCommandBuffer> { // TODO: Implement secondary buffers
         (0..num).map(|_|
                 RawCommandBuffer::new(
-                    self.fbo,
                     self.limits,
                     self.memory.clone()))
                 .collect()
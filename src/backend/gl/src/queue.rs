@@ -1,14 +1,22 @@
 use std::{mem, ptr, slice};
 use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use Starc;
 
 use hal;
+use hal::backend::FastHashMap;
 use hal::error;
 
 use gl;
 use smallvec::SmallVec;
 
 use {command as com, native, state, window};
+use device::{self, Device};
+use {conv, ext};
 use info::LegacyFeatures;
 use {Backend, Share};
 
@@ -32,6 +40,19 @@ struct State {
     num_viewports: usize,
     // Currently set scissor rects.
     num_scissors: usize,
+    // Textures bound to each texture unit.
+    textures: FastHashMap<gl::types::GLuint, (native::Texture, native::TextureTarget)>,
+    // Sampler state of each texture unit, if sampler objects are not supported.
+    sampler_infos: FastHashMap<gl::types::GLuint, hal::image::SamplerInfo>,
+    // Texture units whose sampler state is applied to the bound texture before the next draw.
+    dirty_sampler_units: Vec<gl::types::GLuint>,
+    // Indicates that the bound default framebuffer is incomplete,
+    // e.g. while the window is minimized. Draws and clears are skipped.
+    incomplete_framebuffer: bool,
+    // Bound program pipeline, if no monolithic program is in use.
+    program_pipeline: Option<native::ProgramPipeline>,
+    // State of `GL_FRAMEBUFFER_SRGB` if it differs from the default.
+    framebuffer_srgb: Option<bool>,
 }
 
 impl State {
@@ -43,6 +64,12 @@ impl State {
             index_buffer: None,
             num_viewports: 0,
             num_scissors: 0,
+            textures: FastHashMap::default(),
+            sampler_infos: FastHashMap::default(),
+            dirty_sampler_units: Vec::new(),
+            incomplete_framebuffer: false,
+            program_pipeline: None,
+            framebuffer_srgb: None,
         }
     }
 
@@ -51,6 +78,9 @@ impl State {
     fn flush(&mut self) {
         self.vao = false;
         self.index_buffer = None;
+        self.textures.clear();
+        self.sampler_infos.clear();
+        self.dirty_sampler_units.clear();
 
         // TOOD: reset viewports and scissors
         //       do we need to clear everything from 0..MAX_VIEWPORTS?
@@ -61,6 +91,26 @@ pub struct CommandQueue {
     pub(crate) share: Starc<Share>,
     vao: ArrayBuffer,
     state: State,
+    // Bit mask of the mipmap levels uploaded to each texture.
+    // Only tracked in debug builds for validation.
+    uploaded_levels: FastHashMap<native::Texture, u32>,
+    // Framebuffer for copies between images, created on first use.
+    copy_fbo: native::FrameBuffer,
+    // Programs for shader based blits, keyed by source texture target
+    // and the channel classes of source and destination.
+    blit_programs: FastHashMap<(native::TextureTarget, com::ChannelClass, com::ChannelClass), native::Program>,
+    // Programs for the minimum and maximum depth resolves, keyed by source texture target.
+    depth_resolve_programs: FastHashMap<(native::TextureTarget, native::DepthResolveMode), native::Program>,
+    // Key of the window whose context the commands were last executed in.
+    window: usize,
+    // VAO and copy framebuffer of the contexts of other windows,
+    // as container objects are not shared between contexts.
+    context_objects: FastHashMap<usize, (ArrayBuffer, native::FrameBuffer)>,
+    // Queries reset since they last began, their results are unavailable.
+    reset_queries: HashSet<gl::types::GLuint>,
+    // Completeness of the default framebuffer of each window, checked when first bound.
+    // Forgotten when presenting, as minimizing or restoring the window changes it.
+    default_framebuffer_complete: FastHashMap<usize, bool>,
 }
 
 impl CommandQueue {
@@ -70,6 +120,14 @@ impl CommandQueue {
             share: share.clone(),
             vao,
             state: State::new(),
+            uploaded_levels: FastHashMap::default(),
+            copy_fbo: 0,
+            blit_programs: FastHashMap::default(),
+            depth_resolve_programs: FastHashMap::default(),
+            window: share.current_window.get(),
+            context_objects: FastHashMap::default(),
+            reset_queries: HashSet::new(),
+            default_framebuffer_complete: FastHashMap::default(),
         }
     }
 
@@ -157,15 +215,47 @@ impl CommandQueue {
             &native::ImageView::Surface(surface) => unsafe {
                 gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
             },
-            &native::ImageView::Texture(texture, level) => unsafe {
+            &native::ImageView::Texture(texture, _, level) => unsafe {
                 gl.FramebufferTexture(point, attachment, texture,
                                       level as gl::types::GLint);
             },
-            &native::ImageView::TextureLayer(texture, level, layer) => unsafe {
+            &native::ImageView::TextureLayer(texture, _, level, layer) => unsafe {
                 gl.FramebufferTextureLayer(point, attachment, texture,
                                            level as gl::types::GLint,
                                            layer as gl::types::GLint);
             },
+            &native::ImageView::TextureLayerRange(texture, _, level, ref layers) => unsafe {
+                if layers.start == 0 {
+                    gl.FramebufferTexture(point, attachment, texture,
+                                          level as gl::types::GLint);
+                } else {
+                    warn!("Layered attachments can't start at layer {}, only attaching the first layer", layers.start);
+                    gl.FramebufferTextureLayer(point, attachment, texture,
+                                               level as gl::types::GLint,
+                                               layers.start as gl::types::GLint);
+                }
+            },
+            &native::ImageView::TextureView(texture, _) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture, 0);
+            },
+            &native::ImageView::Cube(texture, level) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture,
+                                      level as gl::types::GLint);
+            },
+            &native::ImageView::CubeFace(texture, level, face) => unsafe {
+                gl.FramebufferTexture2D(point, attachment,
+                                        gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as gl::types::GLenum,
+                                        texture, level as gl::types::GLint);
+            },
+            &native::ImageView::Texture3D(texture, level) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture,
+                                      level as gl::types::GLint);
+            },
+            &native::ImageView::Texture3DSlice(texture, level, slice) => unsafe {
+                gl.FramebufferTextureLayer(point, attachment, texture,
+                                           level as gl::types::GLint,
+                                           slice as gl::types::GLint);
+            },
         }
     }
 
@@ -196,6 +286,7 @@ impl CommandQueue {
     // Reset the state to match our _expected_ state before executing
     // a command buffer.
     fn reset_state(&mut self) {
+        self.switch_context();
         let gl = &self.share.context;
         let priv_caps = &self.share.private_caps;
 
@@ -250,9 +341,742 @@ impl CommandQueue {
                     .collect();
             unsafe { gl.ScissorArrayv(0, scissors.len() as i32, scissors.as_ptr() as *const _)};
         }
+
+        // Reset framebuffer sRGB conversion, only disabled by pipelines
+        if self.state.framebuffer_srgb.take().is_some() {
+            unsafe { gl.Enable(gl::FRAMEBUFFER_SRGB) };
+        }
+
+        // Reset smoothing
+        if priv_caps.primitive_smooth {
+            unsafe {
+                gl.Disable(gl::LINE_SMOOTH);
+                gl.Disable(gl::POLYGON_SMOOTH);
+            }
+        }
+    }
+
+    // Switch to the objects of the current context, if another window
+    // has been made current since the last submission.
+    fn switch_context(&mut self) {
+        let window = self.share.current_window.get();
+        if window == self.window {
+            return;
+        }
+
+        let objects = (self.vao, self.copy_fbo);
+        self.context_objects.insert(self.window, objects);
+        self.window = window;
+        self.state.flush();
+
+        let share = &self.share;
+        let (vao, copy_fbo) = *self.context_objects.entry(window).or_insert_with(|| {
+            // First use of the context, initialize it like the device context.
+            share.init_context();
+            let mut vao = 0;
+            if share.private_caps.vertex_array {
+                unsafe { share.context.GenVertexArrays(1, &mut vao) };
+            }
+            (vao, 0)
+        });
+        self.vao = vao;
+        self.copy_fbo = copy_fbo;
+    }
+
+    // Check if the bound textures have all their mipmap levels uploaded.
+    // Sampling a texture with missing levels results in undefined (usually black) values
+    // as all our samplers use mipmap filtering.
+    fn validate_texture_levels(&mut self) {
+        let gl = &self.share.context;
+        let fix = self.share.options.get().fix_incomplete_textures;
+
+        for (&unit, &(texture, target)) in &self.state.textures {
+            let uploaded = match self.uploaded_levels.get(&texture) {
+                // Never uploaded to, probably a render target.
+                None | Some(&0) => continue,
+                Some(&mask) => mask,
+            };
+            // Number of consecutive levels starting at the base level.
+            let num_uploaded = (!uploaded).trailing_zeros() as gl::types::GLint;
+
+            let mut immutable = 0;
+            let mut num_levels = 0;
+            let mut max_level = 0;
+            unsafe {
+                gl.ActiveTexture(gl::TEXTURE0 + unit);
+                gl.GetTexParameteriv(target, gl::TEXTURE_IMMUTABLE_FORMAT, &mut immutable);
+                gl.GetTexParameteriv(target, gl::TEXTURE_MAX_LEVEL, &mut max_level);
+                if immutable != 0 && self.share.private_caps.texture_immutable_levels {
+                    gl.GetTexParameteriv(target, gl::TEXTURE_IMMUTABLE_LEVELS, &mut num_levels);
+                } else {
+                    // Mutable textures require the full mip chain of the base level.
+                    let level_target = if target == gl::TEXTURE_CUBE_MAP { gl::TEXTURE_CUBE_MAP_POSITIVE_X } else { target };
+                    let mut size = [0; 3];
+                    gl.GetTexLevelParameteriv(level_target, 0, gl::TEXTURE_WIDTH, &mut size[0]);
+                    gl.GetTexLevelParameteriv(level_target, 0, gl::TEXTURE_HEIGHT, &mut size[1]);
+                    if target == gl::TEXTURE_3D {
+                        gl.GetTexLevelParameteriv(level_target, 0, gl::TEXTURE_DEPTH, &mut size[2]);
+                    }
+                    let max_size = size[0].max(size[1]).max(size[2]) as u32;
+                    num_levels = 32 - max_size.leading_zeros() as gl::types::GLint;
+                }
+            }
+
+            if num_uploaded >= num_levels.min(max_level + 1) {
+                continue;
+            }
+
+            if fix {
+                info!("Clamping max level of texture {} to {}", texture, num_uploaded - 1);
+                unsafe { gl.TexParameteri(target, gl::TEXTURE_MAX_LEVEL, num_uploaded - 1) };
+            } else {
+                warn!(
+                    "Texture {} is sampled with {} mipmap levels, but only {} have been uploaded. \
+                     Generate the missing levels or enable `Options::fix_incomplete_textures`.",
+                    texture, num_levels, num_uploaded,
+                );
+            }
+        }
+    }
+
+    // Clear a color draw buffer with all channels unmasked,
+    // restoring the color mask of the bound pipeline afterwards.
+    fn clear_color_unmasked<F: FnOnce(&gl::Gl)>(&self, draw_buffer: gl::types::GLint, clear: F) {
+        let gl = &self.share.context;
+        let indexed = self.share.private_caps.draw_buffers_blend;
+        let mut prev_mask = [0; 4];
+        unsafe {
+            if indexed {
+                gl.GetBooleani_v(gl::COLOR_WRITEMASK, draw_buffer as _, prev_mask.as_mut_ptr());
+                gl.ColorMaski(draw_buffer as _, gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            } else {
+                gl.GetBooleanv(gl::COLOR_WRITEMASK, prev_mask.as_mut_ptr());
+                gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            }
+        }
+        clear(gl);
+        unsafe {
+            if indexed {
+                gl.ColorMaski(draw_buffer as _, prev_mask[0], prev_mask[1], prev_mask[2], prev_mask[3]);
+            } else {
+                gl.ColorMask(prev_mask[0], prev_mask[1], prev_mask[2], prev_mask[3]);
+            }
+        }
+    }
+
+    // Mark the sampler state of a texture unit for applying before the next draw,
+    // binding a texture and its sampler info applies the state once.
+    fn invalidate_sampler_info(&mut self, unit: gl::types::GLuint) {
+        if self.state.sampler_infos.contains_key(&unit) && !self.state.dirty_sampler_units.contains(&unit) {
+            self.state.dirty_sampler_units.push(unit);
+        }
+    }
+
+    fn apply_sampler_infos(&mut self) {
+        let units = mem::replace(&mut self.state.dirty_sampler_units, Vec::new());
+        for &unit in &units {
+            self.apply_sampler_info(unit);
+        }
+    }
+
+    // Store the sampler state of a texture unit in the parameters of the texture
+    // bound to it, for contexts without sampler objects.
+    fn apply_sampler_info(&self, unit: gl::types::GLuint) {
+        let target = match self.state.textures.get(&unit) {
+            // Multisample textures aren't filtered.
+            Some(&(_, gl::TEXTURE_2D_MULTISAMPLE)) |
+            Some(&(_, gl::TEXTURE_2D_MULTISAMPLE_ARRAY)) |
+            None => return,
+            Some(&(_, target)) => target,
+        };
+        let info = match self.state.sampler_infos.get(&unit) {
+            Some(info) => info,
+            None => return,
+        };
+
+        let gl = &self.share.context;
+        let legacy = self.share.legacy_features;
+        let (min, mag) = conv::filter_to_gl(info.mag_filter, info.min_filter, info.mip_filter);
+        let (s, t, r) = info.wrap_mode;
+        let (compare_mode, compare_func) = conv::sampler_comparison_to_gl(info.comparison);
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE0 + unit);
+            if self.share.features.contains(hal::Features::SAMPLER_ANISOTROPY) {
+                let max = self.share.private_caps.max_texture_anisotropy;
+                let anisotropy = device::clamp_anisotropy(info.anisotropic, max);
+                gl.TexParameterf(target, gl::TEXTURE_MAX_ANISOTROPY_EXT, anisotropy);
+            }
+            gl.TexParameteri(target, gl::TEXTURE_MIN_FILTER, min as _);
+            gl.TexParameteri(target, gl::TEXTURE_MAG_FILTER, mag as _);
+            gl.TexParameteri(target, gl::TEXTURE_WRAP_S, device::wrap_to_gl(legacy, s) as _);
+            gl.TexParameteri(target, gl::TEXTURE_WRAP_T, device::wrap_to_gl(legacy, t) as _);
+            gl.TexParameteri(target, gl::TEXTURE_WRAP_R, device::wrap_to_gl(legacy, r) as _);
+            if legacy.contains(LegacyFeatures::SAMPLER_LOD_BIAS) {
+                gl.TexParameterf(target, gl::TEXTURE_LOD_BIAS, info.lod_bias.into());
+            }
+            if legacy.contains(LegacyFeatures::SAMPLER_BORDER_COLOR) {
+                let border = conv::border_color_to_gl(info.border);
+                gl.TexParameterfv(target, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+            }
+            gl.TexParameterf(target, gl::TEXTURE_MIN_LOD, info.lod_range.start.into());
+            gl.TexParameterf(target, gl::TEXTURE_MAX_LOD, info.lod_range.end.into());
+            gl.TexParameteri(target, gl::TEXTURE_COMPARE_MODE, compare_mode as _);
+            if let Some(func) = compare_func {
+                gl.TexParameteri(target, gl::TEXTURE_COMPARE_FUNC, func as _);
+            }
+        }
+    }
+
+    // Blit from color attachment 0 of the read framebuffer into
+    // color attachment 1 of the draw framebuffer.
+    fn blit_framebuffer(
+        &self,
+        src: [gl::types::GLint; 4],
+        dst: [gl::types::GLint; 4],
+        filter: gl::types::GLenum,
+        srgb: bool,
+    ) {
+        let gl = &self.share.context;
+        // `GL_FRAMEBUFFER_SRGB` is enabled by default if supported,
+        // pipelines may override it.
+        let srgb_default = self.state.framebuffer_srgb
+            .unwrap_or(self.share.legacy_features.contains(LegacyFeatures::SRGB_COLOR));
+        unsafe {
+            gl.ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl.DrawBuffers(1, &gl::COLOR_ATTACHMENT1);
+            if srgb != srgb_default {
+                if srgb {
+                    gl.Enable(gl::FRAMEBUFFER_SRGB);
+                } else {
+                    gl.Disable(gl::FRAMEBUFFER_SRGB);
+                }
+            }
+            gl.BlitFramebuffer(
+                src[0], src[1], src[2], src[3],
+                dst[0], dst[1], dst[2], dst[3],
+                gl::COLOR_BUFFER_BIT, filter,
+            );
+            if srgb != srgb_default {
+                if srgb_default {
+                    gl.Enable(gl::FRAMEBUFFER_SRGB);
+                } else {
+                    gl.Disable(gl::FRAMEBUFFER_SRGB);
+                }
+            }
+        }
+    }
+
+    // Enable or disable `GL_FRAMEBUFFER_SRGB`, `None` restores the default state.
+    fn set_framebuffer_srgb(&mut self, srgb: Option<bool>) {
+        if !self.share.legacy_features.contains(LegacyFeatures::SRGB_COLOR) {
+            if srgb.is_some() {
+                warn!("Framebuffer sRGB control is not supported");
+            }
+            return;
+        }
+        let gl = &self.share.context;
+        unsafe {
+            if srgb.unwrap_or(true) {
+                gl.Enable(gl::FRAMEBUFFER_SRGB);
+            } else {
+                gl.Disable(gl::FRAMEBUFFER_SRGB);
+            }
+        }
+        self.state.framebuffer_srgb = if srgb == Some(false) { srgb } else { None };
+    }
+
+    // Limit presentation to one frame per `frame_time`.
+    //
+    // Waits for the GPU to complete the previous frame, so the CPU doesn't run
+    // ahead, and sleeps for the remaining frame time instead of spinning.
+    pub(crate) fn pace_frame(
+        &self,
+        fence: &native::Fence,
+        last_present: &Mutex<Option<Instant>>,
+        frame_time: Duration,
+    ) {
+        let gl = &self.share.context;
+        if self.share.private_caps.sync {
+            let sync = fence.sync.get();
+            if !fence.signaled.get() {
+                // Frames taking longer than the frame time on the GPU aren't capped further.
+                let timeout = frame_time.as_secs() * 1_000_000_000 + frame_time.subsec_nanos() as u64;
+                fence.wait(gl, timeout);
+            }
+            unsafe {
+                if gl.IsSync(sync) == gl::TRUE {
+                    gl.DeleteSync(sync);
+                }
+                fence.sync.set(gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0));
+            }
+            fence.signaled.set(false);
+        }
+
+        let mut last_present = last_present.lock().unwrap();
+        if let Some(last) = *last_present {
+            let elapsed = last.elapsed();
+            if elapsed < frame_time {
+                thread::sleep(frame_time - elapsed);
+            }
+        }
+        *last_present = Some(Instant::now());
+    }
+
+    fn copy_fbo(&mut self) -> native::FrameBuffer {
+        if self.copy_fbo == 0 {
+            unsafe { self.share.context.GenFramebuffers(1, &mut self.copy_fbo) };
+        }
+        self.copy_fbo
+    }
+
+    // Copy a region between two images.
+    //
+    // Uses `glCopyImageSubData` if the formats are copy compatible,
+    // otherwise the values are converted with a blit.
+    fn copy_image(&mut self, src: &native::Image, dst: &native::Image, r: &hal::command::ImageCopy) {
+        let compatible = src.format.surface_desc().bits == dst.format.surface_desc().bits;
+        if self.share.private_caps.copy_image && compatible {
+            let gl = &self.share.context;
+            let (src_name, src_target) = image_name(src);
+            let (dst_name, dst_target) = image_name(dst);
+            let num_layers = (r.src_subresource.layers.end - r.src_subresource.layers.start) as u32;
+            // Layers of array textures are addressed via the depth coordinate.
+            unsafe {
+                gl.CopyImageSubData(
+                    src_name, src_target, r.src_subresource.level as _,
+                    r.src_offset.x, r.src_offset.y, r.src_offset.z + r.src_subresource.layers.start as i32,
+                    dst_name, dst_target, r.dst_subresource.level as _,
+                    r.dst_offset.x, r.dst_offset.y, r.dst_offset.z + r.dst_subresource.layers.start as i32,
+                    r.extent.width as _, r.extent.height as _, r.extent.depth.max(num_layers) as _,
+                );
+            }
+            return;
+        }
+
+        let blit = hal::command::ImageBlit {
+            src_subresource: r.src_subresource.clone(),
+            src_bounds: r.src_offset .. offset_by(r.src_offset, r.extent),
+            dst_subresource: r.dst_subresource.clone(),
+            dst_bounds: r.dst_offset .. offset_by(r.dst_offset, r.extent),
+        };
+        if com::channel_class(src.channel) != com::channel_class(dst.channel) {
+            self.blit_image_shader(src, dst, &blit);
+            return;
+        }
+
+        let fbo = self.copy_fbo();
+        let layered = blit.src_subresource.layers != (0..1) || blit.dst_subresource.layers != (0..1);
+        let layers = blit.src_subresource.layers.clone().zip(blit.dst_subresource.layers.clone());
+        unsafe {
+            self.share.context.BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            self.share.context.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
+        }
+        for (src_layer, dst_layer) in layers {
+            let (src_layer, dst_layer) = if layered { (Some(src_layer), Some(dst_layer)) } else { (None, None) };
+            let src_view = com::image_view(src, blit.src_subresource.level, src_layer);
+            let dst_view = com::image_view(dst, blit.dst_subresource.level, dst_layer);
+            self.bind_target(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, &src_view);
+            self.bind_target(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT1, &dst_view);
+            // Copies don't convert between sRGB and linear encodings.
+            self.blit_framebuffer(bounds_rect(&blit.src_bounds), bounds_rect(&blit.dst_bounds), gl::NEAREST, false);
+        }
+    }
+
+    // Clear a mip level and array layer of a color image.
+    //
+    // Uses `glClearTexSubImage` if available, otherwise the subresource is
+    // attached to a framebuffer and cleared with `glClearBuffer`.
+    fn clear_image(&mut self, image: &native::Image, color: [u32; 4], level: hal::image::Level, layer: hal::image::Layer) {
+        let (gl_format, gl_type) = match image.channel {
+            hal::format::ChannelType::Uint => (gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+            hal::format::ChannelType::Int => (gl::RGBA_INTEGER, gl::INT),
+            _ => (gl::RGBA, gl::FLOAT),
+        };
+        let layered = image.dimensions.num_layers() > 1;
+
+        match image.kind {
+            native::ImageKind::Texture(texture, _) if self.share.private_caps.clear_texture => {
+                let extent = image.dimensions.level_extent(level);
+                // Layers of array textures are addressed via the depth coordinate.
+                let (zoffset, depth) = if layered { (layer as _, 1) } else { (0, extent.depth as _) };
+                unsafe {
+                    self.share.context.ClearTexSubImage(
+                        texture, level as _,
+                        0, 0, zoffset,
+                        extent.width as _, extent.height as _, depth,
+                        gl_format, gl_type, color.as_ptr() as *const _,
+                    );
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        let fbo = self.copy_fbo();
+        let view = com::image_view(image, level, if layered { Some(layer) } else { None });
+        unsafe { self.share.context.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo) };
+        self.bind_target(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, &view);
+
+        let gl = &self.share.context;
+        unsafe {
+            // Save the state overridden by the clear.
+            let mut prev_mask = [0; 4];
+            gl.GetBooleanv(gl::COLOR_WRITEMASK, prev_mask.as_mut_ptr());
+            let scissor = gl.IsEnabled(gl::SCISSOR_TEST) != 0;
+
+            gl.Disable(gl::SCISSOR_TEST);
+            gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl.DrawBuffers(1, &gl::COLOR_ATTACHMENT0);
+            match gl_type {
+                gl::UNSIGNED_INT => gl.ClearBufferuiv(gl::COLOR, 0, color.as_ptr()),
+                gl::INT => gl.ClearBufferiv(gl::COLOR, 0, color.as_ptr() as *const _),
+                _ => gl.ClearBufferfv(gl::COLOR, 0, color.as_ptr() as *const _),
+            }
+
+            gl.ColorMask(prev_mask[0], prev_mask[1], prev_mask[2], prev_mask[3]);
+            if scissor {
+                gl.Enable(gl::SCISSOR_TEST);
+            }
+        }
+    }
+
+    fn blit_program(
+        &mut self,
+        target: native::TextureTarget,
+        src: com::ChannelClass,
+        dst: com::ChannelClass,
+    ) -> Option<native::Program> {
+        if let Some(&program) = self.blit_programs.get(&(target, src, dst)) {
+            return Some(program);
+        }
+
+        let (vs, fs) = blit_shader_sources(self.share.info.version.is_embedded, target, src, dst);
+        let program = match link_program(&self.share.context, &vs, &fs) {
+            Some(program) => program,
+            None => {
+                error!("Failed to link the blit program for {:?} -> {:?}", src, dst);
+                return None;
+            }
+        };
+
+        self.blit_programs.insert((target, src, dst), program);
+        Some(program)
+    }
+
+    fn depth_resolve_program(
+        &mut self,
+        target: native::TextureTarget,
+        mode: native::DepthResolveMode,
+    ) -> Option<native::Program> {
+        if let Some(&program) = self.depth_resolve_programs.get(&(target, mode)) {
+            return Some(program);
+        }
+
+        let (vs, fs) = depth_resolve_shader_sources(self.share.info.version.is_embedded, target, mode);
+        let program = match link_program(&self.share.context, &vs, &fs) {
+            Some(program) => program,
+            None => {
+                error!("Failed to link the {:?} depth resolve program", mode);
+                return None;
+            }
+        };
+
+        self.depth_resolve_programs.insert((target, mode), program);
+        Some(program)
+    }
+
+    // Resolve the depth and stencil aspects of a multisampled image region.
+    //
+    // Sample zero resolves are done with `glBlitFramebuffer`. The minimum and maximum
+    // depth resolves render a triangle fetching all samples of the source texture and
+    // writing the selected value to the depth of the destination, the stencil is still
+    // blitted. The pipeline state touched is restored afterwards.
+    fn resolve_depth_stencil(
+        &mut self,
+        src: &native::Image,
+        dst: &native::Image,
+        r: &hal::command::ImageResolve,
+        mode: native::DepthResolveMode,
+    ) {
+        let aspects = r.src_subresource.aspects;
+        let shader_depth = match mode {
+            native::DepthResolveMode::SampleZero => None,
+            _ if !aspects.contains(hal::format::Aspects::DEPTH) => None,
+            _ => match src.kind {
+                native::ImageKind::Texture(texture, target)
+                    if self.share.private_caps.texture_multisample &&
+                        (target == gl::TEXTURE_2D_MULTISAMPLE || target == gl::TEXTURE_2D_MULTISAMPLE_ARRAY) =>
+                {
+                    match self.depth_resolve_program(target, mode) {
+                        Some(program) => Some((texture, target, program)),
+                        None => return,
+                    }
+                }
+                _ => {
+                    error!("{:?} depth resolves require a sampled multisample texture as source", mode);
+                    return;
+                }
+            },
+        };
+        let mut blit_mask = 0;
+        if aspects.contains(hal::format::Aspects::DEPTH) && shader_depth.is_none() {
+            blit_mask |= gl::DEPTH_BUFFER_BIT;
+        }
+        if aspects.contains(hal::format::Aspects::STENCIL) {
+            blit_mask |= gl::STENCIL_BUFFER_BIT;
+        }
+
+        let src_point = conv::attachment_point(0, Some(src.format));
+        let dst_point = conv::attachment_point(0, Some(dst.format));
+        let rect = [
+            r.dst_offset.x,
+            r.dst_offset.y,
+            r.dst_offset.x + r.extent.width as i32,
+            r.dst_offset.y + r.extent.height as i32,
+        ];
+        let layered = r.src_subresource.layers != (0..1) || r.dst_subresource.layers != (0..1);
+        let capabilities = [gl::BLEND, gl::CULL_FACE, gl::SCISSOR_TEST, gl::STENCIL_TEST];
+
+        // Temporary framebuffers, the copy framebuffer may hold color attachments
+        // with a different sample count.
+        let mut fbos = [0; 2];
+        unsafe {
+            let gl = &self.share.context;
+            gl.GenFramebuffers(2, fbos.as_mut_ptr());
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, fbos[0]);
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbos[1]);
+            gl.ReadBuffer(gl::NONE);
+            // `glDrawBuffer` isn't available on GLES.
+            gl.DrawBuffers(1, &gl::NONE);
+        }
+
+        let layers = r.src_subresource.layers.clone().zip(r.dst_subresource.layers.clone());
+        for (src_layer, dst_layer) in layers {
+            let (src_layer, dst_layer) = if layered { (Some(src_layer), Some(dst_layer)) } else { (None, None) };
+            let src_view = com::image_view(src, r.src_subresource.level, src_layer);
+            let dst_view = com::image_view(dst, r.dst_subresource.level, dst_layer);
+            self.bind_target(gl::READ_FRAMEBUFFER, src_point, &src_view);
+            self.bind_target(gl::DRAW_FRAMEBUFFER, dst_point, &dst_view);
+
+            let gl = &self.share.context;
+            unsafe {
+                if blit_mask != 0 {
+                    let scissor = gl.IsEnabled(gl::SCISSOR_TEST) != 0;
+                    gl.Disable(gl::SCISSOR_TEST);
+                    gl.BlitFramebuffer(
+                        r.src_offset.x, r.src_offset.y,
+                        r.src_offset.x + r.extent.width as i32, r.src_offset.y + r.extent.height as i32,
+                        rect[0], rect[1], rect[2], rect[3],
+                        blit_mask, gl::NEAREST,
+                    );
+                    if scissor {
+                        gl.Enable(gl::SCISSOR_TEST);
+                    }
+                }
+
+                let (texture, target, program) = match shader_depth {
+                    Some(shader_depth) => shader_depth,
+                    None => continue,
+                };
+
+                // Save the state overridden by the resolve.
+                let mut prev_program = 0;
+                let mut prev_viewport = [0; 4];
+                let mut prev_color_mask = [0; 4];
+                let mut prev_depth_mask = 0;
+                let mut prev_depth_func = 0;
+                gl.GetIntegerv(gl::CURRENT_PROGRAM, &mut prev_program);
+                gl.GetIntegerv(gl::VIEWPORT, prev_viewport.as_mut_ptr());
+                gl.GetBooleanv(gl::COLOR_WRITEMASK, prev_color_mask.as_mut_ptr());
+                gl.GetBooleanv(gl::DEPTH_WRITEMASK, &mut prev_depth_mask);
+                gl.GetIntegerv(gl::DEPTH_FUNC, &mut prev_depth_func);
+                let depth_test = gl.IsEnabled(gl::DEPTH_TEST) != 0;
+                let enabled: SmallVec<[bool; 4]> = capabilities
+                    .iter()
+                    .map(|&cap| gl.IsEnabled(cap) != 0)
+                    .collect();
+
+                for &cap in &capabilities {
+                    gl.Disable(cap);
+                }
+                gl.Enable(gl::DEPTH_TEST);
+                gl.DepthFunc(gl::ALWAYS);
+                gl.DepthMask(gl::TRUE);
+                gl.ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+                gl.UseProgram(program);
+                if self.share.private_caps.vertex_array {
+                    gl.BindVertexArray(self.vao);
+                }
+                gl.ActiveTexture(gl::TEXTURE0);
+                gl.BindTexture(target, texture);
+                gl.Viewport(rect[0], rect[1], rect[2] - rect[0], rect[3] - rect[1]);
+
+                let uniform = |name: &[u8]| gl.GetUniformLocation(program, name.as_ptr() as *const _);
+                gl.Uniform1i(uniform(b"u_Source\0"), 0);
+                gl.Uniform1i(uniform(b"u_Layer\0"), src_layer.unwrap_or(0) as _);
+                gl.Uniform1i(uniform(b"u_Samples\0"), src.dimensions.num_samples() as _);
+                gl.Uniform2i(uniform(b"u_Offset\0"), r.src_offset.x - r.dst_offset.x, r.src_offset.y - r.dst_offset.y);
+                gl.DrawArrays(gl::TRIANGLES, 0, 3);
+
+                // Restore the previous state.
+                for (&cap, &enable) in capabilities.iter().zip(enabled.iter()) {
+                    if enable {
+                        gl.Enable(cap);
+                    }
+                }
+                if !depth_test {
+                    gl.Disable(gl::DEPTH_TEST);
+                }
+                gl.DepthFunc(prev_depth_func as _);
+                gl.DepthMask(prev_depth_mask);
+                gl.ColorMask(prev_color_mask[0], prev_color_mask[1], prev_color_mask[2], prev_color_mask[3]);
+                gl.Viewport(prev_viewport[0], prev_viewport[1], prev_viewport[2], prev_viewport[3]);
+                gl.UseProgram(prev_program as _);
+            }
+            // Texture unit 0 has been rebound.
+            self.state.textures.remove(&0);
+        }
+
+        unsafe {
+            self.share.context.DeleteFramebuffers(2, fbos.as_ptr());
+        }
+    }
+
+    // Blit between images by rendering a triangle sampling the source.
+    //
+    // Slow path for conversions, which aren't supported by the fixed function
+    // copies, e.g. between integer and floating point formats. Texels are
+    // fetched with nearest filtering. The pipeline state touched is restored afterwards.
+    fn blit_image_shader(&mut self, src: &native::Image, dst: &native::Image, r: &hal::command::ImageBlit) {
+        let (texture, target) = match src.kind {
+            native::ImageKind::Texture(texture, target)
+                if target == gl::TEXTURE_2D || target == gl::TEXTURE_2D_ARRAY => (texture, target),
+            _ => {
+                error!("Shader blits are only supported for 2D (array) textures as source");
+                return;
+            }
+        };
+        let program = match self.blit_program(target, com::channel_class(src.channel), com::channel_class(dst.channel)) {
+            Some(program) => program,
+            None => return,
+        };
+        let fbo = self.copy_fbo();
+
+        let src_rect = bounds_rect(&r.src_bounds);
+        let dst_rect = bounds_rect(&r.dst_bounds);
+        let capabilities = [gl::BLEND, gl::CULL_FACE, gl::DEPTH_TEST, gl::SCISSOR_TEST, gl::STENCIL_TEST];
+        // Layers of array textures are attached individually, even if only one is blitted.
+        let dst_layered = r.dst_subresource.layers != (0..1) || match dst.kind {
+            native::ImageKind::Texture(_, target) => conv::is_layered_target(target),
+            native::ImageKind::Surface(_) => false,
+        };
+
+        let binding = if target == gl::TEXTURE_2D { gl::TEXTURE_BINDING_2D } else { gl::TEXTURE_BINDING_2D_ARRAY };
+        let sampler_objects = self.share.legacy_features.contains(LegacyFeatures::SAMPLER_OBJECTS);
+
+        let gl = &self.share.context;
+        unsafe {
+            // Save the state overridden by the blit.
+            let mut prev_program = 0;
+            let mut prev_viewport = [0; 4];
+            let mut prev_mask = [0; 4];
+            let mut prev_active_texture = 0;
+            let mut prev_texture = 0;
+            let mut prev_sampler = 0;
+            gl.GetIntegerv(gl::CURRENT_PROGRAM, &mut prev_program);
+            gl.GetIntegerv(gl::VIEWPORT, prev_viewport.as_mut_ptr());
+            gl.GetBooleanv(gl::COLOR_WRITEMASK, prev_mask.as_mut_ptr());
+            gl.GetIntegerv(gl::ACTIVE_TEXTURE, &mut prev_active_texture);
+            gl.ActiveTexture(gl::TEXTURE0);
+            gl.GetIntegerv(binding, &mut prev_texture);
+            if sampler_objects {
+                gl.GetIntegerv(gl::SAMPLER_BINDING, &mut prev_sampler);
+            }
+            let enabled: SmallVec<[bool; 5]> = capabilities
+                .iter()
+                .map(|&cap| gl.IsEnabled(cap) != 0)
+                .collect();
+
+            for &cap in &capabilities {
+                gl.Disable(cap);
+            }
+            gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl.UseProgram(program);
+            if self.share.private_caps.vertex_array {
+                gl.BindVertexArray(self.vao);
+            }
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
+            gl.BindTexture(target, texture);
+            if sampler_objects {
+                gl.BindSampler(0, 0);
+            }
+            gl.Viewport(
+                dst_rect[0].min(dst_rect[2]),
+                dst_rect[1].min(dst_rect[3]),
+                (dst_rect[2] - dst_rect[0]).abs(),
+                (dst_rect[3] - dst_rect[1]).abs(),
+            );
+
+            let uniform = |name: &[u8]| gl.GetUniformLocation(program, name.as_ptr() as *const _);
+            gl.Uniform1i(uniform(b"u_Source\0"), 0);
+            gl.Uniform1i(uniform(b"u_Level\0"), r.src_subresource.level as _);
+            gl.Uniform4i(uniform(b"u_SrcRect\0"), src_rect[0], src_rect[1], src_rect[2], src_rect[3]);
+            gl.Uniform4i(uniform(b"u_DstRect\0"), dst_rect[0], dst_rect[1], dst_rect[2], dst_rect[3]);
+            let layer_location = uniform(b"u_Layer\0");
+
+            let layers = r.src_subresource.layers.clone().zip(r.dst_subresource.layers.clone());
+            for (src_layer, dst_layer) in layers {
+                let dst_view = com::image_view(dst, r.dst_subresource.level, if dst_layered { Some(dst_layer) } else { None });
+                match dst_view {
+                    native::ImageView::Surface(surface) =>
+                        gl.FramebufferRenderbuffer(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, surface),
+                    native::ImageView::Texture(texture, target, level) =>
+                        gl.FramebufferTexture2D(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, target, texture, level as _),
+                    native::ImageView::TextureLayer(texture, _, level, layer) =>
+                        gl.FramebufferTextureLayer(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, texture, level as _, layer as _),
+                    native::ImageView::TextureView(..) |
+                    native::ImageView::TextureLayerRange(..) |
+                    native::ImageView::Cube(..) |
+                    native::ImageView::CubeFace(..) |
+                    native::ImageView::Texture3D(..) |
+                    native::ImageView::Texture3DSlice(..) => unreachable!(),
+                }
+                gl.DrawBuffers(1, &gl::COLOR_ATTACHMENT0);
+                gl.Uniform1i(layer_location, src_layer as _);
+                gl.DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+
+            // Restore the previous state.
+            for (&cap, &enable) in capabilities.iter().zip(enabled.iter()) {
+                if enable {
+                    gl.Enable(cap);
+                }
+            }
+            gl.ColorMask(prev_mask[0], prev_mask[1], prev_mask[2], prev_mask[3]);
+            gl.Viewport(prev_viewport[0], prev_viewport[1], prev_viewport[2], prev_viewport[3]);
+            gl.UseProgram(prev_program as _);
+            gl.BindTexture(target, prev_texture as _);
+            if sampler_objects {
+                gl.BindSampler(0, prev_sampler as _);
+            }
+            gl.ActiveTexture(prev_active_texture as _);
+        }
     }
 
     fn process(&mut self, cmd: &com::Command, data_buf: &[u8]) {
+        if self.state.incomplete_framebuffer {
+            match *cmd {
+                com::Command::Draw { .. } |
+                com::Command::DrawIndexed { .. } |
+                com::Command::ClearBufferColorF(..) |
+                com::Command::ClearBufferColorU(..) |
+                com::Command::ClearBufferColorI(..) |
+                com::Command::ClearBufferDepthStencil(..) |
+                com::Command::InvalidateFramebuffer(..) => return,
+                _ => (),
+            }
+        }
+
         match *cmd {
             com::Command::BindIndexBuffer(buffer) => {
                 let gl = &self.share.context;
@@ -261,6 +1085,10 @@ impl CommandQueue {
             }
 //          com::Command::BindVertexBuffers(_data_ptr) =>
             com::Command::Draw { primitive, ref vertices, ref instances } => {
+                self.apply_sampler_infos();
+                if cfg!(debug_assertions) {
+                    self.validate_texture_levels();
+                }
                 let gl = &self.share.context;
                 let legacy = &self.share.legacy_features;
                 if instances == &(0u32..1) {
@@ -299,6 +1127,10 @@ impl CommandQueue {
                 }
             }
             com::Command::DrawIndexed { primitive, index_type, index_count, index_buffer_offset, base_vertex, ref instances } => {
+                self.apply_sampler_infos();
+                if cfg!(debug_assertions) {
+                    self.validate_texture_levels();
+                }
                 let gl = &self.share.context;
                 let legacy = &self.share.legacy_features;
                 let offset = index_buffer_offset as *const gl::types::GLvoid;
@@ -370,6 +1202,7 @@ impl CommandQueue {
                 }
             }
             com::Command::Dispatch(count) => {
+                self.apply_sampler_infos();
                 // Capability support is given by which queue types will be exposed.
                 // If there is no compute support, this pattern should never be reached
                 // because no queue with compute capability can be created.
@@ -377,6 +1210,7 @@ impl CommandQueue {
                 unsafe { gl.DispatchCompute(count[0], count[1], count[2]) };
             }
             com::Command::DispatchIndirect(buffer, offset) => {
+                self.apply_sampler_infos();
                 // Capability support is given by which queue types will be exposed.
                 // If there is no compute support, this pattern should never be reached
                 // because no queue with compute capability can be created.
@@ -399,8 +1233,15 @@ impl CommandQueue {
                 if num_viewports == 1 {
                     let view = viewports[0];
                     let depth_range  = depth_ranges[0];
-                    unsafe { gl.Viewport(view[0] as i32, view[1] as i32, view[2] as i32, view[3] as i32) };
-                    unsafe { gl.DepthRange(depth_range[0], depth_range[1]) };
+                    let fractional = view.iter().any(|v| v.fract() != 0.0);
+                    if fractional && self.share.private_caps.viewport_array {
+                        // Keep sub-pixel offsets, the integer path would truncate them.
+                        unsafe { gl.ViewportIndexedf(first_viewport, view[0], view[1], view[2], view[3]) };
+                        unsafe { gl.DepthRangef(depth_range[0] as f32, depth_range[1] as f32) };
+                    } else {
+                        unsafe { gl.Viewport(view[0] as i32, view[1] as i32, view[2] as i32, view[3] as i32) };
+                        unsafe { gl.DepthRange(depth_range[0], depth_range[1]) };
+                    }
                 } else if num_viewports > 1 {
                     // Support for these functions is coupled with the support
                     // of multiple viewports.
@@ -414,7 +1255,9 @@ impl CommandQueue {
                 let num_scissors = scissors.len();
                 assert!(0 < num_scissors && num_scissors <= self.share.limits.max_viewports);
 
-                if num_scissors == 1 {
+                // `glScissor` sets the scissors of all viewports, which would
+                // override the other indices if multiple viewports are supported.
+                if self.share.limits.max_viewports == 1 {
                     let scissor = scissors[0];
                     unsafe { gl.Scissor(scissor[0], scissor[1], scissor[2], scissor[3]) };
                 } else {
@@ -423,17 +1266,177 @@ impl CommandQueue {
                     unsafe { gl.ScissorArrayv(first_scissor, num_scissors as i32, scissors.as_ptr() as *const _) };
                 }
             }
+            com::Command::SetDepthBias(bias) => {
+                state::set_depth_bias(&self.share.context, bias, self.share.info.version.is_embedded);
+            }
+            com::Command::SetDepthClamp(enable) => unsafe {
+                if enable {
+                    self.share.context.Enable(gl::DEPTH_CLAMP);
+                } else {
+                    self.share.context.Disable(gl::DEPTH_CLAMP);
+                }
+            }
+            com::Command::SetUniform(uniform, data_ptr) => unsafe {
+                let gl = &self.share.context;
+                let native::UniformDesc { program, location, utype, count, .. } = uniform;
+                if let Some(pipeline) = self.state.program_pipeline {
+                    // Direct `glUniform*` to the stage program owning the uniform.
+                    gl.ActiveShaderProgram(pipeline, program);
+                }
+                let data = Self::get_raw(data_buf, data_ptr).as_ptr();
+                match utype {
+                    gl::FLOAT => gl.Uniform1fv(location, count, data as _),
+                    gl::FLOAT_VEC2 => gl.Uniform2fv(location, count, data as _),
+                    gl::FLOAT_VEC3 => gl.Uniform3fv(location, count, data as _),
+                    gl::FLOAT_VEC4 => gl.Uniform4fv(location, count, data as _),
+                    gl::INT | gl::BOOL => gl.Uniform1iv(location, count, data as _),
+                    gl::INT_VEC2 | gl::BOOL_VEC2 => gl.Uniform2iv(location, count, data as _),
+                    gl::INT_VEC3 | gl::BOOL_VEC3 => gl.Uniform3iv(location, count, data as _),
+                    gl::INT_VEC4 | gl::BOOL_VEC4 => gl.Uniform4iv(location, count, data as _),
+                    gl::UNSIGNED_INT => gl.Uniform1uiv(location, count, data as _),
+                    gl::UNSIGNED_INT_VEC2 => gl.Uniform2uiv(location, count, data as _),
+                    gl::UNSIGNED_INT_VEC3 => gl.Uniform3uiv(location, count, data as _),
+                    gl::UNSIGNED_INT_VEC4 => gl.Uniform4uiv(location, count, data as _),
+                    gl::FLOAT_MAT2 => gl.UniformMatrix2fv(location, count, gl::FALSE, data as _),
+                    gl::FLOAT_MAT3 => gl.UniformMatrix3fv(location, count, gl::FALSE, data as _),
+                    gl::FLOAT_MAT4 => gl.UniformMatrix4fv(location, count, gl::FALSE, data as _),
+                    _ => error!("Unsupported uniform type {:x}", utype),
+                }
+            }
+            com::Command::SetDepth(ref depth) => {
+                state::bind_depth(&self.share.context, depth);
+            }
+            com::Command::SetStencil(ref stencil) => {
+                state::bind_stencil(&self.share.context, stencil);
+            }
+            com::Command::SetDepthBounds(ref bounds) => unsafe {
+                let gl = &self.share.context;
+                match *bounds {
+                    _ if !self.share.private_caps.depth_bounds_test => {
+                        if bounds.is_some() {
+                            warn!("Depth bounds test is not supported");
+                        }
+                    }
+                    Some(ref bounds) => {
+                        gl.Enable(ext::DEPTH_BOUNDS_TEST_EXT);
+                        self.share.ext.DepthBoundsEXT(bounds.start as _, bounds.end as _);
+                    }
+                    None => gl.Disable(ext::DEPTH_BOUNDS_TEST_EXT),
+                }
+            }
+            com::Command::SetSampleShading(rate) => unsafe {
+                let gl = &self.share.context;
+                match rate {
+                    _ if !self.share.private_caps.sample_shading => {
+                        if rate.is_some() {
+                            warn!("Sample shading is not supported");
+                        }
+                    }
+                    Some(rate) => {
+                        gl.Enable(gl::SAMPLE_SHADING);
+                        gl.MinSampleShading(rate);
+                    }
+                    None => gl.Disable(gl::SAMPLE_SHADING),
+                }
+            }
+            com::Command::SetFramebufferSrgb(srgb) => {
+                self.set_framebuffer_srgb(srgb);
+            }
+            com::Command::SetSampleLocations(locations) => {
+                if !self.share.private_caps.sample_locations {
+                    error!("Programmable sample locations require GL_ARB_sample_locations");
+                    return;
+                }
+
+                let gl = &self.share.context;
+                // Sample locations are a parameter of framebuffer objects.
+                let mut fbo = 0;
+                unsafe { gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut fbo) };
+                if fbo == 0 {
+                    warn!("Sample locations of the default framebuffer can't be programmed");
+                    return;
+                }
+                let locations = Self::get::<[f32; 2]>(data_buf, locations);
+                let nv = self.share.private_caps.sample_locations_nv;
+                unsafe {
+                    let enable = if locations.is_empty() { gl::FALSE } else { gl::TRUE };
+                    gl.FramebufferParameteri(
+                        gl::DRAW_FRAMEBUFFER,
+                        ext::FRAMEBUFFER_PROGRAMMABLE_SAMPLE_LOCATIONS_ARB,
+                        enable as _,
+                    );
+                    if !locations.is_empty() {
+                        let ptr = locations.as_ptr() as *const f32;
+                        if nv {
+                            self.share.ext.FramebufferSampleLocationsfvNV(gl::DRAW_FRAMEBUFFER, 0, locations.len() as _, ptr);
+                        } else {
+                            self.share.ext.FramebufferSampleLocationsfvARB(gl::DRAW_FRAMEBUFFER, 0, locations.len() as _, ptr);
+                        }
+                    }
+                }
+            }
+            com::Command::MemoryBarrier(bits) => {
+                if self.share.private_caps.memory_barrier {
+                    unsafe { self.share.context.MemoryBarrier(bits) };
+                } else {
+                    warn!("Memory barriers require GL_ARB_shader_image_load_store");
+                }
+            }
+            com::Command::BindTransformFeedbackBuffer(index, buffer, offset, size) => unsafe {
+                if self.share.private_caps.transform_feedback {
+                    self.share.context.BindBufferRange(
+                        gl::TRANSFORM_FEEDBACK_BUFFER, index, buffer, offset as _, size as _,
+                    );
+                }
+            }
+            com::Command::BeginTransformFeedback(primitive) => unsafe {
+                if self.share.private_caps.transform_feedback {
+                    self.share.context.BeginTransformFeedback(primitive);
+                } else {
+                    error!("Transform feedback is not supported");
+                }
+            }
+            com::Command::PauseTransformFeedback => unsafe {
+                if self.share.private_caps.transform_feedback_pause {
+                    self.share.context.PauseTransformFeedback();
+                } else {
+                    error!("Pausing transform feedback requires GL_ARB_transform_feedback2");
+                }
+            }
+            com::Command::ResumeTransformFeedback => unsafe {
+                if self.share.private_caps.transform_feedback_pause {
+                    self.share.context.ResumeTransformFeedback();
+                }
+            }
+            com::Command::EndTransformFeedback => unsafe {
+                if self.share.private_caps.transform_feedback {
+                    self.share.context.EndTransformFeedback();
+                }
+            }
+            com::Command::SetScissorTest(enable) => unsafe {
+                if enable {
+                    self.share.context.Enable(gl::SCISSOR_TEST);
+                } else {
+                    self.share.context.Disable(gl::SCISSOR_TEST);
+                }
+            }
             com::Command::SetBlendColor(color) => {
                 state::set_blend_color(&self.share.context, color);
             }
-            com::Command::ClearBufferColorF(draw_buffer, cv) => unsafe {
-                self.share.context.ClearBufferfv(gl::COLOR, draw_buffer, cv.as_ptr());
+            com::Command::ClearBufferColorF(draw_buffer, cv) => {
+                self.clear_color_unmasked(draw_buffer, |gl| unsafe {
+                    gl.ClearBufferfv(gl::COLOR, draw_buffer, cv.as_ptr())
+                });
             }
-            com::Command::ClearBufferColorU(draw_buffer, cv) => unsafe {
-                self.share.context.ClearBufferuiv(gl::COLOR, draw_buffer, cv.as_ptr());
+            com::Command::ClearBufferColorU(draw_buffer, cv) => {
+                self.clear_color_unmasked(draw_buffer, |gl| unsafe {
+                    gl.ClearBufferuiv(gl::COLOR, draw_buffer, cv.as_ptr())
+                });
             }
-            com::Command::ClearBufferColorI(draw_buffer, cv) => unsafe {
-                self.share.context.ClearBufferiv(gl::COLOR, draw_buffer, cv.as_ptr());
+            com::Command::ClearBufferColorI(draw_buffer, cv) => {
+                self.clear_color_unmasked(draw_buffer, |gl| unsafe {
+                    gl.ClearBufferiv(gl::COLOR, draw_buffer, cv.as_ptr())
+                });
             }
             com::Command::ClearBufferDepthStencil(depth, stencil) => unsafe {
                 let (target, depth, stencil) = match (depth, stencil) {
@@ -443,7 +1446,27 @@ impl CommandQueue {
                     _ => unreachable!(),
                 };
 
-                self.share.context.ClearBufferfi(target, 0, depth, stencil as _);
+                // Clears are masked by the depth and stencil write state of the bound pipeline.
+                let gl = &self.share.context;
+                let mut prev_depth_mask = gl::TRUE;
+                let mut prev_stencil_masks = [!0; 2];
+                if target != gl::STENCIL {
+                    gl.GetBooleanv(gl::DEPTH_WRITEMASK, &mut prev_depth_mask);
+                    gl.DepthMask(gl::TRUE);
+                }
+                if target != gl::DEPTH {
+                    gl.GetIntegerv(gl::STENCIL_WRITEMASK, &mut prev_stencil_masks[0]);
+                    gl.GetIntegerv(gl::STENCIL_BACK_WRITEMASK, &mut prev_stencil_masks[1]);
+                    gl.StencilMask(!0);
+                }
+                gl.ClearBufferfi(target, 0, depth, stencil as _);
+                if prev_depth_mask == gl::FALSE {
+                    gl.DepthMask(gl::FALSE);
+                }
+                if target != gl::DEPTH {
+                    gl.StencilMaskSeparate(gl::FRONT, prev_stencil_masks[0] as _);
+                    gl.StencilMaskSeparate(gl::BACK, prev_stencil_masks[1] as _);
+                }
             }
             com::Command::DrawBuffers(draw_buffers) => unsafe {
                 let draw_buffers = Self::get::<gl::types::GLenum>(data_buf, draw_buffers);
@@ -452,76 +1475,282 @@ impl CommandQueue {
                     draw_buffers.as_ptr(),
                 );
             }
+            com::Command::InvalidateFramebuffer(point, attachments) => {
+                // Invalidation is only a hint to the driver, skip if not available.
+                if self.share.private_caps.framebuffer_invalidate {
+                    let attachments = Self::get::<gl::types::GLenum>(data_buf, attachments);
+                    unsafe {
+                        self.share.context.InvalidateFramebuffer(
+                            point,
+                            attachments.len() as _,
+                            attachments.as_ptr(),
+                        );
+                    }
+                }
+            }
+            com::Command::BlitFramebuffer(src, dst, filter, srgb) => {
+                self.blit_framebuffer(src, dst, filter, srgb);
+            }
+            com::Command::BindCopyFrameBuffer => {
+                let fbo = self.copy_fbo();
+                let gl = &self.share.context;
+                unsafe {
+                    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+                    gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
+                }
+            }
             com::Command::BindFrameBuffer(point, frame_buffer) => {
                 if self.share.private_caps.framebuffer {
                     let gl = &self.share.context;
                     unsafe { gl.BindFramebuffer(point, frame_buffer) };
+                    self.state.incomplete_framebuffer = frame_buffer == native::DEFAULT_FRAMEBUFFER &&
+                        !*self.default_framebuffer_complete
+                            .entry(self.share.current_window.get())
+                            .or_insert_with(|| unsafe { gl.CheckFramebufferStatus(point) } == gl::FRAMEBUFFER_COMPLETE);
+                    if self.state.incomplete_framebuffer {
+                        debug!("Default framebuffer is incomplete, skipping draws");
+                    }
                 } else if frame_buffer != 0 {
                     error!("Tried to bind FBO {} without FBO support!", frame_buffer);
                 }
             }
-            com::Command::BindTargetView(point, attachment, view) => {
-                self.bind_target(point, attachment, &view)
-            }
-            com::Command::SetDrawColorBuffers(num) => {
-                state::bind_draw_color_buffers(&self.share.context, num);
+            com::Command::BindTargetView(point, attachment, ref view) => {
+                self.bind_target(point, attachment, view)
             }
             com::Command::SetPatchSize(num) => unsafe {
                 self.share.context.PatchParameteri(gl::PATCH_VERTICES, num);
             }
-            com::Command::BindProgram(program) => unsafe {
+            com::Command::SetSmooth(lines, polygons) => {
+                // Pipelines don't enable smoothing without support, but still
+                // disable it when bound, which is invalid in core profiles.
+                if !self.share.private_caps.primitive_smooth {
+                    return;
+                }
+                let gl = &self.share.context;
+                for &(cap, enable) in &[(gl::LINE_SMOOTH, lines), (gl::POLYGON_SMOOTH, polygons)] {
+                    if enable {
+                        unsafe { gl.Enable(cap) };
+                    } else {
+                        unsafe { gl.Disable(cap) };
+                    }
+                }
+            }
+            com::Command::BeginQuery(target, query) => unsafe {
+                self.reset_queries.remove(&query);
+                self.share.context.BeginQuery(target, query);
+            }
+            com::Command::EndQuery(target) => unsafe {
+                self.share.context.EndQuery(target);
+            }
+            com::Command::WriteTimestamp(query) => unsafe {
+                self.reset_queries.remove(&query);
+                self.share.context.QueryCounter(query, gl::TIMESTAMP);
+            }
+            com::Command::ResetQueries(data_ptr) => {
+                let queries = Self::get::<gl::types::GLuint>(data_buf, data_ptr);
+                self.reset_queries.extend(queries.iter().cloned());
+            }
+            com::Command::CopyQueryResults(data_ptr, buffer, offset, stride, flags) => {
+                if !self.share.private_caps.query_buffer {
+                    error!("Copying query results into buffers requires GL_ARB_query_buffer_object");
+                    return;
+                }
+
+                let gl = &self.share.context;
+                let queries = Self::get::<gl::types::GLuint>(data_buf, data_ptr);
+                // Partial results aren't available in GL, unfinished queries
+                // leave the last written value instead.
+                let pname = if flags.contains(native::QueryResultFlags::WAIT) {
+                    gl::QUERY_RESULT
+                } else {
+                    gl::QUERY_RESULT_NO_WAIT
+                };
+                let bits_64 = flags.contains(native::QueryResultFlags::BITS_64);
+                let result_size = if bits_64 { 8 } else { 4 };
+
+                unsafe { gl.BindBuffer(gl::QUERY_BUFFER, buffer) };
+                for (i, &query) in queries.iter().enumerate() {
+                    // With a buffer bound to `GL_QUERY_BUFFER` the pointer is
+                    // interpreted as offset into the buffer.
+                    let dst = offset + i as hal::buffer::Offset * stride;
+                    if self.reset_queries.contains(&query) {
+                        // The GL query still holds the result from before the reset,
+                        // or doesn't exist yet if it never began.
+                        let zero = [0u8; 8];
+                        let write_zero = |dst: hal::buffer::Offset| unsafe {
+                            gl.BufferSubData(gl::QUERY_BUFFER, dst as _, result_size as _, zero.as_ptr() as *const _);
+                        };
+                        if flags.contains(native::QueryResultFlags::WITH_AVAILABILITY) {
+                            write_zero(dst + result_size);
+                        }
+                        if flags.contains(native::QueryResultFlags::PARTIAL) {
+                            write_zero(dst);
+                        }
+                        continue;
+                    }
+                    let write = |pname, dst: hal::buffer::Offset| if bits_64 {
+                        unsafe { gl.GetQueryObjectui64v(query, pname, dst as *mut _) };
+                    } else {
+                        unsafe { gl.GetQueryObjectuiv(query, pname, dst as *mut _) };
+                    };
+                    if flags.contains(native::QueryResultFlags::WITH_AVAILABILITY) {
+                        // Queried before the result, so a stale result is never
+                        // marked as available.
+                        write(gl::QUERY_RESULT_AVAILABLE, dst + result_size);
+                    }
+                    write(pname, dst);
+                }
+                unsafe { gl.BindBuffer(gl::QUERY_BUFFER, 0) };
+            }
+            com::Command::BindProgram(native::BoundProgram::Program(program)) => unsafe {
                 self.share.context.UseProgram(program);
+                self.state.program_pipeline = None;
+            }
+            com::Command::BindProgram(native::BoundProgram::Pipeline(pipeline)) => unsafe {
+                let gl = &self.share.context;
+                // The current program takes precedence over program pipelines.
+                gl.UseProgram(0);
+                gl.BindProgramPipeline(pipeline);
+                self.state.program_pipeline = Some(pipeline);
             }
             com::Command::BindBlendSlot(slot, ref blend) => {
-                state::bind_blend_slot(&self.share.context, slot, blend);
+                if self.share.private_caps.draw_buffers_blend {
+                    state::bind_blend_slot(&self.share.context, slot, blend);
+                } else if slot == 0 {
+                    state::bind_blend(&self.share.context, blend);
+                } else {
+                    warn!("Independent blending requires GL_ARB_draw_buffers_blend, target {} uses the blend state of target 0", slot);
+                }
             }
             com::Command::BindAttribute(ref attribute, handle, stride, function_type) => unsafe {
                 use native::VertexAttribFunction::*;
 
-                let &native::AttributeDesc { location, size, format, offset, .. } = attribute;
+                let &native::AttributeDesc { location, size, format, offset, normalized, divisor, .. } = attribute;
                 let offset = offset as *const gl::types::GLvoid;
                 let gl = &self.share.context;
 
                 gl.BindBuffer(gl::ARRAY_BUFFER, handle);
 
                 match function_type {
-                    Float => gl.VertexAttribPointer(location, size, format, gl::FALSE, stride, offset),
+                    Float => gl.VertexAttribPointer(location, size, format, normalized as _, stride, offset),
                     Integer => gl.VertexAttribIPointer(location, size, format, stride, offset),
                     Double => gl.VertexAttribLPointer(location, size, format, stride, offset),
                 }
 
                 gl.EnableVertexAttribArray(location);
+                // Always set the divisor, the attribute may have been instanced before.
+                // Divisors larger than the instance count are valid,
+                // the attribute stays at the first element.
+                if self.share.features.contains(hal::Features::INSTANCE_RATE) {
+                    gl.VertexAttribDivisor(location, divisor);
+                } else if divisor != 0 {
+                    error!("Instanced arrays are not supported");
+                }
                 gl.BindBuffer(gl::ARRAY_BUFFER, 0);
             }
             /*
             com::Command::UnbindAttribute(ref attribute) => unsafe {
                 self.share.context.DisableVertexAttribArray(attribute.location);
             }*/
-            com::Command::CopyBufferToBuffer(src, dst, ref r) => unsafe {
+            com::Command::CopyBufferToBuffer(src, dst, regions) => {
                 let gl = &self.share.context;
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, src);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, dst);
-                gl.CopyBufferSubData(
-                    gl::PIXEL_UNPACK_BUFFER, gl::PIXEL_PACK_BUFFER,
-                    r.src as _, r.dst as _, r.size as _,
-                );
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                state::with_pixel_buffer(gl, gl::PIXEL_UNPACK_BUFFER, src, || {
+                    state::with_pixel_buffer(gl, gl::PIXEL_PACK_BUFFER, dst, || {
+                        for r in Self::get::<hal::command::BufferCopy>(data_buf, regions) {
+                            unsafe {
+                                gl.CopyBufferSubData(
+                                    gl::PIXEL_UNPACK_BUFFER, gl::PIXEL_PACK_BUFFER,
+                                    r.src as _, r.dst as _, r.size as _,
+                                )
+                            };
+                        }
+                    })
+                });
+            }
+            com::Command::UpdateBuffer(buffer, offset, data_ptr) => {
+                let gl = &self.share.context;
+                let data = Self::get_raw(data_buf, data_ptr);
+                state::with_pixel_buffer(gl, gl::PIXEL_UNPACK_BUFFER, buffer, || unsafe {
+                    gl.BufferSubData(gl::PIXEL_UNPACK_BUFFER, offset as _, data.len() as _, data.as_ptr() as *const _);
+                });
             }
             com::Command::CopyBufferToTexture(buffer, texture, ref r) => unsafe {
                 // TODO: Fix format and active texture
                 assert_eq!(r.image_offset.z, 0);
                 let gl = &self.share.context;
                 gl.ActiveTexture(gl::TEXTURE0);
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer);
                 gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.TexSubImage2D(
-                    gl::TEXTURE_2D, r.image_layers.level as _,
-                    r.image_offset.x, r.image_offset.y,
-                    r.image_extent.width as _, r.image_extent.height as _,
-                    gl::RGBA, gl::UNSIGNED_BYTE, ptr::null(),
-                );
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                state::with_pixel_buffer(gl, gl::PIXEL_UNPACK_BUFFER, buffer, || {
+                    gl.TexSubImage2D(
+                        gl::TEXTURE_2D, r.image_layers.level as _,
+                        r.image_offset.x, r.image_offset.y,
+                        r.image_extent.width as _, r.image_extent.height as _,
+                        gl::RGBA, gl::UNSIGNED_BYTE, ptr::null(),
+                    )
+                });
+                // Texture unit 0 has been rebound.
+                self.state.textures.remove(&0);
+
+                if cfg!(debug_assertions) {
+                    // Levels beyond the mask aren't validated.
+                    *self.uploaded_levels.entry(texture).or_insert(0) |= 1u32.checked_shl(r.image_layers.level as _).unwrap_or(0);
+                }
+            }
+            com::Command::CopyBufferToCompressedTexture(buffer, texture, target, int_format, layout, ref r) => unsafe {
+                let gl = &self.share.context;
+                // Layers of arrays and slices of 3D textures are uploaded one at a time.
+                let (z_start, slices) = match target {
+                    gl::TEXTURE_3D => (r.image_offset.z, r.image_extent.depth as i32),
+                    gl::TEXTURE_2D_ARRAY => (r.image_layers.layers.start as i32, r.image_layers.layers.len() as i32),
+                    _ => (0, 1),
+                };
+                // Rows of blocks are uploaded one at a time if the buffer isn't tightly packed.
+                let rows = if layout.row_pitch == layout.row_size {
+                    let blocks_y = (r.image_extent.height + layout.block_height - 1) / layout.block_height;
+                    vec![(0, r.image_extent.height, 0, blocks_y * layout.row_size)]
+                } else {
+                    (0 .. r.image_extent.height)
+                        .step_by(layout.block_height as usize)
+                        .enumerate()
+                        .map(|(i, y)| {
+                            let height = layout.block_height.min(r.image_extent.height - y);
+                            (y, height, i as u32 * layout.row_pitch, layout.row_size)
+                        })
+                        .collect()
+                };
+
+                gl.ActiveTexture(gl::TEXTURE0);
+                gl.BindTexture(target, texture);
+                state::with_pixel_buffer(gl, gl::PIXEL_UNPACK_BUFFER, buffer, || {
+                    for slice in 0 .. slices {
+                        let slice_offset = r.buffer_offset + (slice as u32 * layout.slice_pitch) as hal::buffer::Offset;
+                        for &(y, height, row_offset, size) in &rows {
+                            let ptr = (slice_offset + row_offset as hal::buffer::Offset) as *const _;
+                            if target == gl::TEXTURE_2D {
+                                gl.CompressedTexSubImage2D(
+                                    target, r.image_layers.level as _,
+                                    r.image_offset.x, r.image_offset.y + y as i32,
+                                    r.image_extent.width as _, height as _,
+                                    int_format, size as _, ptr,
+                                );
+                            } else {
+                                gl.CompressedTexSubImage3D(
+                                    target, r.image_layers.level as _,
+                                    r.image_offset.x, r.image_offset.y + y as i32, z_start + slice,
+                                    r.image_extent.width as _, height as _, 1,
+                                    int_format, size as _, ptr,
+                                );
+                            }
+                        }
+                    }
+                });
+                // Texture unit 0 has been rebound.
+                self.state.textures.remove(&0);
+
+                if cfg!(debug_assertions) {
+                    // Levels beyond the mask aren't validated.
+                    *self.uploaded_levels.entry(texture).or_insert(0) |= 1u32.checked_shl(r.image_layers.level as _).unwrap_or(0);
+                }
             }
             com::Command::CopyBufferToSurface(..) => {
                 unimplemented!() //TODO: use FBO
@@ -532,38 +1761,132 @@ impl CommandQueue {
                 assert_eq!(r.image_offset, hal::image::Offset { x: 0, y: 0, z: 0 });
                 let gl = &self.share.context;
                 gl.ActiveTexture(gl::TEXTURE0);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
                 gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.GetTexImage(
-                    gl::TEXTURE_2D, r.image_layers.level as _,
-                    //r.image_offset.x, r.image_offset.y,
-                    //r.image_extent.width as _, r.image_extent.height as _,
-                    gl::RGBA, gl::UNSIGNED_BYTE, ptr::null_mut(),
-                );
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                state::with_pixel_buffer(gl, gl::PIXEL_PACK_BUFFER, buffer, || {
+                    gl.GetTexImage(
+                        gl::TEXTURE_2D, r.image_layers.level as _,
+                        //r.image_offset.x, r.image_offset.y,
+                        //r.image_extent.width as _, r.image_extent.height as _,
+                        gl::RGBA, gl::UNSIGNED_BYTE, ptr::null_mut(),
+                    )
+                });
+                // Texture unit 0 has been rebound.
+                self.state.textures.remove(&0);
             }
             com::Command::CopySurfaceToBuffer(..) => {
                 unimplemented!() //TODO: use FBO
             }
-            com::Command::CopyImageToTexture(..) => {
-                unimplemented!() //TODO: use FBO
+            com::Command::CopyImage(ref src, ref dst, ref r) => {
+                self.copy_image(src, dst, r);
             }
-            com::Command::CopyImageToSurface(..) => {
-                unimplemented!() //TODO: use FBO
+            com::Command::ClearImage(ref image, color, level, layer) => {
+                self.clear_image(image, color, level, layer);
+            }
+            com::Command::BlitImageShader(ref src, ref dst, ref r) => {
+                self.blit_image_shader(src, dst, r);
+            }
+            com::Command::ResolveDepthStencil(ref src, ref dst, ref r, mode) => {
+                self.resolve_depth_stencil(src, dst, r, mode);
             }
             com::Command::BindBufferRange(target, index, buffer, offset, size) => unsafe {
                 let gl = &self.share.context;
                 gl.BindBufferRange(target, index, buffer, offset, size);
             }
-            com::Command::BindTexture(index, texture) => unsafe {
+            com::Command::BindTexture(index, texture, target) => unsafe {
                 let gl = &self.share.context;
                 gl.ActiveTexture(gl::TEXTURE0 + index);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
+                gl.BindTexture(target, texture);
+                self.state.textures.insert(index, (texture, target));
+                self.invalidate_sampler_info(index);
+            }
+            com::Command::BindInputAttachment(index, framebuffer, attachment) => unsafe {
+                let view = self.share.framebuffer_attachments
+                    .borrow()
+                    .get(&framebuffer)
+                    .and_then(|views| views.get(attachment).cloned());
+                // Input attachments are read as 2D textures, multisampled ones per sample.
+                let (texture, target) = match view {
+                    Some(native::ImageView::Texture(texture, target, _)) |
+                    Some(native::ImageView::TextureView(texture, target))
+                        if target == gl::TEXTURE_2D || target == gl::TEXTURE_2D_MULTISAMPLE =>
+                    {
+                        (texture, target)
+                    }
+                    Some(view) => {
+                        warn!("Input attachment {} can't be read as 2D texture: {:?}", attachment, view);
+                        return;
+                    }
+                    None => {
+                        error!("Framebuffer {} has no attachment {}", framebuffer, attachment);
+                        return;
+                    }
+                };
+                let gl = &self.share.context;
+                gl.ActiveTexture(gl::TEXTURE0 + index);
+                gl.BindTexture(target, texture);
+                self.state.textures.insert(index, (texture, target));
+                self.invalidate_sampler_info(index);
+            }
+            com::Command::ResolveAttachment(framebuffer, src, dst, rect) => unsafe {
+                let resolve = match self.resolve_framebuffer(framebuffer) {
+                    Some(resolve) => resolve,
+                    None => {
+                        error!("Framebuffer {} has no resolve attachments", framebuffer);
+                        return;
+                    }
+                };
+                let gl = &self.share.context;
+                let mut read_framebuffer = 0;
+                let mut read_buffer = 0;
+                gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read_framebuffer);
+                gl.GetIntegerv(gl::READ_BUFFER, &mut read_buffer);
+                // Blits are clipped by the scissor test.
+                let scissor = gl.IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+                if scissor {
+                    gl.Disable(gl::SCISSOR_TEST);
+                }
+
+                gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+                gl.ReadBuffer(src);
+                gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, resolve);
+                gl.DrawBuffers(1, &dst);
+                gl.BlitFramebuffer(
+                    rect[0], rect[1], rect[2], rect[3],
+                    rect[0], rect[1], rect[2], rect[3],
+                    gl::COLOR_BUFFER_BIT, gl::NEAREST,
+                );
+
+                if scissor {
+                    gl.Enable(gl::SCISSOR_TEST);
+                }
+                gl.ReadBuffer(read_buffer as _);
+                gl.BindFramebuffer(gl::READ_FRAMEBUFFER, read_framebuffer as _);
+                gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+            }
+            com::Command::BindImageTexture(unit, texture, level, layered, layer, access, format) => unsafe {
+                if !self.share.private_caps.image_load_store {
+                    error!("Storage images require GL_ARB_shader_image_load_store");
+                    return;
+                }
+                let gl = &self.share.context;
+                gl.BindImageTexture(
+                    unit,
+                    texture,
+                    level as _,
+                    if layered { gl::TRUE } else { gl::FALSE },
+                    layer as _,
+                    access,
+                    format,
+                );
             }
             com::Command::BindSampler(index, sampler) => unsafe {
                 let gl = &self.share.context;
                 gl.BindSampler(index, sampler);
             }
+            com::Command::BindSamplerInfo(index, ref info) => {
+                self.state.sampler_infos.insert(index, info.clone());
+                self.invalidate_sampler_info(index);
+            }
             /*
             com::Command::BindConstantBuffer(pso::ConstantBufferParam(buffer, _, slot)) => unsafe {
                 self.share.context.BindBufferBase(gl::UNIFORM_BUFFER, slot as gl::types::GLuint, buffer);
@@ -672,15 +1995,156 @@ impl CommandQueue {
         }
     }
     
-    fn signal_fence(&mut self, fence: &native::Fence) {
+    // Framebuffer object holding the resolve attachments of a framebuffer
+    // in the current context, created on first use.
+    fn resolve_framebuffer(&self, framebuffer: native::FrameBuffer) -> Option<native::FrameBuffer> {
+        let mut resolves = self.share.resolve_framebuffers.borrow_mut();
+        let native::ResolveFrameBuffer { ref attachments, ref mut objects } = *resolves.get_mut(&framebuffer)?;
+        let share = &self.share;
+        let object = *objects.entry(self.window).or_insert_with(|| {
+            let gl = &share.context;
+            let target = gl::DRAW_FRAMEBUFFER;
+            let mut name = 0;
+            unsafe {
+                gl.GenFramebuffers(1, &mut name);
+                gl.BindFramebuffer(target, name);
+            }
+            for &(att_point, ref view) in attachments {
+                if share.private_caps.framebuffer_texture {
+                    Device::bind_target(gl, target, att_point, view);
+                } else {
+                    Device::bind_target_compat(gl, target, att_point, view);
+                }
+            }
+            name
+        });
+        Some(object)
+    }
+
+    // Wait on the GPU for a semaphore signaled from another context.
+    fn wait_semaphore(&self, semaphore: &native::Semaphore) {
+        if self.share.private_caps.sync {
+            semaphore.wait(&self.share.context);
+        }
+    }
+
+    pub(crate) fn signal_fence(&mut self, fence: &native::Fence) {
         if self.share.private_caps.sync {
             let gl = &self.share.context;
-            let sync = unsafe {
-                gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)
-            };
+            // Fences signaled again without a reset would leak their previous sync object.
+            fence.reset(gl);
+            fence.sync.set(unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) });
+        }
+        fence.signaled.set(!self.share.private_caps.sync);
+    }
 
-            fence.0.set(sync);
+    /// Present `image` to the window of `swapchain` and swap its buffers, for
+    /// renderers compositing their final image into a texture instead of the
+    /// default framebuffer. The commands rendering the image have to be submitted first.
+    ///
+    /// The image is flipped to the window orientation. With `scale` it's stretched
+    /// over the whole window with linear filtering, otherwise it's copied at its
+    /// size to the top-left corner. Multisampled images are resolved.
+    #[cfg(feature = "glutin")]
+    pub fn present_image(
+        &mut self,
+        swapchain: &window::glutin::Swapchain,
+        image: &native::Image,
+        scale: bool,
+    ) -> Result<(), ()> {
+        use hal::queue::RawCommandQueue;
+
+        swapchain.make_current();
+        if swapchain.is_out_of_date() {
+            return Err(());
         }
+        self.switch_context();
+
+        let extent = image.dimensions.level_extent(0);
+        let (width, height) = (extent.width as gl::types::GLint, extent.height as gl::types::GLint);
+        let window = swapchain.extent();
+        let window_height = window.height as gl::types::GLint;
+        let (dst_width, dst_height, filter) = if scale {
+            (window.width as gl::types::GLint, window_height, gl::LINEAR)
+        } else {
+            (width, height, gl::NEAREST)
+        };
+
+        // Resolving blits can't flip or scale, resolve into a temporary renderbuffer first.
+        let resolve_format = if image.dimensions.num_samples() > 1 {
+            match conv::image_format_to_gl(image.format) {
+                Some(int_format) => Some(int_format),
+                None => {
+                    error!("Format {:?} of the presented image can't be resolved", image.format);
+                    return Err(());
+                }
+            }
+        } else {
+            None
+        };
+
+        let fbo = self.copy_fbo();
+        let layer = if image.dimensions.num_layers() > 1 { Some(0) } else { None };
+        unsafe { self.share.context.BindFramebuffer(gl::READ_FRAMEBUFFER, fbo) };
+        self.bind_target(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, &com::image_view(image, 0, layer));
+
+        let gl = &self.share.context;
+        unsafe {
+            gl.ReadBuffer(gl::COLOR_ATTACHMENT0);
+            let scissor = gl.IsEnabled(gl::SCISSOR_TEST) != 0;
+            gl.Disable(gl::SCISSOR_TEST);
+
+            if let Some(int_format) = resolve_format {
+                let mut resolve_target = swapchain.resolve_target.lock().unwrap();
+                let target = resolve_target.get_or_insert_with(|| {
+                    let (mut surface, mut fbo) = (0, 0);
+                    gl.GenRenderbuffers(1, &mut surface);
+                    gl.GenFramebuffers(1, &mut fbo);
+                    gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
+                    gl.FramebufferRenderbuffer(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, surface);
+                    gl.DrawBuffers(1, &gl::COLOR_ATTACHMENT0);
+                    window::glutin::ResolveTarget { fbo, surface, storage: (0, 0, 0) }
+                });
+                // Only reallocated if the presented images change.
+                if target.storage != (int_format, width, height) {
+                    gl.BindRenderbuffer(gl::RENDERBUFFER, target.surface);
+                    gl.RenderbufferStorage(gl::RENDERBUFFER, int_format, width, height);
+                    target.storage = (int_format, width, height);
+                }
+                gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, target.fbo);
+                gl.BlitFramebuffer(
+                    0, 0, width, height,
+                    0, 0, width, height,
+                    gl::COLOR_BUFFER_BIT, gl::NEAREST,
+                );
+                gl.BindFramebuffer(gl::READ_FRAMEBUFFER, target.fbo);
+                gl.ReadBuffer(gl::COLOR_ATTACHMENT0);
+            }
+
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            // The default framebuffer only accepts `BACK` through `glDrawBuffers` on GLES,
+            // desktop GL requires `glDrawBuffer` for it.
+            if self.share.info.version.is_embedded {
+                gl.DrawBuffers(1, &gl::BACK);
+            } else {
+                gl.DrawBuffer(gl::BACK);
+            }
+            gl.BlitFramebuffer(
+                0, 0, width, height,
+                0, window_height, dst_width, window_height - dst_height,
+                gl::COLOR_BUFFER_BIT, filter,
+            );
+
+            if scissor {
+                gl.Enable(gl::SCISSOR_TEST);
+            }
+        }
+        if let Err(err) = self.share.check() {
+            error!("Error presenting image: {:?}", err);
+            return Err(());
+        }
+
+        self.present(Some((swapchain, 0)), ::std::iter::empty::<native::Semaphore>())
     }
 }
 
@@ -693,35 +2157,62 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         IC: IntoIterator,
         IC::Item: Borrow<com::RawCommandBuffer>,
     {
-        use pool::BufferMemory;
+        use pool::{BufferMemory, OwnedBuffer};
+        use std::sync::atomic::Ordering;
+        self.share.check_current();
+        self.switch_context();
+        for &(semaphore, _) in submit_info.wait_semaphores {
+            self.wait_semaphore(semaphore);
+        }
         {
             for buf in submit_info.cmd_buffers {
                 let cb = buf.borrow();
-                let memory = cb
+                if cb.one_time_submit && cb.submitted.swap(true, Ordering::AcqRel) {
+                    error!("Command buffers recorded with `ONE_TIME_SUBMIT` can only be submitted once");
+                    continue;
+                }
+                let mut memory = cb
                     .memory
                     .try_lock()
                     .expect("Trying to submit a command buffers, while memory is in-use.");
 
-                let buffer = match *memory {
-                    BufferMemory::Linear(ref buffer) => buffer,
-                    BufferMemory::Individual { ref storage, .. } => {
-                        storage.get(&cb.id).unwrap()
+                {
+                    let buffer = match *memory {
+                        BufferMemory::Linear(ref buffer) => buffer,
+                        BufferMemory::Individual { ref storage, .. } => {
+                            storage.get(&cb.id).unwrap()
+                        }
+                    };
+
+                    assert!(buffer.commands.len() >= (cb.buf.offset+cb.buf.size) as usize);
+                    let commands = &buffer.commands[cb.buf.offset as usize..(cb.buf.offset+cb.buf.size) as usize];
+                    self.reset_state();
+                    for com in commands {
+                        self.process(com, &buffer.data);
                     }
-                };
+                }
 
-                assert!(buffer.commands.len() >= (cb.buf.offset+cb.buf.size) as usize);
-                let commands = &buffer.commands[cb.buf.offset as usize..(cb.buf.offset+cb.buf.size) as usize];
-                self.reset_state();
-                for com in commands {
-                    self.process(com, &buffer.data);
+                if cb.one_time_submit {
+                    // The commands can't be executed again, release their memory
+                    // instead of keeping it around until the next reset.
+                    if let BufferMemory::Individual { ref mut storage, .. } = *memory {
+                        storage.insert(cb.id, OwnedBuffer::new());
+                    }
                 }
             }
         }
+        // Submissions of a single context are executed in order,
+        // only waits of other contexts need a sync object.
+        if self.share.private_caps.sync && self.share.has_other_contexts() {
+            for semaphore in submit_info.signal_semaphores {
+                semaphore.signal(&self.share.context);
+            }
+        }
         fence.map(|fence| self.signal_fence(fence));
     }
 
     #[cfg(feature = "glutin")]
-    fn present<IS, S, IW>(&mut self, swapchains: IS, _wait_semaphores: IW) -> Result<(), ()>
+    fn present<IS, S, IW>(&mut self, swapchains: IS, wait_semaphores: IW) -> Result<(), ()>
     where
         IS: IntoIterator<Item = (S, hal::SwapImageIndex)>,
         S: Borrow<window::glutin::Swapchain>,
@@ -729,16 +2220,69 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         IW::Item: Borrow<native::Semaphore>,
     {
         use glutin::GlContext;
+        use std::sync::atomic::Ordering;
 
+        let gl = &self.share.context;
+        let mut result = Ok(());
+        let mut wait_semaphores = Some(wait_semaphores);
         for swapchain in swapchains {
-            swapchain.0
-                .borrow()
-                .window
-                .swap_buffers()
-                .unwrap();
+            let swapchain = swapchain.0.borrow();
+            swapchain.make_current();
+            self.default_framebuffer_complete.remove(&self.share.current_window.get());
+            // Rendering may have been submitted from another context.
+            if let Some(semaphores) = wait_semaphores.take() {
+                for semaphore in semaphores {
+                    self.wait_semaphore(semaphore.borrow());
+                }
+            }
+            if swapchain.is_out_of_date() {
+                // Nothing has been rendered, retry once the window is restored.
+                result = Err(());
+                continue;
+            }
+            // Make sure all commands are submitted, without waiting for their completion.
+            // Pacing sleeps before the swap, so the frame is submitted ahead of it.
+            if !swapchain.flushes_on_swap() || swapchain.frame_time.is_some() {
+                unsafe { gl.Flush(); }
+            }
+            if let Some(frame_time) = swapchain.frame_time {
+                self.pace_frame(&swapchain.pacing_fence, &swapchain.last_present, frame_time);
+            }
+            if swapchain.swap_on_present {
+                swapchain
+                    .window
+                    .swap_buffers()
+                    .unwrap();
+            } else {
+                // The caller swaps the buffers.
+                swapchain.ready.store(true, Ordering::Release);
+            }
+
+            match swapchain.present_sync {
+                window::glutin::PresentSync::Flush => (),
+                window::glutin::PresentSync::Fence => {
+                    let fence = &swapchain.frame_fence;
+                    if self.share.private_caps.sync {
+                        unsafe {
+                            let sync = fence.sync.get();
+                            if gl.IsSync(sync) == gl::TRUE {
+                                gl.DeleteSync(sync);
+                            }
+                            fence.sync.set(gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0));
+                            // Submit the fence itself, otherwise it may only signal
+                            // with the commands of the next frame.
+                            gl.Flush();
+                        }
+                        fence.signaled.set(false);
+                    }
+                }
+                window::glutin::PresentSync::Finish => unsafe {
+                    gl.Finish();
+                },
+            }
         }
 
-        Ok(())
+        result
     }
 
     fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
@@ -746,3 +2290,172 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         Ok(())
     }
 }
+
+// GL name and target of an image for `glCopyImageSubData`.
+fn image_name(image: &native::Image) -> (gl::types::GLuint, gl::types::GLenum) {
+    match image.kind {
+        native::ImageKind::Surface(surface) => (surface, gl::RENDERBUFFER),
+        native::ImageKind::Texture(texture, target) => (texture, target),
+    }
+}
+
+fn offset_by(offset: hal::image::Offset, extent: hal::image::Extent) -> hal::image::Offset {
+    hal::image::Offset {
+        x: offset.x + extent.width as i32,
+        y: offset.y + extent.height as i32,
+        z: offset.z + extent.depth as i32,
+    }
+}
+
+fn bounds_rect(bounds: &Range<hal::image::Offset>) -> [gl::types::GLint; 4] {
+    [bounds.start.x, bounds.start.y, bounds.end.x, bounds.end.y]
+}
+
+// Compile and link a program from vertex and fragment shader sources,
+// `None` if linking failed.
+fn link_program(gl: &gl::Gl, vs: &str, fs: &str) -> Option<native::Program> {
+    unsafe {
+        let program = gl.CreateProgram();
+        let shaders = [(gl::VERTEX_SHADER, vs), (gl::FRAGMENT_SHADER, fs)];
+        for &(stage, source) in &shaders {
+            let shader = gl.CreateShader(stage);
+            let length = source.len() as gl::types::GLint;
+            let source = source.as_ptr() as *const gl::types::GLchar;
+            gl.ShaderSource(shader, 1, &source, &length);
+            gl.CompileShader(shader);
+            gl.AttachShader(program, shader);
+            gl.DeleteShader(shader);
+        }
+        gl.LinkProgram(program);
+
+        let mut status = 0;
+        gl.GetProgramiv(program, gl::LINK_STATUS, &mut status);
+        if status == 0 {
+            gl.DeleteProgram(program);
+            return None;
+        }
+        Some(program)
+    }
+}
+
+// Vertex and fragment shader of the minimum and maximum depth resolves.
+//
+// A single triangle covers the viewport, each fragment reduces the depth
+// of all samples of the corresponding source texel.
+fn depth_resolve_shader_sources(
+    is_embedded: bool,
+    target: native::TextureTarget,
+    mode: native::DepthResolveMode,
+) -> (String, String) {
+    let header = if is_embedded {
+        "#version 310 es\nprecision highp float;\nprecision highp int;\nprecision highp sampler2DMS;\n"
+    } else {
+        "#version 150\n"
+    };
+    let (header, sampler, coord) = if target == gl::TEXTURE_2D_MULTISAMPLE_ARRAY {
+        let header = if is_embedded {
+            "#version 320 es\nprecision highp float;\nprecision highp int;\nprecision highp sampler2DMSArray;\n"
+        } else {
+            header
+        };
+        (header, "sampler2DMSArray", "ivec3(coord, u_Layer)")
+    } else {
+        (header, "sampler2DMS", "coord")
+    };
+    let reduce = match mode {
+        native::DepthResolveMode::Min => "min",
+        native::DepthResolveMode::Max => "max",
+        native::DepthResolveMode::SampleZero => unreachable!(),
+    };
+
+    let vs = format!(
+        "{}\
+         void main() {{\n\
+         \x20   vec2 pos = vec2(float((gl_VertexID & 1) << 2), float((gl_VertexID & 2) << 1)) - 1.0;\n\
+         \x20   gl_Position = vec4(pos, 0.0, 1.0);\n\
+         }}\n",
+        header,
+    );
+    let fs = format!(
+        "{header}\
+         uniform {sampler} u_Source;\n\
+         uniform int u_Layer;\n\
+         uniform int u_Samples;\n\
+         uniform ivec2 u_Offset;\n\
+         void main() {{\n\
+         \x20   ivec2 coord = ivec2(gl_FragCoord.xy) + u_Offset;\n\
+         \x20   float depth = texelFetch(u_Source, {coord}, 0).r;\n\
+         \x20   for (int i = 1; i < u_Samples; ++i) {{\n\
+         \x20       depth = {reduce}(depth, texelFetch(u_Source, {coord}, i).r);\n\
+         \x20   }}\n\
+         \x20   gl_FragDepth = depth;\n\
+         }}\n",
+        header = header,
+        sampler = sampler,
+        coord = coord,
+        reduce = reduce,
+    );
+    (vs, fs)
+}
+
+// Vertex and fragment shader of the shader based blits.
+//
+// A single triangle covers the viewport, each fragment fetches the
+// corresponding texel of the source rectangle.
+fn blit_shader_sources(
+    is_embedded: bool,
+    target: native::TextureTarget,
+    src: com::ChannelClass,
+    dst: com::ChannelClass,
+) -> (String, String) {
+    fn prefix(class: com::ChannelClass) -> &'static str {
+        match class {
+            com::ChannelClass::Float => "",
+            com::ChannelClass::Uint => "u",
+            com::ChannelClass::Int => "i",
+        }
+    }
+
+    let header = if is_embedded {
+        "#version 300 es\nprecision highp float;\nprecision highp int;\n\
+         precision highp sampler2D;\nprecision highp sampler2DArray;\n\
+         precision highp usampler2D;\nprecision highp usampler2DArray;\n\
+         precision highp isampler2D;\nprecision highp isampler2DArray;\n"
+    } else {
+        "#version 150\n"
+    };
+    let (sampler, coord) = if target == gl::TEXTURE_2D_ARRAY {
+        ("sampler2DArray", "ivec3(coord, u_Layer)")
+    } else {
+        ("sampler2D", "coord")
+    };
+
+    let vs = format!(
+        "{}\
+         void main() {{\n\
+         \x20   vec2 pos = vec2(float((gl_VertexID & 1) << 2), float((gl_VertexID & 2) << 1)) - 1.0;\n\
+         \x20   gl_Position = vec4(pos, 0.0, 1.0);\n\
+         }}\n",
+        header,
+    );
+    let fs = format!(
+        "{header}\
+         uniform {src}{sampler} u_Source;\n\
+         uniform int u_Level;\n\
+         uniform int u_Layer;\n\
+         uniform ivec4 u_SrcRect;\n\
+         uniform ivec4 u_DstRect;\n\
+         out {dst}vec4 o_Color;\n\
+         void main() {{\n\
+         \x20   vec2 t = (gl_FragCoord.xy - vec2(u_DstRect.xy)) / vec2(u_DstRect.zw - u_DstRect.xy);\n\
+         \x20   ivec2 coord = u_SrcRect.xy + ivec2(t * vec2(u_SrcRect.zw - u_SrcRect.xy));\n\
+         \x20   o_Color = {dst}vec4(texelFetch(u_Source, {coord}, u_Level));\n\
+         }}\n",
+        header = header,
+        src = prefix(src),
+        dst = prefix(dst),
+        sampler = sampler,
+        coord = coord,
+    );
+    (vs, fs)
+}
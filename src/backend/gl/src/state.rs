@@ -30,6 +30,28 @@ pub fn bind_polygon_mode(gl: &gl::Gl, mode: pso::PolygonMode, bias: Option<pso::
     }
 }
 
+pub fn set_depth_bias(gl: &gl::Gl, bias: Option<pso::DepthBias>, is_embedded: bool) {
+    // GLES only supports offsets for filled polygons.
+    let offsets: &[gl::types::GLenum] = if is_embedded {
+        &[gl::POLYGON_OFFSET_FILL]
+    } else {
+        &[gl::POLYGON_OFFSET_FILL, gl::POLYGON_OFFSET_LINE, gl::POLYGON_OFFSET_POINT]
+    };
+    match bias {
+        Some(bias) => unsafe {
+            for &offset in offsets {
+                gl.Enable(offset);
+            }
+            gl.PolygonOffset(bias.slope_factor as _, bias.const_factor as _);
+        },
+        None => unsafe {
+            for &offset in offsets {
+                gl.Disable(offset);
+            }
+        },
+    }
+}
+
 pub fn bind_rasterizer(gl: &gl::Gl, r: &pso::Rasterizer, is_embedded: bool) {
     use hal::pso::FrontFace::*;
 
@@ -111,23 +133,14 @@ fn map_operation(op: pso::StencilOp) -> gl::types::GLenum {
     }
 }
 
-pub fn bind_stencil(
-    gl: &gl::Gl,
-    stencil: &pso::StencilTest,
-    (ref_front, ref_back): (pso::StencilValue, pso::StencilValue),
-    cull: Option<pso::Face>,
-) {
-    fn bind_side(gl: &gl::Gl, face: gl::types::GLenum, side: &pso::StencilFace, ref_value: pso::StencilValue) {
+pub fn bind_stencil(gl: &gl::Gl, stencil: &pso::StencilTest) {
+    // Dynamic states are expected to be resolved by the command buffer.
+    fn bind_side(gl: &gl::Gl, face: gl::types::GLenum, side: &pso::StencilFace) {
         unsafe {
-            let mr = match side.mask_read {
-                pso::State::Static(v) => v,
-                pso::State::Dynamic => !0,
-            };
-            let mw = match side.mask_write {
-                pso::State::Static(v) => v,
-                pso::State::Dynamic => !0,
-            };
-            gl.StencilFuncSeparate(face, map_comparison(side.fun), ref_value as _, mr);
+            let reference = side.reference.static_or(0);
+            let mr = side.mask_read.static_or(!0);
+            let mw = side.mask_write.static_or(!0);
+            gl.StencilFuncSeparate(face, map_comparison(side.fun), reference as _, mr);
             gl.StencilMaskSeparate(face, mw);
             gl.StencilOpSeparate(face, map_operation(side.op_fail), map_operation(side.op_depth_fail), map_operation(side.op_pass));
         }
@@ -135,14 +148,8 @@ pub fn bind_stencil(
     match *stencil {
         pso::StencilTest::On { ref front, ref back } => {
             unsafe { gl.Enable(gl::STENCIL_TEST) };
-            if let Some(cf) = cull {
-                if !cf.contains(pso::Face::FRONT) {
-                    bind_side(gl, gl::FRONT, front, ref_front);
-                }
-                if !cf.contains(pso::Face::BACK) {
-                    bind_side(gl, gl::BACK, back, ref_back);
-                }
-            }
+            bind_side(gl, gl::FRONT, front);
+            bind_side(gl, gl::BACK, back);
         }
         pso::StencilTest::Off => unsafe {
             gl.Disable(gl::STENCIL_TEST);
@@ -243,3 +250,23 @@ pub fn set_blend_color(gl: &gl::Gl, color: pso::ColorValue) {
         gl.BlendColor(color[0], color[1], color[2], color[3])
     };
 }
+
+/// Run `f` with `buffer` bound to the pixel transfer `target`,
+/// restoring the previously bound buffer afterwards.
+pub fn with_pixel_buffer<T, F: FnOnce() -> T>(
+    gl: &gl::Gl, target: gl::types::GLenum, buffer: gl::types::GLuint, f: F,
+) -> T {
+    let binding = match target {
+        gl::PIXEL_PACK_BUFFER => gl::PIXEL_PACK_BUFFER_BINDING,
+        gl::PIXEL_UNPACK_BUFFER => gl::PIXEL_UNPACK_BUFFER_BINDING,
+        _ => panic!("Unexpected pixel buffer target {:#x}", target),
+    };
+    let mut previous = 0;
+    unsafe {
+        gl.GetIntegerv(binding, &mut previous);
+        gl.BindBuffer(target, buffer);
+    }
+    let result = f();
+    unsafe { gl.BindBuffer(target, previous as _) };
+    result
+}
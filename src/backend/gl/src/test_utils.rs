@@ -0,0 +1,652 @@
+//! Fake GL implementation for tests.
+//!
+//! The entry points record their calls per thread, tests inspect them via
+//! `calls`. Queries return plausible defaults, integer queries can be
+//! overridden with `set_integer`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::os::raw::c_void;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+use hal;
+use hal::queue::QueueFamilyId;
+
+use command::RawCommandBuffer;
+use native as n;
+use queue::CommandQueue;
+use {Backend, Device, PhysicalDevice};
+
+/// Value returned by integer queries which aren't overridden.
+const DEFAULT_INTEGER: GLint = 64;
+
+thread_local! {
+    static CALLS: RefCell<Vec<(&'static str, Vec<i64>)>> = RefCell::new(Vec::new());
+    static INTEGERS: RefCell<Vec<(GLenum, GLint)>> = RefCell::new(Vec::new());
+    static ENABLED: RefCell<HashSet<GLenum>> = RefCell::new(HashSet::new());
+    static VERSION: Cell<&'static [u8]> = Cell::new(b"4.5.0 Fake\0");
+    static NEXT_NAME: Cell<GLuint> = Cell::new(1);
+    // Storage of mapped ranges, released with the thread of the test.
+    static MAPPINGS: RefCell<Vec<Box<[u8]>>> = RefCell::new(Vec::new());
+}
+
+fn record(name: &'static str, args: Vec<i64>) {
+    CALLS.with(|calls| calls.borrow_mut().push((name, args)));
+}
+
+/// Arguments of the recorded calls of a GL function, named without `gl` prefix.
+pub fn calls(name: &str) -> Vec<Vec<i64>> {
+    CALLS.with(|calls| {
+        calls
+            .borrow()
+            .iter()
+            .filter(|&&(call, _)| call == name)
+            .map(|&(_, ref args)| args.clone())
+            .collect()
+    })
+}
+
+/// Names of all recorded calls in order.
+pub fn call_names() -> Vec<&'static str> {
+    CALLS.with(|calls| calls.borrow().iter().map(|&(name, _)| name).collect())
+}
+
+/// Forget the calls recorded so far.
+pub fn clear_calls() {
+    CALLS.with(|calls| calls.borrow_mut().clear());
+}
+
+/// Override the value returned by integer queries of `pname`.
+pub fn set_integer(pname: GLenum, value: GLint) {
+    INTEGERS.with(|integers| integers.borrow_mut().push((pname, value)));
+}
+
+/// Set the version string reported by the context, NUL terminated.
+/// Has to be called before creating the adapter.
+pub fn set_version(version: &'static [u8]) {
+    VERSION.with(|v| v.set(version));
+}
+
+fn integer(pname: GLenum) -> GLint {
+    if pname == gl::NUM_EXTENSIONS || pname == gl::CONTEXT_PROFILE_MASK {
+        return 0;
+    }
+    INTEGERS.with(|integers| {
+        integers
+            .borrow()
+            .iter()
+            .rev()
+            .find(|&&(name, _)| name == pname)
+            .map_or(DEFAULT_INTEGER, |&(_, value)| value)
+    })
+}
+
+fn gen_names(n: GLsizei, names: *mut GLuint) {
+    for i in 0 .. n as isize {
+        let name = NEXT_NAME.with(|next| {
+            let name = next.get();
+            next.set(name + 1);
+            name
+        });
+        unsafe { *names.offset(i) = name };
+    }
+}
+
+extern "system" fn get_error() -> GLenum {
+    gl::NO_ERROR
+}
+
+extern "system" fn get_string(name: GLenum) -> *const GLubyte {
+    let string: &'static [u8] = match name {
+        gl::VERSION => VERSION.with(|v| v.get()),
+        gl::SHADING_LANGUAGE_VERSION => {
+            if VERSION.with(|v| v.get()).starts_with(b"OpenGL ES") {
+                b"OpenGL ES GLSL ES 3.20\0"
+            } else {
+                b"4.50\0"
+            }
+        }
+        gl::VENDOR => b"Fake\0",
+        gl::RENDERER => b"Fake Renderer\0",
+        _ => b"\0",
+    };
+    string.as_ptr()
+}
+
+extern "system" fn get_integerv(pname: GLenum, data: *mut GLint) {
+    unsafe { *data = integer(pname) };
+}
+
+extern "system" fn get_integeri_v(pname: GLenum, _: GLuint, data: *mut GLint) {
+    unsafe { *data = integer(pname) };
+}
+
+extern "system" fn get_integer64v(pname: GLenum, data: *mut GLint64) {
+    unsafe { *data = integer(pname) as _ };
+}
+
+extern "system" fn get_floatv(pname: GLenum, data: *mut GLfloat) {
+    unsafe { *data = integer(pname) as _ };
+}
+
+extern "system" fn get_booleanv(pname: GLenum, data: *mut GLboolean) {
+    unsafe { *data = (integer(pname) != 0) as _ };
+}
+
+extern "system" fn get_tex_parameteriv(_: GLenum, pname: GLenum, data: *mut GLint) {
+    unsafe { *data = integer(pname) };
+}
+
+extern "system" fn get_tex_level_parameteriv(_: GLenum, _: GLint, pname: GLenum, data: *mut GLint) {
+    unsafe { *data = integer(pname) };
+}
+
+extern "system" fn enable(cap: GLenum) {
+    record("Enable", vec![cap as i64]);
+    ENABLED.with(|enabled| enabled.borrow_mut().insert(cap));
+}
+
+extern "system" fn disable(cap: GLenum) {
+    record("Disable", vec![cap as i64]);
+    ENABLED.with(|enabled| enabled.borrow_mut().remove(&cap));
+}
+
+extern "system" fn get_booleani_v(pname: GLenum, _: GLuint, data: *mut GLboolean) {
+    unsafe { *data = (integer(pname) != 0) as _ };
+}
+
+extern "system" fn is_enabled(cap: GLenum) -> GLboolean {
+    ENABLED.with(|enabled| enabled.borrow().contains(&cap)) as _
+}
+
+extern "system" fn gen_textures(n: GLsizei, names: *mut GLuint) {
+    gen_names(n, names);
+    record("GenTextures", vec![n as i64]);
+}
+
+extern "system" fn gen_objects(n: GLsizei, names: *mut GLuint) {
+    gen_names(n, names);
+}
+
+extern "system" fn create_object() -> GLuint {
+    let mut name = 0;
+    gen_names(1, &mut name);
+    name
+}
+
+extern "system" fn create_shader(_: GLenum) -> GLuint {
+    create_object()
+}
+
+extern "system" fn get_object_iv(_: GLuint, pname: GLenum, data: *mut GLint) {
+    let value = match pname {
+        gl::LINK_STATUS | gl::COMPILE_STATUS | gl::VALIDATE_STATUS => 1,
+        _ => 0,
+    };
+    unsafe { *data = value };
+}
+
+extern "system" fn get_location(_: GLuint, _: *const GLchar) -> GLint {
+    -1
+}
+
+extern "system" fn check_framebuffer_status(target: GLenum) -> GLenum {
+    record("CheckFramebufferStatus", vec![target as i64]);
+    gl::FRAMEBUFFER_COMPLETE
+}
+
+extern "system" fn fence_sync(_: GLenum, _: GLbitfield) -> GLsync {
+    record("FenceSync", vec![]);
+    create_object() as usize as GLsync
+}
+
+extern "system" fn client_wait_sync(sync: GLsync, _: GLbitfield, _: GLuint64) -> GLenum {
+    record("ClientWaitSync", vec![sync as i64]);
+    gl::ALREADY_SIGNALED
+}
+
+extern "system" fn get_synciv(_: GLsync, _: GLenum, _: GLsizei, _: *mut GLsizei, values: *mut GLint) {
+    unsafe { *values = gl::SIGNALED as _ };
+}
+
+extern "system" fn is_object(_: GLuint) -> GLboolean {
+    gl::TRUE
+}
+
+extern "system" fn is_sync(_: GLsync) -> GLboolean {
+    gl::TRUE
+}
+
+extern "system" fn unmap_buffer(target: GLenum) -> GLboolean {
+    record("UnmapBuffer", vec![target as i64]);
+    gl::TRUE
+}
+
+extern "system" fn map_buffer_range(
+    target: GLenum, offset: GLintptr, length: GLsizeiptr, access: GLbitfield,
+) -> *mut c_void {
+    record("MapBufferRange", vec![target as i64, offset as i64, length as i64, access as i64]);
+    let mut storage = vec![0u8; length as usize].into_boxed_slice();
+    let ptr = storage.as_mut_ptr();
+    MAPPINGS.with(|mappings| mappings.borrow_mut().push(storage));
+    ptr as *mut _
+}
+
+extern "system" fn get_framebuffer_attachment_parameteriv(
+    _: GLenum, _: GLenum, pname: GLenum, data: *mut GLint,
+) {
+    unsafe { *data = integer(pname) };
+}
+
+extern "system" fn get_internalformativ(_: GLenum, _: GLenum, pname: GLenum, _: GLsizei, data: *mut GLint) {
+    unsafe { *data = integer(pname) };
+}
+
+// Buffer variables named `Unused...` aren't active.
+extern "system" fn get_program_resource_index(_: GLuint, _: GLenum, name: *const GLchar) -> GLuint {
+    let name = unsafe { ::std::ffi::CStr::from_ptr(name) };
+    if name.to_bytes().starts_with(b"Unused") {
+        gl::INVALID_INDEX
+    } else {
+        0
+    }
+}
+
+extern "system" fn get_program_resourceiv(
+    _: GLuint, _: GLenum, _: GLuint, count: GLsizei, props: *const GLenum, _: GLsizei, _: *mut GLsizei,
+    params: *mut GLint,
+) {
+    for i in 0 .. count as isize {
+        unsafe { *params.offset(i) = integer(*props.offset(i)) };
+    }
+}
+
+extern "system" fn get_info_log(_: GLuint, _: GLsizei, length: *mut GLsizei, log: *mut GLchar) {
+    unsafe {
+        if !length.is_null() {
+            *length = 0;
+        }
+        if !log.is_null() {
+            *log = 0;
+        }
+    }
+}
+
+// Records the bits of the four components of border colors.
+fn record_parameterfv(name: &'static str, object: GLuint, pname: GLenum, params: *const GLfloat) {
+    let count = if pname == gl::TEXTURE_BORDER_COLOR { 4 } else { 1 };
+    let mut args = vec![object as i64, pname as i64];
+    args.extend((0 .. count).map(|i| unsafe { *params.offset(i) }.to_bits() as i64));
+    record(name, args);
+}
+
+extern "system" fn sampler_parameterfv(sampler: GLuint, pname: GLenum, params: *const GLfloat) {
+    record_parameterfv("SamplerParameterfv", sampler, pname, params);
+}
+
+extern "system" fn tex_parameterfv(target: GLenum, pname: GLenum, params: *const GLfloat) {
+    record_parameterfv("TexParameterfv", target, pname, params);
+}
+
+macro_rules! recorders {
+    ($($name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            #[allow(non_snake_case)]
+            extern "system" fn $name($($arg: $ty),*) {
+                record(stringify!($name), vec![$($arg as i64),*]);
+            }
+        )*
+
+        fn recorder(symbol: &str) -> *const c_void {
+            $(
+                if symbol == concat!("gl", stringify!($name)) {
+                    return $name as *const c_void;
+                }
+            )*
+            ptr::null()
+        }
+    };
+}
+
+recorders! {
+    ActiveTexture(texture: GLenum);
+    ActiveShaderProgram(pipeline: GLuint, program: GLuint);
+    AttachShader(program: GLuint, shader: GLuint);
+    BeginQuery(target: GLenum, id: GLuint);
+    BeginTransformFeedback(mode: GLenum);
+    BindBuffer(target: GLenum, buffer: GLuint);
+    BindBufferBase(target: GLenum, index: GLuint, buffer: GLuint);
+    BindBufferRange(target: GLenum, index: GLuint, buffer: GLuint, offset: GLintptr, size: GLsizeiptr);
+    BindFragDataLocation(program: GLuint, color: GLuint, name: *const GLchar);
+    BindFramebuffer(target: GLenum, framebuffer: GLuint);
+    BindImageTexture(
+        unit: GLuint, texture: GLuint, level: GLint, layered: GLboolean,
+        layer: GLint, access: GLenum, format: GLenum
+    );
+    BindProgramPipeline(pipeline: GLuint);
+    BindRenderbuffer(target: GLenum, renderbuffer: GLuint);
+    BindSampler(unit: GLuint, sampler: GLuint);
+    BindTexture(target: GLenum, texture: GLuint);
+    BindVertexArray(array: GLuint);
+    BlendColor(r: GLfloat, g: GLfloat, b: GLfloat, a: GLfloat);
+    BlendEquationSeparate(rgb: GLenum, alpha: GLenum);
+    BlendEquationSeparateiARB(buffer: GLuint, rgb: GLenum, alpha: GLenum);
+    BlendFuncSeparate(src_rgb: GLenum, dst_rgb: GLenum, src_alpha: GLenum, dst_alpha: GLenum);
+    BlendFuncSeparateiARB(
+        buffer: GLuint, src_rgb: GLenum, dst_rgb: GLenum, src_alpha: GLenum, dst_alpha: GLenum
+    );
+    BlitFramebuffer(
+        src_x0: GLint, src_y0: GLint, src_x1: GLint, src_y1: GLint,
+        dst_x0: GLint, dst_y0: GLint, dst_x1: GLint, dst_y1: GLint,
+        mask: GLbitfield, filter: GLenum
+    );
+    BufferData(target: GLenum, size: GLsizeiptr, data: *const c_void, usage: GLenum);
+    BufferStorage(target: GLenum, size: GLsizeiptr, data: *const c_void, flags: GLbitfield);
+    BufferSubData(target: GLenum, offset: GLintptr, size: GLsizeiptr, data: *const c_void);
+    ClearBufferfi(buffer: GLenum, drawbuffer: GLint, depth: GLfloat, stencil: GLint);
+    ClearBufferfv(buffer: GLenum, drawbuffer: GLint, value: *const GLfloat);
+    ClearBufferiv(buffer: GLenum, drawbuffer: GLint, value: *const GLint);
+    ClearBufferuiv(buffer: GLenum, drawbuffer: GLint, value: *const GLuint);
+    ClearTexImage(texture: GLuint, level: GLint, format: GLenum, ty: GLenum, data: *const c_void);
+    ClipControl(origin: GLenum, depth: GLenum);
+    ColorMask(r: GLboolean, g: GLboolean, b: GLboolean, a: GLboolean);
+    ColorMaski(index: GLuint, r: GLboolean, g: GLboolean, b: GLboolean, a: GLboolean);
+    CompileShader(shader: GLuint);
+    CompressedTexSubImage2D(
+        target: GLenum, level: GLint, x: GLint, y: GLint, width: GLsizei, height: GLsizei,
+        format: GLenum, size: GLsizei, data: *const c_void
+    );
+    CompressedTexSubImage3D(
+        target: GLenum, level: GLint, x: GLint, y: GLint, z: GLint,
+        width: GLsizei, height: GLsizei, depth: GLsizei, format: GLenum, size: GLsizei, data: *const c_void
+    );
+    CopyBufferSubData(
+        read: GLenum, write: GLenum, read_offset: GLintptr, write_offset: GLintptr, size: GLsizeiptr
+    );
+    CopyImageSubData(
+        src: GLuint, src_target: GLenum, src_level: GLint, src_x: GLint, src_y: GLint, src_z: GLint,
+        dst: GLuint, dst_target: GLenum, dst_level: GLint, dst_x: GLint, dst_y: GLint, dst_z: GLint,
+        width: GLsizei, height: GLsizei, depth: GLsizei
+    );
+    CullFace(mode: GLenum);
+    DeleteBuffers(n: GLsizei, names: *const GLuint);
+    DeleteFramebuffers(n: GLsizei, names: *const GLuint);
+    DeleteProgram(program: GLuint);
+    DeleteProgramPipelines(n: GLsizei, names: *const GLuint);
+    DeleteQueries(n: GLsizei, names: *const GLuint);
+    DeleteRenderbuffers(n: GLsizei, names: *const GLuint);
+    DeleteSamplers(n: GLsizei, names: *const GLuint);
+    DeleteShader(shader: GLuint);
+    DeleteSync(sync: GLsync);
+    DeleteTextures(n: GLsizei, names: *const GLuint);
+    DeleteVertexArrays(n: GLsizei, names: *const GLuint);
+    DepthFunc(func: GLenum);
+    DepthBoundsEXT(min: GLdouble, max: GLdouble);
+    DepthMask(flag: GLboolean);
+    DepthRange(near: GLdouble, far: GLdouble);
+    DepthRangef(near: GLfloat, far: GLfloat);
+    DepthRangeArrayv(first: GLuint, count: GLsizei, v: *const GLdouble);
+    DetachShader(program: GLuint, shader: GLuint);
+    Disablei(cap: GLenum, index: GLuint);
+    DisableVertexAttribArray(index: GLuint);
+    DispatchCompute(x: GLuint, y: GLuint, z: GLuint);
+    DispatchComputeIndirect(offset: GLintptr);
+    DrawArrays(mode: GLenum, first: GLint, count: GLsizei);
+    DrawArraysInstanced(mode: GLenum, first: GLint, count: GLsizei, instances: GLsizei);
+    DrawArraysInstancedBaseInstance(
+        mode: GLenum, first: GLint, count: GLsizei, instances: GLsizei, base_instance: GLuint
+    );
+    DrawBuffer(buffer: GLenum);
+    DrawBuffers(n: GLsizei, buffers: *const GLenum);
+    DrawElements(mode: GLenum, count: GLsizei, ty: GLenum, offset: *const c_void);
+    DrawElementsBaseVertex(
+        mode: GLenum, count: GLsizei, ty: GLenum, offset: *const c_void, base_vertex: GLint
+    );
+    DrawElementsInstanced(
+        mode: GLenum, count: GLsizei, ty: GLenum, offset: *const c_void, instances: GLsizei
+    );
+    DrawElementsInstancedBaseVertex(
+        mode: GLenum, count: GLsizei, ty: GLenum, offset: *const c_void, instances: GLsizei,
+        base_vertex: GLint
+    );
+    DrawElementsInstancedBaseVertexBaseInstance(
+        mode: GLenum, count: GLsizei, ty: GLenum, offset: *const c_void, instances: GLsizei,
+        base_vertex: GLint, base_instance: GLuint
+    );
+    Enablei(cap: GLenum, index: GLuint);
+    EnableVertexAttribArray(index: GLuint);
+    EndQuery(target: GLenum);
+    EndTransformFeedback();
+    Finish();
+    Flush();
+    FlushMappedBufferRange(target: GLenum, offset: GLintptr, length: GLsizeiptr);
+    FramebufferParameteri(target: GLenum, pname: GLenum, param: GLint);
+    FramebufferRenderbuffer(target: GLenum, attachment: GLenum, rb_target: GLenum, renderbuffer: GLuint);
+    FramebufferTexture(target: GLenum, attachment: GLenum, texture: GLuint, level: GLint);
+    FramebufferTexture2D(
+        target: GLenum, attachment: GLenum, tex_target: GLenum, texture: GLuint, level: GLint
+    );
+    FramebufferTexture3D(
+        target: GLenum, attachment: GLenum, tex_target: GLenum, texture: GLuint, level: GLint, zoffset: GLint
+    );
+    FramebufferTextureLayer(target: GLenum, attachment: GLenum, texture: GLuint, level: GLint, layer: GLint);
+    FrontFace(mode: GLenum);
+    GenerateMipmap(target: GLenum);
+    // Only called with a buffer bound to `GL_QUERY_BUFFER`, `params` is an offset into it.
+    GetQueryObjectuiv(id: GLuint, pname: GLenum, params: *mut GLuint);
+    GetQueryObjectui64v(id: GLuint, pname: GLenum, params: *mut GLuint64);
+    Hint(target: GLenum, mode: GLenum);
+    InvalidateFramebuffer(target: GLenum, count: GLsizei, attachments: *const GLenum);
+    LineWidth(width: GLfloat);
+    LinkProgram(program: GLuint);
+    MemoryBarrier(barriers: GLbitfield);
+    MinSampleShading(value: GLfloat);
+    PatchParameteri(pname: GLenum, value: GLint);
+    PauseTransformFeedback();
+    PixelStorei(pname: GLenum, param: GLint);
+    PolygonMode(face: GLenum, mode: GLenum);
+    PolygonOffset(factor: GLfloat, units: GLfloat);
+    ProgramParameteri(program: GLuint, pname: GLenum, value: GLint);
+    ReadBuffer(buffer: GLenum);
+    RenderbufferStorage(target: GLenum, format: GLenum, width: GLsizei, height: GLsizei);
+    RenderbufferStorageMultisample(
+        target: GLenum, samples: GLsizei, format: GLenum, width: GLsizei, height: GLsizei
+    );
+    ResumeTransformFeedback();
+    SamplerParameterf(sampler: GLuint, pname: GLenum, param: GLfloat);
+    SamplerParameteri(sampler: GLuint, pname: GLenum, param: GLint);
+    Scissor(x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    ScissorArrayv(first: GLuint, count: GLsizei, v: *const GLint);
+    ScissorIndexed(index: GLuint, x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    ShaderSource(shader: GLuint, count: GLsizei, strings: *const *const GLchar, lengths: *const GLint);
+    StencilFuncSeparate(face: GLenum, func: GLenum, reference: GLint, mask: GLuint);
+    StencilMask(mask: GLuint);
+    StencilMaskSeparate(face: GLenum, mask: GLuint);
+    StencilOpSeparate(face: GLenum, fail: GLenum, depth_fail: GLenum, pass: GLenum);
+    TexBuffer(target: GLenum, format: GLenum, buffer: GLuint);
+    TexBufferRange(target: GLenum, format: GLenum, buffer: GLuint, offset: GLintptr, size: GLsizeiptr);
+    TexImage2DMultisample(
+        target: GLenum, samples: GLsizei, format: GLenum, width: GLsizei, height: GLsizei,
+        fixed: GLboolean
+    );
+    TexImage3DMultisample(
+        target: GLenum, samples: GLsizei, format: GLenum, width: GLsizei, height: GLsizei,
+        depth: GLsizei, fixed: GLboolean
+    );
+    TexParameterf(target: GLenum, pname: GLenum, param: GLfloat);
+    TexParameteri(target: GLenum, pname: GLenum, param: GLint);
+    TexStorage2D(target: GLenum, levels: GLsizei, format: GLenum, width: GLsizei, height: GLsizei);
+    TexStorage3D(
+        target: GLenum, levels: GLsizei, format: GLenum, width: GLsizei, height: GLsizei, depth: GLsizei
+    );
+    TexSubImage2D(
+        target: GLenum, level: GLint, x: GLint, y: GLint, width: GLsizei, height: GLsizei,
+        format: GLenum, ty: GLenum, data: *const c_void
+    );
+    TextureView(
+        texture: GLuint, target: GLenum, orig: GLuint, format: GLenum,
+        min_level: GLuint, num_levels: GLuint, min_layer: GLuint, num_layers: GLuint
+    );
+    TransformFeedbackVaryings(program: GLuint, count: GLsizei, varyings: *const *const GLchar, mode: GLenum);
+    Uniform1i(location: GLint, v0: GLint);
+    Uniform2i(location: GLint, v0: GLint, v1: GLint);
+    Uniform4i(location: GLint, v0: GLint, v1: GLint, v2: GLint, v3: GLint);
+    Uniform1fv(location: GLint, count: GLsizei, value: *const GLfloat);
+    Uniform1iv(location: GLint, count: GLsizei, value: *const GLint);
+    Uniform1uiv(location: GLint, count: GLsizei, value: *const GLuint);
+    Uniform2fv(location: GLint, count: GLsizei, value: *const GLfloat);
+    Uniform2iv(location: GLint, count: GLsizei, value: *const GLint);
+    Uniform2uiv(location: GLint, count: GLsizei, value: *const GLuint);
+    Uniform3fv(location: GLint, count: GLsizei, value: *const GLfloat);
+    Uniform3iv(location: GLint, count: GLsizei, value: *const GLint);
+    Uniform3uiv(location: GLint, count: GLsizei, value: *const GLuint);
+    Uniform4fv(location: GLint, count: GLsizei, value: *const GLfloat);
+    Uniform4iv(location: GLint, count: GLsizei, value: *const GLint);
+    Uniform4uiv(location: GLint, count: GLsizei, value: *const GLuint);
+    UniformMatrix2fv(location: GLint, count: GLsizei, transpose: GLboolean, value: *const GLfloat);
+    UniformMatrix3fv(location: GLint, count: GLsizei, transpose: GLboolean, value: *const GLfloat);
+    UniformMatrix4fv(location: GLint, count: GLsizei, transpose: GLboolean, value: *const GLfloat);
+    UseProgram(program: GLuint);
+    UseProgramStages(pipeline: GLuint, stages: GLbitfield, program: GLuint);
+    ValidateProgramPipeline(pipeline: GLuint);
+    VertexAttribDivisor(index: GLuint, divisor: GLuint);
+    VertexAttribIPointer(index: GLuint, size: GLint, ty: GLenum, stride: GLsizei, offset: *const c_void);
+    VertexAttribLPointer(index: GLuint, size: GLint, ty: GLenum, stride: GLsizei, offset: *const c_void);
+    VertexAttribPointer(
+        index: GLuint, size: GLint, ty: GLenum, normalized: GLboolean, stride: GLsizei, offset: *const c_void
+    );
+    Viewport(x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    ViewportArrayv(first: GLuint, count: GLsizei, v: *const GLfloat);
+    ViewportIndexedf(index: GLuint, x: GLfloat, y: GLfloat, width: GLfloat, height: GLfloat);
+    WaitSync(sync: GLsync, flags: GLbitfield, timeout: GLuint64);
+}
+
+/// Entry point loader of the fake implementation.
+pub fn load(symbol: &str) -> *const c_void {
+    match symbol {
+        "glGetError" => get_error as *const _,
+        "glGetString" => get_string as *const _,
+        "glGetIntegerv" => get_integerv as *const _,
+        "glGetIntegeri_v" => get_integeri_v as *const _,
+        "glGetInteger64v" => get_integer64v as *const _,
+        "glGetFloatv" => get_floatv as *const _,
+        "glGetBooleanv" => get_booleanv as *const _,
+        "glGetBooleani_v" => get_booleani_v as *const _,
+        "glGetTexParameteriv" => get_tex_parameteriv as *const _,
+        "glGetTexLevelParameteriv" => get_tex_level_parameteriv as *const _,
+        "glEnable" => enable as *const _,
+        "glDisable" => disable as *const _,
+        "glIsEnabled" => is_enabled as *const _,
+        "glGenTextures" => gen_textures as *const _,
+        "glGenBuffers" | "glGenFramebuffers" | "glGenRenderbuffers" | "glGenVertexArrays" |
+        "glGenSamplers" | "glGenQueries" | "glGenProgramPipelines" => gen_objects as *const _,
+        "glCreateProgram" => create_object as *const _,
+        "glCreateShader" => create_shader as *const _,
+        "glGetProgramiv" | "glGetShaderiv" | "glGetProgramPipelineiv" => get_object_iv as *const _,
+        "glGetUniformLocation" | "glGetAttribLocation" => get_location as *const _,
+        "glCheckFramebufferStatus" => check_framebuffer_status as *const _,
+        "glFenceSync" => fence_sync as *const _,
+        "glClientWaitSync" => client_wait_sync as *const _,
+        "glGetSynciv" => get_synciv as *const _,
+        "glIsSync" => is_sync as *const _,
+        "glIsBuffer" => is_object as *const _,
+        "glMapBufferRange" => map_buffer_range as *const _,
+        "glSamplerParameterfv" => sampler_parameterfv as *const _,
+        "glTexParameterfv" => tex_parameterfv as *const _,
+        "glUnmapBuffer" => unmap_buffer as *const _,
+        "glGetFramebufferAttachmentParameteriv" => get_framebuffer_attachment_parameteriv as *const _,
+        "glGetInternalformativ" => get_internalformativ as *const _,
+        "glGetProgramResourceIndex" => get_program_resource_index as *const _,
+        "glGetProgramResourceiv" => get_program_resourceiv as *const _,
+        "glGetProgramInfoLog" | "glGetShaderInfoLog" | "glGetProgramPipelineInfoLog" => get_info_log as *const _,
+        _ => recorder(symbol),
+    }
+}
+
+/// Create a GL function table of the fake implementation.
+pub fn fake_gl() -> gl::Gl {
+    gl::Gl::load_with(load)
+}
+
+/// Create an adapter on the fake implementation.
+pub fn adapter() -> hal::Adapter<Backend> {
+    PhysicalDevice::new_adapter(load)
+}
+
+/// Open a device and its queue on an adapter of the fake implementation.
+pub fn open_adapter(adapter: &hal::Adapter<Backend>) -> (Device, CommandQueue) {
+    let family = &adapter.queue_families[0];
+    let mut gpu = hal::PhysicalDevice::open(&adapter.physical_device, &[(family, &[1.0])]).unwrap();
+    let queue = gpu.queues.take_raw(QueueFamilyId(0)).unwrap().remove(0);
+    (gpu.device, queue)
+}
+
+/// Open a device and its queue on the fake implementation.
+pub fn open() -> (Device, CommandQueue) {
+    open_adapter(&adapter())
+}
+
+/// Allocate a primary command buffer and begin recording it.
+pub fn command_buffer(device: &Device) -> RawCommandBuffer {
+    use hal::command::{CommandBufferFlags, RawLevel};
+    use hal::pool::CommandPoolCreateFlags;
+
+    let mut pool = hal::Device::create_command_pool(device, QueueFamilyId(0), CommandPoolCreateFlags::empty());
+    let mut buffer = hal::pool::RawCommandPool::allocate(&mut pool, 1, RawLevel::Primary).remove(0);
+    hal::command::RawCommandBuffer::begin(&mut buffer, CommandBufferFlags::empty(), Default::default());
+    buffer
+}
+
+/// Finish recording `buffer` and execute it on the queue.
+pub fn submit(queue: &mut CommandQueue, mut buffer: RawCommandBuffer, fence: Option<&n::Fence>) {
+    use hal::queue::{RawCommandQueue, RawSubmission};
+
+    hal::command::RawCommandBuffer::finish(&mut buffer);
+    let submission = RawSubmission {
+        cmd_buffers: Some(&buffer),
+        wait_semaphores: &[],
+        signal_semaphores: &[],
+    };
+    unsafe { queue.submit_raw(submission, fence) };
+}
+
+/// Graphics pipeline drawing triangles with program 1 and all states off.
+pub fn graphics_pipeline() -> n::GraphicsPipeline {
+    n::GraphicsPipeline {
+        program: n::BoundProgram::Program(1),
+        stage_programs: Vec::new(),
+        primitive: gl::TRIANGLES,
+        patch_size: None,
+        blend_targets: Vec::new(),
+        attributes: Vec::new(),
+        vertex_buffers: Vec::new(),
+        smooth: (false, false),
+        depth_bias: None,
+        depth_clamp: false,
+        depth_bounds: None,
+        depth: hal::pso::DepthTest::Off,
+        stencil: hal::pso::StencilTest::Off,
+        uniforms: Vec::new(),
+        sample_shading: None,
+        framebuffer_srgb: None,
+    }
+}
+
+/// Create an image and bind it to newly allocated memory.
+pub fn create_image(
+    device: &Device,
+    kind: hal::image::Kind,
+    num_levels: hal::image::Level,
+    format: hal::format::Format,
+    usage: hal::image::Usage,
+    flags: hal::image::StorageFlags,
+) -> Result<n::Image, hal::image::CreationError> {
+    let unbound = hal::Device::create_image(
+        device, kind, num_levels, format, hal::image::Tiling::Optimal, usage, flags,
+    )?;
+    let requirements = hal::Device::get_image_requirements(device, &unbound);
+    let memory = hal::Device::allocate_memory(device, hal::MemoryTypeId(0), requirements.size).unwrap();
+    Ok(hal::Device::bind_image_memory(device, &memory, 0, unbound).unwrap())
+}
@@ -25,6 +25,10 @@
 //!
 //! Headless initialization without a window.
 //!
+//! Devices created from a headless context don't have a default framebuffer
+//! and can't create swapchains, but otherwise expose the full queue functionality,
+//! e.g. for running compute work and reading back the results.
+//!
 //! ```no_run
 //! extern crate glutin;
 //! extern crate gfx_backend_gl;
@@ -43,13 +47,44 @@
 //! }
 //! ```
 
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gl;
 use hal::{self, format as f, image};
 
-use {Backend as B, Device, PhysicalDevice, QueueFamily, Starc};
+use {conv, native as n, Backend as B, Device, PhysicalDevice, QueueFamily, Share, Starc};
 
 use glutin::{self, GlContext};
 
 
+// Key identifying the context of a window.
+fn window_key(window: &Starc<glutin::GlWindow>) -> usize {
+    &**window as *const glutin::GlWindow as usize
+}
+
+// Make the context of a window current.
+fn make_window_current(share: &Share, window: &Starc<glutin::GlWindow>) {
+    let key = window_key(window);
+    if share.current_window.get() == key && window.is_current() {
+        return;
+    }
+    unsafe { window.make_current().unwrap() };
+    register_window_context(share, window);
+    share.current_window.set(key);
+}
+
+// Record the context of a window for checking that a context of the device is current.
+fn register_window_context(share: &Share, window: &Starc<glutin::GlWindow>) {
+    // Don't keep closed windows alive.
+    let window_ref = Arc::downgrade(&window.arc);
+    share.register_context(window_key(window), Box::new(move || {
+        window_ref.upgrade().map_or(false, |window| window.is_current())
+    }));
+}
+
 fn get_window_extent(window: &glutin::GlWindow) -> image::Extent {
     let px = window.get_inner_size().unwrap().to_physical(window.get_hidpi_factor());
     image::Extent {
@@ -59,14 +94,119 @@ fn get_window_extent(window: &glutin::GlWindow) -> image::Extent {
     }
 }
 
+/// Synchronization with the GPU performed when presenting.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PresentSync {
+    /// Only flush the pending commands, the CPU doesn't wait for the GPU.
+    Flush,
+    /// Flush and signal the frame fence of the swapchain,
+    /// which can be waited on for frame pacing.
+    Fence,
+    /// Wait until the GPU has completed all commands via `glFinish`.
+    /// Stalls every frame, intended for debugging.
+    Finish,
+}
+
 pub struct Swapchain {
     // Underlying window, required for presentation
     pub(crate) window: Starc<glutin::GlWindow>,
+    // Swap the window buffers when presenting.
+    pub(crate) swap_on_present: bool,
+    // Set by `present` if the caller has to swap the buffers manually.
+    pub(crate) ready: AtomicBool,
+    // Synchronization done by `present`.
+    pub(crate) present_sync: PresentSync,
+    // Signaled once the GPU completed the last presented frame.
+    pub(crate) frame_fence: n::Fence,
+    // Minimum time between presented frames, if capped.
+    pub(crate) frame_time: Option<Duration>,
+    // Fence of the previous frame, waited on when pacing frames.
+    pub(crate) pacing_fence: n::Fence,
+    // Time of the last frame cap enforcement.
+    pub(crate) last_present: Mutex<Option<Instant>>,
+    // Target of `CommandQueue::present_image` resolving multisampled images,
+    // created on first use in the context of the window.
+    pub(crate) resolve_target: Mutex<Option<ResolveTarget>>,
+    pub(crate) share: Starc<Share>,
+}
+
+/// Framebuffer object with a renderbuffer color attachment.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResolveTarget {
+    pub(crate) fbo: gl::types::GLuint,
+    pub(crate) surface: gl::types::GLuint,
+    // Internal format and size of the renderbuffer storage.
+    pub(crate) storage: (gl::types::GLenum, gl::types::GLint, gl::types::GLint),
+}
+
+impl Swapchain {
+    /// Fence signaled once the GPU completed the last presented frame,
+    /// if the swapchain was created with `PresentSync::Fence`.
+    ///
+    /// Wait on it with `Device::wait_for_fence` to limit the number of frames in flight.
+    /// The fence is replaced on each `present` and must not be destroyed or reset.
+    pub fn frame_fence(&self) -> &n::Fence {
+        &self.frame_fence
+    }
+
+    /// Returns `true` if presenting swaps the window buffers.
+    pub fn swap_on_present(&self) -> bool {
+        self.swap_on_present
+    }
+
+    /// Returns `true` if presenting swaps the buffers and the swap implicitly
+    /// flushes the commands of the context.
+    ///
+    /// `glXSwapBuffers` (GLX), `SwapBuffers` (WGL), `eglSwapBuffers` (EGL) and
+    /// `flushBuffer` (CGL) all flush the current context. On the web the canvas is
+    /// only composited once control returns to the browser, and buffers swapped by
+    /// the caller are swapped after `present` returned, so neither flushes in time.
+    pub fn flushes_on_swap(&self) -> bool {
+        self.swap_on_present && !cfg!(target_os = "emscripten")
+    }
+
+    /// Returns `true` once after each `present` when the buffer swap is left to the caller,
+    /// signaling that the frame is finished and the buffers can be swapped.
+    pub fn take_ready(&self) -> bool {
+        self.ready.swap(false, Ordering::AcqRel)
+    }
+
+    // Size of the default framebuffer of the window in pixels.
+    pub(crate) fn extent(&self) -> image::Extent {
+        get_window_extent(&self.window)
+    }
+
+    // Check if the default framebuffer of the window is unavailable,
+    // e.g. while it's minimized.
+    pub(crate) fn is_out_of_date(&self) -> bool {
+        // Incomplete framebuffers are also detected when binding them
+        // for rendering, see `CommandQueue::process`.
+        let extent = self.extent();
+        extent.width == 0 || extent.height == 0
+    }
+
+    // Make the context of the window current, so the default framebuffer
+    // refers to this window.
+    pub(crate) fn make_current(&self) {
+        make_window_current(&self.share, &self.window);
+    }
+
+    // Check if the context of the window is the current one.
+    pub(crate) fn is_current(&self) -> bool {
+        self.share.current_window.get() == window_key(&self.window)
+    }
 }
 
 impl hal::Swapchain<B> for Swapchain {
     fn acquire_image(&mut self, _sync: hal::FrameSync<B>) -> Result<hal::SwapImageIndex, ()> {
         // TODO: sync
+        // Commands submitted afterwards render into the default framebuffer of this window.
+        self.make_current();
+        if self.is_out_of_date() {
+            // The window is minimized or the framebuffer is unavailable,
+            // the caller should wait and retry.
+            return Err(());
+        }
         Ok(0)
     }
 }
@@ -76,15 +216,54 @@ impl hal::Swapchain<B> for Swapchain {
 // and actually respect the swapchain configuration provided by the user.
 pub struct Surface {
     window: Starc<glutin::GlWindow>,
+    swap_on_present: bool,
+    present_sync: PresentSync,
+    frame_time: Option<Duration>,
 }
 
 impl Surface {
+    /// Create a surface from a window.
+    ///
+    /// Additional windows driven by the same device need contexts sharing their
+    /// objects with the context of the window the adapter was enumerated from,
+    /// see `glutin::ContextBuilder::with_shared_lists`. Each window has its own
+    /// swapchain, acquiring an image makes the context of its window current.
+    /// Framebuffers created by the device can only be used in its own context.
     pub fn from_window(window: glutin::GlWindow) -> Self {
         Surface {
-            window: Starc::new(window)
+            window: Starc::new(window),
+            swap_on_present: true,
+            present_sync: PresentSync::Flush,
+            frame_time: None,
         }
     }
 
+    /// Configure if presenting swaps the window buffers (enabled by default).
+    ///
+    /// When disabled, `present` only flushes the pending commands and the
+    /// caller is responsible for swapping the buffers, see `Swapchain::take_ready`.
+    /// Only affects swapchains created afterwards.
+    pub fn set_swap_on_present(&mut self, swap_on_present: bool) {
+        self.swap_on_present = swap_on_present;
+    }
+
+    /// Configure the synchronization with the GPU when presenting
+    /// (`PresentSync::Flush` by default).
+    /// Only affects swapchains created afterwards.
+    pub fn set_present_sync(&mut self, present_sync: PresentSync) {
+        self.present_sync = present_sync;
+    }
+
+    /// Cap the frame rate by presenting at most one frame per `frame_time`
+    /// (disabled by default).
+    ///
+    /// Presenting waits for the GPU to complete the previous frame and sleeps
+    /// for the remaining frame time, without requiring vsync.
+    /// Only affects swapchains created afterwards.
+    pub fn set_frame_time_cap(&mut self, frame_time: Option<Duration>) {
+        self.frame_time = frame_time;
+    }
+
     pub fn get_window(&self) -> &glutin::GlWindow {
         &*self.window
     }
@@ -93,14 +272,64 @@ impl Surface {
         &self.window
     }
 
-    fn swapchain_formats(&self) -> Vec<f::Format> {
+    /// Query the actual format of the default framebuffer of the window,
+    /// including its bit depth, e.g. to detect 10-bit or floating point output.
+    ///
+    /// Makes the context of the window current. Returns `None` if the format
+    /// can't be queried or has no matching `Format`.
+    pub fn framebuffer_format(&self, physical_device: &PhysicalDevice) -> Option<f::Format> {
+        let share = &physical_device.0;
+        if !share.private_caps.framebuffer {
+            return None;
+        }
+        make_window_current(share, &self.window);
+
+        let gl = &share.context;
+        let attachment = if share.info.version.is_embedded {
+            gl::BACK
+        } else if self.window.get_pixel_format().double_buffer {
+            gl::BACK_LEFT
+        } else {
+            gl::FRONT_LEFT
+        };
+        let get = |pname| {
+            let mut value = 0;
+            unsafe {
+                gl.GetFramebufferAttachmentParameteriv(gl::DRAW_FRAMEBUFFER, attachment, pname, &mut value);
+            }
+            value
+        };
+
+        let mut prev_framebuffer = 0;
+        unsafe {
+            gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut prev_framebuffer);
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, n::DEFAULT_FRAMEBUFFER);
+        }
+        let bits = [
+            get(gl::FRAMEBUFFER_ATTACHMENT_RED_SIZE),
+            get(gl::FRAMEBUFFER_ATTACHMENT_GREEN_SIZE),
+            get(gl::FRAMEBUFFER_ATTACHMENT_BLUE_SIZE),
+            get(gl::FRAMEBUFFER_ATTACHMENT_ALPHA_SIZE),
+        ];
+        let component_type = get(gl::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE) as gl::types::GLenum;
+        let srgb = get(gl::FRAMEBUFFER_ATTACHMENT_COLOR_ENCODING) as gl::types::GLenum == gl::SRGB;
+        unsafe { gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, prev_framebuffer as _) };
+
+        if let Err(err) = share.check() {
+            warn!("Error querying the default framebuffer format: {:?}", err);
+            return None;
+        }
+        conv::framebuffer_attachment_format(bits, component_type, srgb)
+    }
+
+    fn swapchain_formats(&self, physical_device: &PhysicalDevice) -> Vec<f::Format> {
         let pixel_format = self.window.get_pixel_format();
         let color_bits = pixel_format.color_bits;
         let alpha_bits = pixel_format.alpha_bits;
         let srgb = pixel_format.srgb;
 
         // TODO: expose more formats
-        match (color_bits, alpha_bits, srgb) {
+        let mut formats = match (color_bits, alpha_bits, srgb) {
             (24, 8, true) => vec![
                 f::Format::Rgba8Srgb,
                 f::Format::Bgra8Srgb,
@@ -110,7 +339,16 @@ impl Surface {
                 f::Format::Bgra8Unorm,
             ],
             _ => vec![],
+        };
+
+        // The framebuffer may have a higher bit depth than the pixel format reports,
+        // e.g. 10-bit or floating point for HDR output.
+        if let Some(format) = self.framebuffer_format(physical_device) {
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
         }
+        formats
     }
 }
 
@@ -125,7 +363,7 @@ impl hal::Surface<B> for Surface {
     }
 
     fn compatibility(
-        &self, _: &PhysicalDevice
+        &self, physical_device: &PhysicalDevice
     ) -> (hal::SurfaceCapabilities, Option<Vec<f::Format>>, Vec<hal::PresentMode>) {
         let ex = get_window_extent(&self.window);
         let extent = hal::window::Extent2D::from(ex);
@@ -141,7 +379,7 @@ impl hal::Surface<B> for Surface {
         };
         let present_modes = vec![hal::PresentMode::Fifo]; //TODO
 
-        (caps, Some(self.swapchain_formats()), present_modes)
+        (caps, Some(self.swapchain_formats(physical_device)), present_modes)
     }
 
     fn supports_queue_family(&self, _: &QueueFamily) -> bool { true }
@@ -155,6 +393,16 @@ impl Device {
     ) -> (Swapchain, hal::Backbuffer<B>) {
         let swapchain = Swapchain {
             window: surface.window.clone(),
+            swap_on_present: surface.swap_on_present,
+            ready: AtomicBool::new(false),
+            present_sync: surface.present_sync,
+            // Nothing has been presented yet, so waiting returns immediately.
+            frame_fence: n::Fence::new(ptr::null(), true),
+            frame_time: surface.frame_time,
+            pacing_fence: n::Fence::new(ptr::null(), true),
+            last_present: Mutex::new(None),
+            resolve_target: Mutex::new(None),
+            share: self.share.clone(),
         };
         let backbuffer = hal::Backbuffer::Framebuffer(0);
         (swapchain, backbuffer)
@@ -166,6 +414,8 @@ impl hal::Instance for Surface {
     fn enumerate_adapters(&self) -> Vec<hal::Adapter<B>> {
         unsafe { self.window.make_current().unwrap() };
         let adapter = PhysicalDevice::new_adapter(|s| self.window.get_proc_address(s) as *const _);
+        register_window_context(&adapter.physical_device.0, &self.window);
+        adapter.physical_device.0.current_window.set(window_key(&self.window));
         vec![adapter]
     }
 }
@@ -190,6 +440,9 @@ pub fn config_context(
 }
 
 
+/// Instance backed by a context without a window.
+///
+/// Glutin creates the context via pbuffers or surfaceless EGL where available.
 pub struct Headless(pub glutin::HeadlessContext);
 
 unsafe impl Send for Headless {}
@@ -352,11 +352,18 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
     fn limits(&self) -> hal::Limits {
         hal::Limits {
             max_texture_size: 4096, // TODO: feature set
+            max_image_array_layers: 2048,
+            max_image_3d_size: 2048,
+            max_uniform_buffer_bindings: 31, // Buffer argument table entries per stage
+            max_storage_buffer_bindings: 31,
             max_patch_size: 0, // No tessellation
 
             // Note: The maximum number of supported viewports and scissor rectangles varies by device.
             // TODO: read from Metal Feature Sets.
             max_viewports: 1,
+            max_viewport_dimensions: [4096; 2], // TODO: feature set
+            sub_pixel_precision_bits: 4, // TODO: not exposed by Metal
+            viewport_sub_pixel_bits: 0,
 
             min_buffer_copy_offset_alignment: self.private_caps.buffer_alignment,
             min_buffer_copy_pitch_alignment: 4,
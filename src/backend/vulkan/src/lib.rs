@@ -632,8 +632,15 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 
         Limits {
             max_texture_size: limits.max_image_dimension3d as _,
+            max_image_array_layers: limits.max_image_array_layers as _,
+            max_image_3d_size: limits.max_image_dimension3d as _,
+            max_uniform_buffer_bindings: limits.max_descriptor_set_uniform_buffers as _,
+            max_storage_buffer_bindings: limits.max_descriptor_set_storage_buffers as _,
             max_patch_size: limits.max_tessellation_patch_size as PatchSize,
             max_viewports: limits.max_viewports as _,
+            max_viewport_dimensions: limits.max_viewport_dimensions,
+            sub_pixel_precision_bits: limits.sub_pixel_precision_bits,
+            viewport_sub_pixel_bits: limits.viewport_sub_pixel_bits,
             max_compute_group_count: [max_group_count[0] as _, max_group_count[1] as _, max_group_count[2] as _],
             max_compute_group_size: [max_group_size[0] as _, max_group_size[1] as _, max_group_size[2] as _],
             max_vertex_input_attributes: limits.max_vertex_input_attributes as _,
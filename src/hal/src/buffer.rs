@@ -53,6 +53,18 @@ pub enum ViewCreationError {
         /// Unsupported format passed on view creation.
         format: Option<format::Format>,
     },
+    /// Buffer view range exceeds the buffer.
+    #[fail(display = "Buffer view range out of bounds.")]
+    OutOfBounds,
+    /// Buffer views of a sub-range of the buffer are not supported.
+    #[fail(display = "Buffer view sub-ranges unsupported.")]
+    UnsupportedRange,
+    /// Buffer view offset is not aligned to `min_texel_buffer_offset_alignment`.
+    #[fail(display = "Buffer view offset {} is not aligned.", offset)]
+    UnalignedOffset {
+        /// Offset passed on view creation.
+        offset: u64,
+    },
 }
 
 bitflags!(
@@ -121,6 +133,8 @@ bitflags!(
         const MEMORY_READ = 0x8000;
         ///
         const MEMORY_WRITE = 0x10000;
+        /// Write vertex data captured by transform feedback.
+        const TRANSFORM_FEEDBACK_WRITE = 0x2000000;
     }
 );
 
@@ -231,10 +231,24 @@ bitflags! {
 pub struct Limits {
     /// Maximum supported texture size.
     pub max_texture_size: usize,
+    /// Maximum number of layers of an array image.
+    pub max_image_array_layers: usize,
+    /// Maximum width, height and depth of a 3D image.
+    pub max_image_3d_size: usize,
+    /// Maximum number of uniform buffers bound at once, summed over all descriptor sets.
+    pub max_uniform_buffer_bindings: usize,
+    /// Maximum number of storage buffers bound at once, summed over all descriptor sets.
+    pub max_storage_buffer_bindings: usize,
     /// Maximum number of vertices for each patch.
     pub max_patch_size: PatchSize,
     /// Maximum number of viewports.
     pub max_viewports: usize,
+    /// Maximum width and height of a viewport.
+    pub max_viewport_dimensions: [u32; 2],
+    /// Number of bits of subpixel precision of the rasterizer in window coordinates.
+    pub sub_pixel_precision_bits: u32,
+    /// Number of bits of subpixel precision of the viewport bounds.
+    pub viewport_sub_pixel_bits: u32,
     ///
     pub max_compute_group_count: WorkGroupCount,
     ///
@@ -48,6 +48,13 @@ pub struct AttributeDesc {
     pub binding: BufferIndex,
     /// Attribute element description.
     pub element: Element<format::Format>,
+    /// Override if integer data is normalized when converted to floating point,
+    /// instead of following the format, where only `Unorm` and `Inorm` are normalized.
+    /// `None` keeps the default of the format.
+    ///
+    /// Only respected by the OpenGL backend.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub normalized: Option<bool>,
 }
 
 /// Describes whether or not primitive restart is supported for